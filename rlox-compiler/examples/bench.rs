@@ -0,0 +1,40 @@
+// times the VM on a few tight programs, run with:
+// cargo run -p rlox-compiler --release --example bench
+use std::rc::Rc;
+use std::time::{ Duration, Instant };
+use rlox_compiler::{ Chunk, Compiler, VM };
+use rlox_test_utils::parse;
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&str, &str); 5] = [
+    ("fibonacci", "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); } var result = fib(25);"),
+    ("global loop", "var i = 0; while (i < 1000000) { i = i + 1; }"),
+    ("local loop", "{ var total = 0; for (var i = 0; i < 1000000; i = i + 1) { total = total + i % 7; } }"),
+    ("properties", "class Counter { inc() { this.n = this.n + 1; } } { var c = Counter(); c.n = 0; while (c.n < 1000000) c.inc(); }"),
+    ("strings", "{ var s = \"\"; for (var i = 0; i < 100000; i = i + 1) { s = \"ab\" + i; if (s == \"ab1\") s = s + \"!\"; } }"),
+];
+
+fn compile(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    Compiler::new(&mut chunk).compile(parse(source)).expect("Failed to compile source");
+
+    chunk
+}
+
+fn main() {
+    for (name, source) in PROGRAMS.iter() {
+        let chunk = Rc::new(compile(source));
+
+        let mut best = Duration::from_secs(u64::MAX);
+        for _ in 0..RUNS {
+            let mut vm = VM::new(Rc::clone(&chunk));
+
+            let start = Instant::now();
+            vm.run().expect("Failed to run program");
+            best = best.min(start.elapsed());
+        }
+
+        println!("{:12} {:>8.2}ms (best of {})", name, best.as_secs_f64() * 1000.0, RUNS);
+    }
+}
@@ -0,0 +1,53 @@
+// counts the allocations made running string-heavy code, run with:
+// cargo run -p rlox-compiler --release --example strings
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::rc::Rc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Instant;
+use rlox_compiler::{ Chunk, Compiler, VM };
+use rlox_test_utils::parse;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// rebuilds a string that is already alive, interning finds the existing object instead of allocating another one
+const SOURCE: &str = "
+var greeting = \"hello world\";
+var matches = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+    var built = \"hello \" + \"world\";
+    if (built == greeting) matches = matches + 1;
+}
+";
+
+fn compile(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    Compiler::new(&mut chunk).compile(parse(source)).expect("Failed to compile source");
+
+    chunk
+}
+
+fn main() {
+    let mut vm = VM::new(Rc::new(compile(SOURCE)));
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    vm.run().expect("Failed to run program");
+    let elapsed = start.elapsed();
+
+    println!("{} allocations {:.2}ms", ALLOCATIONS.load(Ordering::Relaxed) - before, elapsed.as_secs_f64() * 1000.0);
+}
@@ -0,0 +1,820 @@
+use std::collections::{ HashMap, HashSet };
+use std::io::{ self, Write };
+use std::rc::Rc;
+use std::str::FromStr;
+use crate::serialize::validate_with_line;
+use crate::{ Chunk, Object, OpCode, Value };
+
+// assembly is one item per line, `;` starts a comment. items belong to the section last switched to, `.code` if none
+// has been yet:
+//
+//   .constants     one constant per line, a number, a "string" (with \" \\ \n \r \t escapes), nil, true or false,
+//                  or a function, `fun <name> <arity> {` followed by its chunk's own sections and a closing `}`
+//   .globals       one global name per line, a global's slot is its position in the list
+//   .code          `name:` labels, and instructions as their disassembled name followed by their operands, e.g.
+//                  `OP_CONSTANT 0`, `OP_INVOKE 1 2` or `OP_CLOSURE 0 local 1 upvalue 0`
+//
+// jumps and loops take a label, or a raw distance when it's a number. each instruction records the assembly line it
+// came from as its line, so runtime errors point back into the assembly
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub description: AsmErrorDescription,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AsmErrorDescription {
+    UnknownSection(String),
+    UnknownInstruction(String),
+    WrongOperandCount { expected: usize, provided: usize },
+    InvalidOperand(String),
+    InvalidConstant(String),
+    TooManyConstants,
+    DuplicateGlobal(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    // jumps can only go forwards and loops backwards
+    WrongJumpDirection(String),
+    JumpTooLarge(String),
+    // the code fails the checks a loaded .loxc goes through, e.g. a local slot that can't exist
+    InvalidCode(String),
+    // a function constant's `}` is missing, or a `}` closes nothing
+    UnterminatedFunction(String),
+    UnmatchedBrace,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Constants,
+    Globals,
+    Code,
+}
+
+#[derive(Clone, Copy)]
+enum JumpKind {
+    Jump,
+    JumpIfFalse,
+    Loop,
+}
+
+enum Instruction {
+    Op(OpCode),
+    // resolved once every label's offset is known
+    Jump(JumpKind, String),
+}
+
+pub fn assemble(source: &str) -> Result<Chunk, AsmError> {
+    let mut lines = source.lines().enumerate().map(|(index, line)| (index + 1, line));
+
+    let mut chunk = Chunk::new();
+    if let Some(line) = assemble_chunk(&mut lines, &mut chunk)? {
+        return Err(AsmError { line, description: AsmErrorDescription::UnmatchedBrace });
+    }
+
+    if let Err((e, line)) = validate_with_line(&chunk) {
+        return Err(AsmError { line, description: AsmErrorDescription::InvalidCode(format!("{:?}", e)) });
+    }
+
+    Ok(chunk)
+}
+
+// reads sections into `chunk` until the end of the source, or until a `}` closing a function's chunk, returning that
+// `}`'s line
+fn assemble_chunk(lines: &mut dyn Iterator<Item = (usize, &str)>, chunk: &mut Chunk) -> Result<Option<usize>, AsmError> {
+    let mut section = Section::Code;
+
+    let mut instructions = Vec::new();
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    let mut closing_line = None;
+
+    while let Some((line_number, line)) = lines.next() {
+        let error = |description| AsmError { line: line_number, description };
+
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "}" {
+            closing_line = Some(line_number);
+            break;
+        }
+
+        if let Some(name) = line.strip_prefix('.') {
+            section = match name {
+                "constants" => Section::Constants,
+                "globals" => Section::Globals,
+                "code" => Section::Code,
+                _ => return Err(error(AsmErrorDescription::UnknownSection(line.into()))),
+            };
+            continue;
+        }
+
+        match section {
+            Section::Constants => {
+                let value = match parse_function_header(line) {
+                    Some((name, arity)) => {
+                        // the function's chunk shares the script's globals
+                        let mut function_chunk = Chunk::with_global_names(Rc::clone(chunk.global_names()));
+                        if assemble_chunk(lines, &mut function_chunk)?.is_none() {
+                            return Err(error(AsmErrorDescription::UnterminatedFunction(name)));
+                        }
+
+                        Value::Object(Rc::new(Object::Function { name, arity, chunk: Rc::new(function_chunk) }))
+                    },
+                    None => parse_constant(line).ok_or_else(|| error(AsmErrorDescription::InvalidConstant(line.into())))?,
+                };
+                chunk.add_constant(value).map_err(|_| error(AsmErrorDescription::TooManyConstants))?;
+            },
+            Section::Globals => {
+                let slot = chunk.global_slot(line).map_err(|e| error(AsmErrorDescription::InvalidOperand(e)))?;
+                if slot as usize != chunk.global_names().borrow().len() - 1 {
+                    return Err(error(AsmErrorDescription::DuplicateGlobal(line.into())));
+                }
+            },
+            Section::Code => {
+                if let Some(label) = line.strip_suffix(':') {
+                    if labels.insert(label.to_owned(), offset).is_some() {
+                        return Err(error(AsmErrorDescription::DuplicateLabel(label.into())));
+                    }
+                    continue;
+                }
+
+                let mut words = line.split_whitespace();
+                let mnemonic = words.next().unwrap_or_default();
+                let operands: Vec<&str> = words.collect();
+
+                let instruction = parse_instruction(mnemonic, &operands).map_err(error)?;
+                // every jump encodes to the same length whatever its distance, so offsets are known before resolving
+                offset += match &instruction {
+                    Instruction::Op(op) => op.byte_length(),
+                    Instruction::Jump(..) => OpCode::Jump(0).byte_length(),
+                };
+                instructions.push((instruction, line_number));
+            },
+        }
+    }
+
+    for (instruction, line) in instructions {
+        let op = match instruction {
+            Instruction::Op(op) => op,
+            Instruction::Jump(kind, label) => {
+                let error = |description| AsmError { line, description };
+
+                let target = *labels.get(&label).ok_or_else(|| error(AsmErrorDescription::UndefinedLabel(label.clone())))?;
+                let next_offset = chunk.len() + OpCode::Jump(0).byte_length();
+                let distance = match kind {
+                    JumpKind::Jump | JumpKind::JumpIfFalse => target.checked_sub(next_offset),
+                    JumpKind::Loop => next_offset.checked_sub(target),
+                };
+
+                let distance = distance.ok_or_else(|| error(AsmErrorDescription::WrongJumpDirection(label.clone())))?;
+                if distance > u16::MAX as usize {
+                    return Err(error(AsmErrorDescription::JumpTooLarge(label)));
+                }
+
+                jump(kind, distance as u16)
+            },
+        };
+
+        chunk.add(op, line);
+    }
+
+    Ok(closing_line)
+}
+
+// `fun <name> <arity> {`, the start of a function constant
+fn parse_function_header(line: &str) -> Option<(String, u8)> {
+    let mut words = line.split_whitespace();
+    if words.next()? != "fun" {
+        return None;
+    }
+
+    let name = words.next()?;
+    let arity = words.next()?.parse().ok()?;
+    match (words.next(), words.next()) {
+        (Some("{"), None) => Some((name.into(), arity)),
+        _ => None,
+    }
+}
+
+fn jump(kind: JumpKind, distance: u16) -> OpCode {
+    match kind {
+        JumpKind::Jump => OpCode::Jump(distance),
+        JumpKind::JumpIfFalse => OpCode::JumpIfFalse(distance),
+        JumpKind::Loop => OpCode::Loop(distance),
+    }
+}
+
+fn parse_instruction(mnemonic: &str, operands: &[&str]) -> Result<Instruction, AsmErrorDescription> {
+    let op = match mnemonic {
+        "OP_TRUE" => no_operands(operands, OpCode::True)?,
+        "OP_FALSE" => no_operands(operands, OpCode::False)?,
+        "OP_NIL" => no_operands(operands, OpCode::Nil)?,
+        "OP_POP" => no_operands(operands, OpCode::Pop)?,
+        "OP_EQUAL" => no_operands(operands, OpCode::Equal)?,
+        "OP_GREATER" => no_operands(operands, OpCode::Greater)?,
+        "OP_LESS" => no_operands(operands, OpCode::Less)?,
+        "OP_ADD" => no_operands(operands, OpCode::Add)?,
+        "OP_SUBTRACT" => no_operands(operands, OpCode::Subtract)?,
+        "OP_MULTIPLY" => no_operands(operands, OpCode::Multiply)?,
+        "OP_DIVIDE" => no_operands(operands, OpCode::Divide)?,
+        "OP_NOT" => no_operands(operands, OpCode::Not)?,
+        "OP_NEGATE" => no_operands(operands, OpCode::Negate)?,
+        "OP_MODULO" => no_operands(operands, OpCode::Modulo)?,
+        "OP_PRINT" => no_operands(operands, OpCode::Print)?,
+        "OP_RETURN" => no_operands(operands, OpCode::Return)?,
+        "OP_CLOSE_UPVALUE" => no_operands(operands, OpCode::CloseUpvalue)?,
+        "OP_INHERIT" => no_operands(operands, OpCode::Inherit)?,
+
+        "OP_CONSTANT" => OpCode::Constant(one_operand(operands)?),
+        "OP_GET_LOCAL" => OpCode::GetLocal(one_operand(operands)?),
+        "OP_SET_LOCAL" => OpCode::SetLocal(one_operand(operands)?),
+        "OP_GET_GLOBAL" => OpCode::GetGlobal(one_operand(operands)?),
+        "OP_DEFINE_GLOBAL" => OpCode::DefineGlobal(one_operand(operands)?),
+        "OP_SET_GLOBAL" => OpCode::SetGlobal(one_operand(operands)?),
+        "OP_CALL" => OpCode::Call(one_operand(operands)?),
+        "OP_GET_UPVALUE" => OpCode::GetUpvalue(one_operand(operands)?),
+        "OP_SET_UPVALUE" => OpCode::SetUpvalue(one_operand(operands)?),
+        "OP_CLASS" => OpCode::Class(one_operand(operands)?),
+        "OP_GET_PROPERTY" => OpCode::GetProperty(one_operand(operands)?),
+        "OP_SET_PROPERTY" => OpCode::SetProperty(one_operand(operands)?),
+        "OP_METHOD" => OpCode::Method(one_operand(operands)?),
+        "OP_GET_SUPER" => OpCode::GetSuper(one_operand(operands)?),
+        "OP_GET_LOCAL_LONG" => OpCode::GetLocalLong(one_operand(operands)?),
+        "OP_SET_LOCAL_LONG" => OpCode::SetLocalLong(one_operand(operands)?),
+        "OP_ARRAY" => OpCode::Array(one_operand(operands)?),
+        "OP_CONSTANT_LONG" => OpCode::ConstantLong(long_operand(operands)?),
+        "OP_GET_GLOBAL_LONG" => OpCode::GetGlobalLong(long_operand(operands)?),
+        "OP_DEFINE_GLOBAL_LONG" => OpCode::DefineGlobalLong(long_operand(operands)?),
+        "OP_SET_GLOBAL_LONG" => OpCode::SetGlobalLong(long_operand(operands)?),
+
+        "OP_CLASS_LONG" => OpCode::ClassLong(long_operand(operands)?),
+        "OP_GET_PROPERTY_LONG" => OpCode::GetPropertyLong(long_operand(operands)?),
+        "OP_SET_PROPERTY_LONG" => OpCode::SetPropertyLong(long_operand(operands)?),
+        "OP_METHOD_LONG" => OpCode::MethodLong(long_operand(operands)?),
+        "OP_GET_SUPER_LONG" => OpCode::GetSuperLong(long_operand(operands)?),
+
+        "OP_INVOKE" | "OP_SUPER_INVOKE" => {
+            check_operand_count(operands, 2)?;
+            let (index, arg_count) = (parse_operand(operands[0])?, parse_operand(operands[1])?);
+            if mnemonic == "OP_INVOKE" { OpCode::Invoke(index, arg_count) } else { OpCode::SuperInvoke(index, arg_count) }
+        },
+        "OP_INVOKE_LONG" | "OP_SUPER_INVOKE_LONG" => {
+            check_operand_count(operands, 2)?;
+            let (index, arg_count) = (parse_long_operand(operands[0])?, parse_operand(operands[1])?);
+            if mnemonic == "OP_INVOKE_LONG" { OpCode::InvokeLong(index, arg_count) } else { OpCode::SuperInvokeLong(index, arg_count) }
+        },
+        "OP_CLOSURE" => {
+            let upvalues = closure_upvalues(operands)?;
+            OpCode::Closure(parse_operand(operands[0])?, upvalues)
+        },
+        "OP_CLOSURE_LONG" => {
+            let upvalues = closure_upvalues(operands)?;
+            OpCode::ClosureLong(parse_long_operand(operands[0])?, upvalues)
+        },
+
+        "OP_JUMP" => return jump_instruction(JumpKind::Jump, operands),
+        "OP_JUMP_IF_FALSE" => return jump_instruction(JumpKind::JumpIfFalse, operands),
+        "OP_LOOP" => return jump_instruction(JumpKind::Loop, operands),
+
+        _ => return Err(AsmErrorDescription::UnknownInstruction(mnemonic.into())),
+    };
+
+    Ok(Instruction::Op(op))
+}
+
+fn jump_instruction(kind: JumpKind, operands: &[&str]) -> Result<Instruction, AsmErrorDescription> {
+    check_operand_count(operands, 1)?;
+
+    match operands[0].parse() {
+        Ok(distance) => Ok(Instruction::Op(jump(kind, distance))),
+        Err(_) => Ok(Instruction::Jump(kind, operands[0].into())),
+    }
+}
+
+fn check_operand_count(operands: &[&str], expected: usize) -> Result<(), AsmErrorDescription> {
+    if operands.len() == expected {
+        Ok(())
+    } else {
+        Err(AsmErrorDescription::WrongOperandCount { expected, provided: operands.len() })
+    }
+}
+
+fn no_operands(operands: &[&str], op: OpCode) -> Result<OpCode, AsmErrorDescription> {
+    check_operand_count(operands, 0)?;
+    Ok(op)
+}
+
+fn one_operand<T: FromStr>(operands: &[&str]) -> Result<T, AsmErrorDescription> {
+    check_operand_count(operands, 1)?;
+    parse_operand(operands[0])
+}
+
+fn long_operand(operands: &[&str]) -> Result<u32, AsmErrorDescription> {
+    check_operand_count(operands, 1)?;
+    parse_long_operand(operands[0])
+}
+
+// the long opcodes only have 24 bits for their operand
+fn parse_long_operand(operand: &str) -> Result<u32, AsmErrorDescription> {
+    let value: u32 = parse_operand(operand)?;
+    if value >= 1 << 24 {
+        return Err(AsmErrorDescription::InvalidOperand(operand.into()));
+    }
+
+    Ok(value)
+}
+
+// the function constant followed by `local <index>` or `upvalue <index>` for each upvalue
+fn closure_upvalues(operands: &[&str]) -> Result<Vec<(bool, u8)>, AsmErrorDescription> {
+    if operands.len().is_multiple_of(2) {
+        return Err(AsmErrorDescription::WrongOperandCount { expected: operands.len() + 1, provided: operands.len() });
+    }
+
+    operands[1..].chunks(2)
+        .map(|upvalue| {
+            let is_local = match upvalue[0] {
+                "local" => true,
+                "upvalue" => false,
+                operand => return Err(AsmErrorDescription::InvalidOperand(operand.into())),
+            };
+            Ok((is_local, parse_operand(upvalue[1])?))
+        })
+        .collect()
+}
+
+fn parse_operand<T: FromStr>(operand: &str) -> Result<T, AsmErrorDescription> {
+    operand.parse().map_err(|_| AsmErrorDescription::InvalidOperand(operand.into()))
+}
+
+fn parse_constant(constant: &str) -> Option<Value> {
+    match constant {
+        "nil" => Some(Value::Nil),
+        "true" => Some(Value::Boolean(true)),
+        "false" => Some(Value::Boolean(false)),
+
+        _ if constant.starts_with('"') => {
+            let contents = constant.strip_prefix('"')?.strip_suffix('"')?;
+            Some(Value::new_string(unescape(contents)?))
+        },
+        _ => constant.parse().ok().map(Value::Number),
+    }
+}
+
+// `;` starts a comment unless it's inside a string constant
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => { },
+        }
+    }
+
+    line
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                _ => return None,
+            },
+            // an unescaped quote would have ended the string
+            '"' => return None,
+            c => c,
+        });
+    }
+
+    Some(unescaped)
+}
+
+// writes the chunk in the form `assemble` reads, jumps going to generated labels
+pub fn write_assembly(w: &mut dyn Write, chunk: &Chunk) -> io::Result<()> {
+    write_chunk(w, chunk, "", true)
+}
+
+// function chunks are written nested inside their constant, indented past it. they share the script's global names,
+// so only the script writes them
+fn write_chunk(w: &mut dyn Write, chunk: &Chunk, indent: &str, globals: bool) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    writeln!(w, "{}.constants", indent)?;
+    for constant in chunk.constants() {
+        match constant {
+            Value::Object(obj) => match obj.as_ref() {
+                Object::String(s) => writeln!(w, "{}    \"{}\"", indent, escape(s))?,
+                Object::Function { name, arity, chunk } => {
+                    if name.is_empty() || name.contains(char::is_whitespace) {
+                        return Err(invalid(format!("can't write a function named {:?} as assembly", name)));
+                    }
+
+                    writeln!(w, "{}    fun {} {} {{", indent, name, arity)?;
+                    write_chunk(w, chunk, &format!("{}        ", indent), false)?;
+                    writeln!(w, "{}    }}", indent)?;
+                },
+                obj => return Err(invalid(format!("can't write a {} constant as assembly", obj.type_name()))),
+            },
+            value => writeln!(w, "{}    {}", indent, value)?,
+        }
+    }
+
+    if globals {
+        writeln!(w, "{}.globals", indent)?;
+        let global_names = chunk.global_names().borrow();
+        for slot in 0..global_names.len() {
+            writeln!(w, "{}    {}", indent, global_names.name(slot as u32).map_err(invalid)?)?;
+        }
+    }
+
+    let mut instructions = Vec::new();
+    let mut targets = HashSet::new();
+    let mut offset = 0;
+    while offset < chunk.len() {
+        let (op, next_offset) = chunk.decode(offset).map_err(|e| invalid(format!("failed to decode instruction at {}: {:?}", offset, e)))?;
+        match op {
+            OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) => { targets.insert(next_offset + distance as usize); },
+            OpCode::Loop(distance) => { targets.insert(next_offset.checked_sub(distance as usize).ok_or_else(|| invalid(format!("loop at {} jumps before the start of the chunk", offset)))?); },
+            OpCode::Unknown(byte) => return Err(invalid(format!("unknown opcode {} at {}", byte, offset))),
+            _ => { },
+        }
+
+        instructions.push((offset, op, next_offset));
+        offset = next_offset;
+    }
+
+    let label = |offset: usize| format!("L{:04x}", offset);
+
+    writeln!(w, "{}.code", indent)?;
+    for (offset, op, next_offset) in instructions {
+        if targets.contains(&offset) {
+            writeln!(w, "{}{}:", indent, label(offset))?;
+        }
+
+        write!(w, "{}    ", indent)?;
+        match op {
+            OpCode::Jump(distance) => writeln!(w, "OP_JUMP {}", label(next_offset + distance as usize))?,
+            OpCode::JumpIfFalse(distance) => writeln!(w, "OP_JUMP_IF_FALSE {}", label(next_offset + distance as usize))?,
+            OpCode::Loop(distance) => writeln!(w, "OP_LOOP {}", label(next_offset - distance as usize))?,
+
+            op => writeln!(w, "{}", mnemonic(&op))?,
+        }
+    }
+
+    // a jump to the very end of the chunk has no instruction to hang its label on
+    if targets.contains(&chunk.len()) {
+        writeln!(w, "{}{}:", indent, label(chunk.len()))?;
+    }
+
+    Ok(())
+}
+
+// the instruction as `assemble` reads it, jumps write their raw distance
+fn mnemonic(op: &OpCode) -> String {
+    match op {
+        OpCode::Constant(index) => format!("OP_CONSTANT {}", index),
+        OpCode::True => "OP_TRUE".into(),
+        OpCode::False => "OP_FALSE".into(),
+        OpCode::Nil => "OP_NIL".into(),
+        OpCode::Pop => "OP_POP".into(),
+
+        OpCode::GetLocal(slot) => format!("OP_GET_LOCAL {}", slot),
+        OpCode::SetLocal(slot) => format!("OP_SET_LOCAL {}", slot),
+        OpCode::GetGlobal(slot) => format!("OP_GET_GLOBAL {}", slot),
+        OpCode::DefineGlobal(slot) => format!("OP_DEFINE_GLOBAL {}", slot),
+        OpCode::SetGlobal(slot) => format!("OP_SET_GLOBAL {}", slot),
+
+        OpCode::Equal => "OP_EQUAL".into(),
+        OpCode::Greater => "OP_GREATER".into(),
+        OpCode::Less => "OP_LESS".into(),
+        OpCode::Add => "OP_ADD".into(),
+        OpCode::Subtract => "OP_SUBTRACT".into(),
+        OpCode::Multiply => "OP_MULTIPLY".into(),
+        OpCode::Divide => "OP_DIVIDE".into(),
+        OpCode::Not => "OP_NOT".into(),
+        OpCode::Negate => "OP_NEGATE".into(),
+        OpCode::Modulo => "OP_MODULO".into(),
+
+        OpCode::Print => "OP_PRINT".into(),
+        OpCode::Jump(distance) => format!("OP_JUMP {}", distance),
+        OpCode::JumpIfFalse(distance) => format!("OP_JUMP_IF_FALSE {}", distance),
+        OpCode::Return => "OP_RETURN".into(),
+        OpCode::Call(arg_count) => format!("OP_CALL {}", arg_count),
+
+        OpCode::Closure(index, upvalues) => closure_mnemonic("OP_CLOSURE", (*index).into(), upvalues),
+        OpCode::GetUpvalue(index) => format!("OP_GET_UPVALUE {}", index),
+        OpCode::SetUpvalue(index) => format!("OP_SET_UPVALUE {}", index),
+        OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE".into(),
+
+        OpCode::Class(index) => format!("OP_CLASS {}", index),
+        OpCode::GetProperty(index) => format!("OP_GET_PROPERTY {}", index),
+        OpCode::SetProperty(index) => format!("OP_SET_PROPERTY {}", index),
+        OpCode::Method(index) => format!("OP_METHOD {}", index),
+        OpCode::Invoke(index, arg_count) => format!("OP_INVOKE {} {}", index, arg_count),
+        OpCode::Inherit => "OP_INHERIT".into(),
+        OpCode::GetSuper(index) => format!("OP_GET_SUPER {}", index),
+        OpCode::SuperInvoke(index, arg_count) => format!("OP_SUPER_INVOKE {} {}", index, arg_count),
+
+        OpCode::Loop(distance) => format!("OP_LOOP {}", distance),
+
+        OpCode::ConstantLong(index) => format!("OP_CONSTANT_LONG {}", index),
+        OpCode::GetGlobalLong(slot) => format!("OP_GET_GLOBAL_LONG {}", slot),
+        OpCode::DefineGlobalLong(slot) => format!("OP_DEFINE_GLOBAL_LONG {}", slot),
+        OpCode::SetGlobalLong(slot) => format!("OP_SET_GLOBAL_LONG {}", slot),
+
+        OpCode::GetLocalLong(slot) => format!("OP_GET_LOCAL_LONG {}", slot),
+        OpCode::SetLocalLong(slot) => format!("OP_SET_LOCAL_LONG {}", slot),
+
+        OpCode::Array(count) => format!("OP_ARRAY {}", count),
+
+        OpCode::ClosureLong(index, upvalues) => closure_mnemonic("OP_CLOSURE_LONG", *index, upvalues),
+        OpCode::ClassLong(index) => format!("OP_CLASS_LONG {}", index),
+        OpCode::GetPropertyLong(index) => format!("OP_GET_PROPERTY_LONG {}", index),
+        OpCode::SetPropertyLong(index) => format!("OP_SET_PROPERTY_LONG {}", index),
+        OpCode::MethodLong(index) => format!("OP_METHOD_LONG {}", index),
+        OpCode::InvokeLong(index, arg_count) => format!("OP_INVOKE_LONG {} {}", index, arg_count),
+        OpCode::GetSuperLong(index) => format!("OP_GET_SUPER_LONG {}", index),
+        OpCode::SuperInvokeLong(index, arg_count) => format!("OP_SUPER_INVOKE_LONG {} {}", index, arg_count),
+
+        OpCode::Unknown(byte) => format!("unknown opcode {}", byte),
+    }
+}
+
+fn closure_mnemonic(name: &str, index: u32, upvalues: &[(bool, u8)]) -> String {
+    let mut s = format!("{} {}", name, index);
+    for (is_local, upvalue_index) in upvalues {
+        s += &format!(" {} {}", if *is_local { "local" } else { "upvalue" }, upvalue_index);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_interpreter::CapturedOutput;
+    use crate::{ disassemble_to_string, VM };
+    use crate::test_utils::compile;
+    use super::*;
+
+
+    fn write(chunk: &Chunk) -> String {
+        let mut output = Vec::new();
+        write_assembly(&mut output, chunk).expect("Failed to write assembly");
+        String::from_utf8(output).unwrap()
+    }
+
+    fn assemble_error(source: &str) -> AsmError {
+        match assemble(source) {
+            Ok(_) => panic!("Expected assembling {:?} to fail", source),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = compile("\
+var greeting = \"say; \\\\ hi\n\";
+var i = 0;
+while (i < 10) {
+    if (i % 2 == 0 and i != 4) print greeting + i;
+    else { var half = i / 2; print -half; }
+    i = i + 1;
+}
+for (var j = 0; j < 3; j = j + 1) {
+    if (j == 1) continue;
+    if (j == 2) break;
+    print j;
+}
+print !nil or false;
+");
+
+        let assembly = write(&chunk);
+        let assembled = assemble(&assembly).expect("Failed to assemble");
+
+        assert_eq!(assembled.as_bytes().as_slice(), chunk.as_bytes().as_slice());
+        assert_eq!(write(&assembled), assembly);
+
+        let constants: Vec<String> = assembled.constants().iter().map(Value::to_string).collect();
+        assert_eq!(constants, chunk.constants().iter().map(Value::to_string).collect::<Vec<_>>());
+        assert_eq!(assembled.global_name(0).unwrap(), "greeting");
+    }
+
+    #[test]
+    fn test_assemble() {
+        let chunk = assemble("\
+.constants
+    1.5
+    \"a;b\" ; the string keeps its semicolon
+.globals
+    total
+.code
+    OP_CONSTANT 1
+    OP_DEFINE_GLOBAL 0
+start:
+    OP_TRUE
+    OP_JUMP_IF_FALSE end
+    OP_POP
+    OP_CONSTANT 0
+    OP_POP
+    OP_LOOP start
+end:
+    OP_POP
+    OP_RETURN
+").expect("Failed to assemble");
+
+        // lines are the lines of the assembly
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    7 OP_CONSTANT      1 'a;b'
+0x0002    8 OP_DEFINE_GLOBAL 0 'total'
+L0:
+0x0004   10 OP_TRUE
+0x0005   11 OP_JUMP_IF_FALSE -> L1
+0x0008   12 OP_POP
+0x0009   13 OP_CONSTANT      0 '1.5'
+0x000b   14 OP_POP
+0x000c   15 OP_LOOP          -> L0
+L1:
+0x000f   17 OP_POP
+0x0010   18 OP_RETURN
+== constants ==
+   0 number   '1.5'
+   1 string   'a;b'
+");
+    }
+
+    #[test]
+    fn test_raw_jumps_and_closures() {
+        // parsed on their own, as these operands don't make up a valid chunk
+        let instructions = [("OP_JUMP", vec!["3"]), ("OP_LOOP", vec!["0"]), ("OP_CLOSURE", vec!["0", "local", "1", "upvalue", "2"]),
+            ("OP_CLOSURE_LONG", vec!["256", "local", "1"]), ("OP_SUPER_INVOKE_LONG", vec!["256", "2"])];
+        let ops: Vec<OpCode> = instructions.iter()
+            .map(|(mnemonic, operands)| match parse_instruction(mnemonic, operands) {
+                Ok(Instruction::Op(op)) => op,
+                _ => panic!("Failed to parse {}", mnemonic),
+            })
+            .collect();
+
+        assert_eq!(ops, vec![OpCode::Jump(3), OpCode::Loop(0), OpCode::Closure(0, vec![(true, 1), (false, 2)]),
+            OpCode::ClosureLong(256, vec![(true, 1)]), OpCode::SuperInvokeLong(256, 2)]);
+    }
+
+    #[test]
+    fn test_errors() {
+        use AsmErrorDescription::*;
+
+        assert_eq!(assemble_error("OP_NIL\nOP_PUSH 1"), AsmError { line: 2, description: UnknownInstruction("OP_PUSH".into()) });
+        assert_eq!(assemble_error("OP_NIL 1").description, WrongOperandCount { expected: 0, provided: 1 });
+        assert_eq!(assemble_error("OP_CONSTANT").description, WrongOperandCount { expected: 1, provided: 0 });
+        assert_eq!(assemble_error("OP_CONSTANT 256").description, InvalidOperand("256".into()));
+        assert_eq!(assemble_error("OP_CONSTANT_LONG 16777216").description, InvalidOperand("16777216".into()));
+        assert_eq!(assemble_error("OP_CLOSURE 0 local").description, WrongOperandCount { expected: 3, provided: 2 });
+        assert_eq!(assemble_error("OP_CLOSURE 0 global 1").description, InvalidOperand("global".into()));
+        assert_eq!(assemble_error(".data").description, UnknownSection(".data".into()));
+        assert_eq!(assemble_error(".constants\n\"unterminated").description, InvalidConstant("\"unterminated".into()));
+        assert_eq!(assemble_error(".constants\n\"bad \\q escape\"").description, InvalidConstant("\"bad \\q escape\"".into()));
+        assert_eq!(assemble_error(".globals\na\nb\na").description, DuplicateGlobal("a".into()));
+
+        assert_eq!(assemble_error("a:\nOP_NIL\na:"), AsmError { line: 3, description: DuplicateLabel("a".into()) });
+        assert_eq!(assemble_error("OP_NIL\n\nOP_JUMP nowhere"), AsmError { line: 3, description: UndefinedLabel("nowhere".into()) });
+        assert_eq!(assemble_error("start:\nOP_JUMP start").description, WrongJumpDirection("start".into()));
+        assert_eq!(assemble_error("OP_LOOP end\nOP_NIL\nend:").description, WrongJumpDirection("end".into()));
+        assert_eq!(assemble_error(&format!("OP_JUMP end\n{}end:", "OP_NIL\n".repeat(70000))).description, JumpTooLarge("end".into()));
+
+        // the same checks as loading bytecode, pointing at the offending line
+        let error = assemble_error("OP_NIL\nOP_GET_UPVALUE 5\nOP_RETURN");
+        assert_eq!(error.line, 2);
+        assert!(matches!(error.description, InvalidCode(ref e) if e.starts_with("InvalidUpvalue")), "{:?}", error);
+        assert_eq!(assemble_error("OP_NIL\nOP_GET_LOCAL 1").line, 2);
+        assert_eq!(assemble_error("OP_CONSTANT 0").line, 1);
+    }
+
+    // the code of the chunk and of every function nested in it
+    fn all_code(chunk: &Chunk) -> Vec<Vec<u8>> {
+        let mut code = vec![chunk.as_bytes().as_slice().to_vec()];
+        for constant in chunk.constants() {
+            if let Value::Object(obj) = constant {
+                if let Object::Function { chunk, .. } = obj.as_ref() {
+                    code.extend(all_code(chunk));
+                }
+            }
+        }
+        code
+    }
+
+    #[test]
+    fn test_round_trip_functions() {
+        let chunk = compile("\
+fun counter(start) {
+    var count = start;
+    fun increment() {
+        fun add(n) { count = count + n; return count; }
+        return add(1);
+    }
+    return increment;
+}
+class A { init(x) { this.x = x; } get() { return this.x; } }
+class B < A { get() { return super.get() * 2; } }
+var next = counter(5);
+print next() + B(3).get();
+");
+
+        let assembly = write(&chunk);
+        assert!(assembly.contains("    fun counter 1 {\n        .constants\n"), "{}", assembly);
+        assert!(assembly.contains("                fun add 1 {\n"), "{}", assembly);
+
+        let assembled = assemble(&assembly).expect("Failed to assemble");
+        assert_eq!(all_code(&assembled), all_code(&chunk));
+        assert_eq!(write(&assembled), assembly);
+
+        let output = CapturedOutput::new();
+        let mut vm = VM::new(Rc::new(assembled));
+        vm.set_output(Box::new(output.clone()));
+        vm.run().expect("Failed to run assembled chunk");
+        assert_eq!(output.contents(), "12\n");
+    }
+
+    #[test]
+    fn test_assemble_function() {
+        let chunk = assemble("\
+.constants
+    fun double 1 {
+        .constants
+            2
+        .code
+            OP_GET_LOCAL 1
+            OP_CONSTANT 0
+            OP_MULTIPLY
+            OP_RETURN
+    }
+    21
+.code
+    OP_CLOSURE 0
+    OP_CONSTANT 1
+    OP_CALL 1
+    OP_PRINT
+    OP_RETURN
+").expect("Failed to assemble");
+
+        match &chunk.constants()[0] {
+            Value::Object(obj) => match obj.as_ref() {
+                // functions share the script's global names, and their lines are lines of the assembly too
+                Object::Function { name, arity: 1, chunk: function_chunk } => {
+                    assert_eq!(name, "double");
+                    assert!(Rc::ptr_eq(function_chunk.global_names(), chunk.global_names()));
+                    assert_eq!(function_chunk.line(0), 6);
+                },
+                obj => panic!("Expected a function, got {:?}", obj),
+            },
+            value => panic!("Expected a function, got {:?}", value),
+        }
+
+        let output = CapturedOutput::new();
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_output(Box::new(output.clone()));
+        vm.run().expect("Failed to run assembled chunk");
+        assert_eq!(output.contents(), "42\n");
+    }
+
+    #[test]
+    fn test_function_errors() {
+        use AsmErrorDescription::*;
+
+        assert_eq!(assemble_error(".constants\nfun f 0 {\n.code\nOP_NIL"), AsmError { line: 2, description: UnterminatedFunction("f".into()) });
+        assert_eq!(assemble_error("OP_NIL\n}"), AsmError { line: 2, description: UnmatchedBrace });
+        assert_eq!(assemble_error(".constants\nfun f {").description, InvalidConstant("fun f {".into()));
+        assert_eq!(assemble_error(".constants\nfun f 256 {").description, InvalidConstant("fun f 256 {".into()));
+
+        // invalid code in a function points at its line in the assembly, not the script's
+        let error = assemble_error(".constants\nfun f 0 {\n.code\nOP_NIL\nOP_GET_LOCAL 3\nOP_RETURN\n}\n.code\nOP_CLOSURE 0\nOP_RETURN");
+        assert_eq!(error.line, 5);
+        assert!(matches!(error.description, InvalidCode(ref e) if e.starts_with("InvalidLocalSlot")), "{:?}", error);
+    }
+}
@@ -1,61 +1,422 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
-use crate::op::{ OpCode, DecodeError };
-use crate::Value;
+use crate::op::{ OpCode, DecodeError, OP_JUMP, OP_JUMP_IF_FALSE, OP_LOOP };
+use crate::{ GlobalNames, Object, Value };
 use crate::disasm::disassemble_instruction;
 
+const MAX_CONSTANTS: usize = 1 << 24;
+
+#[derive(Debug)]
 pub struct Chunk {
     code: Vec<u8>,
-    lines: HashMap<usize, usize>,
-    constants: Vec<Rc<Value>>,
+    // run-length encoded `(count, line)`, each run covering `count` consecutive bytes of code
+    lines: Vec<(usize, usize)>,
+    constants: Vec<Value>,
+    // shared with the chunks of the functions declared in this one, and any chunk compiled incrementally after it
+    global_names: Rc<RefCell<GlobalNames>>,
+    files: FileTable,
+}
+
+// which source file each part of the code was compiled from, as `(name, first offset)` in offset order. empty for code
+// compiled from a single unnamed source, and not serialized
+#[derive(Clone, Debug, Default)]
+pub struct FileTable {
+    files: Vec<(Rc<str>, usize)>,
+}
+
+impl FileTable {
+    pub fn file(&self, offset: usize) -> Option<&Rc<str>> {
+        self.files.iter().rev().find(|&&(_, start)| start <= offset).map(|(name, _)| name)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    fn start(&mut self, name: Rc<str>, offset: usize) {
+        // a file that compiled to nothing owns no code
+        if let Some((_, start)) = self.files.last() {
+            if *start == offset {
+                self.files.pop();
+            }
+        }
+
+        self.files.push((name, offset));
+    }
 }
 
 pub struct ChunkReference {
     offset: usize,
     length: usize,
+    // the opcode byte the instruction was added with, so a patch can check it's still the same instruction
+    opcode: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    // the referenced instruction has been truncated away, or overwritten by something else
+    InstructionChanged { offset: usize, expected: u8, found: Option<u8> },
+    OpcodeMismatch { expected: u8, found: u8 },
+    LengthMismatch { expected: usize, found: usize },
+    // only jumps have a distance operand to rewrite
+    NotAJump(u8),
+}
+
+#[derive(Debug)]
+pub enum MergeError {
+    // the combined constant pool would be too big for the long operands to address
+    TooManyConstants,
+    // offsets are into the chunk being merged in
+    Decode { offset: usize, error: DecodeError },
+    InvalidGlobal { offset: usize, slot: u32 },
+    TooManyGlobals,
+    // widening operands pushed a jump's target further than its 16-bit distance can reach
+    JumpTooFar { offset: usize },
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
+        Chunk::with_global_names(Rc::new(RefCell::new(GlobalNames::new())))
+    }
+    pub fn with_global_names(global_names: Rc<RefCell<GlobalNames>>) -> Chunk {
         Chunk {
             code: Vec::new(),
-            lines: HashMap::new(),
+            lines: Vec::new(),
             constants: Vec::new(),
+            global_names,
+            files: FileTable::default(),
         }
     }
 
+    // for rebuilding a chunk that was serialized, the parts are expected to have been validated against each other
+    pub(crate) fn from_parts(code: Vec<u8>, lines: Vec<(usize, usize)>, constants: Vec<Value>, global_names: Rc<RefCell<GlobalNames>>) -> Chunk {
+        Chunk { code, lines, constants, global_names, files: FileTable::default() }
+    }
+
     pub fn len(&self) -> usize { self.code.len() }
 
-    pub fn add_constant(&mut self, value: Value) -> Result<u8, String> {
-        if self.constants.len() >= 255 {
+    // indices past u8 can only be loaded through the long opcodes, which take 24 bits
+    pub fn add_constant(&mut self, value: Value) -> Result<u32, String> {
+        if self.constants.len() >= MAX_CONSTANTS {
             Err(String::from("too many local constants"))
         } else {
-            self.constants.push(Rc::new(value));
-            Ok((self.constants.len() - 1) as u8)
+            self.constants.push(value);
+            Ok((self.constants.len() - 1) as u32)
         }
     }
 
+    // finds an existing constant equal to `value`, NaN is never equal to itself so will never be found
+    pub fn constant_index_of(&self, value: &Value) -> Option<u32> {
+        self.constants.iter().position(|constant| constant.is_equal(value)).map(|index| index as u32)
+    }
+
     pub fn add(&mut self, op: OpCode, line: usize) -> ChunkReference {
-        let mut bytes = op.encode();
+        let bytes = op.encode();
 
         let offset = self.code.len();
         let length = bytes.len();
 
-        self.lines.insert(offset, line);
-        self.code.append(&mut bytes);
+        self.push_line(length, line);
+        self.code.extend_from_slice(&bytes);
 
-        ChunkReference { offset, length }
+        ChunkReference { offset, length, opcode: bytes[0] }
     }
 
-    pub fn patch(&mut self, location: &ChunkReference, op: OpCode) {
+    fn truncate(&mut self, length: usize) {
+        self.code.truncate(length);
+
+        let mut lines = Vec::new();
+        let mut covered = 0;
+        for &(count, line) in &self.lines {
+            if covered >= length {
+                break;
+            }
+
+            lines.push((count.min(length - covered), line));
+            covered += count;
+        }
+        self.lines = lines;
+        self.files.files.retain(|&(_, start)| start <= length);
+    }
+
+    fn push_line(&mut self, count: usize, line: usize) {
+        match self.lines.last_mut() {
+            Some((last_count, last_line)) if *last_line == line => *last_count += count,
+            _ => self.lines.push((count, line)),
+        }
+    }
+
+    // only the operands can change, the placeholder already says what the instruction is
+    pub fn patch(&mut self, location: &ChunkReference, op: OpCode) -> Result<(), PatchError> {
+        self.check_reference(location)?;
+
         let patch_bytes = op.encode();
+        if patch_bytes[0] != location.opcode {
+            return Err(PatchError::OpcodeMismatch { expected: location.opcode, found: patch_bytes[0] });
+        }
         if patch_bytes.len() != location.length {
-            panic!("Attempting to patch {} bytes into a {} byte location", patch_bytes.len(), location.length);
+            return Err(PatchError::LengthMismatch { expected: location.length, found: patch_bytes.len() });
+        }
+
+        self.code[location.offset..location.offset + location.length].copy_from_slice(&patch_bytes);
+
+        Ok(())
+    }
+
+    pub fn patch_jump_operand(&mut self, location: &ChunkReference, distance: u16) -> Result<(), PatchError> {
+        self.check_reference(location)?;
+
+        if !matches!(location.opcode, OP_JUMP | OP_JUMP_IF_FALSE | OP_LOOP) {
+            return Err(PatchError::NotAJump(location.opcode));
+        }
+
+        let operand = location.offset + 1;
+        self.code[operand..operand + 2].copy_from_slice(&distance.to_be_bytes());
+
+        Ok(())
+    }
+
+    fn check_reference(&self, location: &ChunkReference) -> Result<(), PatchError> {
+        let found = self.code.get(location.offset).copied();
+        if found != Some(location.opcode) || location.offset + location.length > self.code.len() {
+            return Err(PatchError::InstructionChanged { offset: location.offset, expected: location.opcode, found });
+        }
+
+        Ok(())
+    }
+
+    pub fn global_names(&self) -> &Rc<RefCell<GlobalNames>> {
+        &self.global_names
+    }
+    pub fn global_slot(&self, name: &str) -> Result<u32, String> {
+        self.global_names.borrow_mut().resolve(name)
+    }
+    pub fn global_name(&self, slot: u32) -> Result<String, String> {
+        self.global_names.borrow().name(slot).map(String::from)
+    }
+
+    // appends `other` after this chunk, re-indexing any constants it references to their new position in the pool
+    // and any globals to their slot in this chunk's names. a compiled script ends in a return, which is dropped so
+    // this chunk runs on into `other`
+    pub fn merge(mut self, other: Chunk) -> Result<Chunk, MergeError> {
+        let mut last = None;
+        let mut offset = 0;
+        while let Ok((op, next_offset)) = self.decode(offset) {
+            last = Some((offset, op));
+            offset = next_offset;
+        }
+        if let Some((offset, OpCode::Return)) = last {
+            self.truncate(offset);
+        }
+
+        let constant_offset = self.constants.len();
+        if constant_offset + other.constants.len() > MAX_CONSTANTS {
+            return Err(MergeError::TooManyConstants);
+        }
+
+        let reindex = |index: u32| index + constant_offset as u32;
+        let global_names = Rc::clone(&self.global_names);
+        let reslot = |offset: usize, slot: u32| -> Result<u32, MergeError> {
+            if Rc::ptr_eq(&global_names, &other.global_names) {
+                Ok(slot)
+            } else {
+                let name = other.global_name(slot).map_err(|_| MergeError::InvalidGlobal { offset, slot })?;
+                global_names.borrow_mut().resolve(&name).map_err(|_| MergeError::TooManyGlobals)
+            }
+        };
+
+        // an operand re-indexed past a byte switches to the instruction's long form
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < other.code.len() {
+            let (op, next_offset) = other.decode(offset).map_err(|error| MergeError::Decode { offset, error })?;
+
+            let op = match op {
+                OpCode::Constant(index) => widen(reindex(index.into()), OpCode::Constant, OpCode::ConstantLong),
+                OpCode::GetGlobal(slot) => widen(reslot(offset, slot.into())?, OpCode::GetGlobal, OpCode::GetGlobalLong),
+                OpCode::DefineGlobal(slot) => widen(reslot(offset, slot.into())?, OpCode::DefineGlobal, OpCode::DefineGlobalLong),
+                OpCode::SetGlobal(slot) => widen(reslot(offset, slot.into())?, OpCode::SetGlobal, OpCode::SetGlobalLong),
+                OpCode::Closure(index, upvalues) => {
+                    let index = reindex(index.into());
+                    if index <= u8::MAX as u32 { OpCode::Closure(index as u8, upvalues) } else { OpCode::ClosureLong(index, upvalues) }
+                },
+                OpCode::Class(index) => widen(reindex(index.into()), OpCode::Class, OpCode::ClassLong),
+                OpCode::GetProperty(index) => widen(reindex(index.into()), OpCode::GetProperty, OpCode::GetPropertyLong),
+                OpCode::SetProperty(index) => widen(reindex(index.into()), OpCode::SetProperty, OpCode::SetPropertyLong),
+                OpCode::Method(index) => widen(reindex(index.into()), OpCode::Method, OpCode::MethodLong),
+                OpCode::Invoke(index, arg_count) => widen(reindex(index.into()), |index| OpCode::Invoke(index, arg_count), |index| OpCode::InvokeLong(index, arg_count)),
+                OpCode::GetSuper(index) => widen(reindex(index.into()), OpCode::GetSuper, OpCode::GetSuperLong),
+                OpCode::SuperInvoke(index, arg_count) => widen(reindex(index.into()), |index| OpCode::SuperInvoke(index, arg_count), |index| OpCode::SuperInvokeLong(index, arg_count)),
+                OpCode::ConstantLong(index) => OpCode::ConstantLong(reindex(index)),
+                OpCode::GetGlobalLong(slot) => OpCode::GetGlobalLong(reslot(offset, slot)?),
+                OpCode::DefineGlobalLong(slot) => OpCode::DefineGlobalLong(reslot(offset, slot)?),
+                OpCode::SetGlobalLong(slot) => OpCode::SetGlobalLong(reslot(offset, slot)?),
+                OpCode::ClosureLong(index, upvalues) => OpCode::ClosureLong(reindex(index), upvalues),
+                OpCode::ClassLong(index) => OpCode::ClassLong(reindex(index)),
+                OpCode::GetPropertyLong(index) => OpCode::GetPropertyLong(reindex(index)),
+                OpCode::SetPropertyLong(index) => OpCode::SetPropertyLong(reindex(index)),
+                OpCode::MethodLong(index) => OpCode::MethodLong(reindex(index)),
+                OpCode::InvokeLong(index, arg_count) => OpCode::InvokeLong(reindex(index), arg_count),
+                OpCode::GetSuperLong(index) => OpCode::GetSuperLong(reindex(index)),
+                OpCode::SuperInvokeLong(index, arg_count) => OpCode::SuperInvokeLong(reindex(index), arg_count),
+
+                op => op,
+            };
+
+            instructions.push((offset, op));
+            offset = next_offset;
+        }
+
+        // widened instructions move everything after them along, so jumps are re-targeted as in `optimize`
+        let base = self.code.len();
+        let mut new_offsets = vec![0; other.code.len() + 1];
+        let mut length = 0;
+        for (offset, op) in &instructions {
+            new_offsets[*offset] = length;
+            length += op.byte_length();
+        }
+        new_offsets[other.code.len()] = length;
+
+        for (name, start) in &other.files.files {
+            self.files.start(Rc::clone(name), base + new_offsets[*start]);
         }
 
-        for (i, b) in patch_bytes.iter().enumerate() {
-            self.code[location.offset + i] = *b;
+        for (offset, op) in instructions {
+            let new_offset = new_offsets[offset];
+            let target = jump_target(offset, &op).and_then(|target| new_offsets.get(target).copied());
+            let distance = |distance: Option<usize>| -> Result<u16, MergeError> {
+                distance.filter(|&distance| distance <= u16::MAX as usize)
+                    .map(|distance| distance as u16)
+                    .ok_or(MergeError::JumpTooFar { offset })
+            };
+
+            let op = match (op, target) {
+                (OpCode::Jump(_), Some(target)) => OpCode::Jump(distance(target.checked_sub(new_offset + 3))?),
+                (OpCode::JumpIfFalse(_), Some(target)) => OpCode::JumpIfFalse(distance(target.checked_sub(new_offset + 3))?),
+                (OpCode::Loop(_), Some(target)) => OpCode::Loop(distance((new_offset + 3).checked_sub(target))?),
+                (op, _) => op,
+            };
+
+            let line = other.line(offset);
+            let mut bytes = op.encode();
+            self.push_line(bytes.len(), line);
+            self.code.append(&mut bytes);
         }
+
+        self.constants.extend(other.constants);
+
+        Ok(self)
+    }
+
+    // a copy of this chunk, and of the chunks of functions declared in it, with instruction sequences that have no effect
+    // removed. jumps are re-targeted around the removed code, and a sequence a jump lands in the middle of is kept
+    pub fn optimize(&self) -> Chunk {
+        let constants = self.constants.iter().map(optimize_constant).collect();
+
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            match self.decode(offset) {
+                Ok((op, next_offset)) => {
+                    instructions.push((offset, op));
+                    offset = next_offset;
+                },
+                // without every instruction there's no telling where jumps land, so leave the code as it is
+                Err(_) => {
+                    let mut chunk = Chunk::from_parts(self.code.clone(), self.lines.clone(), constants, Rc::clone(&self.global_names));
+                    chunk.files = self.files.clone();
+                    return chunk;
+                },
+            }
+        }
+
+        let targets: HashSet<usize> = instructions.iter().filter_map(|(offset, op)| jump_target(*offset, op)).collect();
+        let op_at = |offset: usize| instructions.binary_search_by_key(&offset, |&(offset, _)| offset).ok().map(|index| &instructions[index].1);
+        let is_number_constant = |op: &OpCode| match op {
+            OpCode::Constant(index) => matches!(self.constant(*index as u32), Ok(Value::Number(_))),
+            OpCode::ConstantLong(index) => matches!(self.constant(*index), Ok(Value::Number(_))),
+            _ => false,
+        };
+
+        let mut removed = vec![false; instructions.len()];
+        let mut index = 0;
+        while index < instructions.len() {
+            let (offset, op) = &instructions[index];
+
+            // a jump to the next instruction
+            if let OpCode::Jump(0) = op {
+                removed[index] = true;
+                index += 1;
+                continue;
+            }
+
+            let pair = match instructions.get(index + 1) {
+                Some((next_offset, next_op)) if !targets.contains(next_offset) => match (op, next_op) {
+                    // pushing something without side effects only to pop it again
+                    (OpCode::Constant(_), OpCode::Pop) | (OpCode::ConstantLong(_), OpCode::Pop) |
+                    (OpCode::Nil, OpCode::Pop) | (OpCode::True, OpCode::Pop) | (OpCode::False, OpCode::Pop) => true,
+                    // `!!` turns a value into a boolean, which only makes no difference when the value is popped after
+                    // testing it, on both sides of the jump. that's the condition of an `if` or `while`
+                    (OpCode::Not, OpCode::Not) => match instructions.get(index + 2) {
+                        Some((jump_offset, jump @ OpCode::JumpIfFalse(_))) => {
+                            matches!(instructions.get(index + 3), Some((_, OpCode::Pop)))
+                                && matches!(jump_target(*jump_offset, jump).and_then(op_at), Some(OpCode::Pop))
+                        },
+                        _ => false,
+                    },
+                    // negating anything but a number is an error, so only a number constant can skip both
+                    (OpCode::Negate, OpCode::Negate) => {
+                        !targets.contains(offset) && index > 0 && !removed[index - 1] && is_number_constant(&instructions[index - 1].1)
+                    },
+                    _ => false,
+                },
+                _ => false,
+            };
+
+            if pair {
+                removed[index] = true;
+                removed[index + 1] = true;
+                index += 2;
+            } else {
+                index += 1;
+            }
+        }
+
+        // removed instructions map to wherever the next kept instruction ends up
+        let mut new_offsets = vec![0; self.code.len() + 1];
+        let mut length = 0;
+        for ((offset, op), &removed) in instructions.iter().zip(&removed) {
+            new_offsets[*offset] = length;
+            if !removed {
+                length += op.byte_length();
+            }
+        }
+        new_offsets[self.code.len()] = length;
+
+        let mut chunk = Chunk::with_global_names(Rc::clone(&self.global_names));
+        chunk.constants = constants;
+        for (name, start) in &self.files.files {
+            chunk.files.start(Rc::clone(name), new_offsets[*start]);
+        }
+
+        for ((offset, op), removed) in instructions.into_iter().zip(removed) {
+            if removed {
+                continue;
+            }
+
+            let new_offset = new_offsets[offset];
+            let target = jump_target(offset, &op);
+            let op = match (op, target) {
+                (OpCode::Jump(_), Some(target)) => OpCode::Jump((new_offsets[target] - new_offset - 3) as u16),
+                (OpCode::JumpIfFalse(_), Some(target)) => OpCode::JumpIfFalse((new_offsets[target] - new_offset - 3) as u16),
+                (OpCode::Loop(_), Some(target)) => OpCode::Loop((new_offset + 3 - new_offsets[target]) as u16),
+                (op, _) => op,
+            };
+
+            chunk.add(op, self.line(offset));
+        }
+
+        chunk
     }
 
     pub fn decode(&self, offset: usize) -> Result<(OpCode, usize), DecodeError> {
@@ -69,23 +430,369 @@ impl Chunk {
     pub fn as_bytes(&self) -> ::std::slice::Iter<u8> {
         self.code.iter()
     }
-    pub fn constant(&self, index: u8) -> Result<Rc<Value>, String> {
-        let len = self.constants.len() as u8;
-        if index >= len {
-            Err(format!("invalid constant index {} of {}", index, len))
-        } else {
-            Ok(Rc::clone(&self.constants[index as usize]))
+    pub fn constant(&self, index: u32) -> Result<&Value, String> {
+        match self.constants.get(index as usize) {
+            Some(constant) => Ok(constant),
+            None => Err(format!("invalid constant index {} of {}", index, self.constants.len())),
         }
     }
-    pub fn line(&self, mut offset: usize) -> usize {
-        while offset > 0 {
-            if let Some(&line) = self.lines.get(&offset) {
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+    pub(crate) fn lines(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+    // offsets past the end of the code belong to the last line
+    pub fn line(&self, offset: usize) -> usize {
+        let mut end = 0;
+        for &(count, line) in &self.lines {
+            end += count;
+            if offset < end {
                 return line;
             }
+        }
+
+        self.lines.last().map_or(0, |&(_, line)| line)
+    }
+
+    // code added from now on was compiled from the file `name`
+    pub fn start_file(&mut self, name: Rc<str>) {
+        self.files.start(name, self.code.len());
+    }
+    pub fn files(&self) -> &FileTable {
+        &self.files
+    }
+    // the file, if known, and line the code at `offset` was compiled from
+    pub fn location(&self, offset: usize) -> (Option<&str>, usize) {
+        (self.files.file(offset).map(|name| name.as_ref()), self.line(offset))
+    }
+}
+
+// where the jump at `offset` goes, if it's a jump and that's within the code it could have been decoded from
+fn widen(index: u32, short: impl FnOnce(u8) -> OpCode, long: impl FnOnce(u32) -> OpCode) -> OpCode {
+    if index <= u8::MAX as u32 { short(index as u8) } else { long(index) }
+}
 
-            offset -= 1;
+fn jump_target(offset: usize, op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) => Some(offset + 3 + *distance as usize),
+        OpCode::Loop(distance) => (offset + 3).checked_sub(*distance as usize),
+        _ => None,
+    }
+}
+
+fn optimize_constant(constant: &Value) -> Value {
+    match constant {
+        Value::Object(object) => match object.as_ref() {
+            Object::Function { name, arity, chunk } => {
+                Value::Object(Rc::new(Object::Function { name: name.clone(), arity: *arity, chunk: Rc::new(chunk.optimize()) }))
+            },
+            _ => constant.clone(),
+        },
+        _ => constant.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_interpreter::CapturedOutput;
+    use crate::{ VM, disassemble_to_string };
+    use crate::test_utils::compile;
+    use crate::op::{ OP_CONSTANT, OP_CONSTANT_LONG, OP_POP };
+    use super::*;
+
+
+    #[test]
+    fn test_merge() {
+        let first = compile("var a = \"a\";\nvar b = 1;");
+        let second = compile("var c = a + b;\n\nprint c;");
+
+        // the first chunk's return is dropped so it runs on into the second
+        let first_length = first.len() - 1;
+        let merged = first.merge(second).expect("Failed to merge chunks");
+
+        // the second chunk's globals are resolved against the first's names, `c` being the only new one
+        assert_eq!(merged.constants.len(), 2);
+        assert_eq!(merged.global_names().borrow().len(), 3);
+        assert_eq!(merged.line(0), 1);
+        assert_eq!(merged.line(4), 2);
+        assert_eq!(merged.line(first_length), 1);
+        assert_eq!(merged.line(merged.len() - 1), 3);
+
+        match merged.decode(first_length) {
+            Ok((OpCode::GetGlobal(0), _)) => { },
+            _ => panic!("Expected first instruction of merged chunk to reference global 0"),
         }
+        assert_eq!(merged.global_name(0).unwrap(), "a");
+
+        let output = disassemble_to_string(&merged);
+        assert!(output.contains("OP_DEFINE_GLOBAL 2 'c'"));
+        assert!(output.contains("OP_GET_GLOBAL    2 'c'"));
 
-        *self.lines.get(&0).unwrap_or(&0)
+        assert_eq!(run(merged), (String::from("a1\n"), true));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_patch() {
+        let mut chunk = Chunk::new();
+        let jump = chunk.add(OpCode::JumpIfFalse(0), 1);
+        chunk.add(OpCode::Pop, 1);
+        chunk.patch(&jump, OpCode::JumpIfFalse(1)).unwrap();
+
+        assert_eq!(chunk.decode(0).unwrap(), (OpCode::JumpIfFalse(1), 3));
+    }
+
+    #[test]
+    fn test_patch_different_op() {
+        let mut chunk = Chunk::new();
+        let jump = chunk.add(OpCode::JumpIfFalse(0), 1);
+        let constant = chunk.add(OpCode::Constant(0), 1);
+
+        assert_eq!(chunk.patch(&jump, OpCode::Jump(1)), Err(PatchError::OpcodeMismatch { expected: OP_JUMP_IF_FALSE, found: OP_JUMP }));
+        assert_eq!(chunk.patch(&constant, OpCode::ConstantLong(0)), Err(PatchError::OpcodeMismatch { expected: OP_CONSTANT, found: OP_CONSTANT_LONG }));
+        let closure = chunk.add(OpCode::Closure(0, vec![]), 1);
+        assert_eq!(chunk.patch(&closure, OpCode::Closure(0, vec![(true, 1)])), Err(PatchError::LengthMismatch { expected: 3, found: 5 }));
+        assert_eq!(chunk.patch(&closure, OpCode::Closure(1, vec![])), Ok(()));
+        assert_eq!(chunk.decode(5).unwrap(), (OpCode::Closure(1, vec![]), 8));
+
+        // nothing is written when a patch is rejected
+        assert_eq!(chunk.decode(0).unwrap(), (OpCode::JumpIfFalse(0), 3));
+    }
+
+    #[test]
+    fn test_patch_changed_instruction() {
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Nil, 1);
+        let jump = chunk.add(OpCode::Jump(0), 1);
+        chunk.truncate(1);
+        chunk.add(OpCode::Constant(0), 1);
+        chunk.add(OpCode::Pop, 1);
+
+        assert_eq!(chunk.patch(&jump, OpCode::Jump(1)), Err(PatchError::InstructionChanged { offset: 1, expected: OP_JUMP, found: Some(OP_CONSTANT) }));
+        assert_eq!(chunk.patch_jump_operand(&jump, 1), Err(PatchError::InstructionChanged { offset: 1, expected: OP_JUMP, found: Some(OP_CONSTANT) }));
+
+        chunk.truncate(1);
+        assert_eq!(chunk.patch(&jump, OpCode::Jump(1)), Err(PatchError::InstructionChanged { offset: 1, expected: OP_JUMP, found: None }));
+    }
+
+    #[test]
+    fn test_patch_jump_operand() {
+        let mut chunk = Chunk::new();
+        let jump = chunk.add(OpCode::Jump(0), 1);
+        let jump_if_false = chunk.add(OpCode::JumpIfFalse(0), 1);
+        let pop = chunk.add(OpCode::Pop, 1);
+        let loop_ = chunk.add(OpCode::Loop(0), 1);
+
+        chunk.patch_jump_operand(&jump, 0x1234).unwrap();
+        chunk.patch_jump_operand(&jump_if_false, 1).unwrap();
+        chunk.patch_jump_operand(&loop_, u16::MAX).unwrap();
+        assert_eq!(chunk.patch_jump_operand(&pop, 1), Err(PatchError::NotAJump(OP_POP)));
+
+        assert_eq!(chunk.decode(0).unwrap(), (OpCode::Jump(0x1234), 3));
+        assert_eq!(chunk.decode(3).unwrap(), (OpCode::JumpIfFalse(1), 6));
+        assert_eq!(chunk.decode(6).unwrap(), (OpCode::Pop, 7));
+        assert_eq!(chunk.decode(7).unwrap(), (OpCode::Loop(u16::MAX), 10));
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.line(0), 0);
+
+        chunk.add(OpCode::Nil, 1);
+        chunk.add(OpCode::Constant(0), 1);
+        chunk.add(OpCode::Pop, 2);
+        chunk.add(OpCode::JumpIfFalse(0), 4);
+        chunk.add(OpCode::Pop, 2);
+
+        // runs of the same line are merged
+        assert_eq!(chunk.lines, vec![(3, 1), (1, 2), (3, 4), (1, 2)]);
+
+        let expected = [1, 1, 1, 2, 4, 4, 4, 2];
+        assert_eq!(chunk.len(), expected.len());
+        for (offset, &line) in expected.iter().enumerate() {
+            assert_eq!(chunk.line(offset), line, "line of offset {}", offset);
+        }
+        assert_eq!(chunk.line(chunk.len()), 2);
+    }
+
+    #[test]
+    fn test_lines_match_compiled_instructions() {
+        let chunk = compile("var a = 1;\n\nvar b = a;\nprint a +\n  b;");
+
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while let Ok((_, next_offset)) = chunk.decode(offset) {
+            lines.push(chunk.line(offset));
+            for inside in offset..next_offset {
+                assert_eq!(chunk.line(inside), chunk.line(offset), "line of offset {} inside the instruction at {}", inside, offset);
+            }
+            offset = next_offset;
+        }
+
+        assert_eq!(lines, vec![1, 1, 3, 3, 4, 5, 4, 4, 4]);
+    }
+
+    #[test]
+    fn test_files() {
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Nil, 1);
+        assert!(chunk.files().is_empty());
+        assert_eq!(chunk.location(0), (None, 1));
+
+        chunk.start_file(Rc::from("a.lox"));
+        chunk.add(OpCode::Nil, 1);
+        chunk.start_file(Rc::from("empty.lox"));
+        chunk.start_file(Rc::from("b.lox"));
+        chunk.add(OpCode::Pop, 3);
+
+        assert_eq!(chunk.location(0), (None, 1));
+        assert_eq!(chunk.location(1), (Some("a.lox"), 1));
+        assert_eq!(chunk.location(2), (Some("b.lox"), 3));
+        assert_eq!(chunk.location(chunk.len()), (Some("b.lox"), 3));
+
+        let mut other = Chunk::new();
+        other.start_file(Rc::from("c.lox"));
+        other.add(OpCode::Nil, 7);
+        let merged = chunk.merge(other).expect("Failed to merge chunks");
+        assert_eq!(merged.location(2), (Some("b.lox"), 3));
+        assert_eq!(merged.location(3), (Some("c.lox"), 7));
+    }
+
+    #[test]
+    fn test_constant_index_of() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(1.0)).unwrap();
+        chunk.add_constant(Value::new_string("a".into())).unwrap();
+        chunk.add_constant(Value::Nil).unwrap();
+
+        assert_eq!(chunk.constant_index_of(&Value::Number(1.0)), Some(0));
+        assert_eq!(chunk.constant_index_of(&Value::new_string("a".into())), Some(1));
+        assert_eq!(chunk.constant_index_of(&Value::Nil), Some(2));
+        assert_eq!(chunk.constant_index_of(&Value::Number(2.0)), None);
+        assert_eq!(chunk.constant_index_of(&Value::Boolean(false)), None);
+    }
+
+    #[test]
+    fn test_constant_index_of_nan() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(std::f64::NAN)).unwrap();
+
+        assert_eq!(chunk.constant_index_of(&Value::Number(std::f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_compiler_reuses_constants() {
+        let chunk = compile("var a = 1; var b = a + 1; print \"a\";");
+
+        // global names aren't constants, leaving the number and the string
+        assert_eq!(chunk.constants.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_widens_operands() {
+        // the first chunk has more than 256 constants and globals, so the second's short operands no longer fit
+        let first = compile(&(0..300).map(|i| format!("var a{} = {};\n", i, i)).collect::<String>());
+        let second = compile("\
+class A { init(n) { this.n = n; } get() { return this.n; } }
+var i = 0;
+while (i < 3) {
+    if (i == 1) print A(i).get();
+    i = i + 1;
+}
+print a299;
+");
+
+        let merged = first.merge(second).expect("Failed to merge chunks");
+
+        let output = disassemble_to_string(&merged);
+        for name in &["OP_CLOSURE_LONG", "OP_CLASS_LONG", "OP_METHOD_LONG", "OP_INVOKE_LONG", "OP_GET_GLOBAL_LONG"] {
+            assert!(output.contains(name), "Expected {} in {}", name, output);
+        }
+        assert_eq!(run(merged), (String::from("1\n299\n"), true));
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let mut other = Chunk::new();
+        other.add(OpCode::Nil, 1);
+        other.add(OpCode::GetGlobal(3), 1);
+        assert!(matches!(compile("var a = 1;").merge(other), Err(MergeError::InvalidGlobal { offset: 1, slot: 3 })));
+
+        // a constant instruction missing its operand
+        let other = Chunk::from_parts(vec![OP_POP, OP_CONSTANT], vec![(2, 1)], vec![], Rc::new(RefCell::new(GlobalNames::new())));
+        assert!(matches!(compile("var a = 1;").merge(other), Err(MergeError::Decode { offset: 1, .. })));
+    }
+
+    // including the instructions of functions declared in the chunk
+    fn instruction_count(chunk: &Chunk) -> usize {
+        let mut count = chunk.constants.iter()
+            .map(|constant| match constant {
+                Value::Object(object) => match object.as_ref() {
+                    Object::Function { chunk, .. } => instruction_count(chunk),
+                    _ => 0,
+                },
+                _ => 0,
+            })
+            .sum();
+        let mut offset = 0;
+        while let Ok((_, next_offset)) = chunk.decode(offset) {
+            count += 1;
+            offset = next_offset;
+        }
+
+        count
+    }
+
+    fn run(chunk: Chunk) -> (String, bool) {
+        let output = CapturedOutput::new();
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_output(Box::new(output.clone()));
+        let succeeded = vm.run().is_ok();
+
+        (output.contents(), succeeded)
+    }
+
+    #[test]
+    fn test_optimize() {
+        let programs = [
+            "1; nil; true; \"a\"; print 2;",
+            "print --1; print -(-2.5);",
+            "var a = 0; if (!!a) print \"yes\"; else print \"no\"; while (!!(a < 3)) { a = a + 1; 4; } print a;",
+            "for (var i = 0; i < 3; i = i + 1) { 1; if (i == 1) continue; print i; }",
+            "fun f(n) { 1; if (!!n) return --n; return nil; } print f(3); print f(false);",
+            "{ var a = 1; 2; { 3; var b = a; print b; } }",
+        ];
+
+        for source in programs.iter() {
+            let chunk = compile(source);
+            let line = chunk.line(chunk.len() - 1);
+
+            let optimized = chunk.optimize();
+            assert!(instruction_count(&optimized) < instruction_count(&chunk), "nothing removed from {}", source);
+            assert_eq!(optimized.line(optimized.len() - 1), line);
+
+            let expected = run(chunk);
+            assert!(expected.1, "failed to run {}", source);
+            assert_eq!(run(optimized), expected, "output of {}", source);
+        }
+    }
+
+    #[test]
+    fn test_optimize_keeps_effects() {
+        // `!!` makes a boolean, and negating a string is an error, so these have to stay
+        let programs = [
+            ("print !!1;", "true\n", true),
+            ("var a = nil; print !!a and 2;", "false\n", true),
+            ("print --\"a\";", "", false),
+        ];
+
+        for &(source, output, succeeds) in programs.iter() {
+            let chunk = compile(source);
+
+            let optimized = chunk.optimize();
+            assert_eq!(instruction_count(&optimized), instruction_count(&chunk), "removed from {}", source);
+            assert_eq!(run(optimized), (output.to_string(), succeeds), "output of {}", source);
+        }
+    }
+}
@@ -1,27 +1,100 @@
 use std::rc::Rc;
 use rlox_scanner::Token;
-use rlox_parser::{Expr, Stmt};
-use crate::chunk::ChunkReference;
+use rlox_parser::{Expr, Func, Stmt};
+use rlox_scanner::SourceToken;
+use crate::chunk::{ ChunkReference, PatchError };
+use crate::serialize::stack_heights;
 use crate::{Chunk, Object, OpCode, Value};
-use crate::op::OpCode::JumpIfFalse;
 
 pub struct Compiler<'a> {
+    // the chunk of the function currently being compiled, enclosing functions' chunks are swapped out into `enclosing`
     chunk: &'a mut Chunk,
 
     locals: Vec<Local>,
-    scope_depth: u8,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    function_type: FunctionType,
+
+    enclosing: Vec<EnclosingFunction>,
+    // the class declarations enclosing the code being compiled, innermost last
+    classes: Vec<ClassState>,
+    // the loops enclosing the code being compiled in the current function, innermost last
+    loops: Vec<LoopState>,
+    // offsets of jumps emitted in the current function's chunk but not yet patched
+    unresolved_jumps: Vec<usize>,
 }
 
-pub struct Local {
-    pub name: String,
-    pub scope_depth: u8,
+struct Local {
+    name: String,
+    scope_depth: usize,
+    is_captured: bool,
+    // false while the local's own initializer is being compiled
+    initialized: bool,
+}
+
+struct Upvalue {
+    is_local: bool,
+    index: u8,
+}
+
+struct EnclosingFunction {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    function_type: FunctionType,
+    loops: Vec<LoopState>,
+    unresolved_jumps: Vec<usize>,
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+struct LoopState {
+    // locals deeper than this were declared inside the loop and need discarding when jumping out of an iteration
+    scope_depth: usize,
+    breaks: Vec<JumpPatchReference>,
+    continues: Vec<JumpPatchReference>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
 }
 
 #[derive(Debug)]
 pub enum CompilerError {
     TooManyConstants,
     TooManyLocals,
+    TooManyUpvalues,
+    TooManyGlobals,
+    // OP_ARRAY counts its elements with a single byte
+    TooManyElements { line: usize },
+    // statements only the tree-walking interpreter can run so far
+    Unsupported { statement: &'static str, line: usize },
+    // upvalues address the enclosing function's locals with a single byte
+    CapturedLocalOutOfRange(String),
     VariableAlreadyDeclared(String),
+    ReadOwnInitializer { name: String, line: usize },
+    ThisOutsideClass,
+    SuperOutsideSubclass,
+    ClassInheritsFromItself(String),
+    ReturnValueFromInitializer,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    JumpTooLarge { distance: usize, line: usize },
+    UnresolvedJump(usize),
+    // a scope was ended without one being open, a bug in the compiler rather than the source
+    UnbalancedScope,
+    // as is code that doesn't leave just its locals on the stack when it runs on past a function's last statement, or
+    // that reaches `offset` with different stack heights
+    UnbalancedStack { offset: usize },
+    // as is patching an instruction that's not the one emitted
+    InvalidPatch(PatchError),
 }
 
 impl<'a> Compiler<'a> {
@@ -30,17 +103,41 @@ impl<'a> Compiler<'a> {
             chunk,
 
             locals: Vec::new(),
+            upvalues: Vec::new(),
             scope_depth: 0,
+            function_type: FunctionType::Script,
+
+            enclosing: Vec::new(),
+            classes: Vec::new(),
+            loops: Vec::new(),
+            unresolved_jumps: Vec::new(),
         }
     }
 }
 
-type JumpOpFactory = Box<dyn Fn(i16) -> OpCode>;
+// the forward jumps, emitted with a placeholder distance and patched once the target is known
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JumpKind {
+    Jump,
+    JumpIfFalse,
+}
+
+impl JumpKind {
+    fn op(self, distance: u16) -> OpCode {
+        match self {
+            JumpKind::Jump => OpCode::Jump(distance),
+            JumpKind::JumpIfFalse => OpCode::JumpIfFalse(distance),
+        }
+    }
+}
 
 struct JumpPatchReference {
     chunk_ref: ChunkReference,
+    // jump distances are measured from the end of the jump instruction
     offset: usize,
-    op_factory: JumpOpFactory,
+    kind: JumpKind,
+    // the line of the code leading up to the jump, for reporting errors
+    line: usize,
 }
 
 struct JumpLoopReference {
@@ -48,14 +145,78 @@ struct JumpLoopReference {
 }
 
 impl<'a> Compiler<'a> {
+    // compiles a whole script, ending in the OP_RETURN that tells the VM it's finished. the VM doesn't treat running off
+    // the end of the code as finishing, that's a decode error
     pub fn compile(&mut self, statements: Vec<Stmt>) -> Result<(), CompilerError> {
+        self.compile_statements(statements)?;
+        self.emit_script_return()
+    }
+
+    fn compile_statements(&mut self, statements: Vec<Stmt>) -> Result<(), CompilerError> {
         for statement in statements {
             self.compile_stmt(statement)?;
         }
-        
+
+        if let Some(&offset) = self.unresolved_jumps.first() {
+            return Err(CompilerError::UnresolvedJump(offset));
+        }
+
+        Ok(())
+    }
+
+    // compiles each file's statements in order as one script, recording which file the code came from for error messages
+    pub fn compile_multiple_files(&mut self, files: Vec<(String, Vec<Stmt>)>) -> Result<(), CompilerError> {
+        for (name, statements) in files {
+            self.chunk.start_file(Rc::from(name));
+            self.compile_statements(statements)?;
+        }
+        self.emit_script_return()
+    }
+
+    // read-only views of the function currently being compiled, for tools showing what's in scope,
+    // e.g. where compilation stopped after an error
+    pub fn local_count(&self) -> usize {
+        self.locals.len()
+    }
+    pub fn local_name(&self, index: usize) -> Option<&str> {
+        self.locals.get(index).map(|local| local.name.as_str())
+    }
+    pub fn current_scope_depth(&self) -> usize {
+        self.scope_depth
+    }
+
+    // compiles a bare expression entered at the REPL, printing its value instead of discarding it
+    pub fn compile_expression(&mut self, expr: Expr) -> Result<(), CompilerError> {
+        let line = expr_line(&expr);
+        self.compile_expr(expr)?;
+        self.chunk.add(OpCode::Print, line);
+
         Ok(())
     }
 
+    // compiles a line entered at the REPL, echoing the value of a trailing expression statement instead of discarding it
+    pub fn compile_repl(&mut self, mut statements: Vec<Stmt>) -> Result<(), CompilerError> {
+        let echo = match statements.last() {
+            Some(Stmt::Expression(_)) => statements.pop(),
+            _ => None,
+        };
+
+        self.compile_statements(statements)?;
+        if let Some(Stmt::Expression(expr)) = echo {
+            self.compile_expression(expr)?;
+        }
+        self.emit_script_return()
+    }
+
+    // the script has no return statement of its own at the end, so it goes on the last line compiled
+    fn emit_script_return(&mut self) -> Result<(), CompilerError> {
+        let end = self.chunk.len();
+        let line = self.last_line();
+        self.chunk.add(OpCode::Return, line);
+
+        self.check_stack_balance(0, end)
+    }
+
     fn compile_stmt(&mut self, stmt: Stmt) -> Result<(), CompilerError> {
         match stmt {
             Stmt::Block(stmts) => {
@@ -63,78 +224,168 @@ impl<'a> Compiler<'a> {
                 for stmt in stmts {
                     self.compile_stmt(stmt)?;
                 }
-                self.end_scope();
+                self.end_scope(self.last_line())?;
+            },
+            Stmt::Class(name, superclass, methods) => {
+                if self.scope_depth > 0 {
+                    self.declare_local(name.lexeme.clone())?;
+                }
+
+                let constant = self.add_string(name.lexeme.clone())?;
+                self.emit_constant_op(constant, OpCode::Class, OpCode::ClassLong, name.line);
+
+                if self.scope_depth == 0 {
+                    self.define_variable(name.clone())?;
+                }
+
+                self.classes.push(ClassState { has_superclass: superclass.is_some() });
+
+                // the superclass lives in a hidden `super` local for the class body, methods capture it as an upvalue
+                if let Some(superclass) = &superclass {
+                    if superclass.lexeme == name.lexeme {
+                        return Err(CompilerError::ClassInheritsFromItself(name.lexeme));
+                    }
+
+                    self.compile_expr(Expr::Var(superclass.clone()))?;
+                    self.begin_scope();
+                    self.declare_local(String::from("super"))?;
+
+                    self.compile_expr(Expr::Var(name.clone()))?;
+                    self.chunk.add(OpCode::Inherit, superclass.line);
+                }
+
+                // the class is loaded back onto the stack for OP_METHOD to attach each method to
+                self.compile_expr(Expr::Var(name.clone()))?;
+                for method in methods {
+                    let method_name = method.name.clone();
+                    let function_type = if method_name.lexeme == "init" { FunctionType::Initializer } else { FunctionType::Method };
+
+                    self.compile_closure(method, function_type)?;
+
+                    let constant = self.add_string(method_name.lexeme)?;
+                    self.emit_constant_op(constant, OpCode::Method, OpCode::MethodLong, method_name.line);
+                }
+                self.chunk.add(OpCode::Pop, name.line);
+
+                if superclass.is_some() {
+                    self.end_scope(name.line)?;
+                }
+                self.classes.pop();
             },
-            Stmt::Class(_, _) => unimplemented!(),
             Stmt::Expression(expr) => {
+                let line = expr_line(&expr);
                 self.compile_expr(expr)?;
-                self.chunk.add(OpCode::Pop, 0); // TODO get line
+                self.chunk.add(OpCode::Pop, line);
+            },
+            Stmt::Destructure(names, _) => return Err(CompilerError::Unsupported { statement: "var (...)", line: names[0].line }),
+            Stmt::ForIn(name, _, _) => return Err(CompilerError::Unsupported { statement: "for ... in", line: name.line }),
+            Stmt::Function(func) => {
+                let name = func.name.clone();
+
+                // locals are declared before the body so the function can refer to itself
+                if self.scope_depth > 0 {
+                    self.declare_local(name.lexeme.clone())?;
+                }
+
+                self.compile_closure(func, FunctionType::Function)?;
+
+                if self.scope_depth == 0 {
+                    self.define_variable(name)?;
+                }
             },
-            Stmt::Function(_) => unimplemented!(),
             Stmt::If(cond, true_branch, false_branch) => {
+                let line = expr_line(&cond);
                 self.compile_expr(cond)?;
 
-                let false_jump = self.jump(Box::new(OpCode::JumpIfFalse));
+                let false_jump = self.jump(JumpKind::JumpIfFalse, line);
 
-                self.chunk.add(OpCode::Pop, 0); // TODO line number
+                self.chunk.add(OpCode::Pop, line);
                 self.compile_stmt(*true_branch)?;
 
-                match false_branch {
-                    Some(false_branch) => {
-                        let true_jump = self.jump(Box::new(OpCode::Jump));
+                // the condition needs popping on the false path too, even without an else branch
+                let true_jump = self.jump(JumpKind::Jump, line);
 
-                        self.resolve_jump(&false_jump);
-                        self.chunk.add(OpCode::Pop, 0); // TODO line number
-                        self.compile_stmt(*false_branch)?;
-                        self.resolve_jump(&true_jump);
-                    },
-                    None => {
-                        self.resolve_jump(&false_jump);
-                    },
+                self.resolve_jump(&false_jump)?;
+                self.chunk.add(OpCode::Pop, line);
+                if let Some(false_branch) = false_branch {
+                    self.compile_stmt(*false_branch)?;
                 }
+                self.resolve_jump(&true_jump)?;
             }
             Stmt::Print(expr) => {
+                let line = expr_line(&expr);
                 self.compile_expr(expr)?;
-                self.chunk.add(OpCode::Print, 0); // TODO get line
+                self.chunk.add(OpCode::Print, line);
+            },
+            Stmt::Return(token, expr) => {
+                match expr {
+                    Some(_) if self.function_type == FunctionType::Initializer => return Err(CompilerError::ReturnValueFromInitializer),
+                    Some(expr) => self.compile_expr(expr)?,
+                    None => self.emit_implicit_return_value(token.line),
+                }
+
+                self.chunk.add(OpCode::Return, token.line);
             },
-            Stmt::Return(_, _) => unimplemented!(),
             Stmt::Var(name, expr) => {
+                // a local is declared before its initializer so the initializer can't resolve to an outer variable
+                // of the same name, it's an error to read it until it's defined
+                let is_local = self.scope_depth > 0;
+                if is_local {
+                    self.declare_local(name.lexeme.clone())?;
+                    self.set_last_local_initialized(false);
+                }
+
                 if let Some(expr) = expr {
                     self.compile_expr(expr)?;
                 } else {
                     self.chunk.add(OpCode::Nil, name.line);
                 }
 
-                if self.scope_depth > 0 {
-                    if self.locals.len() == std::u8::MAX as usize {
-                        return Err(CompilerError::TooManyLocals);
-                    }
-
-                    let existing_in_scope = self.locals.iter().any(|x| x.scope_depth == self.scope_depth && x.name == name.lexeme);
-                    if existing_in_scope {
-                        return Err(CompilerError::VariableAlreadyDeclared(name.lexeme));
-                    }
-
-                    self.locals.push(Local {
-                        name: name.lexeme,
-                        scope_depth: self.scope_depth,
-                    });
+                if is_local {
+                    self.set_last_local_initialized(true);
                 } else {
-                    let constant = self.add_string(name.lexeme)?;
-                    self.chunk.add(OpCode::DefineGlobal(constant), 0);
+                    self.define_variable(name)?;
                 }
             },
-            Stmt::While(condition, body) => {
+            Stmt::While(condition, body, increment) => {
+                let line = expr_line(&condition);
                 let loop_start = self.loop_start();
                 self.compile_expr(condition)?;
-                let exit_jump = self.jump(Box::new(OpCode::JumpIfFalse));
+                let exit_jump = self.jump(JumpKind::JumpIfFalse, line);
+
+                self.chunk.add(OpCode::Pop, line);
+                self.loops.push(LoopState { scope_depth: self.scope_depth, breaks: Vec::new(), continues: Vec::new() });
+                let result = self.compile_stmt(*body);
+                let loop_state = self.loops.pop().expect("loop state missing");
+                result?;
+
+                for continue_jump in &loop_state.continues {
+                    self.resolve_jump(continue_jump)?;
+                }
+                if let Some(increment) = increment {
+                    let increment_line = expr_line(&increment);
+                    self.compile_expr(increment)?;
+                    self.chunk.add(OpCode::Pop, increment_line);
+                }
+                self.jump_loop(&loop_start, line)?;
 
-                self.chunk.add(OpCode::Pop, 0); // TODO line number
-                self.compile_stmt(*body)?;
-                self.jump_loop(&loop_start, Box::new(OpCode::Jump));
+                self.resolve_jump(&exit_jump)?;
+                self.chunk.add(OpCode::Pop, line);
 
-                self.resolve_jump(&exit_jump);
-                self.chunk.add(OpCode::Pop, 0); // TODO line number
+                // breaks skip the pop above, the condition was already popped at the start of the iteration
+                for break_jump in &loop_state.breaks {
+                    self.resolve_jump(break_jump)?;
+                }
+            },
+            Stmt::Break(token) => {
+                self.discard_loop_locals(&token, CompilerError::BreakOutsideLoop)?;
+                let jump = self.jump(JumpKind::Jump, token.line);
+                self.loops.last_mut().unwrap().breaks.push(jump);
+            },
+            Stmt::Continue(token) => {
+                self.discard_loop_locals(&token, CompilerError::ContinueOutsideLoop)?;
+                let jump = self.jump(JumpKind::Jump, token.line);
+                self.loops.last_mut().unwrap().continues.push(jump);
             },
         }
 
@@ -146,14 +397,13 @@ impl<'a> Compiler<'a> {
             Expr::Assign(name, value) => {
                 self.compile_expr(*value)?;
 
-                match self.resolve_local(&name.lexeme) {
-                    Some(local) => {
-                        self.chunk.add(OpCode::SetLocal(local), name.line);
-                    },
-                    None => {
-                        let constant = self.add_string(name.lexeme)?;
-                        self.chunk.add(OpCode::SetGlobal(constant), name.line);
-                    }
+                if let Some(local) = self.resolve_local(&name)? {
+                    self.emit_local_op(local, OpCode::SetLocal, OpCode::SetLocalLong, name.line);
+                } else if let Some(upvalue) = self.resolve_upvalue(self.enclosing.len(), &name.lexeme)? {
+                    self.chunk.add(OpCode::SetUpvalue(upvalue), name.line);
+                } else {
+                    let slot = self.global_slot(&name.lexeme)?;
+                    self.emit_constant_op(slot, OpCode::SetGlobal, OpCode::SetGlobalLong, name.line);
                 }
             },
             Expr::Binary(left, op, right) => {
@@ -172,37 +422,104 @@ impl<'a> Compiler<'a> {
                     Token::Minus => self.chunk.add(OpCode::Subtract, op.line),
                     Token::Star => self.chunk.add(OpCode::Multiply, op.line),
                     Token::Slash => self.chunk.add(OpCode::Divide, op.line),
+                    Token::Percent => self.chunk.add(OpCode::Modulo, op.line),
 
                     _ => { panic!("Invalid binary operation {:?}", op.token); },
                 };
             },
-            Expr::Call(_, _, _) => unimplemented!(),
+            Expr::Call(callee, paren, arguments) => {
+                let arg_count = arguments.len() as u8;
+
+                // `super.method(...)` likewise skips the bound method, the superclass is pushed last for OP_SUPER_INVOKE to pop
+                if let Expr::Super(keyword, method) = *callee {
+                    self.check_super()?;
+
+                    self.compile_expr(Expr::This(synthetic_token(Token::This, "this", keyword.line)))?;
+                    for argument in arguments {
+                        self.compile_expr(argument)?;
+                    }
+                    self.compile_expr(Expr::Var(synthetic_token(Token::Super, "super", keyword.line)))?;
+
+                    let constant = self.add_string(method.lexeme)?;
+                    self.emit_constant_op(constant, |index| OpCode::SuperInvoke(index, arg_count), |index| OpCode::SuperInvokeLong(index, arg_count), paren.line);
+
+                    return Ok(());
+                }
+
+                // `object.method(...)` invokes the method directly rather than creating a bound method to call
+                if let Expr::Get(object, name) = *callee {
+                    self.compile_expr(*object)?;
+                    for argument in arguments {
+                        self.compile_expr(argument)?;
+                    }
+
+                    let constant = self.add_string(name.lexeme)?;
+                    self.emit_constant_op(constant, |index| OpCode::Invoke(index, arg_count), |index| OpCode::InvokeLong(index, arg_count), paren.line);
+
+                    return Ok(());
+                }
+
+                self.compile_expr(*callee)?;
+                for argument in arguments {
+                    self.compile_expr(argument)?;
+                }
+
+                self.chunk.add(OpCode::Call(arg_count), paren.line);
+            },
+            Expr::Get(object, name) => {
+                self.compile_expr(*object)?;
+
+                let constant = self.add_string(name.lexeme)?;
+                self.emit_constant_op(constant, OpCode::GetProperty, OpCode::GetPropertyLong, name.line);
+            },
+            Expr::Set(object, name, value) => {
+                self.compile_expr(*object)?;
+                self.compile_expr(*value)?;
+
+                let constant = self.add_string(name.lexeme)?;
+                self.emit_constant_op(constant, OpCode::SetProperty, OpCode::SetPropertyLong, name.line);
+            },
             Expr::Logical(left, op, right) => {
                 self.compile_expr(*left)?;
 
                 match &op.token {
                     Token::Or => {
-                        let else_jump = self.jump(Box::new(OpCode::JumpIfFalse));
-                        let end_jump = self.jump(Box::new(OpCode::Jump));
+                        let else_jump = self.jump(JumpKind::JumpIfFalse, op.line);
+                        let end_jump = self.jump(JumpKind::Jump, op.line);
 
-                        self.resolve_jump(&else_jump);
+                        self.resolve_jump(&else_jump)?;
                         self.chunk.add(OpCode::Pop, op.line);
                         self.compile_expr(*right)?;
 
-                        self.resolve_jump(&end_jump);
+                        self.resolve_jump(&end_jump)?;
                     },
                     Token::And => {
-                        let jump = self.jump(Box::new(OpCode::JumpIfFalse));
+                        let jump = self.jump(JumpKind::JumpIfFalse, op.line);
 
                         self.chunk.add(OpCode::Pop, op.line);
                         self.compile_expr(*right)?;
 
-                        self.resolve_jump(&jump);
+                        self.resolve_jump(&jump)?;
                     },
 
                     _ => panic!("Invalid logical operation {:?}", op.token)
                 }
             },
+            // like an if statement, but each branch leaves its value on the stack
+            Expr::Ternary(condition, question, then_branch, else_branch) => {
+                self.compile_expr(*condition)?;
+
+                let else_jump = self.jump(JumpKind::JumpIfFalse, question.line);
+                self.chunk.add(OpCode::Pop, question.line);
+                self.compile_expr(*then_branch)?;
+                let end_jump = self.jump(JumpKind::Jump, question.line);
+
+                self.resolve_jump(&else_jump)?;
+                self.chunk.add(OpCode::Pop, question.line);
+                self.compile_expr(*else_branch)?;
+
+                self.resolve_jump(&end_jump)?;
+            },
             Expr::Unary(op, value) => {
                 self.compile_expr(*value)?;
 
@@ -214,24 +531,52 @@ impl<'a> Compiler<'a> {
                 };
             },
             Expr::Grouping(expr) => self.compile_expr(*expr)?,
+            Expr::List(bracket, elements) => {
+                if elements.len() > u8::MAX as usize {
+                    return Err(CompilerError::TooManyElements { line: bracket.line });
+                }
+                let count = elements.len() as u8;
+
+                for element in elements {
+                    self.compile_expr(element)?;
+                }
+
+                self.chunk.add(OpCode::Array(count), bracket.line);
+            },
+            Expr::Super(keyword, method) => {
+                self.check_super()?;
+
+                self.compile_expr(Expr::This(synthetic_token(Token::This, "this", keyword.line)))?;
+                self.compile_expr(Expr::Var(synthetic_token(Token::Super, "super", keyword.line)))?;
+
+                let constant = self.add_string(method.lexeme)?;
+                self.emit_constant_op(constant, OpCode::GetSuper, OpCode::GetSuperLong, method.line);
+            },
+            Expr::This(token) => {
+                if self.classes.is_empty() {
+                    return Err(CompilerError::ThisOutsideClass);
+                }
+
+                // methods reserve slot 0 for `this`, so it resolves like any other variable
+                self.compile_expr(Expr::Var(token))?;
+            },
             Expr::Var(name) => {
-                match self.resolve_local(&name.lexeme) {
-                    Some(local) => {
-                        self.chunk.add(OpCode::GetLocal(local), name.line);
-                    },
-                    None => {
-                        let constant = self.add_string(name.lexeme)?;
-                        self.chunk.add(OpCode::GetGlobal(constant), name.line);
-                    }
+                if let Some(local) = self.resolve_local(&name)? {
+                    self.emit_local_op(local, OpCode::GetLocal, OpCode::GetLocalLong, name.line);
+                } else if let Some(upvalue) = self.resolve_upvalue(self.enclosing.len(), &name.lexeme)? {
+                    self.chunk.add(OpCode::GetUpvalue(upvalue), name.line);
+                } else {
+                    let slot = self.global_slot(&name.lexeme)?;
+                    self.emit_constant_op(slot, OpCode::GetGlobal, OpCode::GetGlobalLong, name.line);
                 }
             },
             Expr::String(token, value) => {
                 let constant = self.add_string(value)?;
-                self.chunk.add(OpCode::Constant(constant), token.line);
+                self.emit_constant_op(constant, OpCode::Constant, OpCode::ConstantLong, token.line);
             },
             Expr::Number(token, value) => {
-                let constant = self.chunk.add_constant(Value::Number(value)).map_err(|_| CompilerError::TooManyConstants)?;
-                self.chunk.add(OpCode::Constant(constant), token.line);
+                let constant = self.add_constant(Value::Number(value))?;
+                self.emit_constant_op(constant, OpCode::Constant, OpCode::ConstantLong, token.line);
             },
             Expr::Boolean(token, value) => {
                 self.chunk.add(if value { OpCode::True } else { OpCode::False }, token.line);
@@ -244,56 +589,327 @@ impl<'a> Compiler<'a> {
         Ok(())
     }
 
-    fn add_string(&mut self, s: String) -> Result<u8, CompilerError> {
-        let object = Rc::new(Object::String(s));
-        let constant = self.chunk.add_constant(Value::Object(object)).map_err(|_| CompilerError::TooManyConstants)?;
+    // the value being defined must already be on the stack, locals just leave it there as their slot
+    fn define_variable(&mut self, name: SourceToken) -> Result<(), CompilerError> {
+        if self.scope_depth > 0 {
+            self.declare_local(name.lexeme)
+        } else {
+            let slot = self.global_slot(&name.lexeme)?;
+            self.emit_constant_op(slot, OpCode::DefineGlobal, OpCode::DefineGlobalLong, name.line);
+
+            Ok(())
+        }
+    }
+    fn declare_local(&mut self, name: String) -> Result<(), CompilerError> {
+        if self.locals.len() == u16::MAX as usize {
+            return Err(CompilerError::TooManyLocals);
+        }
+
+        let existing_in_scope = self.locals.iter().any(|x| x.scope_depth == self.scope_depth && x.name == name);
+        if existing_in_scope {
+            return Err(CompilerError::VariableAlreadyDeclared(name));
+        }
+
+        self.locals.push(Local {
+            name,
+            scope_depth: self.scope_depth,
+            is_captured: false,
+            initialized: true,
+        });
+
+        Ok(())
+    }
+
+    fn set_last_local_initialized(&mut self, initialized: bool) {
+        self.locals.last_mut().expect("no local declared").initialized = initialized;
+    }
+
+    fn check_super(&self) -> Result<(), CompilerError> {
+        match self.classes.last() {
+            Some(class) if class.has_superclass => Ok(()),
+            _ => Err(CompilerError::SuperOutsideSubclass),
+        }
+    }
+
+    // compiles the function and emits the OP_CLOSURE leaving it on the stack
+    fn compile_closure(&mut self, func: Func, function_type: FunctionType) -> Result<(), CompilerError> {
+        let line = func.name.line;
+
+        let (function, upvalues) = self.compile_function(func, function_type)?;
+        let constant = self.add_constant(Value::Object(Rc::new(function)))?;
+        let upvalues = upvalues.iter().map(|upvalue| (upvalue.is_local, upvalue.index)).collect();
+        let op = if constant <= u8::MAX as u32 { OpCode::Closure(constant as u8, upvalues) } else { OpCode::ClosureLong(constant, upvalues) };
+        self.chunk.add(op, line);
+
+        Ok(())
+    }
+    // compiles the function into a fresh chunk, returning it with the upvalues the closure needs to capture
+    fn compile_function(&mut self, func: Func, function_type: FunctionType) -> Result<(Object, Vec<Upvalue>), CompilerError> {
+        // the function's code all comes from the file it's declared in
+        let mut function_chunk = Chunk::with_global_names(Rc::clone(self.chunk.global_names()));
+        if let Some(file) = self.chunk.files().file(self.chunk.len()) {
+            function_chunk.start_file(Rc::clone(file));
+        }
+
+        self.enclosing.push(EnclosingFunction {
+            chunk: std::mem::replace(self.chunk, function_chunk),
+            locals: std::mem::take(&mut self.locals),
+            upvalues: std::mem::take(&mut self.upvalues),
+            scope_depth: std::mem::replace(&mut self.scope_depth, 0),
+            function_type: std::mem::replace(&mut self.function_type, function_type),
+            loops: std::mem::take(&mut self.loops),
+            unresolved_jumps: std::mem::take(&mut self.unresolved_jumps),
+        });
+
+        let Func { name, parameters, body } = func;
+        let result = self.compile_function_body(&name, &parameters, body);
+
+        let enclosing = self.enclosing.pop().expect("enclosing function state missing");
+        let chunk = std::mem::replace(self.chunk, enclosing.chunk);
+        let upvalues = std::mem::replace(&mut self.upvalues, enclosing.upvalues);
+        self.locals = enclosing.locals;
+        self.scope_depth = enclosing.scope_depth;
+        self.function_type = enclosing.function_type;
+        self.loops = enclosing.loops;
+        self.unresolved_jumps = enclosing.unresolved_jumps;
+
+        result?;
+
+        let function = Object::Function {
+            name: name.lexeme,
+            arity: parameters.len() as u8,
+            chunk: Rc::new(chunk),
+        };
+
+        Ok((function, upvalues))
+    }
+    fn compile_function_body(&mut self, name: &SourceToken, parameters: &[SourceToken], body: Vec<Stmt>) -> Result<(), CompilerError> {
+        self.begin_scope();
+        // slot 0 holds the function being called, methods name it `this` as the receiver takes its place
+        // otherwise it can't be named so won't ever be resolved
+        let slot_zero = match self.function_type {
+            FunctionType::Method | FunctionType::Initializer => String::from("this"),
+            _ => String::new(),
+        };
+        self.declare_local(slot_zero)?;
+        for parameter in parameters {
+            self.declare_local(parameter.lexeme.clone())?;
+        }
+
+        self.compile_statements(body)?;
+
+        let end = self.chunk.len();
+        self.emit_implicit_return_value(name.line);
+        self.chunk.add(OpCode::Return, name.line);
+
+        self.check_stack_balance(parameters.len() + 1, end)
+    }
+    // every statement pops what it pushes, so the code reaching `end` after the last statement, if any does, leaves only
+    // the locals still in scope. `slots` is how many values the frame starts with
+    fn check_stack_balance(&self, slots: usize, end: usize) -> Result<(), CompilerError> {
+        let heights = stack_heights(self.chunk, slots)
+            .map_err(|error| CompilerError::UnbalancedStack { offset: error.offset().unwrap_or(end) })?;
+
+        match heights[end] {
+            Some(height) if height != self.locals.len() => Err(CompilerError::UnbalancedStack { offset: end }),
+            _ => Ok(()),
+        }
+    }
+    // initializers always return the instance, everything else returns nil
+    fn emit_implicit_return_value(&mut self, line: usize) {
+        if self.function_type == FunctionType::Initializer {
+            self.chunk.add(OpCode::GetLocal(0), line);
+        } else {
+            self.chunk.add(OpCode::Nil, line);
+        }
+    }
+
+    fn add_string(&mut self, s: String) -> Result<u32, CompilerError> {
+        self.add_constant(Value::new_string(s))
+    }
+    fn add_constant(&mut self, value: Value) -> Result<u32, CompilerError> {
+        match self.chunk.constant_index_of(&value) {
+            Some(constant) => Ok(constant),
+            None => self.chunk.add_constant(value).map_err(|_| CompilerError::TooManyConstants),
+        }
+    }
+    fn global_slot(&mut self, name: &str) -> Result<u32, CompilerError> {
+        self.chunk.global_slot(name).map_err(|_| CompilerError::TooManyGlobals)
+    }
+    // uses the short form when the constant index or global slot fits in a byte, most chunks never need the long one
+    fn emit_constant_op(&mut self, index: u32, short: impl FnOnce(u8) -> OpCode, long: impl FnOnce(u32) -> OpCode, line: usize) {
+        let op = if index <= u8::MAX as u32 { short(index as u8) } else { long(index) };
+
+        self.chunk.add(op, line);
+    }
+    fn emit_local_op(&mut self, local: u16, short: fn(u8) -> OpCode, long: fn(u16) -> OpCode, line: usize) {
+        let op = if local <= u8::MAX as u16 { short(local as u8) } else { long(local) };
 
-        Ok(constant)
+        self.chunk.add(op, line);
     }
 
-    fn jump(&mut self, op_factory: JumpOpFactory) -> JumpPatchReference {
+    fn jump(&mut self, kind: JumpKind, line: usize) -> JumpPatchReference {
+        let chunk_ref = self.chunk.add(kind.op(0), line);
         let offset = self.chunk.len();
-        let chunk_ref = self.chunk.add(OpCode::Jump(0), 0); // TODO line number?
 
-        // TODO track unresolved jumps
+        self.unresolved_jumps.push(offset);
 
-        JumpPatchReference { chunk_ref, offset, op_factory }
+        JumpPatchReference { chunk_ref, offset, kind, line }
     }
-    fn resolve_jump(&mut self, jump: &JumpPatchReference) {
-        let offset = self.chunk.len() - jump.offset;
-        let op = (jump.op_factory)(offset as i16);
-        self.chunk.patch(&jump.chunk_ref, op);
+    fn resolve_jump(&mut self, jump: &JumpPatchReference) -> Result<(), CompilerError> {
+        self.unresolved_jumps.retain(|&offset| offset != jump.offset);
+
+        let distance = self.chunk.len() - jump.offset;
+        let offset = jump_offset(distance, jump.line)?;
+
+        // patching with the jump's own kind checks it against the opcode the placeholder was emitted as
+        self.chunk.patch(&jump.chunk_ref, jump.kind.op(offset)).map_err(CompilerError::InvalidPatch)
     }
     fn loop_start(&self) -> JumpLoopReference {
         JumpLoopReference { offset: self.chunk.len() }
     }
-    fn jump_loop(&mut self, jump: &JumpLoopReference, op_factory: JumpOpFactory) {
-        let offset = -((self.chunk.len() - jump.offset) as i16);
+    fn jump_loop(&mut self, jump: &JumpLoopReference, line: usize) -> Result<(), CompilerError> {
+        // the distance back includes the loop instruction itself as the vm has already read past it
+        let distance = self.chunk.len() + OpCode::Loop(0).byte_length() - jump.offset;
+        let offset = jump_offset(distance, line)?;
+
+        self.chunk.add(OpCode::Loop(offset), line);
+
+        Ok(())
+    }
 
-        self.chunk.add(op_factory(offset), 0); // TODO line number?
+    // ends a scope that was never begun, as if a compile path forgot to begin it
+    #[cfg(test)]
+    pub(crate) fn end_unopened_scope(&mut self) -> Result<(), CompilerError> {
+        self.end_scope(0)
     }
 
-    fn resolve_local(&mut self, name: &String) -> Option<u8> {
-        self.locals.iter().enumerate().rev().find(|(_, local)| &local.name == name).map(|(i, _)| i as u8)
+    // pushes a value nothing pops, as if a compile path forgot its OP_POP
+    #[cfg(test)]
+    pub(crate) fn emit_unbalanced_push(&mut self) {
+        self.chunk.add(OpCode::Nil, 0);
     }
 
-    fn begin_scope(&mut self) {
-        if self.scope_depth == std::u8::MAX {
-            panic!("begin scope will overflow scope depth")
+    // leaves a jump unpatched, as if a compile path forgot to resolve it
+    #[cfg(test)]
+    pub(crate) fn emit_unresolved_jump(&mut self) {
+        self.jump(JumpKind::Jump, 0);
+    }
+
+    // emits the pops for locals declared inside the innermost loop without forgetting them,
+    // compilation carries on in their scope after the break/continue
+    fn discard_loop_locals(&mut self, token: &SourceToken, outside_loop: CompilerError) -> Result<(), CompilerError> {
+        let loop_depth = match self.loops.last() {
+            Some(loop_state) => loop_state.scope_depth,
+            None => return Err(outside_loop),
+        };
+
+        for local in self.locals.iter().rev().take_while(|local| local.scope_depth > loop_depth) {
+            self.chunk.add(if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop }, token.line);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_local(&mut self, name: &SourceToken) -> Result<Option<u16>, CompilerError> {
+        match find_local(&self.locals, &name.lexeme) {
+            Some(local) if !self.locals[local as usize].initialized => {
+                Err(CompilerError::ReadOwnInitializer { name: name.lexeme.clone(), line: name.line })
+            },
+            local => Ok(local),
+        }
+    }
+    // `depth` counts functions from the script (0) to the one being compiled (`self.enclosing.len()`)
+    fn resolve_upvalue(&mut self, depth: usize, name: &String) -> Result<Option<u8>, CompilerError> {
+        if depth == 0 {
+            return Ok(None);
+        }
+
+        if let Some(local) = find_local(self.locals_at(depth - 1), name) {
+            if local > u8::MAX as u16 {
+                return Err(CompilerError::CapturedLocalOutOfRange(name.clone()));
+            }
+
+            self.locals_at(depth - 1)[local as usize].is_captured = true;
+            return self.add_upvalue(depth, true, local as u8).map(Some);
+        }
+
+        if let Some(upvalue) = self.resolve_upvalue(depth - 1, name)? {
+            return self.add_upvalue(depth, false, upvalue).map(Some);
         }
 
+        Ok(None)
+    }
+    fn add_upvalue(&mut self, depth: usize, is_local: bool, index: u8) -> Result<u8, CompilerError> {
+        let upvalues = self.upvalues_at(depth);
+
+        if let Some(existing) = upvalues.iter().position(|upvalue| upvalue.is_local == is_local && upvalue.index == index) {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() == std::u8::MAX as usize {
+            return Err(CompilerError::TooManyUpvalues);
+        }
+
+        upvalues.push(Upvalue { is_local, index });
+        Ok((upvalues.len() - 1) as u8)
+    }
+    fn locals_at(&mut self, depth: usize) -> &mut Vec<Local> {
+        if depth == self.enclosing.len() { &mut self.locals } else { &mut self.enclosing[depth].locals }
+    }
+    fn upvalues_at(&mut self, depth: usize) -> &mut Vec<Upvalue> {
+        if depth == self.enclosing.len() { &mut self.upvalues } else { &mut self.enclosing[depth].upvalues }
+    }
+
+    // for instructions with no token of their own, e.g. the pops closing a block
+    fn last_line(&self) -> usize {
+        self.chunk.line(self.chunk.len().saturating_sub(1))
+    }
+
+    fn begin_scope(&mut self) {
         self.scope_depth += 1;
     }
-    fn end_scope(&mut self) {
-        if self.scope_depth == std::u8::MIN {
-            panic!("ending scope without an open one")
+    fn end_scope(&mut self, line: usize) -> Result<(), CompilerError> {
+        if self.scope_depth == 0 {
+            return Err(CompilerError::UnbalancedScope);
         }
 
         self.scope_depth -= 1;
 
         while !self.locals.is_empty() && self.locals.last().unwrap().scope_depth > self.scope_depth {
-            self.chunk.add(OpCode::Pop, 0); // TODO line number?
-            self.locals.pop();
+            let local = self.locals.pop().unwrap();
+            self.chunk.add(if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop }, line);
         }
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+// the line of an expression's leftmost token
+fn expr_line(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binary(left, _, _) | Expr::Logical(left, _, _) | Expr::Ternary(left, _, _, _) => expr_line(left),
+        Expr::Call(callee, _, _) => expr_line(callee),
+        Expr::Get(object, _) | Expr::Set(object, _, _) => expr_line(object),
+        Expr::Grouping(expr) => expr_line(expr),
+
+        Expr::Assign(token, _) | Expr::Super(token, _) | Expr::Unary(token, _) | Expr::List(token, _) |
+        Expr::This(token) | Expr::Var(token) | Expr::String(token, _) | Expr::Number(token, _) | Expr::Boolean(token, _) | Expr::Nil(token) => token.line,
+    }
+}
+
+fn jump_offset(distance: usize, line: usize) -> Result<u16, CompilerError> {
+    if distance > u16::MAX as usize {
+        Err(CompilerError::JumpTooLarge { distance, line })
+    } else {
+        Ok(distance as u16)
+    }
+}
+
+fn find_local(locals: &[Local], name: &String) -> Option<u16> {
+    locals.iter().enumerate().rev().find(|(_, local)| &local.name == name).map(|(i, _)| i as u16)
+}
+
+// a token the source didn't contain, for resolving the implicit `this` and `super` variables
+fn synthetic_token(token: Token, lexeme: &str, line: usize) -> SourceToken {
+    SourceToken { token, lexeme: String::from(lexeme), line }
+}
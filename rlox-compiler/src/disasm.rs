@@ -1,7 +1,249 @@
+use std::collections::{ BTreeMap, BTreeSet };
 use std::io::Write;
 use crate::chunk::Chunk;
 use crate::op::DecodeError;
-use crate::OpCode;
+use crate::{ Object, OpCode, Value };
+
+// a decoded instruction with its operands resolved against the chunk, the text disassembly is written from these
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub offset: usize,
+    // bytes taken up in the chunk, so the next instruction starts at `offset + length`
+    pub length: usize,
+    pub line: usize,
+    pub name: &'static str,
+    pub operands: Vec<Operand>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    // the value as it prints, or why it couldn't be read when the index is out of range
+    Constant { index: u32, value: String },
+    Global { slot: u32, name: String },
+    // local slots, upvalue indices and element counts
+    Integer(u32),
+    ArgumentCount(u8),
+    // `target` is the absolute offset jumped to, a malformed loop could point before the start of the chunk
+    Jump { distance: u16, backward: bool, target: Option<usize> },
+    Upvalue { is_local: bool, index: u8 },
+    // the operands run off the end of the chunk, disassembly skips just the opcode so whatever follows is still shown
+    Truncated { opcode: u8, message: String },
+}
+
+fn constant(chunk: &Chunk, index: u32) -> Operand {
+    let value = match chunk.constant(index) {
+        Ok(value) => value.to_string(),
+        Err(err) => err,
+    };
+
+    Operand::Constant { index, value }
+}
+
+fn global(chunk: &Chunk, slot: u32) -> Operand {
+    let name = match chunk.global_name(slot) {
+        Ok(name) => name,
+        Err(err) => err,
+    };
+
+    Operand::Global { slot, name }
+}
+
+fn forward_jump(distance: u16, next_offset: usize) -> Operand {
+    Operand::Jump { distance, backward: false, target: Some(next_offset + distance as usize) }
+}
+
+fn closure(chunk: &Chunk, index: u32, upvalues: Vec<(bool, u8)>) -> Vec<Operand> {
+    let mut operands = vec![constant(chunk, index)];
+    operands.extend(upvalues.into_iter().map(|(is_local, index)| Operand::Upvalue { is_local, index }));
+    operands
+}
+
+// the name and operands of an instruction
+fn describe(chunk: &Chunk, op: OpCode, next_offset: usize) -> (&'static str, Vec<Operand>) {
+    let name = op.name();
+    let operands = match op {
+        OpCode::Constant(index) => vec![constant(chunk, index.into())],
+        OpCode::True => vec![],
+        OpCode::False => vec![],
+        OpCode::Nil => vec![],
+        OpCode::Pop => vec![],
+
+        OpCode::GetLocal(slot) => vec![Operand::Integer(slot.into())],
+        OpCode::SetLocal(slot) => vec![Operand::Integer(slot.into())],
+        OpCode::GetGlobal(slot) => vec![global(chunk, slot.into())],
+        OpCode::DefineGlobal(slot) => vec![global(chunk, slot.into())],
+        OpCode::SetGlobal(slot) => vec![global(chunk, slot.into())],
+
+        OpCode::Equal => vec![],
+        OpCode::Greater => vec![],
+        OpCode::Less => vec![],
+        OpCode::Add => vec![],
+        OpCode::Subtract => vec![],
+        OpCode::Multiply => vec![],
+        OpCode::Divide => vec![],
+        OpCode::Not => vec![],
+        OpCode::Negate => vec![],
+        OpCode::Modulo => vec![],
+
+        OpCode::Print => vec![],
+        OpCode::Jump(distance) => vec![forward_jump(distance, next_offset)],
+        OpCode::JumpIfFalse(distance) => vec![forward_jump(distance, next_offset)],
+        OpCode::Return => vec![],
+        OpCode::Call(arg_count) => vec![Operand::Integer(arg_count.into())],
+
+        OpCode::Closure(index, upvalues) => closure(chunk, index.into(), upvalues),
+        OpCode::GetUpvalue(index) => vec![Operand::Integer(index.into())],
+        OpCode::SetUpvalue(index) => vec![Operand::Integer(index.into())],
+        OpCode::CloseUpvalue => vec![],
+
+        OpCode::Class(index) => vec![constant(chunk, index.into())],
+        OpCode::GetProperty(index) => vec![constant(chunk, index.into())],
+        OpCode::SetProperty(index) => vec![constant(chunk, index.into())],
+        OpCode::Method(index) => vec![constant(chunk, index.into())],
+        OpCode::Invoke(index, arg_count) => vec![Operand::ArgumentCount(arg_count), constant(chunk, index.into())],
+        OpCode::Inherit => vec![],
+        OpCode::GetSuper(index) => vec![constant(chunk, index.into())],
+        OpCode::SuperInvoke(index, arg_count) => vec![Operand::ArgumentCount(arg_count), constant(chunk, index.into())],
+
+        OpCode::Loop(distance) => vec![Operand::Jump { distance, backward: true, target: next_offset.checked_sub(distance as usize) }],
+
+        OpCode::ConstantLong(index) => vec![constant(chunk, index)],
+        OpCode::GetGlobalLong(slot) => vec![global(chunk, slot)],
+        OpCode::DefineGlobalLong(slot) => vec![global(chunk, slot)],
+        OpCode::SetGlobalLong(slot) => vec![global(chunk, slot)],
+
+        OpCode::GetLocalLong(slot) => vec![Operand::Integer(slot.into())],
+        OpCode::SetLocalLong(slot) => vec![Operand::Integer(slot.into())],
+
+        OpCode::Array(count) => vec![Operand::Integer(count.into())],
+
+        OpCode::ClosureLong(index, upvalues) => closure(chunk, index, upvalues),
+        OpCode::ClassLong(index) => vec![constant(chunk, index)],
+        OpCode::GetPropertyLong(index) => vec![constant(chunk, index)],
+        OpCode::SetPropertyLong(index) => vec![constant(chunk, index)],
+        OpCode::MethodLong(index) => vec![constant(chunk, index)],
+        OpCode::InvokeLong(index, arg_count) => vec![Operand::ArgumentCount(arg_count), constant(chunk, index)],
+        OpCode::GetSuperLong(index) => vec![constant(chunk, index)],
+        OpCode::SuperInvokeLong(index, arg_count) => vec![Operand::ArgumentCount(arg_count), constant(chunk, index)],
+
+        OpCode::Unknown(opcode) => vec![Operand::Integer(opcode.into())],
+    };
+
+    (name, operands)
+}
+
+// None once `offset` is past the end of the chunk
+fn decode_instruction(chunk: &Chunk, offset: usize) -> Option<Instruction> {
+    let line = chunk.line(offset);
+
+    match chunk.decode(offset) {
+        Ok((op, next_offset)) => {
+            let (name, operands) = describe(chunk, op, next_offset);
+            Some(Instruction { offset, length: next_offset - offset, line, name, operands })
+        },
+
+        Err(DecodeError::EOF) => None,
+        Err(DecodeError::UnexpectedEOF(_, message)) => {
+            let opcode = chunk.as_bytes().nth(offset).copied().unwrap_or_default();
+            Some(Instruction { offset, length: 1, line, name: "<truncated>", operands: vec![Operand::Truncated { opcode, message }] })
+        },
+    }
+}
+
+pub fn disassemble_structured(chunk: &Chunk) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+
+    let mut offset = 0;
+    while let Some(instruction) = decode_instruction(chunk, offset) {
+        offset += instruction.length;
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+// jump targets that start an instruction (or are the end of the chunk) get a label, `L0`, `L1`, ... in offset order
+fn collect_labels(chunk: &Chunk) -> BTreeMap<usize, usize> {
+    let mut boundaries = BTreeSet::new();
+    let mut targets = BTreeSet::new();
+
+    for instruction in disassemble_structured(chunk) {
+        boundaries.insert(instruction.offset);
+
+        for operand in &instruction.operands {
+            if let Operand::Jump { target: Some(target), .. } = operand {
+                targets.insert(*target);
+            }
+        }
+    }
+    boundaries.insert(chunk.len());
+
+    targets.intersection(&boundaries).enumerate().map(|(label, &target)| (target, label)).collect()
+}
+
+pub fn disassemble_chunk(w: &mut dyn Write, chunk: &Chunk) -> std::io::Result<()> {
+    disassemble_chunk_at_depth(w, chunk, 0)
+}
+
+pub fn disassemble_to_string(chunk: &Chunk) -> String {
+    let mut output = Vec::new();
+    disassemble_chunk(&mut output, chunk).expect("Writing to a Vec can't fail");
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+// just the instructions starting in `start_offset..end_offset`, labelled as they would be when disassembling the whole chunk
+pub fn disassemble_range(w: &mut dyn Write, chunk: &Chunk, start_offset: usize, end_offset: usize) -> std::io::Result<()> {
+    let labels = collect_labels(chunk);
+    write_instructions(w, chunk, start_offset, end_offset, &labels)
+}
+
+fn write_instructions(w: &mut dyn Write, chunk: &Chunk, start_offset: usize, end_offset: usize, labels: &BTreeMap<usize, usize>) -> std::io::Result<()> {
+    let mut offset = start_offset;
+    while offset < end_offset {
+        if let Some(label) = labels.get(&offset) {
+            writeln!(w, "L{}:", label)?;
+        }
+
+        match write_instruction(w, chunk, offset, Some(labels))? {
+            Some(next_offset) => offset = next_offset,
+            None => break,
+        }
+    }
+
+    // a jump to the very end of the chunk has no instruction to hang its label on
+    if offset == chunk.len() {
+        if let Some(label) = labels.get(&offset) {
+            writeln!(w, "L{}:", label)?;
+        }
+    }
+
+    Ok(())
+}
+
+// functions nested in functions get their headers indented a level further than their parent's
+fn disassemble_chunk_at_depth(w: &mut dyn Write, chunk: &Chunk, depth: usize) -> std::io::Result<()> {
+    let labels = collect_labels(chunk);
+    write_instructions(w, chunk, 0, chunk.len(), &labels)?;
+
+    if !chunk.constants().is_empty() {
+        writeln!(w, "== constants ==")?;
+        for (index, constant) in chunk.constants().iter().enumerate() {
+            writeln!(w, "{:4} {:8} '{}'", index, constant.type_name(), constant)?;
+        }
+    }
+
+    for constant in chunk.constants() {
+        if let Value::Object(obj) = constant {
+            if let Object::Function { name, chunk, .. } = obj.as_ref() {
+                writeln!(w, "{}== {} ==", "  ".repeat(depth), name)?;
+                disassemble_chunk_at_depth(w, chunk, depth + 1)?;
+            }
+        }
+    }
+
+    Ok(())
+}
 
 fn write_instruction_header(w: &mut dyn Write, chunk: &Chunk, offset: usize) -> std::io::Result<()> {
     write!(w, "{:#06x} ", offset)?;
@@ -15,94 +257,318 @@ fn write_instruction_header(w: &mut dyn Write, chunk: &Chunk, offset: usize) ->
     }
 }
 
-macro_rules! write_constant_op {
-    ($w:ident, $op:expr, $chunk:ident, $index:ident) => {
-        {
-            let value = $chunk.constant($index);
-            match value {
-                Ok(value) => writeln!($w, "{:16} {} '{}'", $op, $index, value)?,
-                Err(err) =>  writeln!($w, "{:16} {} '{}'", $op, $index, err)?,
+// jumps land on a label when disassembling a whole chunk and an offset when tracing one instruction
+fn format_operand(operand: &Operand, labels: Option<&BTreeMap<usize, usize>>) -> String {
+    match operand {
+        Operand::Constant { index, value } => format!("{} '{}'", index, value),
+        Operand::Global { slot, name } => format!("{} '{}'", slot, name),
+        Operand::Integer(value) => value.to_string(),
+        Operand::ArgumentCount(arg_count) => format!("({} args)", arg_count),
+        Operand::Jump { distance, backward, target } => {
+            let sign = if *backward { '-' } else { '+' };
+            match (target, labels.and_then(|labels| target.and_then(|target| labels.get(&target)))) {
+                (_, Some(label)) => format!("-> L{}", label),
+                (Some(target), None) => format!("{}{:#06x} -> {:#06x}", sign, distance, target),
+                (None, None) => format!("{}{:#06x} -> before start of chunk", sign, distance),
             }
-        }
+        },
+        Operand::Upvalue { is_local, index } => format!("{} {}", if *is_local { "local" } else { "upvalue" }, index),
+        Operand::Truncated { opcode, message } => format!("{:#04x}: {}, skipping 1 byte", opcode, message),
+    }
+}
+
+pub fn disassemble_instruction(w: &mut dyn Write, chunk: &Chunk, offset: usize) -> std::io::Result<Option<usize>> {
+    write_instruction(w, chunk, offset, None)
+}
+
+fn write_instruction(w: &mut dyn Write, chunk: &Chunk, offset: usize, labels: Option<&BTreeMap<usize, usize>>) -> std::io::Result<Option<usize>> {
+    let instruction = match decode_instruction(chunk, offset) {
+        Some(instruction) => instruction,
+        None => return Ok(None),
     };
+
+    write_instruction_header(w, chunk, offset)?;
+    writeln!(w, "{}", format_instruction_line(&instruction, labels))?;
+
+    // a closure's upvalues each get a line of their own under it
+    for upvalue in instruction.operands.iter().filter(|operand| matches!(operand, Operand::Upvalue { .. })) {
+        writeln!(w, "{:12}{:16} {}", "", "|", format_operand(upvalue, labels))?;
+    }
+
+    Ok(Some(offset + instruction.length))
 }
 
-pub fn disassemble_chunk(w: &mut dyn Write, chunk: &Chunk) {
-    let mut offset = 0;
-    loop {
-        match disassemble_instruction(w, chunk, offset) {
-            Ok(next_offset) => match next_offset {
-                Some(next_offset) => offset = next_offset,
-                None => break,
-            },
-            Err(e) => panic!("{:?}", e),
-        }
+fn format_instruction_line(instruction: &Instruction, labels: Option<&BTreeMap<usize, usize>>) -> String {
+    let operands: Vec<String> = instruction.operands.iter()
+        .filter(|operand| !matches!(operand, Operand::Upvalue { .. }))
+        .map(|operand| format_operand(operand, labels))
+        .collect();
+
+    if operands.is_empty() {
+        instruction.name.to_string()
+    } else {
+        format!("{:16} {}", instruction.name, operands.join(" "))
     }
 }
 
-pub fn disassemble_instruction(w: &mut dyn Write, chunk: &Chunk, offset: usize) -> std::io::Result<Option<usize>> {
-    match chunk.decode(offset) {
-        Ok((op, next_offset)) => {
-            write_instruction_header(w, chunk, offset)?;
-
-            match op {
-                OpCode::Constant(index) => write_constant_op!(w, "OP_CONSTANT", chunk, index),
-
-                OpCode::True => writeln!(w, "OP_TRUE")?,
-                OpCode::False => writeln!(w, "OP_FALSE")?,
-                OpCode::Nil => writeln!(w, "OP_NIL")?,
-                OpCode::Pop => writeln!(w, "OP_POP")?,
-
-                OpCode::GetLocal(index) => write_constant_op!(w, "OP_GET_LOCAL", chunk, index),
-                OpCode::SetLocal(index) => write_constant_op!(w, "OP_SET_LOCAL", chunk, index),
-                OpCode::GetGlobal(index) => write_constant_op!(w, "OP_GET_GLOBAL", chunk, index),
-                OpCode::DefineGlobal(index) => write_constant_op!(w, "OP_DEFINE_GLOBAL", chunk, index),
-                OpCode::SetGlobal(index) => write_constant_op!(w, "OP_SET_GLOBAL", chunk, index),
-
-                OpCode::Equal => writeln!(w, "OP_EQUAL")?,
-                OpCode::Greater => writeln!(w, "OP_GREATER")?,
-                OpCode::Less => writeln!(w, "OP_LESS")?,
-                OpCode::Add => writeln!(w, "OP_ADD")?,
-                OpCode::Subtract => writeln!(w, "OP_SUBTRACT")?,
-                OpCode::Multiply => writeln!(w, "OP_MULTIPLY")?,
-                OpCode::Divide => writeln!(w, "OP_DIVIDE")?,
-                OpCode::Not => writeln!(w, "OP_NOT")?,
-                OpCode::Negate => writeln!(w, "OP_NEGATE")?,
-
-                OpCode::Print => writeln!(w, "OP_PRINT")?,
-                OpCode::Jump(jump_offset) => writeln!(w, "OP_JUMP {} -> {:#06x}", display_jump_offset(jump_offset), calculate_jump_target(offset, jump_offset))?,
-                OpCode::JumpIfFalse(jump_offset) => writeln!(w, "OP_JUMP_IF_FALSE {} -> {:#06x}", display_jump_offset(jump_offset), calculate_jump_target(offset, jump_offset))?,
-                OpCode::Return => writeln!(w, "OP_RETURN")?,
-
-                OpCode::Unknown(val) => writeln!(w, "Unknown opcode {}", val)?,
-            }
+// the instruction's line of disassembly without the offset and line columns, for pointing at it in error messages
+pub(crate) fn format_instruction(chunk: &Chunk, offset: usize) -> Option<String> {
+    decode_instruction(chunk, offset).map(|instruction| format_instruction_line(&instruction, None))
+}
 
-            Ok(Some(next_offset))
-        },
+// one instruction per line so the output diffs well, jump targets are absolute offsets or null
+pub fn write_disassembly_json(w: &mut dyn Write, instructions: &[Instruction]) -> std::io::Result<()> {
+    if instructions.is_empty() {
+        return writeln!(w, "[]");
+    }
 
-        Err(DecodeError::EOF) => Ok(None),
-        Err(err) => {
-            write_instruction_header(w, chunk, offset)?;
-            writeln!(w, "Error decoding instruction {:?}", err)?;
+    writeln!(w, "[")?;
+    for (i, instruction) in instructions.iter().enumerate() {
+        let operands: Vec<String> = instruction.operands.iter().map(operand_json).collect();
+        let separator = if i + 1 < instructions.len() { "," } else { "" };
 
-            // TODO?
-            Ok(Some(offset + 1))
-        }
+        writeln!(w, "  {{\"offset\": {}, \"length\": {}, \"line\": {}, \"name\": {}, \"operands\": [{}]}}{}",
+            instruction.offset, instruction.length, instruction.line, json_string(instruction.name), operands.join(", "), separator)?;
     }
+    writeln!(w, "]")
 }
 
-fn display_jump_offset(offset: i16) -> String {
-    if offset >= 0 {
-        format!("+{:#04x}", offset)
-    } else {
-        format!("-{:#04x}", -offset)
+fn operand_json(operand: &Operand) -> String {
+    match operand {
+        Operand::Constant { index, value } => format!("{{\"type\": \"constant\", \"index\": {}, \"value\": {}}}", index, json_string(value)),
+        Operand::Global { slot, name } => format!("{{\"type\": \"global\", \"slot\": {}, \"name\": {}}}", slot, json_string(name)),
+        Operand::Integer(value) => format!("{{\"type\": \"integer\", \"value\": {}}}", value),
+        Operand::ArgumentCount(arg_count) => format!("{{\"type\": \"argument_count\", \"value\": {}}}", arg_count),
+        Operand::Jump { distance, backward, target } => {
+            let target = target.map_or("null".to_string(), |target| target.to_string());
+            format!("{{\"type\": \"jump\", \"distance\": {}, \"backward\": {}, \"target\": {}}}", distance, backward, target)
+        },
+        Operand::Upvalue { is_local, index } => format!("{{\"type\": \"upvalue\", \"is_local\": {}, \"index\": {}}}", is_local, index),
+        Operand::Truncated { opcode, message } => format!("{{\"type\": \"truncated\", \"opcode\": {}, \"message\": {}}}", opcode, json_string(message)),
     }
 }
 
-fn calculate_jump_target(base: usize, offset: i16) -> usize {
-    if offset >= 0 {
-        base + offset as usize
-    } else {
-        base - (-offset as usize)
+// lox strings can hold anything but a `"`, including raw newlines
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::GlobalNames;
+    use crate::op::{ OP_JUMP, OP_NIL };
+    use super::*;
+
+    #[test]
+    fn test_disassemble_function_constants() {
+        let mut function_chunk = Chunk::new();
+        function_chunk.add(OpCode::Nil, 1);
+        function_chunk.add(OpCode::Return, 1);
+
+        let mut chunk = Chunk::new();
+        let function = Object::Function { name: "f".into(), arity: 0, chunk: Rc::new(function_chunk) };
+        let constant = chunk.add_constant(Value::Object(Rc::new(function))).unwrap() as u8;
+        chunk.add(OpCode::Constant(constant), 1);
+        chunk.add(OpCode::Call(0), 1);
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_CONSTANT      0 '<fn f>'
+0x0002    | OP_CALL          0
+== constants ==
+   0 function '<fn f>'
+== f ==
+0x0000    1 OP_NIL
+0x0001    | OP_RETURN
+");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_disassemble_closure() {
+        let mut chunk = Chunk::new();
+        let function = Object::Function { name: "f".into(), arity: 0, chunk: Rc::new(Chunk::new()) };
+        let constant = chunk.add_constant(Value::Object(Rc::new(function))).unwrap() as u8;
+        chunk.add(OpCode::Closure(constant, vec![(true, 1), (false, 0)]), 1);
+        chunk.add(OpCode::GetUpvalue(1), 1);
+        chunk.add(OpCode::CloseUpvalue, 1);
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_CLOSURE       0 '<fn f>'
+            |                local 1
+            |                upvalue 0
+0x0007    | OP_GET_UPVALUE   1
+0x0009    | OP_CLOSE_UPVALUE
+== constants ==
+   0 function '<fn f>'
+== f ==
+");
+    }
+
+    #[test]
+    fn test_disassemble_class() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::new_string("Pair".into())).unwrap() as u8;
+        chunk.add(OpCode::Class(constant), 3);
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    3 OP_CLASS         0 'Pair'
+== constants ==
+   0 string   'Pair'
+");
+    }
+
+    #[test]
+    fn test_disassemble_methods() {
+        let mut chunk = Chunk::new();
+        let name = chunk.add_constant(Value::new_string("area".into())).unwrap() as u8;
+        chunk.add(OpCode::Method(name), 1);
+        chunk.add(OpCode::GetProperty(name), 2);
+        chunk.add(OpCode::SetProperty(name), 2);
+        chunk.add(OpCode::Invoke(name, 2), 3);
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_METHOD        0 'area'
+0x0002    2 OP_GET_PROPERTY  0 'area'
+0x0004    | OP_SET_PROPERTY  0 'area'
+0x0006    3 OP_INVOKE        (2 args) 0 'area'
+== constants ==
+   0 string   'area'
+");
+    }
+
+    #[test]
+    fn test_disassemble_jumps() {
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::True, 1);
+        chunk.add(OpCode::JumpIfFalse(4), 1);
+        chunk.add(OpCode::Pop, 1);
+        chunk.add(OpCode::Loop(8), 1);
+        chunk.add(OpCode::Jump(0), 2);
+        chunk.add(OpCode::Loop(20), 2);
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+L0:
+0x0000    1 OP_TRUE
+0x0001    | OP_JUMP_IF_FALSE -> L1
+0x0004    | OP_POP
+0x0005    | OP_LOOP          -> L0
+L1:
+0x0008    2 OP_JUMP          -> L2
+L2:
+0x000b    | OP_LOOP          -0x0014 -> before start of chunk
+");
+    }
+
+    #[test]
+    fn test_disassemble_truncated_operands() {
+        // a jump missing the second byte of its offset, leaving a lone 0x00 that decodes as an OP_CONSTANT missing its index
+        let chunk = Chunk::from_parts(vec![OP_NIL, OP_JUMP, 0x00], vec![(3, 1)], vec![], Rc::new(RefCell::new(GlobalNames::new())));
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_NIL
+0x0001    | <truncated>      0x15: Missing jump offset, skipping 1 byte
+0x0002    | <truncated>      0x00: Missing constant index, skipping 1 byte
+");
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::True, 1);
+        chunk.add(OpCode::JumpIfFalse(2), 1);
+        chunk.add(OpCode::Print, 2);
+        chunk.add(OpCode::Nil, 2);
+        chunk.add(OpCode::Loop(9), 3);
+        chunk.add(OpCode::Return, 3);
+
+        let mut output = Vec::new();
+        disassemble_range(&mut output, &chunk, 1, 6).expect("Failed to disassemble range");
+
+        // labels are numbered across the whole chunk, so the range's only label isn't L0
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+0x0001    | OP_JUMP_IF_FALSE -> L1
+0x0004    2 OP_PRINT
+0x0005    | OP_NIL
+");
+    }
+
+    #[test]
+    fn test_disassemble_structured() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::new_string("f".into())).unwrap() as u8;
+        chunk.add(OpCode::Closure(constant, vec![(true, 1)]), 1);
+        chunk.add(OpCode::Invoke(constant, 2), 2);
+        chunk.add(OpCode::Loop(10), 2);
+
+        assert_eq!(disassemble_structured(&chunk), vec![
+            Instruction { offset: 0, length: 5, line: 1, name: "OP_CLOSURE", operands: vec![
+                Operand::Constant { index: 0, value: "f".into() },
+                Operand::Upvalue { is_local: true, index: 1 },
+            ] },
+            Instruction { offset: 5, length: 3, line: 2, name: "OP_INVOKE", operands: vec![
+                Operand::ArgumentCount(2),
+                Operand::Constant { index: 0, value: "f".into() },
+            ] },
+            Instruction { offset: 8, length: 3, line: 2, name: "OP_LOOP", operands: vec![
+                Operand::Jump { distance: 10, backward: true, target: Some(1) },
+            ] },
+        ]);
+    }
+
+    #[test]
+    fn test_disassembly_json() {
+        let chunk = Chunk::from_parts(vec![OP_NIL, OP_JUMP, 0x00], vec![(3, 1)], vec![], Rc::new(RefCell::new(GlobalNames::new())));
+        let mut instructions = disassemble_structured(&chunk);
+        instructions[0].operands.push(Operand::Constant { index: 0, value: "a \"quoted\"\nline\u{1}".into() });
+
+        let mut output = Vec::new();
+        write_disassembly_json(&mut output, &instructions).expect("Failed to write JSON");
+
+        assert_eq!(String::from_utf8(output).unwrap(), r#"[
+  {"offset": 0, "length": 1, "line": 1, "name": "OP_NIL", "operands": [{"type": "constant", "index": 0, "value": "a \"quoted\"\nline\u0001"}]},
+  {"offset": 1, "length": 1, "line": 1, "name": "<truncated>", "operands": [{"type": "truncated", "opcode": 21, "message": "Missing jump offset"}]},
+  {"offset": 2, "length": 1, "line": 1, "name": "<truncated>", "operands": [{"type": "truncated", "opcode": 0, "message": "Missing constant index"}]}
+]
+"#);
+
+        let mut output = Vec::new();
+        write_disassembly_json(&mut output, &[]).expect("Failed to write JSON");
+        assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_disassemble_constant_long() {
+        let mut chunk = Chunk::new();
+        for i in 0..300 {
+            chunk.add_constant(Value::Number(i as f64)).unwrap();
+            chunk.global_slot(&format!("g{}", i)).unwrap();
+        }
+        chunk.add(OpCode::ConstantLong(299), 1);
+        chunk.add(OpCode::DefineGlobalLong(256), 1);
+
+        let output = disassemble_to_string(&chunk);
+        assert!(output.starts_with("\
+0x0000    1 OP_CONSTANT_LONG 299 '299'
+0x0004    | OP_DEFINE_GLOBAL_LONG 256 'g256'
+== constants ==
+   0 number   '0'
+"));
+        assert!(output.ends_with(" 299 number   '299'\n"));
+    }
+}
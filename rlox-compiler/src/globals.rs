@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+// global slots are addressed by the long opcodes' 24-bit operand at most
+const MAX_GLOBALS: usize = 1 << 24;
+
+// the names of the global variables, a global's slot is its index here. every chunk compiled against the same table
+// resolves a name to the same slot, so incremental compilation (e.g. the REPL) sees the globals defined before it
+#[derive(Debug, Default)]
+pub struct GlobalNames {
+    names: Vec<String>,
+    slots: HashMap<String, u32>,
+}
+
+impl GlobalNames {
+    pub fn new() -> GlobalNames {
+        GlobalNames::default()
+    }
+
+    pub fn len(&self) -> usize { self.names.len() }
+    pub fn is_empty(&self) -> bool { self.names.is_empty() }
+
+    // the slot for `name`, assigning the next free one the first time it is seen
+    pub fn resolve(&mut self, name: &str) -> Result<u32, String> {
+        if let Some(&slot) = self.slots.get(name) {
+            return Ok(slot);
+        }
+
+        if self.names.len() >= MAX_GLOBALS {
+            return Err(String::from("too many globals"));
+        }
+
+        let slot = self.names.len() as u32;
+        self.names.push(name.to_owned());
+        self.slots.insert(name.to_owned(), slot);
+
+        Ok(slot)
+    }
+
+    pub fn name(&self, slot: u32) -> Result<&str, String> {
+        match self.names.get(slot as usize) {
+            Some(name) => Ok(name),
+            None => Err(format!("invalid global slot {} of {}", slot, self.names.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut names = GlobalNames::new();
+
+        assert_eq!(names.resolve("a"), Ok(0));
+        assert_eq!(names.resolve("b"), Ok(1));
+        assert_eq!(names.resolve("a"), Ok(0));
+        assert_eq!(names.len(), 2);
+
+        assert_eq!(names.name(1), Ok("b"));
+        assert!(names.name(2).is_err());
+    }
+}
@@ -1,13 +1,24 @@
+mod asm;
 mod chunk;
 mod compiler;
 mod disasm;
+mod globals;
 mod op;
+mod serialize;
+mod stats;
+mod strings;
+#[cfg(test)]
+mod test_utils;
 mod value;
 mod vm;
 
-pub use chunk::Chunk;
+pub use asm::{ assemble, write_assembly, AsmError, AsmErrorDescription };
+pub use chunk::{ Chunk, FileTable, MergeError, PatchError };
 pub use compiler::{ Compiler, CompilerError };
-pub use disasm::disassemble_chunk;
+pub use disasm::{ disassemble_chunk, disassemble_range, disassemble_structured, disassemble_to_string, write_disassembly_json, Instruction, Operand };
+pub use globals::GlobalNames;
 pub use op::OpCode;
-pub use value::{ Object, Value };
-pub use vm::{ VM, VMError };
\ No newline at end of file
+pub use serialize::DeserializeError;
+pub use stats::ExecutionStats;
+pub use value::{ Object, UpvalueObject, Value };
+pub use vm::{ VM, VMError, DEFAULT_FRAMES_MAX, DEFAULT_STACK_MAX };
\ No newline at end of file
@@ -1,21 +1,158 @@
+use std::cell::RefCell;
+use std::ffi::OsStr;
 use std::io::Write;
+use std::path::Path;
 use std::rc::Rc;
-use rlox_scanner::{ Scanner, ScannerError, Token };
-use rlox_parser::{Parser, ParserError, StmtParser};
-use rlox_compiler::{Chunk, Compiler, CompilerError, VM, VMError, disassemble_chunk};
+use rlox_scanner::{ Scanner, ScannerError, SourceToken };
+use rlox_parser::{Parser, ParserError, Stmt, StmtParser};
+use rlox_compiler::{Chunk, Compiler, CompilerError, DeserializeError, GlobalNames, VM, VMError, disassemble_chunk, disassemble_structured, write_disassembly_json};
 
 #[derive(Debug)]
-enum ReplError {
+enum RloxError {
     Scanner(ScannerError),
     Parser(ParserError),
     Compiler(CompilerError),
+    Deserialize(DeserializeError),
     VM(VMError),
 }
 
+impl RloxError {
+    // mirrors the tree-walking rlox binary, anything stopping the script running is a data error
+    fn exit_code(&self) -> i32 {
+        match self {
+            RloxError::VM(_) => 70,
+
+            _ => 65,
+        }
+    }
+
+    // runtime errors show where in the bytecode they happened, parse errors where in the source
+    fn message(&self) -> String {
+        match self {
+            RloxError::Scanner(err) => format!("{:?}", err),
+            RloxError::Parser(err) => format!("[line {}] Error {}: {:?}", err.line, err.location, err.description),
+            RloxError::Compiler(err) => format!("{:?}", err),
+            RloxError::Deserialize(err) => format!("{:?}", err),
+            RloxError::VM(err) => err.render(),
+        }
+    }
+}
+
+const USAGE: &str = "\
+Usage: rlox-compiler                               start a REPL
+       rlox-compiler build <script.lox> [-o <out>]  compile to bytecode, <script>.loxc by default
+       rlox-compiler run [--stats] [--trace] <script>
+                                                    run a script or previously built bytecode, --stats prints
+                                                    counts of the instructions run to stderr afterwards and
+                                                    --trace prints each instruction to stderr as it runs
+       rlox-compiler disasm [--json] <script>       print a script's bytecode, as JSON for tools to read";
+
+const RUN_OPTIONS: [&str; 2] = ["--stats", "--trace"];
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    std::process::exit(match args.as_slice() {
+        [] => {
+            repl();
+            0
+        },
+        ["build", input] => exit_code(build(input, &Path::new(input).with_extension("loxc"))),
+        ["build", input, "-o", output] => exit_code(build(input, Path::new(output))),
+        ["run", options @ .., input] if options.iter().all(|option| RUN_OPTIONS.contains(option)) => {
+            exit_code(run_file(input, options.contains(&"--stats"), options.contains(&"--trace")))
+        },
+        ["disasm", input] => exit_code(disassemble_file(input, false)),
+        ["disasm", "--json", input] => exit_code(disassemble_file(input, true)),
+
+        _ => {
+            eprintln!("{}", USAGE);
+            64
+        },
+    })
+}
+
+fn exit_code(result: Result<(), i32>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(code) => code,
+    }
+}
+
+fn report(error: RloxError) -> i32 {
+    eprintln!("Error: {}", error.message());
+    error.exit_code()
+}
+
+fn build(input: &str, output: &Path) -> Result<(), i32> {
+    let source = std::fs::read_to_string(input)
+        .map_err(|e| { eprintln!("Failed to read source file: {:?}", e); 66 })?;
+    let chunk = compile_script(&source).map_err(report)?;
+
+    let mut bytes = Vec::new();
+    chunk.serialize(&mut bytes)
+        .map_err(|e| { eprintln!("Failed to serialize chunk: {:?}", e); 65 })?;
+    std::fs::write(output, bytes)
+        .map_err(|e| { eprintln!("Failed to write bytecode file: {:?}", e); 74 })?;
+
+    Ok(())
+}
+
+// `.loxc` files are loaded as bytecode, anything else is compiled in memory first
+fn load(input: &str) -> Result<Chunk, i32> {
+    if Path::new(input).extension() == Some(OsStr::new("loxc")) {
+        let bytes = std::fs::read(input)
+            .map_err(|e| { eprintln!("Failed to read bytecode file: {:?}", e); 66 })?;
+        Chunk::deserialize(&mut bytes.as_slice()).map_err(|e| report(RloxError::Deserialize(e)))
+    } else {
+        let source = std::fs::read_to_string(input)
+            .map_err(|e| { eprintln!("Failed to read source file: {:?}", e); 66 })?;
+        compile_script(&source).map_err(report)
+    }
+}
+
+fn run_file(input: &str, stats: bool, trace: bool) -> Result<(), i32> {
+    let chunk = load(input)?;
+
+    let mut vm = VM::new(Rc::new(chunk));
+    vm.set_stats(stats);
+    if trace {
+        vm.set_trace(Some(Box::new(std::io::stderr())));
+    }
+    let result = vm.run();
+
+    // what ran up to an error is still worth seeing
+    if stats {
+        eprint!("{}", vm.stats());
+    }
+
+    result.map_err(|e| report(RloxError::VM(e)))
+}
+
+// the JSON only covers the script's own instructions, functions declared in it show up as constants
+fn disassemble_file(input: &str, json: bool) -> Result<(), i32> {
+    let chunk = load(input)?;
+
+    let mut stdout = std::io::stdout();
+    let result = if json {
+        write_disassembly_json(&mut stdout, &disassemble_structured(&chunk))
+    } else {
+        disassemble_chunk(&mut stdout, &chunk)
+    };
+
+    result.map_err(|e| { eprintln!("Failed to write disassembly: {:?}", e); 74 })
+}
+
+fn repl() {
     let stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
 
+    // globals outlive the line defining them, so every line is compiled against the same names and run on the same VM
+    let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+    let mut vm = VM::with_global_names(Rc::clone(&global_names));
+    let mut disassemble = false;
+
     loop {
         print!("lox> ");
         stdout.flush().unwrap();
@@ -23,42 +160,171 @@ fn main() {
         let mut buffer = String::new();
         stdin.read_line(&mut buffer).unwrap();
 
-        match run(&buffer) {
-            Err(e) => eprintln!("{:?}", e),
+        // repl commands, anything else is lox
+        match buffer.trim() {
+            ":trace on" => {
+                vm.set_trace(Some(Box::new(std::io::stderr())));
+                continue;
+            },
+            ":trace off" => {
+                vm.set_trace(None);
+                continue;
+            },
+            ":disasm" => {
+                disassemble = !disassemble;
+                println!("disassembly {}", if disassemble { "on" } else { "off" });
+                continue;
+            },
+            _ => { },
+        }
+
+        match run(&buffer, &global_names, &mut vm, disassemble) {
+            Err(e) => eprintln!("{}", e.message()),
             _ => { }
         }
     }
 }
 
-fn run(source: &String) -> Result<(), ReplError> {
+fn run(source: &String, global_names: &Rc<RefCell<GlobalNames>>, vm: &mut VM, disassemble: bool) -> Result<(), RloxError> {
+    let chunk = compile(source, global_names)?;
+
+    if disassemble {
+        disassemble_chunk(&mut std::io::stdout(), &chunk).unwrap();
+    }
+
+    vm.interpret(Rc::new(chunk)).map_err(RloxError::VM)?;
+
+    Ok(())
+}
+
+fn scan(source: &str) -> Result<Vec<SourceToken>, RloxError> {
     let scanner = Scanner::new(source);
     let mut tokens = Vec::new();
     for result in scanner.tokens() {
-        let token = result.map_err(ReplError::Scanner)?;
-
-        match &token.token {
-            Token::NewLine | Token::Whitespace | Token::Comment => { }
+        let token = result.map_err(RloxError::Scanner)?;
 
-            _ => tokens.push(token),
+        if !token.token.is_trivia() {
+            tokens.push(token.to_owned());
         }
     }
 
-    let mut parser = Parser::new(tokens);
-    let mut parser = StmtParser::new(&mut parser);
-    let statements = parser.parse();
+    Ok(tokens)
+}
+
+fn compile_script(source: &str) -> Result<Chunk, RloxError> {
+    let mut statements = Vec::new();
+    let mut parser = Parser::new(scan(source)?);
+    for result in StmtParser::new(&mut parser).parse() {
+        statements.push(result.map_err(RloxError::Parser)?);
+    }
 
     let mut chunk = Chunk::new();
+    Compiler::new(&mut chunk).compile(statements).map_err(RloxError::Compiler)?;
+
+    Ok(chunk)
+}
+
+fn compile(source: &String, global_names: &Rc<RefCell<GlobalNames>>) -> Result<Chunk, RloxError> {
+    let tokens = scan(source)?;
+
+    let mut chunk = Chunk::with_global_names(Rc::clone(global_names));
     let mut compiler = Compiler::new(&mut chunk);
-    for result in statements {
-        let statement = result.map_err(ReplError::Parser)?;
 
-        compiler.compile(vec![statement]).map_err(ReplError::Compiler)?;
+    // a lone expression doesn't need its semicolon, anything else is parsed as statements
+    let mut parser = Parser::new(tokens);
+    let mut parser = StmtParser::new(&mut parser);
+    let statements = match parser.try_parse_expression() {
+        Some(expr) => vec![Stmt::Expression(expr.map_err(RloxError::Parser)?)],
+        None => parser.parse().into_iter().collect::<Result<_, _>>().map_err(RloxError::Parser)?,
+    };
+
+    // the value of a trailing expression statement is printed rather than needing a `print` statement
+    compiler.compile_repl(statements).map_err(RloxError::Compiler)?;
+
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_compiler::OpCode;
+    use rlox_interpreter::CapturedOutput;
+    use super::*;
+
+    fn compile(source: &str) -> Result<Chunk, RloxError> {
+        super::compile(&source.into(), &Rc::new(RefCell::new(GlobalNames::new())))
     }
 
-    disassemble_chunk(&mut std::io::stdout(), &chunk);
+    // the last op of the line itself, before the return ending the script
+    fn last_op(chunk: &Chunk) -> OpCode {
+        let mut offset = 0;
+        let mut ops = Vec::new();
+        while offset < chunk.len() {
+            let (op, next_offset) = chunk.decode(offset).expect("Failed to decode chunk");
+            ops.push(op);
+            offset = next_offset;
+        }
 
-    let mut vm = VM::new(Rc::new(chunk));
-    vm.run().map_err(ReplError::VM)?;
+        assert_eq!(ops.pop(), Some(OpCode::Return));
+        ops.pop().expect("Expected a non-empty line")
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_expression_is_printed() {
+        let chunk = compile("1 + 2").expect("Failed to compile expression");
+        assert!(matches!(last_op(&chunk), OpCode::Print));
+
+        let chunk = compile("\"a\" == \"b\"\n").expect("Failed to compile expression");
+        assert!(matches!(last_op(&chunk), OpCode::Print));
+    }
+
+    #[test]
+    fn test_statements_fall_back() {
+        let chunk = compile("var a = 1; print a;").expect("Failed to compile statements");
+        assert!(matches!(last_op(&chunk), OpCode::Print));
+
+        let chunk = compile("var a = 1;").expect("Failed to compile statement");
+        assert!(matches!(last_op(&chunk), OpCode::DefineGlobal(_)));
+
+        // only the final expression statement is echoed
+        let chunk = compile("1 + 2; var a = 1;").expect("Failed to compile statements");
+        assert!(matches!(last_op(&chunk), OpCode::DefineGlobal(_)));
+
+        match compile("var 1;") {
+            Err(RloxError::Parser(_)) => { },
+            result => panic!("Expected a parser error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_globals_persist_between_lines() {
+        let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+        let mut vm = VM::with_global_names(Rc::clone(&global_names));
+
+        run(&"var a = 1;".into(), &global_names, &mut vm, false).expect("Failed to define global");
+        run(&"a = a + 1;".into(), &global_names, &mut vm, false).expect("Expected the global to still be defined");
+
+        match run(&"b;".into(), &global_names, &mut vm, false) {
+            Err(RloxError::VM(err)) => assert_eq!(err.to_string(), "[line 1] Undefined variable 'b'."),
+            result => panic!("Expected an undefined variable error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_expression_results_are_echoed() {
+        let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+        let mut vm = VM::with_global_names(Rc::clone(&global_names));
+        let output = CapturedOutput::new();
+        vm.set_output(Box::new(output.clone()));
+
+        let mut echo = |line: &str| {
+            let before = output.contents().len();
+            run(&line.into(), &global_names, &mut vm, false).expect("Failed to run line");
+            output.contents()[before..].to_string()
+        };
+
+        assert_eq!(echo("1+2"), "3\n");
+        assert_eq!(echo("var a = 1;"), "");
+        assert_eq!(echo("a * 2;"), "2\n");
+        assert_eq!(echo("a = a + 1;"), "2\n");
+    }
+}
@@ -1,5 +1,8 @@
 use std::convert::TryInto;
 
+// bump whenever opcode values or operand layouts change, so bytecode built against another layout can be rejected
+pub const BYTECODE_VERSION: u8 = 6;
+
 pub const OP_CONSTANT: u8 = 0;
 pub const OP_TRUE: u8 = OP_CONSTANT + 1;
 pub const OP_FALSE: u8 = OP_TRUE + 1;
@@ -21,12 +24,50 @@ pub const OP_MULTIPLY: u8 = OP_SUBTRACT + 1;
 pub const OP_DIVIDE: u8 = OP_MULTIPLY + 1;
 pub const OP_NOT: u8 = OP_DIVIDE + 1;
 pub const OP_NEGATE: u8 = OP_NOT + 1;
+pub const OP_MODULO: u8 = OP_NEGATE + 1;
 
-pub const OP_PRINT: u8 = OP_NEGATE + 1;
+pub const OP_PRINT: u8 = OP_MODULO + 1;
 pub const OP_JUMP: u8 = OP_PRINT + 1;
 pub const OP_JUMP_IF_FALSE: u8 = OP_JUMP + 1;
 pub const OP_RETURN: u8 = OP_JUMP_IF_FALSE + 1;
+pub const OP_CALL: u8 = OP_RETURN + 1;
+
+pub const OP_CLOSURE: u8 = OP_CALL + 1;
+pub const OP_GET_UPVALUE: u8 = OP_CLOSURE + 1;
+pub const OP_SET_UPVALUE: u8 = OP_GET_UPVALUE + 1;
+pub const OP_CLOSE_UPVALUE: u8 = OP_SET_UPVALUE + 1;
+
+pub const OP_CLASS: u8 = OP_CLOSE_UPVALUE + 1;
+pub const OP_GET_PROPERTY: u8 = OP_CLASS + 1;
+pub const OP_SET_PROPERTY: u8 = OP_GET_PROPERTY + 1;
+pub const OP_METHOD: u8 = OP_SET_PROPERTY + 1;
+pub const OP_INVOKE: u8 = OP_METHOD + 1;
+pub const OP_INHERIT: u8 = OP_INVOKE + 1;
+pub const OP_GET_SUPER: u8 = OP_INHERIT + 1;
+pub const OP_SUPER_INVOKE: u8 = OP_GET_SUPER + 1;
+
+pub const OP_LOOP: u8 = OP_SUPER_INVOKE + 1;
+
+pub const OP_CONSTANT_LONG: u8 = OP_LOOP + 1;
+pub const OP_GET_GLOBAL_LONG: u8 = OP_CONSTANT_LONG + 1;
+pub const OP_DEFINE_GLOBAL_LONG: u8 = OP_GET_GLOBAL_LONG + 1;
+pub const OP_SET_GLOBAL_LONG: u8 = OP_DEFINE_GLOBAL_LONG + 1;
 
+pub const OP_GET_LOCAL_LONG: u8 = OP_SET_GLOBAL_LONG + 1;
+pub const OP_SET_LOCAL_LONG: u8 = OP_GET_LOCAL_LONG + 1;
+
+pub const OP_ARRAY: u8 = OP_SET_LOCAL_LONG + 1;
+
+pub const OP_CLOSURE_LONG: u8 = OP_ARRAY + 1;
+pub const OP_CLASS_LONG: u8 = OP_CLOSURE_LONG + 1;
+pub const OP_GET_PROPERTY_LONG: u8 = OP_CLASS_LONG + 1;
+pub const OP_SET_PROPERTY_LONG: u8 = OP_GET_PROPERTY_LONG + 1;
+pub const OP_METHOD_LONG: u8 = OP_SET_PROPERTY_LONG + 1;
+pub const OP_INVOKE_LONG: u8 = OP_METHOD_LONG + 1;
+pub const OP_GET_SUPER_LONG: u8 = OP_INVOKE_LONG + 1;
+pub const OP_SUPER_INVOKE_LONG: u8 = OP_GET_SUPER_LONG + 1;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum OpCode {
     Constant(u8),
     True,
@@ -36,6 +77,7 @@ pub enum OpCode {
 
     GetLocal(u8),
     SetLocal(u8),
+    // globals are addressed by their slot in the chunk's GlobalNames
     GetGlobal(u8),
     DefineGlobal(u8),
     SetGlobal(u8),
@@ -49,18 +91,63 @@ pub enum OpCode {
     Divide,
     Not,
     Negate,
+    Modulo,
 
     Print,
-    Jump(i16),
-    JumpIfFalse(i16),
+    // jump distances are relative to the end of the jump instruction, forwards for jumps and backwards for loops
+    Jump(u16),
+    JumpIfFalse(u16),
     Return,
+    Call(u8),
+
+    // function constant, then (is_local, index) for each upvalue the closure captures
+    Closure(u8, Vec<(bool, u8)>),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    CloseUpvalue,
+
+    Class(u8),
+    GetProperty(u8),
+    SetProperty(u8),
+    Method(u8),
+    // method name constant, then the argument count
+    Invoke(u8, u8),
+    Inherit,
+    GetSuper(u8),
+    // method name constant, then the argument count
+    SuperInvoke(u8, u8),
+
+    Loop(u16),
+
+    // 24-bit constant indices and global slots, for chunks with more than 256 of either
+    ConstantLong(u32),
+    GetGlobalLong(u32),
+    DefineGlobalLong(u32),
+    SetGlobalLong(u32),
+
+    // 16-bit stack slots, for functions with more than 256 locals
+    GetLocalLong(u16),
+    SetLocalLong(u16),
+
+    // pops this many elements, the first element deepest in the stack
+    Array(u8),
+
+    // 24-bit constant indices for the function and name operands above
+    ClosureLong(u32, Vec<(bool, u8)>),
+    ClassLong(u32),
+    GetPropertyLong(u32),
+    SetPropertyLong(u32),
+    MethodLong(u32),
+    InvokeLong(u32, u8),
+    GetSuperLong(u32),
+    SuperInvokeLong(u32, u8),
 
     Unknown(u8),
 }
 
 // length
 impl OpCode {
-    // todo self.Encode().len() ??
+    // must agree with encode(), test_byte_length_matches_encode checks every opcode
     pub fn byte_length(&self) -> usize {
         match self {
             OpCode::Constant(_) => 2,
@@ -84,17 +171,127 @@ impl OpCode {
             OpCode::Divide => 1,
             OpCode::Not => 1,
             OpCode::Negate => 1,
+            OpCode::Modulo => 1,
 
             OpCode::Print => 1,
             OpCode::Jump(_) => 3,
             OpCode::JumpIfFalse(_) => 3,
             OpCode::Return => 1,
+            OpCode::Call(_) => 2,
+
+            OpCode::Closure(_, upvalues) => 3 + 2 * upvalues.len(),
+            OpCode::GetUpvalue(_) => 2,
+            OpCode::SetUpvalue(_) => 2,
+            OpCode::CloseUpvalue => 1,
+
+            OpCode::Class(_) => 2,
+            OpCode::GetProperty(_) => 2,
+            OpCode::SetProperty(_) => 2,
+            OpCode::Method(_) => 2,
+            OpCode::Invoke(_, _) => 3,
+            OpCode::Inherit => 1,
+            OpCode::GetSuper(_) => 2,
+            OpCode::SuperInvoke(_, _) => 3,
+
+            OpCode::Loop(_) => 3,
+
+            OpCode::ConstantLong(_) => 4,
+            OpCode::GetGlobalLong(_) => 4,
+            OpCode::DefineGlobalLong(_) => 4,
+            OpCode::SetGlobalLong(_) => 4,
+
+            OpCode::GetLocalLong(_) => 3,
+            OpCode::SetLocalLong(_) => 3,
+
+            OpCode::Array(_) => 2,
+
+            OpCode::ClosureLong(_, upvalues) => 5 + 2 * upvalues.len(),
+            OpCode::ClassLong(_) => 4,
+            OpCode::GetPropertyLong(_) => 4,
+            OpCode::SetPropertyLong(_) => 4,
+            OpCode::MethodLong(_) => 4,
+            OpCode::InvokeLong(_, _) => 5,
+            OpCode::GetSuperLong(_) => 4,
+            OpCode::SuperInvokeLong(_, _) => 5,
 
             OpCode::Unknown(_) => 1,
         }
     }
 }
 
+// names, as shown in disassembly and execution stats
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Constant(_) => "OP_CONSTANT",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Nil => "OP_NIL",
+            OpCode::Pop => "OP_POP",
+
+            OpCode::GetLocal(_) => "OP_GET_LOCAL",
+            OpCode::SetLocal(_) => "OP_SET_LOCAL",
+            OpCode::GetGlobal(_) => "OP_GET_GLOBAL",
+            OpCode::DefineGlobal(_) => "OP_DEFINE_GLOBAL",
+            OpCode::SetGlobal(_) => "OP_SET_GLOBAL",
+
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::Less => "OP_LESS",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Modulo => "OP_MODULO",
+
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump(_) => "OP_JUMP",
+            OpCode::JumpIfFalse(_) => "OP_JUMP_IF_FALSE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Call(_) => "OP_CALL",
+
+            OpCode::Closure(_, _) => "OP_CLOSURE",
+            OpCode::GetUpvalue(_) => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue(_) => "OP_SET_UPVALUE",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+
+            OpCode::Class(_) => "OP_CLASS",
+            OpCode::GetProperty(_) => "OP_GET_PROPERTY",
+            OpCode::SetProperty(_) => "OP_SET_PROPERTY",
+            OpCode::Method(_) => "OP_METHOD",
+            OpCode::Invoke(_, _) => "OP_INVOKE",
+            OpCode::Inherit => "OP_INHERIT",
+            OpCode::GetSuper(_) => "OP_GET_SUPER",
+            OpCode::SuperInvoke(_, _) => "OP_SUPER_INVOKE",
+
+            OpCode::Loop(_) => "OP_LOOP",
+
+            OpCode::ConstantLong(_) => "OP_CONSTANT_LONG",
+            OpCode::GetGlobalLong(_) => "OP_GET_GLOBAL_LONG",
+            OpCode::DefineGlobalLong(_) => "OP_DEFINE_GLOBAL_LONG",
+            OpCode::SetGlobalLong(_) => "OP_SET_GLOBAL_LONG",
+
+            OpCode::GetLocalLong(_) => "OP_GET_LOCAL_LONG",
+            OpCode::SetLocalLong(_) => "OP_SET_LOCAL_LONG",
+
+            OpCode::Array(_) => "OP_ARRAY",
+
+            OpCode::ClosureLong(_, _) => "OP_CLOSURE_LONG",
+            OpCode::ClassLong(_) => "OP_CLASS_LONG",
+            OpCode::GetPropertyLong(_) => "OP_GET_PROPERTY_LONG",
+            OpCode::SetPropertyLong(_) => "OP_SET_PROPERTY_LONG",
+            OpCode::MethodLong(_) => "OP_METHOD_LONG",
+            OpCode::InvokeLong(_, _) => "OP_INVOKE_LONG",
+            OpCode::GetSuperLong(_) => "OP_GET_SUPER_LONG",
+            OpCode::SuperInvokeLong(_, _) => "OP_SUPER_INVOKE_LONG",
+
+            OpCode::Unknown(_) => "<unknown>",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum DecodeError {
     EOF,
@@ -112,13 +309,109 @@ macro_rules! constant_op {
         }
     };
 }
+fn closure_op(bytes: &[u8]) -> Result<(OpCode, usize), DecodeError> {
+    let (upvalues, length) = closure_upvalues(bytes, 2)?;
+
+    Ok((OpCode::Closure(bytes[1], upvalues), length))
+}
+fn closure_long_op(bytes: &[u8]) -> Result<(OpCode, usize), DecodeError> {
+    let (upvalues, length) = closure_upvalues(bytes, 4)?;
+
+    Ok((OpCode::ClosureLong(u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]), upvalues), length))
+}
+// the upvalue count is at `count_offset`, after the function constant, with the upvalues following it
+fn closure_upvalues(bytes: &[u8], count_offset: usize) -> Result<(Vec<(bool, u8)>, usize), DecodeError> {
+    if bytes.len() <= count_offset {
+        return Err(DecodeError::UnexpectedEOF(1, "Missing closure function or upvalue count".into()));
+    }
+
+    let count = bytes[count_offset] as usize;
+    let length = count_offset + 1 + 2 * count;
+    if bytes.len() < length {
+        return Err(DecodeError::UnexpectedEOF(count_offset + 1, "Missing closure upvalues".into()));
+    }
+
+    let upvalues = bytes[count_offset + 1..length].chunks(2).map(|upvalue| (upvalue[0] != 0, upvalue[1])).collect();
+
+    Ok((upvalues, length))
+}
+fn encode_upvalues(mut bytes: Vec<u8>, upvalues: &[(bool, u8)]) -> Vec<u8> {
+    bytes.push(upvalues.len() as u8);
+    for (is_local, upvalue_index) in upvalues {
+        bytes.push(*is_local as u8);
+        bytes.push(*upvalue_index);
+    }
+    bytes
+}
+
+macro_rules! constant_long_op {
+    ($type:path, $bytes:expr) => {
+        {
+            if $bytes.len() < 4 {
+                Err(DecodeError::UnexpectedEOF(1, "Missing long constant index".into()))
+            } else {
+                Ok(($type(u32::from_be_bytes([0, $bytes[1], $bytes[2], $bytes[3]])), 4))
+            }
+        }
+    };
+}
+fn encode_long(op: u8, index: u32) -> Vec<u8> {
+    let bytes = index.to_be_bytes();
+    vec![op, bytes[1], bytes[2], bytes[3]]
+}
+
+macro_rules! local_long_op {
+    ($type:path, $bytes:expr) => {
+        {
+            if $bytes.len() < 3 {
+                Err(DecodeError::UnexpectedEOF(1, "Missing long local slot".into()))
+            } else {
+                Ok(($type(u16::from_be_bytes([$bytes[1], $bytes[2]])), 3))
+            }
+        }
+    };
+}
+fn encode_local_long(op: u8, slot: u16) -> Vec<u8> {
+    let bytes = slot.to_be_bytes();
+    vec![op, bytes[0], bytes[1]]
+}
+
+macro_rules! invoke_op {
+    ($type:path, $bytes:expr) => {
+        {
+            if $bytes.len() < 3 {
+                Err(DecodeError::UnexpectedEOF(1, "Missing method name or argument count".into()))
+            } else {
+                Ok(($type($bytes[1], $bytes[2]), 3))
+            }
+        }
+    };
+}
+
+macro_rules! invoke_long_op {
+    ($type:path, $bytes:expr) => {
+        {
+            if $bytes.len() < 5 {
+                Err(DecodeError::UnexpectedEOF(1, "Missing long method name or argument count".into()))
+            } else {
+                Ok(($type(u32::from_be_bytes([0, $bytes[1], $bytes[2], $bytes[3]]), $bytes[4]), 5))
+            }
+        }
+    };
+}
+fn encode_invoke_long(op: u8, index: u32, arg_count: u8) -> Vec<u8> {
+    let mut bytes = encode_long(op, index);
+    bytes.push(arg_count);
+    bytes
+}
+
 macro_rules! jump_op {
     ($type:path, $bytes:ident) => {
         {
             if $bytes.len() < 3 {
                 Err(DecodeError::UnexpectedEOF(1, "Missing jump offset".into()))
             } else {
-                let offset = i16::from_be_bytes((&$bytes[1..3]).try_into().unwrap());
+                let offset = u16::from_be_bytes((&$bytes[1..3]).try_into().unwrap());
                 Ok(($type(offset), 3))
             }
         }
@@ -154,11 +447,48 @@ impl OpCode {
             OP_DIVIDE => Ok((OpCode::Divide, 1)),
             OP_NOT => Ok((OpCode::Not, 1)),
             OP_NEGATE => Ok((OpCode::Negate, 1)),
+            OP_MODULO => Ok((OpCode::Modulo, 1)),
 
             OP_PRINT => Ok((OpCode::Print, 1)),
             OP_JUMP => jump_op!(OpCode::Jump, bytes),
             OP_JUMP_IF_FALSE => jump_op!(OpCode::JumpIfFalse, bytes),
             OP_RETURN => Ok((OpCode::Return, 1)),
+            OP_CALL => constant_op!(OpCode::Call, bytes),
+
+            OP_CLOSURE => closure_op(bytes),
+            OP_GET_UPVALUE => constant_op!(OpCode::GetUpvalue, bytes),
+            OP_SET_UPVALUE => constant_op!(OpCode::SetUpvalue, bytes),
+            OP_CLOSE_UPVALUE => Ok((OpCode::CloseUpvalue, 1)),
+
+            OP_CLASS => constant_op!(OpCode::Class, bytes),
+            OP_GET_PROPERTY => constant_op!(OpCode::GetProperty, bytes),
+            OP_SET_PROPERTY => constant_op!(OpCode::SetProperty, bytes),
+            OP_METHOD => constant_op!(OpCode::Method, bytes),
+            OP_INVOKE => invoke_op!(OpCode::Invoke, bytes),
+            OP_INHERIT => Ok((OpCode::Inherit, 1)),
+            OP_GET_SUPER => constant_op!(OpCode::GetSuper, bytes),
+            OP_SUPER_INVOKE => invoke_op!(OpCode::SuperInvoke, bytes),
+
+            OP_LOOP => jump_op!(OpCode::Loop, bytes),
+
+            OP_CONSTANT_LONG => constant_long_op!(OpCode::ConstantLong, bytes),
+            OP_GET_GLOBAL_LONG => constant_long_op!(OpCode::GetGlobalLong, bytes),
+            OP_DEFINE_GLOBAL_LONG => constant_long_op!(OpCode::DefineGlobalLong, bytes),
+            OP_SET_GLOBAL_LONG => constant_long_op!(OpCode::SetGlobalLong, bytes),
+
+            OP_GET_LOCAL_LONG => local_long_op!(OpCode::GetLocalLong, bytes),
+            OP_SET_LOCAL_LONG => local_long_op!(OpCode::SetLocalLong, bytes),
+
+            OP_ARRAY => constant_op!(OpCode::Array, bytes),
+
+            OP_CLOSURE_LONG => closure_long_op(bytes),
+            OP_CLASS_LONG => constant_long_op!(OpCode::ClassLong, bytes),
+            OP_GET_PROPERTY_LONG => constant_long_op!(OpCode::GetPropertyLong, bytes),
+            OP_SET_PROPERTY_LONG => constant_long_op!(OpCode::SetPropertyLong, bytes),
+            OP_METHOD_LONG => constant_long_op!(OpCode::MethodLong, bytes),
+            OP_INVOKE_LONG => invoke_long_op!(OpCode::InvokeLong, bytes),
+            OP_GET_SUPER_LONG => constant_long_op!(OpCode::GetSuperLong, bytes),
+            OP_SUPER_INVOKE_LONG => invoke_long_op!(OpCode::SuperInvokeLong, bytes),
 
             _ => {
                 Ok((OpCode::Unknown(bytes[0]), 1))
@@ -189,13 +519,227 @@ impl OpCode {
             OpCode::Divide => vec![OP_DIVIDE],
             OpCode::Not => vec![OP_NOT],
             OpCode::Negate => vec![OP_NEGATE],
+            OpCode::Modulo => vec![OP_MODULO],
 
             OpCode::Print => vec![OP_PRINT],
             OpCode::Jump(offset) => { let mut b = vec![OP_JUMP]; b.extend_from_slice(&offset.to_be_bytes()[..]); b },
             OpCode::JumpIfFalse(offset) => { let mut b = vec![OP_JUMP_IF_FALSE]; b.extend_from_slice(&offset.to_be_bytes()[..]); b },
             OpCode::Return => vec![OP_RETURN],
+            OpCode::Call(arg_count) => vec![OP_CALL, *arg_count],
+
+            OpCode::Closure(index, upvalues) => encode_upvalues(vec![OP_CLOSURE, *index], upvalues),
+            OpCode::GetUpvalue(index) => vec![OP_GET_UPVALUE, *index],
+            OpCode::SetUpvalue(index) => vec![OP_SET_UPVALUE, *index],
+            OpCode::CloseUpvalue => vec![OP_CLOSE_UPVALUE],
+
+            OpCode::Class(index) => vec![OP_CLASS, *index],
+            OpCode::GetProperty(index) => vec![OP_GET_PROPERTY, *index],
+            OpCode::SetProperty(index) => vec![OP_SET_PROPERTY, *index],
+            OpCode::Method(index) => vec![OP_METHOD, *index],
+            OpCode::Invoke(index, arg_count) => vec![OP_INVOKE, *index, *arg_count],
+            OpCode::Inherit => vec![OP_INHERIT],
+            OpCode::GetSuper(index) => vec![OP_GET_SUPER, *index],
+            OpCode::SuperInvoke(index, arg_count) => vec![OP_SUPER_INVOKE, *index, *arg_count],
+
+            OpCode::Loop(offset) => { let mut b = vec![OP_LOOP]; b.extend_from_slice(&offset.to_be_bytes()[..]); b },
+
+            OpCode::ConstantLong(index) => encode_long(OP_CONSTANT_LONG, *index),
+            OpCode::GetGlobalLong(index) => encode_long(OP_GET_GLOBAL_LONG, *index),
+            OpCode::DefineGlobalLong(index) => encode_long(OP_DEFINE_GLOBAL_LONG, *index),
+            OpCode::SetGlobalLong(index) => encode_long(OP_SET_GLOBAL_LONG, *index),
+
+            OpCode::GetLocalLong(slot) => encode_local_long(OP_GET_LOCAL_LONG, *slot),
+            OpCode::SetLocalLong(slot) => encode_local_long(OP_SET_LOCAL_LONG, *slot),
+
+            OpCode::Array(count) => vec![OP_ARRAY, *count],
+
+            OpCode::ClosureLong(index, upvalues) => encode_upvalues(encode_long(OP_CLOSURE_LONG, *index), upvalues),
+            OpCode::ClassLong(index) => encode_long(OP_CLASS_LONG, *index),
+            OpCode::GetPropertyLong(index) => encode_long(OP_GET_PROPERTY_LONG, *index),
+            OpCode::SetPropertyLong(index) => encode_long(OP_SET_PROPERTY_LONG, *index),
+            OpCode::MethodLong(index) => encode_long(OP_METHOD_LONG, *index),
+            OpCode::InvokeLong(index, arg_count) => encode_invoke_long(OP_INVOKE_LONG, *index, *arg_count),
+            OpCode::GetSuperLong(index) => encode_long(OP_GET_SUPER_LONG, *index),
+            OpCode::SuperInvokeLong(index, arg_count) => encode_invoke_long(OP_SUPER_INVOKE_LONG, *index, *arg_count),
 
             OpCode::Unknown(val) => vec![*val],
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_opcodes() -> Vec<OpCode> {
+        let opcodes = vec![
+            OpCode::Constant(1), OpCode::True, OpCode::False, OpCode::Nil, OpCode::Pop,
+            OpCode::GetLocal(1), OpCode::SetLocal(1), OpCode::GetGlobal(1), OpCode::DefineGlobal(1), OpCode::SetGlobal(1),
+            OpCode::Equal, OpCode::Greater, OpCode::Less, OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide, OpCode::Not, OpCode::Negate, OpCode::Modulo,
+            OpCode::Print, OpCode::Jump(1), OpCode::JumpIfFalse(1), OpCode::Return, OpCode::Call(1),
+            OpCode::Closure(1, vec![]), OpCode::Closure(1, vec![(true, 1), (false, 2)]), OpCode::GetUpvalue(1), OpCode::SetUpvalue(1), OpCode::CloseUpvalue,
+            OpCode::Class(1), OpCode::GetProperty(1), OpCode::SetProperty(1), OpCode::Method(1), OpCode::Invoke(1, 2),
+            OpCode::Inherit, OpCode::GetSuper(1), OpCode::SuperInvoke(1, 2),
+            OpCode::Loop(1),
+            OpCode::ConstantLong(0x010203), OpCode::GetGlobalLong(256), OpCode::DefineGlobalLong(256), OpCode::SetGlobalLong(256),
+            OpCode::GetLocalLong(0x0102), OpCode::SetLocalLong(0x0102),
+            OpCode::Array(2),
+            OpCode::ClosureLong(0x010203, vec![]), OpCode::ClosureLong(0x010203, vec![(true, 1), (false, 2)]),
+            OpCode::ClassLong(256), OpCode::GetPropertyLong(256), OpCode::SetPropertyLong(256), OpCode::MethodLong(256),
+            OpCode::InvokeLong(256, 2), OpCode::GetSuperLong(256), OpCode::SuperInvokeLong(256, 2),
+            OpCode::Unknown(255),
+        ];
+
+        // fails to compile when a new opcode is added, as a reminder to add it to the list above
+        for op in &opcodes {
+            match op {
+                OpCode::Constant(_) | OpCode::True | OpCode::False | OpCode::Nil | OpCode::Pop |
+                OpCode::GetLocal(_) | OpCode::SetLocal(_) | OpCode::GetGlobal(_) | OpCode::DefineGlobal(_) | OpCode::SetGlobal(_) |
+                OpCode::Equal | OpCode::Greater | OpCode::Less | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide | OpCode::Not | OpCode::Negate | OpCode::Modulo |
+                OpCode::Print | OpCode::Jump(_) | OpCode::JumpIfFalse(_) | OpCode::Return | OpCode::Call(_) |
+                OpCode::Closure(_, _) | OpCode::GetUpvalue(_) | OpCode::SetUpvalue(_) | OpCode::CloseUpvalue |
+                OpCode::Class(_) | OpCode::GetProperty(_) | OpCode::SetProperty(_) | OpCode::Method(_) | OpCode::Invoke(_, _) |
+                OpCode::Inherit | OpCode::GetSuper(_) | OpCode::SuperInvoke(_, _) |
+                OpCode::Loop(_) |
+                OpCode::ConstantLong(_) | OpCode::GetGlobalLong(_) | OpCode::DefineGlobalLong(_) | OpCode::SetGlobalLong(_) |
+                OpCode::GetLocalLong(_) | OpCode::SetLocalLong(_) |
+                OpCode::Array(_) |
+                OpCode::ClosureLong(_, _) | OpCode::ClassLong(_) | OpCode::GetPropertyLong(_) | OpCode::SetPropertyLong(_) | OpCode::MethodLong(_) |
+                OpCode::InvokeLong(_, _) | OpCode::GetSuperLong(_) | OpCode::SuperInvokeLong(_, _) |
+                OpCode::Unknown(_) => { }
+            }
+        }
+
+        opcodes
+    }
+
+    // `op` with every operand replaced, 24-bit operands take the low bits of `long`
+    fn with_operands(op: &OpCode, short: u8, wide: u16, long: u32) -> OpCode {
+        let long = long & 0x00ff_ffff;
+
+        match op {
+            OpCode::Constant(_) => OpCode::Constant(short),
+            OpCode::GetLocal(_) => OpCode::GetLocal(short),
+            OpCode::SetLocal(_) => OpCode::SetLocal(short),
+            OpCode::GetGlobal(_) => OpCode::GetGlobal(short),
+            OpCode::DefineGlobal(_) => OpCode::DefineGlobal(short),
+            OpCode::SetGlobal(_) => OpCode::SetGlobal(short),
+            OpCode::Jump(_) => OpCode::Jump(wide),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(wide),
+            OpCode::Call(_) => OpCode::Call(short),
+            OpCode::Closure(_, upvalues) => OpCode::Closure(short, upvalues.iter().map(|&(is_local, _)| (is_local, short)).collect()),
+            OpCode::GetUpvalue(_) => OpCode::GetUpvalue(short),
+            OpCode::SetUpvalue(_) => OpCode::SetUpvalue(short),
+            OpCode::Class(_) => OpCode::Class(short),
+            OpCode::GetProperty(_) => OpCode::GetProperty(short),
+            OpCode::SetProperty(_) => OpCode::SetProperty(short),
+            OpCode::Method(_) => OpCode::Method(short),
+            OpCode::Invoke(_, _) => OpCode::Invoke(short, short),
+            OpCode::GetSuper(_) => OpCode::GetSuper(short),
+            OpCode::SuperInvoke(_, _) => OpCode::SuperInvoke(short, short),
+            OpCode::Loop(_) => OpCode::Loop(wide),
+            OpCode::ConstantLong(_) => OpCode::ConstantLong(long),
+            OpCode::GetGlobalLong(_) => OpCode::GetGlobalLong(long),
+            OpCode::DefineGlobalLong(_) => OpCode::DefineGlobalLong(long),
+            OpCode::SetGlobalLong(_) => OpCode::SetGlobalLong(long),
+            OpCode::GetLocalLong(_) => OpCode::GetLocalLong(wide),
+            OpCode::SetLocalLong(_) => OpCode::SetLocalLong(wide),
+            OpCode::Array(_) => OpCode::Array(short),
+            OpCode::ClosureLong(_, upvalues) => OpCode::ClosureLong(long, upvalues.iter().map(|&(is_local, _)| (is_local, short)).collect()),
+            OpCode::ClassLong(_) => OpCode::ClassLong(long),
+            OpCode::GetPropertyLong(_) => OpCode::GetPropertyLong(long),
+            OpCode::SetPropertyLong(_) => OpCode::SetPropertyLong(long),
+            OpCode::MethodLong(_) => OpCode::MethodLong(long),
+            OpCode::InvokeLong(_, _) => OpCode::InvokeLong(long, short),
+            OpCode::GetSuperLong(_) => OpCode::GetSuperLong(long),
+            OpCode::SuperInvokeLong(_, _) => OpCode::SuperInvokeLong(long, short),
+
+            OpCode::True | OpCode::False | OpCode::Nil | OpCode::Pop |
+            OpCode::Equal | OpCode::Greater | OpCode::Less | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide | OpCode::Not | OpCode::Negate | OpCode::Modulo |
+            OpCode::Print | OpCode::Return | OpCode::CloseUpvalue | OpCode::Inherit |
+            OpCode::Unknown(_) => op.clone(),
+        }
+    }
+
+    // every opcode with its smallest, largest and a spread of operands in between
+    fn all_opcodes_with_operands() -> Vec<OpCode> {
+        let operands = [(0, 0, 0), (1, 0x0102, 0x01_0203), (0x7f, 0x7fff, 0x7f_ffff), (0xa5, 0xa55a, 0xa5_5aa5), (u8::MAX, u16::MAX, u32::MAX)];
+
+        all_opcodes().iter()
+            .flat_map(|op| operands.iter().map(move |&(short, wide, long)| with_operands(op, short, wide, long)))
+            .collect()
+    }
+
+    // FNV-1a over every opcode encoded with a spread of operands, so any change to opcode values or operand layouts changes it
+    fn layout_checksum() -> u64 {
+        all_opcodes_with_operands().iter()
+            .flat_map(|op| op.encode())
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+    }
+
+    #[test]
+    fn test_opcode_layout() {
+        // a layout change means bumping BYTECODE_VERSION and updating the checksum together
+        assert_eq!((layout_checksum(), BYTECODE_VERSION), (0x2d63_4d94_14ca_c80e, 6));
+        assert_eq!(OP_NEGATE, 18);
+        assert_eq!(OP_MODULO, 19);
+        assert_eq!(OP_RETURN, 23);
+        assert_eq!(OP_LOOP, 37);
+        assert_eq!(OP_ARRAY, 44);
+    }
+
+    #[test]
+    fn test_constant_long_round_trip() {
+        let bytes = OpCode::ConstantLong(0x00ab_cdef).encode();
+        assert_eq!(bytes, vec![OP_CONSTANT_LONG, 0xab, 0xcd, 0xef]);
+
+        assert_eq!(OpCode::decode(&bytes).unwrap(), (OpCode::ConstantLong(0x00ab_cdef), 4));
+    }
+
+    #[test]
+    fn test_local_long_round_trip() {
+        let bytes = OpCode::GetLocalLong(0x012c).encode();
+        assert_eq!(bytes, vec![OP_GET_LOCAL_LONG, 0x01, 0x2c]);
+
+        assert_eq!(OpCode::decode(&bytes).unwrap(), (OpCode::GetLocalLong(300), 3));
+    }
+
+    #[test]
+    fn test_byte_length_matches_encode() {
+        for op in all_opcodes() {
+            let bytes = op.encode();
+            assert_eq!(op.byte_length(), bytes.len(), "byte_length disagrees with encode for opcode {}", bytes[0]);
+
+            let (_, decoded_length) = OpCode::decode(&bytes).unwrap();
+            assert_eq!(decoded_length, bytes.len(), "decode length disagrees with encode for opcode {}", bytes[0]);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for op in all_opcodes_with_operands() {
+            let bytes = op.encode();
+            assert_eq!(op.byte_length(), bytes.len(), "byte_length disagrees with encode for {:?}", op);
+            assert_eq!(OpCode::decode(&bytes).unwrap(), (op.clone(), op.byte_length()), "decoding {:?}", bytes);
+
+            // trailing bytes belong to the next instruction
+            let mut followed = bytes.clone();
+            followed.push(OP_RETURN);
+            assert_eq!(OpCode::decode(&followed).unwrap(), (op, bytes.len()), "decoding {:?}", followed);
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        for op in all_opcodes_with_operands() {
+            let bytes = op.encode();
+            for length in 1..bytes.len() {
+                match OpCode::decode(&bytes[..length]) {
+                    Err(DecodeError::UnexpectedEOF(_, _)) => { },
+                    result => panic!("Expected {:?} truncated to {} bytes to be UnexpectedEOF, got {:?}", op, length, result),
+                }
+            }
+        }
+
+        assert!(matches!(OpCode::decode(&[]), Err(DecodeError::EOF)));
+    }
+}
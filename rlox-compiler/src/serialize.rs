@@ -0,0 +1,709 @@
+use std::cell::RefCell;
+use std::io::{ self, Read, Write };
+use std::rc::Rc;
+use crate::op::BYTECODE_VERSION;
+use crate::{ Chunk, GlobalNames, Object, OpCode, Value };
+
+// a compiled script (.loxc), every integer is little-endian:
+//
+//   magic              4 bytes, "LOXC"
+//   format version     u8, FORMAT_VERSION, the layout of this file
+//   bytecode version   u8, BYTECODE_VERSION, the layout of the instructions in it
+//   global names       u32 count, then each name as a string, a global's slot is its position here
+//   chunk
+//
+// where a chunk is:
+//
+//   constants          u32 count, then each constant as a u8 tag followed by its value
+//                        0 nil, 1 boolean (u8), 2 number (f64 bits), 3 string,
+//                        4 function (name as a string, arity u8, then the function's own chunk)
+//   lines              u32 count, then each run as a u32 byte count and a u32 line
+//   code               u32 length, then the bytes
+//
+// strings are a u32 byte length then the UTF-8 bytes. function chunks share the script's global names, so only
+// the script writes them
+const MAGIC: &[u8; 4] = b"LOXC";
+// bump whenever the layout above changes
+const FORMAT_VERSION: u8 = 1;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+// each function's chunk is read recursively, stop corrupt input nesting them deep enough to overflow the stack
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+#[derive(Debug)]
+pub enum DeserializeError {
+    Io(io::Error),
+    Truncated,
+    InvalidMagic([u8; 4]),
+    UnsupportedVersion { format: u8, bytecode: u8 },
+    InvalidGlobalNames(String),
+    InvalidConstantTag(u8),
+    InvalidString(std::string::FromUtf8Error),
+    FunctionsTooDeep,
+    // the line runs must cover exactly the chunk's code
+    LineTableMismatch { lines: u64, code: usize },
+    InvalidInstruction { offset: usize, error: String },
+    InvalidConstantIndex { offset: usize, index: u32, count: usize },
+    InvalidGlobalSlot { offset: usize, slot: u32, count: usize },
+    // `count` is how many values the function has on the stack at that instruction
+    InvalidLocalSlot { offset: usize, slot: u16, count: usize },
+    // `count` is how many upvalues the closures of the function capture
+    InvalidUpvalue { offset: usize, index: u8, count: usize },
+    // OP_CLOSURE's constant has to be a function
+    InvalidClosure { offset: usize },
+    // an instruction pops more than the function has on the stack, or is reached with different stack heights
+    InvalidStackHeight { offset: usize },
+    InvalidJump { offset: usize },
+}
+
+impl DeserializeError {
+    // the instruction validation failed at, in whichever chunk it was validating
+    pub(crate) fn offset(&self) -> Option<usize> {
+        match self {
+            DeserializeError::InvalidInstruction { offset, .. } | DeserializeError::InvalidConstantIndex { offset, .. }
+            | DeserializeError::InvalidGlobalSlot { offset, .. } | DeserializeError::InvalidLocalSlot { offset, .. }
+            | DeserializeError::InvalidUpvalue { offset, .. } | DeserializeError::InvalidClosure { offset }
+            | DeserializeError::InvalidStackHeight { offset } | DeserializeError::InvalidJump { offset } => Some(*offset),
+
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DeserializeError {
+    fn from(error: io::Error) -> DeserializeError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => DeserializeError::Truncated,
+            _ => DeserializeError::Io(error),
+        }
+    }
+}
+
+impl Chunk {
+    // only the constants the compiler makes can be written, i.e. no closures, classes or instances
+    pub fn serialize(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[FORMAT_VERSION, BYTECODE_VERSION])?;
+
+        let global_names = self.global_names().borrow();
+        write_length(w, global_names.len())?;
+        for slot in 0..global_names.len() {
+            let name = global_names.name(slot as u32).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            write_string(w, name)?;
+        }
+
+        write_chunk(w, self)
+    }
+
+    pub fn deserialize(r: &mut dyn Read) -> Result<Chunk, DeserializeError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(DeserializeError::InvalidMagic(magic));
+        }
+
+        let format = read_u8(r)?;
+        let bytecode = read_u8(r)?;
+        if format != FORMAT_VERSION || bytecode != BYTECODE_VERSION {
+            return Err(DeserializeError::UnsupportedVersion { format, bytecode });
+        }
+
+        let mut global_names = GlobalNames::new();
+        for slot in 0..read_u32(r)? {
+            let name = read_string(r)?;
+            if global_names.resolve(&name).map_err(DeserializeError::InvalidGlobalNames)? != slot {
+                return Err(DeserializeError::InvalidGlobalNames(format!("duplicate global name '{}'", name)));
+            }
+        }
+
+        let chunk = read_chunk(r, &Rc::new(RefCell::new(global_names)), 0)?;
+        validate(&chunk)?;
+
+        Ok(chunk)
+    }
+}
+
+fn write_chunk(w: &mut dyn Write, chunk: &Chunk) -> io::Result<()> {
+    write_length(w, chunk.constants().len())?;
+    for constant in chunk.constants() {
+        write_constant(w, constant)?;
+    }
+
+    write_length(w, chunk.lines().len())?;
+    for &(count, line) in chunk.lines() {
+        write_length(w, count)?;
+        write_length(w, line)?;
+    }
+
+    write_length(w, chunk.len())?;
+    w.write_all(chunk.as_bytes().as_slice())
+}
+
+fn write_constant(w: &mut dyn Write, constant: &Value) -> io::Result<()> {
+    match constant {
+        Value::Nil => w.write_all(&[TAG_NIL]),
+        Value::Boolean(value) => w.write_all(&[TAG_BOOLEAN, *value as u8]),
+        Value::Number(value) => {
+            w.write_all(&[TAG_NUMBER])?;
+            w.write_all(&value.to_bits().to_le_bytes())
+        },
+        Value::Object(obj) => match obj.as_ref() {
+            Object::String(value) => {
+                w.write_all(&[TAG_STRING])?;
+                write_string(w, value)
+            },
+            Object::Function { name, arity, chunk } => {
+                w.write_all(&[TAG_FUNCTION])?;
+                write_string(w, name)?;
+                w.write_all(&[*arity])?;
+                write_chunk(w, chunk)
+            },
+
+            obj => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't serialize a {} constant", obj.type_name()))),
+        },
+    }
+}
+
+fn write_length(w: &mut dyn Write, length: usize) -> io::Result<()> {
+    if length > u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} doesn't fit in 32 bits", length)));
+    }
+
+    w.write_all(&(length as u32).to_le_bytes())
+}
+
+fn write_string(w: &mut dyn Write, value: &str) -> io::Result<()> {
+    write_length(w, value.len())?;
+    w.write_all(value.as_bytes())
+}
+
+fn read_chunk(r: &mut dyn Read, global_names: &Rc<RefCell<GlobalNames>>, depth: usize) -> Result<Chunk, DeserializeError> {
+    let mut constants = Vec::new();
+    for _ in 0..read_u32(r)? {
+        constants.push(read_constant(r, global_names, depth)?);
+    }
+
+    let mut lines = Vec::new();
+    for _ in 0..read_u32(r)? {
+        let count = read_u32(r)? as usize;
+        let line = read_u32(r)? as usize;
+        lines.push((count, line));
+    }
+
+    let code = read_bytes(r)?;
+
+    let covered = lines.iter().map(|&(count, _)| count as u64).sum();
+    if covered != code.len() as u64 {
+        return Err(DeserializeError::LineTableMismatch { lines: covered, code: code.len() });
+    }
+
+    Ok(Chunk::from_parts(code, lines, constants, Rc::clone(global_names)))
+}
+
+fn read_constant(r: &mut dyn Read, global_names: &Rc<RefCell<GlobalNames>>, depth: usize) -> Result<Value, DeserializeError> {
+    match read_u8(r)? {
+        TAG_NIL => Ok(Value::Nil),
+        TAG_BOOLEAN => Ok(Value::Boolean(read_u8(r)? != 0)),
+        TAG_NUMBER => {
+            let mut bytes = [0; 8];
+            r.read_exact(&mut bytes)?;
+            Ok(Value::Number(f64::from_bits(u64::from_le_bytes(bytes))))
+        },
+        TAG_STRING => Ok(Value::new_string(read_string(r)?)),
+        TAG_FUNCTION => {
+            if depth >= MAX_FUNCTION_DEPTH {
+                return Err(DeserializeError::FunctionsTooDeep);
+            }
+
+            let name = read_string(r)?;
+            let arity = read_u8(r)?;
+            let chunk = read_chunk(r, global_names, depth + 1)?;
+
+            Ok(Value::Object(Rc::new(Object::Function { name, arity, chunk: Rc::new(chunk) })))
+        },
+
+        tag => Err(DeserializeError::InvalidConstantTag(tag)),
+    }
+}
+
+// checks every instruction decodes and only references constants, globals, locals, upvalues and offsets that exist,
+// the script's frame starts with an empty stack and no upvalues
+pub(crate) fn validate(chunk: &Chunk) -> Result<(), DeserializeError> {
+    validate_function(chunk, 0, 0).map_err(|(error, _)| error)
+}
+
+// like `validate`, also returning the line of the failing instruction from whichever function's chunk it's in
+pub(crate) fn validate_with_line(chunk: &Chunk) -> Result<(), (DeserializeError, usize)> {
+    validate_function(chunk, 0, 0)
+}
+
+fn validate_function(chunk: &Chunk, slots: usize, upvalues: usize) -> Result<(), (DeserializeError, usize)> {
+    let captures = check_function(chunk, slots, upvalues)
+        .map_err(|error| {
+            let line = error.offset().map_or(0, |offset| chunk.line(offset));
+            (error, line)
+        })?;
+
+    for (constant, captured) in chunk.constants().iter().zip(captures) {
+        if let Value::Object(obj) = constant {
+            if let Object::Function { arity, chunk, .. } = obj.as_ref() {
+                validate_function(chunk, *arity as usize + 1, captured.unwrap_or(0))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// `slots` is how many values the function's frame starts with, the callee and its arguments, and `upvalues` how many
+// the closures over it capture. returns the fewest upvalues any OP_CLOSURE gives each constant
+fn check_function(chunk: &Chunk, slots: usize, upvalues: usize) -> Result<Vec<Option<usize>>, DeserializeError> {
+    let constant_count = chunk.constants().len();
+    let global_count = chunk.global_names().borrow().len();
+
+    let mut starts = vec![false; chunk.len()];
+    let mut jumps = Vec::new();
+    // the fewest upvalues any OP_CLOSURE gives each function constant, its code can't use more than that
+    let mut captures: Vec<Option<usize>> = vec![None; constant_count];
+
+    let mut offset = 0;
+    while offset < chunk.len() {
+        starts[offset] = true;
+        let (op, next_offset) = decode(chunk, offset)?;
+
+        let constant = match op {
+            OpCode::Constant(index) | OpCode::Closure(index, _) | OpCode::Class(index) | OpCode::GetProperty(index)
+            | OpCode::SetProperty(index) | OpCode::Method(index) | OpCode::Invoke(index, _) | OpCode::GetSuper(index)
+            | OpCode::SuperInvoke(index, _) => Some(index.into()),
+            OpCode::ConstantLong(index) | OpCode::ClosureLong(index, _) | OpCode::ClassLong(index) | OpCode::GetPropertyLong(index)
+            | OpCode::SetPropertyLong(index) | OpCode::MethodLong(index) | OpCode::InvokeLong(index, _) | OpCode::GetSuperLong(index)
+            | OpCode::SuperInvokeLong(index, _) => Some(index),
+
+            _ => None,
+        };
+        if let Some(index) = constant {
+            if index as usize >= constant_count {
+                return Err(DeserializeError::InvalidConstantIndex { offset, index, count: constant_count });
+            }
+        }
+
+        let global = match op {
+            OpCode::GetGlobal(slot) | OpCode::DefineGlobal(slot) | OpCode::SetGlobal(slot) => Some(slot.into()),
+            OpCode::GetGlobalLong(slot) | OpCode::DefineGlobalLong(slot) | OpCode::SetGlobalLong(slot) => Some(slot),
+
+            _ => None,
+        };
+        if let Some(slot) = global {
+            if slot as usize >= global_count {
+                return Err(DeserializeError::InvalidGlobalSlot { offset, slot, count: global_count });
+            }
+        }
+
+        let closure = match op {
+            OpCode::Closure(index, ref refs) => Some((index.into(), refs)),
+            OpCode::ClosureLong(index, ref refs) => Some((index, refs)),
+
+            _ => None,
+        };
+        if let Some((index, refs)) = closure {
+            match &chunk.constants()[index as usize] {
+                Value::Object(obj) if matches!(obj.as_ref(), Object::Function { .. }) => { },
+                _ => return Err(DeserializeError::InvalidClosure { offset }),
+            }
+
+            if let Some(&(_, index)) = refs.iter().find(|&&(is_local, index)| !is_local && index as usize >= upvalues) {
+                return Err(DeserializeError::InvalidUpvalue { offset, index, count: upvalues });
+            }
+
+            let captured = &mut captures[index as usize];
+            *captured = Some(captured.map_or(refs.len(), |captured| captured.min(refs.len())));
+        }
+
+        match op {
+            OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) if next_offset + distance as usize > chunk.len() => {
+                return Err(DeserializeError::InvalidJump { offset });
+            },
+            OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) => jumps.push((offset, next_offset + distance as usize)),
+            OpCode::Loop(distance) if distance as usize > next_offset => {
+                return Err(DeserializeError::InvalidJump { offset });
+            },
+            OpCode::Loop(distance) => jumps.push((offset, next_offset - distance as usize)),
+            OpCode::GetUpvalue(index) | OpCode::SetUpvalue(index) if index as usize >= upvalues => {
+                return Err(DeserializeError::InvalidUpvalue { offset, index, count: upvalues });
+            },
+            OpCode::Unknown(op) => {
+                return Err(DeserializeError::InvalidInstruction { offset, error: format!("unknown opcode {}", op) });
+            },
+
+            _ => { },
+        }
+
+        offset = next_offset;
+    }
+
+    // jumping to the very end is left for the VM to report, like running off it
+    for (offset, target) in jumps {
+        if target < chunk.len() && !starts[target] {
+            return Err(DeserializeError::InvalidJump { offset });
+        }
+    }
+
+    stack_heights(chunk, slots)?;
+
+    Ok(captures)
+}
+
+// follows every path through the code tracking how many values are on the stack, so locals are only read from slots
+// that exist. the compiler always leaves the same number of values on the stack whichever way an instruction is reached.
+// returns the height each instruction starts with, none for those no path reaches
+pub(crate) fn stack_heights(chunk: &Chunk, slots: usize) -> Result<Vec<Option<usize>>, DeserializeError> {
+    let mut heights = vec![None; chunk.len()];
+    let mut pending = vec![(0, slots)];
+
+    while let Some((offset, height)) = pending.pop() {
+        if offset >= chunk.len() {
+            continue;
+        }
+        match heights[offset] {
+            Some(seen) if seen == height => continue,
+            Some(_) => return Err(DeserializeError::InvalidStackHeight { offset }),
+            None => heights[offset] = Some(height),
+        }
+
+        let (op, next_offset) = decode(chunk, offset)?;
+
+        let local = match op {
+            OpCode::GetLocal(slot) | OpCode::SetLocal(slot) => Some(slot.into()),
+            OpCode::GetLocalLong(slot) | OpCode::SetLocalLong(slot) => Some(slot),
+            OpCode::Closure(_, ref refs) | OpCode::ClosureLong(_, ref refs) => refs.iter().filter(|&&(is_local, _)| is_local).map(|&(_, index)| index.into()).max(),
+
+            _ => None,
+        };
+        if let Some(slot) = local {
+            if slot as usize >= height {
+                return Err(DeserializeError::InvalidLocalSlot { offset, slot, count: height });
+            }
+        }
+
+        let (pops, pushes) = stack_effect(&op);
+        if pops > height {
+            return Err(DeserializeError::InvalidStackHeight { offset });
+        }
+        let height = height - pops + pushes;
+
+        match op {
+            OpCode::Jump(distance) => pending.push((next_offset + distance as usize, height)),
+            OpCode::JumpIfFalse(distance) => {
+                pending.push((next_offset + distance as usize, height));
+                pending.push((next_offset, height));
+            },
+            OpCode::Loop(distance) => pending.push((next_offset - distance as usize, height)),
+            OpCode::Return => { },
+
+            _ => pending.push((next_offset, height)),
+        }
+    }
+
+    Ok(heights)
+}
+
+// how many values `op` takes off the stack and how many it leaves in their place, whatever it reads has to be there
+fn stack_effect(op: &OpCode) -> (usize, usize) {
+    match *op {
+        OpCode::Constant(_) | OpCode::ConstantLong(_) | OpCode::True | OpCode::False | OpCode::Nil
+        | OpCode::GetLocal(_) | OpCode::GetLocalLong(_) | OpCode::GetGlobal(_) | OpCode::GetGlobalLong(_)
+        | OpCode::GetUpvalue(_) | OpCode::Closure(_, _) | OpCode::ClosureLong(_, _) | OpCode::Class(_) | OpCode::ClassLong(_) => (0, 1),
+        OpCode::Pop | OpCode::DefineGlobal(_) | OpCode::DefineGlobalLong(_) | OpCode::Print | OpCode::CloseUpvalue => (1, 0),
+        OpCode::SetLocal(_) | OpCode::SetLocalLong(_) | OpCode::SetGlobal(_) | OpCode::SetGlobalLong(_) | OpCode::SetUpvalue(_)
+        | OpCode::Not | OpCode::Negate | OpCode::GetProperty(_) | OpCode::GetPropertyLong(_) | OpCode::JumpIfFalse(_) => (1, 1),
+        OpCode::Equal | OpCode::Greater | OpCode::Less | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide
+        | OpCode::Modulo | OpCode::SetProperty(_) | OpCode::SetPropertyLong(_) | OpCode::Method(_) | OpCode::MethodLong(_)
+        | OpCode::Inherit | OpCode::GetSuper(_) | OpCode::GetSuperLong(_) => (2, 1),
+        // the callee, or the receiver for invokes, is replaced by the result
+        OpCode::Call(arg_count) | OpCode::Invoke(_, arg_count) | OpCode::InvokeLong(_, arg_count) => (arg_count as usize + 1, 1),
+        // the superclass is on top of the arguments
+        OpCode::SuperInvoke(_, arg_count) | OpCode::SuperInvokeLong(_, arg_count) => (arg_count as usize + 2, 1),
+        OpCode::Array(count) => (count as usize, 1),
+        OpCode::Jump(_) | OpCode::Loop(_) | OpCode::Return | OpCode::Unknown(_) => (0, 0),
+    }
+}
+
+fn decode(chunk: &Chunk, offset: usize) -> Result<(OpCode, usize), DeserializeError> {
+    chunk.decode(offset).map_err(|e| DeserializeError::InvalidInstruction { offset, error: format!("{:?}", e) })
+}
+
+fn read_u8(r: &mut dyn Read) -> Result<u8, DeserializeError> {
+    let mut bytes = [0; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(r: &mut dyn Read) -> Result<u32, DeserializeError> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// reads through `take` rather than allocating the length up front, a corrupt length fails as truncated instead
+fn read_bytes(r: &mut dyn Read) -> Result<Vec<u8>, DeserializeError> {
+    let length = read_u32(r)? as usize;
+
+    let mut bytes = Vec::new();
+    Read::take(&mut *r, length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Err(DeserializeError::Truncated);
+    }
+
+    Ok(bytes)
+}
+
+fn read_string(r: &mut dyn Read) -> Result<String, DeserializeError> {
+    String::from_utf8(read_bytes(r)?).map_err(DeserializeError::InvalidString)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(chunk: &Chunk) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        chunk.serialize(&mut bytes).expect("Failed to serialize chunk");
+        bytes
+    }
+
+    fn sample_chunk() -> Chunk {
+        let mut function_chunk = Chunk::new();
+        function_chunk.add(OpCode::Nil, 2);
+        function_chunk.add(OpCode::Return, 2);
+
+        let mut chunk = Chunk::with_global_names(Rc::clone(function_chunk.global_names()));
+        let slot = chunk.global_slot("f").unwrap() as u8;
+        let function = chunk.add_constant(Value::Object(Rc::new(Object::Function { name: "f".into(), arity: 0, chunk: Rc::new(function_chunk) }))).unwrap() as u8;
+        let string = chunk.add_constant(Value::new_string("hi".into())).unwrap() as u8;
+        chunk.add_constant(Value::Number(1.5)).unwrap();
+        chunk.add_constant(Value::Boolean(true)).unwrap();
+        chunk.add_constant(Value::Nil).unwrap();
+        chunk.add(OpCode::Closure(function, vec![]), 1);
+        chunk.add(OpCode::DefineGlobal(slot), 1);
+        chunk.add(OpCode::Constant(string), 3);
+        chunk.add(OpCode::Print, 3);
+        chunk.add(OpCode::Return, 3);
+
+        chunk
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = sample_chunk();
+        let bytes = serialize(&chunk);
+        assert_eq!(&bytes[..6], &[b'L', b'O', b'X', b'C', FORMAT_VERSION, BYTECODE_VERSION]);
+
+        let deserialized = Chunk::deserialize(&mut bytes.as_slice()).expect("Failed to deserialize chunk");
+
+        assert_eq!(deserialized.as_bytes().as_slice(), chunk.as_bytes().as_slice());
+        assert_eq!(deserialized.lines(), chunk.lines());
+        assert_eq!(deserialized.global_name(0).unwrap(), "f");
+        assert_eq!(deserialized.constants().len(), 5);
+        for (actual, expected) in deserialized.constants().iter().zip(chunk.constants()) {
+            assert_eq!(actual.to_string(), expected.to_string());
+        }
+
+        // the function's chunk resolves globals against the same names as the script
+        match deserialized.constants()[0] {
+            Value::Object(ref obj) => match obj.as_ref() {
+                Object::Function { chunk: function_chunk, .. } => assert!(Rc::ptr_eq(function_chunk.global_names(), deserialized.global_names())),
+                obj => panic!("Expected a function, got {:?}", obj),
+            },
+            ref value => panic!("Expected a function, got {:?}", value),
+        }
+
+        assert_eq!(serialize(&deserialized), bytes);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        match Chunk::deserialize(&mut &b"LOXB\x01\x04"[..]) {
+            Err(DeserializeError::InvalidMagic(magic)) => assert_eq!(&magic, b"LOXB"),
+            result => panic!("Expected InvalidMagic, got {:?}", result),
+        }
+
+        let mut bytes = serialize(&sample_chunk());
+        bytes[5] = BYTECODE_VERSION + 1;
+        match Chunk::deserialize(&mut bytes.as_slice()) {
+            Err(DeserializeError::UnsupportedVersion { format: FORMAT_VERSION, bytecode }) => assert_eq!(bytecode, BYTECODE_VERSION + 1),
+            result => panic!("Expected UnsupportedVersion, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = serialize(&sample_chunk());
+
+        for length in 0..bytes.len() {
+            match Chunk::deserialize(&mut &bytes[..length]) {
+                Err(DeserializeError::Truncated) => { },
+                result => panic!("Expected Truncated for {} of {} bytes, got {:?}", length, bytes.len(), result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupt_bytes_dont_panic() {
+        let bytes = serialize(&sample_chunk());
+
+        for offset in 0..bytes.len() {
+            for &corrupt in &[0x00, 0x7f, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[offset] = corrupt;
+                let _ = Chunk::deserialize(&mut corrupted.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_references() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Nil).unwrap();
+        chunk.add(OpCode::Constant(1), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidConstantIndex { offset: 0, index: 1, count: 1 }) => { },
+            result => panic!("Expected InvalidConstantIndex, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Nil, 1);
+        chunk.add(OpCode::GetGlobalLong(3), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidGlobalSlot { offset: 1, slot: 3, count: 0 }) => { },
+            result => panic!("Expected InvalidGlobalSlot, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Loop(4), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidJump { offset: 0 }) => { },
+            result => panic!("Expected InvalidJump, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Unknown(255), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidInstruction { offset: 0, .. }) => { },
+            result => panic!("Expected InvalidInstruction, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_invalid_locals_and_upvalues() {
+        // OP_SET_UPVALUE 0 in the script, which has no upvalues
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::SetUpvalue(0), 1);
+        chunk.add(OpCode::Return, 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidUpvalue { offset: 0, index: 0, count: 0 }) => { },
+            result => panic!("Expected InvalidUpvalue, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Nil, 1);
+        chunk.add(OpCode::GetLocalLong(1), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidLocalSlot { offset: 1, slot: 1, count: 1 }) => { },
+            result => panic!("Expected InvalidLocalSlot, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Pop, 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidStackHeight { offset: 0 }) => { },
+            result => panic!("Expected InvalidStackHeight, got {:?}", result),
+        }
+
+        // one path reaches the OP_RETURN with the condition still on the stack, the other without it
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::True, 1);
+        chunk.add(OpCode::JumpIfFalse(1), 1);
+        chunk.add(OpCode::Pop, 1);
+        chunk.add(OpCode::Return, 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidStackHeight { offset: 5 }) => { },
+            result => panic!("Expected InvalidStackHeight, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Nil).unwrap();
+        chunk.add(OpCode::Closure(0, vec![]), 1);
+        match Chunk::deserialize(&mut serialize(&chunk).as_slice()) {
+            Err(DeserializeError::InvalidClosure { offset: 0 }) => { },
+            result => panic!("Expected InvalidClosure, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_function_frames() {
+        // a function's frame starts with the callee and its arguments, and the upvalues its closure captures
+        fn script(function_code: &[OpCode], arity: u8, captures: Vec<(bool, u8)>) -> Result<Chunk, DeserializeError> {
+            let mut function_chunk = Chunk::new();
+            for op in function_code {
+                function_chunk.add(op.clone(), 1);
+            }
+            function_chunk.add(OpCode::Return, 1);
+
+            let mut chunk = Chunk::with_global_names(Rc::clone(function_chunk.global_names()));
+            let function = Object::Function { name: "f".into(), arity, chunk: Rc::new(function_chunk) };
+            chunk.add_constant(Value::Object(Rc::new(function))).unwrap();
+            chunk.add(OpCode::Nil, 1);
+            chunk.add(OpCode::Closure(0, captures), 1);
+            chunk.add(OpCode::Return, 1);
+
+            Chunk::deserialize(&mut serialize(&chunk).as_slice())
+        }
+
+        script(&[OpCode::GetLocal(2), OpCode::GetUpvalue(0)], 2, vec![(true, 0)]).expect("Failed to deserialize chunk");
+
+        match script(&[OpCode::GetLocal(3)], 2, vec![]) {
+            Err(DeserializeError::InvalidLocalSlot { offset: 0, slot: 3, count: 3 }) => { },
+            result => panic!("Expected InvalidLocalSlot, got {:?}", result.map(|_| ())),
+        }
+        match script(&[OpCode::GetUpvalue(1)], 0, vec![(true, 0)]) {
+            Err(DeserializeError::InvalidUpvalue { offset: 0, index: 1, count: 1 }) => { },
+            result => panic!("Expected InvalidUpvalue, got {:?}", result.map(|_| ())),
+        }
+        match script(&[], 0, vec![(true, 1)]) {
+            Err(DeserializeError::InvalidLocalSlot { offset: 1, slot: 1, count: 1 }) => { },
+            result => panic!("Expected InvalidLocalSlot, got {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_popped_captured_local() {
+        // passes validation but pops the captured local 1 without closing it, so OP_CLOSE_UPVALUE has no slot to close
+        let function_chunk = Chunk::new();
+        let mut chunk = Chunk::with_global_names(Rc::clone(function_chunk.global_names()));
+        let function = Object::Function { name: "f".into(), arity: 0, chunk: Rc::new(function_chunk) };
+        chunk.add_constant(Value::Object(Rc::new(function))).unwrap();
+        for op in [OpCode::Nil, OpCode::Nil, OpCode::Closure(0, vec![(true, 1)]), OpCode::Pop, OpCode::Pop, OpCode::CloseUpvalue, OpCode::Return] {
+            chunk.add(op, 1);
+        }
+
+        let chunk = Chunk::deserialize(&mut serialize(&chunk).as_slice()).expect("Failed to deserialize chunk");
+        match crate::VM::new(Rc::new(chunk)).run() {
+            Err(crate::VMError::StackTooSmall(2, 1)) => { },
+            result => panic!("Expected StackTooSmall, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unserializable_constant() {
+        let mut chunk = Chunk::new();
+        let class = Object::Class { name: "A".into(), methods: RefCell::new(Default::default()) };
+        chunk.add_constant(Value::Object(Rc::new(class))).unwrap();
+
+        assert_eq!(chunk.serialize(&mut Vec::new()).map_err(|e| e.kind()), Err(io::ErrorKind::InvalidInput));
+    }
+}
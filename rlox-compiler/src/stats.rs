@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::fmt::{ Display, Formatter };
+use std::rc::Rc;
+use crate::{ Chunk, OpCode };
+
+// a summary of what the VM ran while stats were enabled, counts are sorted most executed first
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutionStats {
+    pub instructions: u64,
+    pub max_stack_depth: usize,
+    pub opcodes: Vec<(&'static str, u64)>,
+    // instructions run in each function's own code, not counting the functions it calls
+    pub functions: Vec<(String, u64)>,
+}
+
+impl ExecutionStats {
+    pub fn opcode_count(&self, name: &str) -> u64 {
+        self.opcodes.iter().find(|(opcode, _)| *opcode == name).map_or(0, |&(_, count)| count)
+    }
+}
+
+impl Display for ExecutionStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "instructions     {:>12}", self.instructions)?;
+        writeln!(f, "max stack depth  {:>12}", self.max_stack_depth)?;
+
+        writeln!(f, "\n{:24} {:>12}", "opcode", "count")?;
+        for (name, count) in &self.opcodes {
+            writeln!(f, "{:24} {:>12}", name, count)?;
+        }
+
+        writeln!(f, "\n{:24} {:>12}", "function", "count")?;
+        for (name, count) in &self.functions {
+            writeln!(f, "{:24} {:>12}", name, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+// gathered by the VM as it runs. functions are keyed by their chunk's address, and each entry holds on to the chunk
+// so the address can't be freed and reused by another function while stats are being collected
+#[derive(Default)]
+pub(crate) struct StatsCollector {
+    instructions: u64,
+    max_stack_depth: usize,
+    opcodes: HashMap<&'static str, u64>,
+    functions: HashMap<*const Chunk, (Rc<Chunk>, String, u64)>,
+}
+
+impl StatsCollector {
+    // the script's chunk is never called so is named here, the first time it's seen
+    pub(crate) fn record(&mut self, op: &OpCode, chunk: &Rc<Chunk>, stack_depth: usize) {
+        self.instructions += 1;
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+        *self.opcodes.entry(op.name()).or_insert(0) += 1;
+        self.function(chunk, "<script>").2 += 1;
+    }
+
+    pub(crate) fn called(&mut self, chunk: &Rc<Chunk>, name: &str) {
+        self.function(chunk, name);
+    }
+
+    fn function(&mut self, chunk: &Rc<Chunk>, name: &str) -> &mut (Rc<Chunk>, String, u64) {
+        self.functions.entry(Rc::as_ptr(chunk)).or_insert_with(|| (chunk.clone(), name.to_owned(), 0))
+    }
+
+    pub(crate) fn summary(&self) -> ExecutionStats {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&name, &count)| (name, count)).collect();
+        opcodes.sort_by(|(a_name, a_count), (b_name, b_count)| b_count.cmp(a_count).then(a_name.cmp(b_name)));
+
+        let mut functions: Vec<_> = self.functions.values().filter(|(_, _, count)| *count > 0).map(|(_, name, count)| (name.clone(), *count)).collect();
+        functions.sort_by(|(a_name, a_count), (b_name, b_count)| b_count.cmp(a_count).then(a_name.cmp(b_name)));
+
+        ExecutionStats { instructions: self.instructions, max_stack_depth: self.max_stack_depth, opcodes, functions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_chunks_are_counted_separately() {
+        let mut stats = StatsCollector::default();
+
+        let first = Rc::new(Chunk::new());
+        stats.called(&first, "first");
+        stats.record(&OpCode::Nil, &first, 1);
+        drop(first);
+
+        // the collector keeps the first chunk alive, so the second can't be allocated at its address
+        let second = Rc::new(Chunk::new());
+        stats.called(&second, "second");
+        stats.record(&OpCode::Nil, &second, 1);
+        stats.record(&OpCode::Nil, &second, 1);
+
+        assert_eq!(stats.summary().functions, vec![(String::from("second"), 2), (String::from("first"), 1)]);
+    }
+}
@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{ Rc, Weak };
+use crate::Object;
+
+thread_local! {
+    // every string object made through `intern`, keyed by contents. objects can't leave the thread they were made on
+    // so one table per thread shares them with every chunk and VM. weak so strings nothing else holds can be freed
+    static STRINGS: RefCell<HashMap<String, Weak<Object>>> = RefCell::new(HashMap::new());
+}
+
+// the string object for `s`, the same object is returned for equal strings while any of them is still alive
+pub fn intern(s: String) -> Rc<Object> {
+    STRINGS.with(|strings| {
+        let mut strings = strings.borrow_mut();
+
+        if let Some(existing) = strings.get(&s).and_then(Weak::upgrade) {
+            return existing;
+        }
+
+        // about to grow, drop the entries for freed strings first in case that makes room
+        if strings.len() == strings.capacity() {
+            strings.retain(|_, string| string.strong_count() > 0);
+        }
+
+        let object = Rc::new(Object::String(s.clone()));
+        strings.insert(s, Rc::downgrade(&object));
+
+        object
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = intern("interned".into());
+        let b = intern(String::from("intern") + "ed");
+        let c = intern("other".into());
+
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+        assert_eq!(b.to_string(), "interned");
+    }
+
+    #[test]
+    fn test_freed_strings_are_recreated() {
+        drop(intern("short lived".into()));
+
+        // the table only held a weak reference, so this is a fresh object rather than a dangling one
+        let string = intern("short lived".into());
+        assert_eq!(string.to_string(), "short lived");
+        assert_eq!(Rc::strong_count(&string), 1);
+    }
+}
@@ -0,0 +1,13 @@
+use rlox_test_utils::parse;
+use crate::serialize::validate;
+use crate::{ Chunk, Compiler };
+
+// scans, parses and compiles the source into a fresh chunk, panicking on any error
+pub(crate) fn compile(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    Compiler::new(&mut chunk).compile(parse(source)).expect("Failed to compile source");
+    // anything the compiler makes has to pass the checks a loaded .loxc does
+    validate(&chunk).expect("Compiled chunk failed validation");
+
+    chunk
+}
@@ -1,5 +1,9 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Error};
 use std::rc::Rc;
+use crate::Chunk;
+use crate::strings::intern;
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -12,11 +16,26 @@ pub enum Value {
 #[derive(Debug)]
 pub enum Object {
     String(String),
+    Function { name: String, arity: u8, chunk: Rc<Chunk> },
+    Closure { function: Rc<Object>, upvalues: Vec<Rc<RefCell<UpvalueObject>>> },
+    Class { name: String, methods: RefCell<HashMap<String, Rc<Object>>> },
+    Instance { class: Rc<Object>, fields: RefCell<HashMap<String, Value>> },
+    // a method closure read off an instance, calling it puts `receiver` in slot 0 as `this`
+    BoundMethod { receiver: Value, method: Rc<Object> },
+    Array(RefCell<Vec<Value>>),
+}
+
+// a captured variable, open while it still lives in its stack slot and closed once that slot is popped
+#[derive(Debug)]
+pub enum UpvalueObject {
+    Open(usize),
+    Closed(Value),
 }
 
 impl Value {
+    // strings are interned, equal strings made here share one object
     pub fn new_string(s: String) -> Value {
-        Value::Object(Rc::new(Object::String(s)))
+        Value::Object(intern(s))
     }
 
     pub fn as_number(&self) -> Result<f64, ()> {
@@ -29,6 +48,29 @@ impl Value {
         }
     }
 
+    // borrows the contents of a string object, unlike `VM::as_string` which clones them
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Object(obj) => match obj.as_ref() {
+                Object::String(s) => Some(s),
+                _ => None,
+            },
+
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        use Value::*;
+
+        match self {
+            Nil => "nil",
+            Boolean(_) => "boolean",
+            Number(_) => "number",
+            Object(obj) => obj.type_name(),
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         use Value::*;
 
@@ -47,7 +89,7 @@ impl Value {
             (Nil, Nil) => true,
             (Boolean(left), Boolean(right)) => *left == *right,
             (Number(left), Number(right)) => *left == *right,
-            (Object(left), Object(right)) => left.is_equal(right),
+            (Object(left), Object(right)) => Rc::ptr_eq(left, right) || left.is_equal(right),
 
             _ => false
         }
@@ -55,11 +97,30 @@ impl Value {
 }
 
 impl Object {
+    pub fn type_name(&self) -> &'static str {
+        use Object::*;
+
+        match self {
+            String(_) => "string",
+            Function { .. } | Closure { .. } | BoundMethod { .. } => "function",
+            Class { .. } => "class",
+            Instance { .. } => "instance",
+            // named to match the tree-walking interpreter's lists
+            Array(_) => "list",
+        }
+    }
+
     pub fn is_equal(&self, other: &Object) -> bool {
         use Object::*;
 
         match (self, other) {
             (String(left), String(right)) => *left == *right,
+            (Function { .. }, Function { .. }) => std::ptr::eq(self, other),
+            (Closure { .. }, Closure { .. }) => std::ptr::eq(self, other),
+            (Class { .. }, Class { .. }) => std::ptr::eq(self, other),
+            (Instance { .. }, Instance { .. }) => std::ptr::eq(self, other),
+            (BoundMethod { .. }, BoundMethod { .. }) => std::ptr::eq(self, other),
+            (Array(_), Array(_)) => std::ptr::eq(self, other),
 
             _ => false,
         }
@@ -84,6 +145,35 @@ impl Display for Object {
 
         match self {
             String(val) => write!(f, "{}", val),
+            Function { name, .. } => write!(f, "<fn {}>", name),
+            Closure { function, .. } => write!(f, "{}", function),
+            Class { name, .. } => write!(f, "{}", name),
+            Instance { class, .. } => write!(f, "{} instance", class),
+            BoundMethod { method, .. } => write!(f, "{}", method),
+            Array(elements) => write_array(f, &elements.borrow(), 1),
+        }
+    }
+}
+
+// arrays nested deeper than this print as `...`, which also stops an array containing itself from recursing forever
+const MAX_ARRAY_DISPLAY_DEPTH: usize = 3;
+
+fn write_array(f: &mut Formatter<'_>, elements: &[Value], depth: usize) -> Result<(), Error> {
+    if depth > MAX_ARRAY_DISPLAY_DEPTH {
+        return f.write_str("...");
+    }
+
+    f.write_str("[")?;
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 { f.write_str(", ")?; }
+
+        match element {
+            Value::Object(obj) => match obj.as_ref() {
+                Object::Array(nested) => write_array(f, &nested.borrow(), depth + 1)?,
+                _ => write!(f, "{}", element)?,
+            },
+            _ => write!(f, "{}", element)?,
         }
     }
+    f.write_str("]")
 }
@@ -1,24 +1,69 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt::{ Display, Formatter };
+use std::io::Write;
 use std::rc::Rc;
-use crate::{Chunk, Object, OpCode, Value};
-use crate::disasm::disassemble_instruction;
+use crate::{Chunk, GlobalNames, Object, OpCode, UpvalueObject, Value};
+use crate::disasm::{ disassemble_instruction, format_instruction };
 use crate::op::DecodeError;
+use crate::stats::{ ExecutionStats, StatsCollector };
+
+// a runaway recursion or a missing pop in the compiled code fails with `VMError::StackOverflow` once it passes these
+pub const DEFAULT_STACK_MAX: usize = 1 << 16;
+pub const DEFAULT_FRAMES_MAX: usize = 1 << 10;
+// how many values from the top of the stack a runtime error keeps for `VMError::render`
+const ERROR_STACK_DEPTH: usize = 4;
 
 pub struct VM {
+    frames: Vec<CallFrame>,
+    frames_max: usize,
+
+    // values are held directly, only objects live behind an `Rc`
+    stack: Vec<Value>,
+    stack_max: usize,
+    // indexed by global slot, the names are only needed for error messages
+    globals: Vec<Option<Value>>,
+    global_names: Rc<RefCell<GlobalNames>>,
+    // upvalues still pointing into the stack, these need closing when their slot is popped
+    open_upvalues: Vec<Rc<RefCell<UpvalueObject>>>,
+
+    // where `print` writes to, stdout unless an embedder redirects it
+    output: Box<dyn Write>,
+
+    // instructions left to run before failing with `VMError::OutOfFuel`, unlimited when `None`
+    fuel: Option<u64>,
+    fuel_consumed: u64,
+
+    // only gathered when enabled, the cost otherwise is a branch per instruction
+    stats: Option<StatsCollector>,
+    // when set, the stack and each instruction are written here before it's run
+    tracer: Option<Box<dyn Write>>,
+}
+
+struct CallFrame {
     chunk: Rc<Chunk>,
     ip: usize,
-
-    stack: Vec<Rc<Value>>,
-    globals: HashMap<String, Rc<Value>>,
+    // index into the stack of the frame's first local, for functions this is the callee itself
+    slots: usize,
+    upvalues: Vec<Rc<RefCell<UpvalueObject>>>,
 }
 
 #[derive(Debug)]
 pub enum VMError {
     Decode(DecodeError),
     InvalidOpCode(u8),
-    InvalidConstant(u8, String),
+    InvalidConstant(u32, String),
+    InvalidGlobal(u32, String),
     StackTooSmall(usize, usize),
-    Runtime(usize, RuntimeError),
+    // `depth` is the number of values on the stack, or of call frames when a call went too deep
+    StackOverflow { depth: usize, line: usize },
+    // `ip` is the offset of the failing `instruction` in the chunk that was running, which for an error inside a
+    // function is that function's chunk, so both are captured when the error happens. `stack` is topmost value first.
+    // `file` is only known for code compiled through `Compiler::compile_multiple_files`
+    Runtime { file: Option<Rc<str>>, line: usize, ip: usize, instruction: String, stack: Vec<String>, error: RuntimeError },
+    Output(std::io::Error),
+    // `consumed` is the number of instructions run since the fuel was last set
+    OutOfFuel { consumed: u64 },
 }
 
 #[derive(Debug)]
@@ -27,172 +72,704 @@ pub enum RuntimeError {
     ExpectedString,
     ExpectedIdentifier,
     UndefinedGlobal(String),
-    UndefinedLocal(u8),
+    UndefinedLocal(u16),
+    UndefinedUpvalue(u8),
     InvalidAdditionArguments,
+    CalleeNotCallable,
+    UnexpectedNumberOfArguments { expected: u8, provided: u8 },
+    ExpectedInstance,
+    ExpectedClass,
+    UndefinedProperty(String),
+    DivideByZero,
+    InvalidComparisonArguments(&'static str, &'static str),
+}
+
+// worded to match clox's runtime errors
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::ExpectedNumber => write!(f, "Operands must be numbers."),
+            RuntimeError::ExpectedString => write!(f, "Operand must be a string."),
+            RuntimeError::ExpectedIdentifier => write!(f, "Expected an identifier constant."),
+            RuntimeError::UndefinedGlobal(name) => write!(f, "Undefined variable '{}'.", name),
+            RuntimeError::UndefinedLocal(slot) => write!(f, "Undefined local in slot {}.", slot),
+            RuntimeError::UndefinedUpvalue(index) => write!(f, "Undefined upvalue {}.", index),
+            RuntimeError::InvalidAdditionArguments => write!(f, "Operands must be two numbers or two strings."),
+            RuntimeError::CalleeNotCallable => write!(f, "Can only call functions and classes."),
+            RuntimeError::UnexpectedNumberOfArguments { expected, provided } => write!(f, "Expected {} arguments but got {}.", expected, provided),
+            RuntimeError::ExpectedInstance => write!(f, "Only instances have properties."),
+            RuntimeError::ExpectedClass => write!(f, "Superclass must be a class."),
+            RuntimeError::UndefinedProperty(name) => write!(f, "Undefined property '{}'.", name),
+            RuntimeError::DivideByZero => write!(f, "Division by zero."),
+            RuntimeError::InvalidComparisonArguments(left, right) => write!(f, "Operands must be two numbers or two strings, got {} and {}.", left, right),
+        }
+    }
+}
+
+impl Display for VMError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VMError::Decode(err) => write!(f, "Failed to decode instruction: {:?}.", err),
+            VMError::InvalidOpCode(opcode) => write!(f, "Invalid opcode {}.", opcode),
+            VMError::InvalidConstant(index, err) => write!(f, "Invalid constant {}: {}.", index, err),
+            VMError::InvalidGlobal(slot, err) => write!(f, "Invalid global {}: {}.", slot, err),
+            VMError::StackTooSmall(needed, len) => write!(f, "Expected {} values on the stack but there were {}.", needed, len),
+            VMError::StackOverflow { line, .. } => write!(f, "[line {}] Stack overflow.", line),
+            VMError::Runtime { file: Some(file), line, error, .. } => write!(f, "[line {} in '{}'] {}", line, file, error),
+            VMError::Runtime { file: None, line, error, .. } => write!(f, "[line {}] {}", line, error),
+            VMError::Output(err) => write!(f, "Failed to write output: {}.", err),
+            VMError::OutOfFuel { consumed } => write!(f, "Out of fuel after {} instructions.", consumed),
+        }
+    }
 }
 
-macro_rules! run_number_op {
+impl std::error::Error for VMError { }
+impl std::error::Error for RuntimeError { }
+
+impl VMError {
+    // the message plus, for runtime errors, the instruction that failed and what was on top of the stack
+    pub fn render(&self) -> String {
+        match self {
+            VMError::Runtime { ip, instruction, stack, .. } => {
+                let stack: String = stack.iter().map(|value| format!("[{}]", value)).collect();
+                format!("{}\n  at {:#06x} {}\n  stack (top first): {}", self, ip, instruction, stack)
+            },
+
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Pops numeric operands off the `$target` VM's stack, evaluates `$op` with them and pushes the result.
+///
+/// `$op` is any expression over the named operands, and the result is wrapped in `$result`
+/// (defaulting to `Value::Number`). `$idents` are bound in stack order: the first ident is the top
+/// of the stack (offset 0), so binary ops are written `right, left`.
+///
+/// Every operand is peeked and type checked before any are dropped, so on error the stack is left
+/// untouched. A stack with fewer than `$idents` values fails with `VMError::StackTooSmall`, and a
+/// non-numeric operand with `RuntimeError::ExpectedNumber`.
+macro_rules! pop_number_op {
     // entry cases
     ( $target:ident, $op:expr ; $($idents:ident),+ ) => {
-        run_number_op!($target, $op, Value::Number; $($idents),+ ; 0);
+        pop_number_op!($target, $op, Value::Number; $($idents),+ ; 0)
     };
     ( $target:ident, $op:expr, $result:path ; $($idents:ident),+ ) => {
-        run_number_op!($target, $op, $result; $($idents),+ ; 0);
+        pop_number_op!($target, $op, $result; $($idents),+ ; 0)
     };
 
     // base case
     ( $target:ident, $op:expr, $result:path ; ; $count:expr ) => {
         {
             $target.drop($count)?;
-            $target.push(Rc::new($result($op)));
+            $target.push($result($op))?;
         }
     };
 
     // final case (could this be removed?)
     ( $target:ident, $op:expr, $result:path ; $ident:ident ; $count:expr ) => {
         {
-            let $ident = $target.as_number($target.peek($count)?.as_ref())?;
-            run_number_op!($target, $op, $result ; ; $count + 1);
+            let $ident = $target.as_number($target.peek($count)?)?;
+            pop_number_op!($target, $op, $result ; ; $count + 1);
         }
     };
 
     // recursive case
     ( $target:ident, $op:expr, $result:path ; $ident:ident, $($idents:ident),* ; $count:expr ) => {
         {
-            let $ident = $target.as_number($target.peek($count)?.as_ref())?;
-            run_number_op!($target, $op, $result ; $($idents),* ; $count + 1);
+            let $ident = $target.as_number($target.peek($count)?)?;
+            pop_number_op!($target, $op, $result ; $($idents),* ; $count + 1);
         }
     };
 }
 
 impl VM {
     pub fn new(chunk: Rc<Chunk>) -> VM {
+        let mut vm = VM::with_global_names(Rc::clone(chunk.global_names()));
+        vm.frames.push(CallFrame { chunk, ip: 0, slots: 0, upvalues: Vec::new() });
+
+        vm
+    }
+    // a VM with nothing to run yet, for running several chunks compiled against `global_names` through `interpret`
+    pub fn with_global_names(global_names: Rc<RefCell<GlobalNames>>) -> VM {
         VM {
-            chunk,
-            ip: 0,
+            frames: Vec::new(),
+            frames_max: DEFAULT_FRAMES_MAX,
 
             stack: Vec::new(),
-            globals: HashMap::new(),
+            stack_max: DEFAULT_STACK_MAX,
+            globals: Vec::new(),
+            global_names,
+            open_upvalues: Vec::new(),
+
+            output: Box::new(std::io::stdout()),
+
+            fuel: None,
+            fuel_consumed: 0,
+
+            stats: None,
+            tracer: None,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), VMError> {
-        loop {
-            #[cfg(feature = "trace_execution")]
-            {
-                self.print_stack();
-                disassemble_instruction(&mut std::io::stderr(), &self.chunk, self.ip).unwrap();
-            }
+    pub fn set_limits(&mut self, stack_max: usize, frames_max: usize) {
+        self.stack_max = stack_max;
+        self.frames_max = frames_max;
+    }
+
+    // bounds how many instructions can run, across every later `run` and `interpret` until it's set again. every
+    // instruction costs one, including the backward jump closing each loop iteration
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+        self.fuel_consumed = 0;
+    }
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
 
-            let (op, mut next_ip) = self.chunk.decode(self.ip).map_err(VMError::Decode)?;
+    // turning stats on starts counting from zero, they're kept across `run` and `interpret` until turned off
+    pub fn set_stats(&mut self, enabled: bool) {
+        self.stats = if enabled { Some(StatsCollector::default()) } else { None };
+    }
+    // empty unless stats are enabled
+    pub fn stats(&self) -> ExecutionStats {
+        self.stats.as_ref().map(StatsCollector::summary).unwrap_or_default()
+    }
 
-            match op {
-                OpCode::Constant(index) => {
-                    let value = self.chunk.constant(index).map_err(|e| VMError::InvalidConstant(index, e))?;
-                    self.push(value);
-                },
-                OpCode::True => self.push(Rc::new(Value::Boolean(true))),
-                OpCode::False => self.push(Rc::new(Value::Boolean(false))),
-                OpCode::Nil => self.push(Rc::new(Value::Nil)),
-                OpCode::Pop => { self.pop()?; },
+    pub fn set_trace(&mut self, tracer: Option<Box<dyn Write>>) {
+        self.tracer = tracer;
+    }
 
-                OpCode::GetLocal(index) => {
-                    let value = self.stack.get(index as usize).map(Rc::clone);
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
 
-                    match value {
-                        Some(value) => self.push(value),
-                        None => return Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::UndefinedLocal(index))),
-                    }
-                },
-                OpCode::SetLocal(index) => {
-                    let value = self.peek(0)?;
+    // a copy of the value stack, bottom first. a failed script's temporaries are kept until the next `interpret`
+    pub fn take_stack_snapshot(&self) -> Vec<Value> {
+        self.stack.clone()
+    }
 
-                    std::mem::replace(&mut self.stack[index as usize], value);
-                },
-                OpCode::GetGlobal(index) => {
-                    let ident = self.as_identifier(self.chunk.constant(index).map_err(|e| VMError::InvalidConstant(index, e))?.as_ref())?;
-                    let value = self.globals.get(&ident).map(Rc::clone);
+    // runs another script, keeping the globals defined by the ones before it
+    pub fn interpret(&mut self, chunk: Rc<Chunk>) -> Result<(), VMError> {
+        debug_assert!(Rc::ptr_eq(chunk.global_names(), &self.global_names), "chunk was compiled against different global names");
 
-                    match value {
-                        Some(value) => self.push(value),
-                        None => return Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::UndefinedGlobal(ident))),
-                    }
-                }
-                OpCode::DefineGlobal(index) => {
-                    let ident = self.as_identifier(self.chunk.constant(index).map_err(|e| VMError::InvalidConstant(index, e))?.as_ref())?;
-                    let value = self.peek(0)?;
+        // an earlier script failing part way through leaves its frames and temporaries behind
+        self.frames.clear();
+        self.stack.clear();
+        self.open_upvalues.clear();
 
-                    self.globals.insert(ident, value);
-                    self.drop(1)?;
-                }
-                OpCode::SetGlobal(index) => {
-                    let ident = self.as_identifier(self.chunk.constant(index).map_err(|e| VMError::InvalidConstant(index, e))?.as_ref())?;
-                    let value = self.peek(0)?;
+        self.frames.push(CallFrame { chunk, ip: 0, slots: 0, upvalues: Vec::new() });
+        self.run()
+    }
 
-                    if !self.globals.contains_key(&ident) {
-                        return Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::UndefinedGlobal(ident)));
-                    }
+    // dispatch is a plain `match` on the decoded op. a table of per-opcode handler fns indexed by the opcode byte was
+    // tried and measured 10-20% slower across examples/bench.rs, the indirect calls can't be inlined and still need
+    // the op decoded first, so the match stays
+    pub fn run(&mut self) -> Result<(), VMError> {
+        loop {
+            if self.tracer.is_some() {
+                self.trace().map_err(VMError::Output)?;
+            }
 
-                    self.globals.insert(ident, value);
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    return Err(VMError::OutOfFuel { consumed: self.fuel_consumed });
                 }
 
+                *fuel -= 1;
+                self.fuel_consumed += 1;
+            }
+
+            let ip = self.frame().ip;
+            let (op, mut next_ip) = self.chunk().decode(ip).map_err(VMError::Decode)?;
+
+            if let Some(stats) = &mut self.stats {
+                let frame = self.frames.last().expect("VM has no call frames");
+                stats.record(&op, &frame.chunk, self.stack.len());
+            }
+
+            match op {
+                OpCode::Constant(index) => {
+                    let value = self.constant(index.into())?.clone();
+                    self.push(value)?;
+                },
+                OpCode::ConstantLong(index) => {
+                    let value = self.constant(index)?.clone();
+                    self.push(value)?;
+                },
+                OpCode::True => self.push(Value::Boolean(true))?,
+                OpCode::False => self.push(Value::Boolean(false))?,
+                OpCode::Nil => self.push(Value::Nil)?,
+                OpCode::Pop => { self.pop()?; },
+
+                OpCode::GetLocal(index) => self.get_local(index.into())?,
+                OpCode::GetLocalLong(index) => self.get_local(index)?,
+                OpCode::SetLocal(index) => self.set_local(index.into())?,
+                OpCode::SetLocalLong(index) => self.set_local(index)?,
+                OpCode::GetGlobal(index) => self.get_global(index.into())?,
+                OpCode::GetGlobalLong(index) => self.get_global(index)?,
+                OpCode::DefineGlobal(index) => self.define_global(index.into())?,
+                OpCode::DefineGlobalLong(index) => self.define_global(index)?,
+                OpCode::SetGlobal(index) => self.set_global(index.into())?,
+                OpCode::SetGlobalLong(index) => self.set_global(index)?,
+
                 OpCode::Equal => {
                     let right = self.pop()?;
                     let left = self.pop()?;
 
-                    let value = Value::Boolean(left.is_equal(right.as_ref()));
+                    let value = Value::Boolean(left.is_equal(&right));
 
-                    self.push(Rc::new(value));
+                    self.push(value)?;
                 },
-                OpCode::Greater => run_number_op!(self, left > right, Value::Boolean ; right, left),
-                OpCode::Less => run_number_op!(self, left < right, Value::Boolean ; right, left),
+                OpCode::Greater => self.compare(|left, right| left > right, |left, right| left > right)?,
+                OpCode::Less => self.compare(|left, right| left < right, |left, right| left < right)?,
                 OpCode::Add => {
                     let right = self.peek(0)?;
                     let left = self.peek(1)?;
 
-                    let result = if let (Value::Number(left), Value::Number(right)) = (left.as_ref(), right.as_ref()) {
+                    let result = if let (Value::Number(left), Value::Number(right)) = (left, right) {
                         Value::Number(left + right)
-                    } else if let Ok(left) = self.as_string(left.as_ref()) {
+                    } else if let Ok(left) = self.as_string(left) {
                         Value::new_string(left.to_owned() + &right.to_string())
-                    } else if let Ok(right) = self.as_string(right.as_ref()) {
+                    } else if let Ok(right) = self.as_string(right) {
                         Value::new_string(left.to_string() + &right)
                     } else {
-                        return Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::InvalidAdditionArguments))
+                        return Err(self.runtime_error(RuntimeError::InvalidAdditionArguments))
                     };
 
                     self.drop(2)?;
-                    self.push(Rc::new(result));
+                    self.push(result)?;
+                },
+                OpCode::Subtract => pop_number_op!(self, left - right ; right, left),
+                OpCode::Multiply => pop_number_op!(self, left * right ; right, left),
+                OpCode::Divide => {
+                    self.check_divisor()?;
+                    pop_number_op!(self, left / right ; right, left)
                 },
-                OpCode::Subtract => run_number_op!(self, left - right ; right, left),
-                OpCode::Multiply => run_number_op!(self, left * right ; right, left),
-                OpCode::Divide => run_number_op!(self, left / right ; right, left),
                 OpCode::Not => {
                     let value = self.pop()?;
-                    let new_value = Value::Boolean(!self.is_truthy(value.as_ref()));
-                    self.push(Rc::new(new_value))
+                    let new_value = Value::Boolean(!self.is_truthy(&value));
+                    self.push(new_value)?
+                },
+                OpCode::Negate => pop_number_op!(self, -value ; value),
+                OpCode::Modulo => {
+                    self.check_divisor()?;
+                    pop_number_op!(self, left % right ; right, left)
                 },
-                OpCode::Negate => run_number_op!(self, -value ; value),
 
                 OpCode::Print => {
-                    println!("{}", self.pop()?);
+                    let value = self.pop()?;
+                    writeln!(self.output, "{}", value).map_err(VMError::Output)?;
                 },
                 OpCode::Jump(offset) => {
-                    next_ip = self.calculate_jump_target(self.ip, offset);
+                    next_ip += offset as usize;
                 },
                 OpCode::JumpIfFalse(offset) => {
-                    if !self.is_truthy(self.peek(0)?.as_ref()) {
-                        next_ip = self.calculate_jump_target(self.ip, offset);
+                    if !self.is_truthy(self.peek(0)?) {
+                        next_ip += offset as usize;
+                    }
+                },
+                OpCode::Loop(offset) => {
+                    next_ip -= offset as usize;
+                },
+                OpCode::Call(arg_count) => {
+                    self.call(arg_count, next_ip)?;
+
+                    continue;
+                },
+                OpCode::Closure(index, upvalue_refs) => self.closure(index.into(), upvalue_refs)?,
+                OpCode::ClosureLong(index, upvalue_refs) => self.closure(index, upvalue_refs)?,
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.upvalue(index)?;
+                    let value = match &*upvalue.borrow() {
+                        UpvalueObject::Open(slot) => self.stack.get(*slot).cloned(),
+                        UpvalueObject::Closed(value) => Some(value.clone()),
+                    };
+
+                    match value {
+                        Some(value) => self.push(value)?,
+                        None => return Err(self.runtime_error(RuntimeError::UndefinedUpvalue(index))),
                     }
                 },
+                OpCode::SetUpvalue(index) => {
+                    let value = self.peek(0)?.clone();
+                    let upvalue = self.upvalue(index)?;
+
+                    let mut upvalue = upvalue.borrow_mut();
+                    let target = match &mut *upvalue {
+                        UpvalueObject::Open(slot) => self.stack.get_mut(*slot),
+                        UpvalueObject::Closed(closed) => Some(closed),
+                    };
+
+                    match target {
+                        Some(target) => *target = value,
+                        None => return Err(self.runtime_error(RuntimeError::UndefinedUpvalue(index))),
+                    }
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1)?;
+                    self.pop()?;
+                },
+                OpCode::Class(index) => self.class(index.into())?,
+                OpCode::ClassLong(index) => self.class(index)?,
+                OpCode::GetProperty(index) => self.get_property(index.into())?,
+                OpCode::GetPropertyLong(index) => self.get_property(index)?,
+                OpCode::SetProperty(index) => self.set_property(index.into())?,
+                OpCode::SetPropertyLong(index) => self.set_property(index)?,
+                OpCode::Method(index) => self.method(index.into())?,
+                OpCode::MethodLong(index) => self.method(index)?,
+                OpCode::Invoke(index, arg_count) => {
+                    self.invoke_constant(index.into(), arg_count, next_ip)?;
+
+                    continue;
+                },
+                OpCode::InvokeLong(index, arg_count) => {
+                    self.invoke_constant(index, arg_count, next_ip)?;
+
+                    continue;
+                },
+                OpCode::Inherit => {
+                    let superclass = self.peek(1)?;
+                    let subclass = self.peek(0)?;
+
+                    // methods are copied down when the class is declared, so lookups never walk the hierarchy
+                    let inherited = self.as_class_methods(superclass)?.borrow().clone();
+                    self.as_class_methods(subclass)?.borrow_mut().extend(inherited);
+
+                    self.drop(1)?;
+                },
+                OpCode::GetSuper(index) => self.get_super(index.into())?,
+                OpCode::GetSuperLong(index) => self.get_super(index)?,
+                OpCode::SuperInvoke(index, arg_count) => {
+                    self.super_invoke(index.into(), arg_count, next_ip)?;
+
+                    continue;
+                },
+                OpCode::SuperInvokeLong(index, arg_count) => {
+                    self.super_invoke(index, arg_count, next_ip)?;
+
+                    continue;
+                },
                 OpCode::Return => {
-                    return Ok(())
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots)?;
+
+                    // the script has no caller to return a value to
+                    if self.frames.is_empty() {
+                        // every statement pops what it pushes, only a top level `return` leaves a value behind
+                        debug_assert!(self.stack.len() <= 1, "script finished with {} values on the stack", self.stack.len());
+
+                        self.stack.clear();
+                        return Ok(());
+                    }
+
+                    let result = self.pop()?;
+                    self.stack.truncate(frame.slots);
+                    self.push(result)?;
+
+                    continue;
+                },
+
+                OpCode::Array(count) => {
+                    let count = count as usize;
+                    if count > self.stack.len() {
+                        return Err(VMError::StackTooSmall(count, self.stack.len()));
+                    }
+
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.push(Value::Object(Rc::new(Object::Array(RefCell::new(elements)))))?;
                 },
 
                 // TODO return error
                 OpCode::Unknown(val) => return Err(VMError::InvalidOpCode(val)),
             }
 
-            self.ip = next_ip
+            self.frame_mut().ip = next_ip
+        }
+    }
+
+    // `return_ip` is where the caller resumes once the callee returns
+    fn call(&mut self, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let callee = self.peek(arg_count as usize)?.clone();
+
+        match &callee {
+            Value::Object(obj) => match obj.as_ref() {
+                Object::Class { .. } => self.instantiate(Rc::clone(obj), arg_count, return_ip),
+                Object::BoundMethod { receiver, method } => {
+                    // the receiver takes the callee's slot, becoming `this` in the method
+                    let slot = self.stack.len() - arg_count as usize - 1;
+                    self.stack[slot] = receiver.clone();
+
+                    self.call_function(method, arg_count, return_ip)
+                },
+                function => self.call_function(function, arg_count, return_ip),
+            },
+
+            _ => Err(self.runtime_error(RuntimeError::CalleeNotCallable)),
+        }
+    }
+    // calls `function` with the callee slot and arguments already on the stack
+    fn call_function(&mut self, function: &Object, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let (function, upvalues) = match function {
+            Object::Closure { function, upvalues } => (function.as_ref(), upvalues.clone()),
+            function @ Object::Function { .. } => (function, Vec::new()),
+
+            _ => return Err(self.runtime_error(RuntimeError::CalleeNotCallable)),
+        };
+        let (name, arity, chunk) = match function {
+            Object::Function { name, arity, chunk } => (name, *arity, Rc::clone(chunk)),
+
+            _ => return Err(self.runtime_error(RuntimeError::CalleeNotCallable)),
+        };
+
+        if arity != arg_count {
+            return Err(self.runtime_error(RuntimeError::UnexpectedNumberOfArguments { expected: arity, provided: arg_count }));
+        }
+
+        if self.frames.len() >= self.frames_max {
+            return Err(VMError::StackOverflow { depth: self.frames.len(), line: self.line() });
+        }
+
+        self.frame_mut().ip = return_ip;
+
+        if let Some(stats) = &mut self.stats {
+            stats.called(&chunk, name);
+        }
+
+        let slots = self.stack.len() - arg_count as usize - 1;
+        self.frames.push(CallFrame { chunk, ip: 0, slots, upvalues });
+
+        Ok(())
+    }
+
+    fn instantiate(&mut self, class: Rc<Object>, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let init = match class.as_ref() {
+            Object::Class { methods, .. } => methods.borrow().get("init").cloned(),
+            _ => None,
+        };
+
+        // the instance takes the class' place on the stack, as the result of the call and as `this` for init
+        let instance = Object::Instance { class, fields: RefCell::new(HashMap::new()) };
+        let slot = self.stack.len() - arg_count as usize - 1;
+        self.stack[slot] = Value::Object(Rc::new(instance));
+
+        match init {
+            Some(init) => self.call_function(&init, arg_count, return_ip),
+            None if arg_count != 0 => Err(self.runtime_error(RuntimeError::UnexpectedNumberOfArguments { expected: 0, provided: arg_count })),
+            None => {
+                self.frame_mut().ip = return_ip;
+                Ok(())
+            },
+        }
+    }
+
+    // calls a method on the receiver below the arguments without allocating a bound method for it
+    fn invoke(&mut self, name: &str, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let receiver = self.peek(arg_count as usize)?;
+        let (class, fields) = self.as_instance(receiver)?;
+
+        // a field holding a function is called like any other value
+        let field = fields.borrow().get(name).cloned();
+        if let Some(field) = field {
+            let slot = self.stack.len() - arg_count as usize - 1;
+            self.stack[slot] = field;
+
+            return self.call(arg_count, return_ip);
+        }
+
+        let method = self.find_method(class, name)?;
+        self.call_function(&method, arg_count, return_ip)
+    }
+
+    fn find_method(&self, class: &Object, name: &str) -> Result<Rc<Object>, VMError> {
+        let method = match class {
+            Object::Class { methods, .. } => methods.borrow().get(name).cloned(),
+            _ => None,
+        };
+
+        method.ok_or_else(|| self.runtime_error(RuntimeError::UndefinedProperty(name.to_owned())))
+    }
+    fn find_super_method(&self, superclass: &Value, name: &str) -> Result<Rc<Object>, VMError> {
+        let method = self.as_class_methods(superclass)?.borrow().get(name).cloned();
+
+        method.ok_or_else(|| self.runtime_error(RuntimeError::UndefinedProperty(name.to_owned())))
+    }
+
+    fn closure(&mut self, index: u32, upvalue_refs: Vec<(bool, u8)>) -> Result<(), VMError> {
+        let function = match self.constant(index)? {
+            Value::Object(obj) => Rc::clone(obj),
+            value => return Err(VMError::InvalidConstant(index, format!("expected a function but got {}", value))),
+        };
+
+        let mut upvalues = Vec::new();
+        for (is_local, upvalue_index) in upvalue_refs {
+            let upvalue = if is_local {
+                let slot = self.frame().slots + upvalue_index as usize;
+                if slot >= self.stack.len() {
+                    return Err(self.runtime_error(RuntimeError::UndefinedLocal(upvalue_index.into())));
+                }
+
+                self.capture_upvalue(slot)
+            } else {
+                self.upvalue(upvalue_index)?
+            };
+
+            upvalues.push(upvalue);
+        }
+
+        self.push(Value::Object(Rc::new(Object::Closure { function, upvalues })))
+    }
+    fn class(&mut self, index: u32) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let class = Object::Class { name: name.to_owned(), methods: RefCell::new(HashMap::new()) };
+
+        self.push(Value::Object(Rc::new(class)))
+    }
+    fn get_property(&mut self, index: u32) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let receiver = self.peek(0)?;
+        let (class, fields) = self.as_instance(receiver)?;
+
+        // fields shadow methods of the same name
+        let value = match fields.borrow().get(name) {
+            Some(value) => value.clone(),
+            None => {
+                let method = self.find_method(class, name)?;
+                Value::Object(Rc::new(Object::BoundMethod { receiver: receiver.clone(), method }))
+            },
+        };
+
+        self.drop(1)?;
+        self.push(value)
+    }
+    fn set_property(&mut self, index: u32) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let value = self.peek(0)?.clone();
+        let receiver = self.peek(1)?;
+        let (_, fields) = self.as_instance(receiver)?;
+
+        // only a new field needs its own copy of the name
+        let mut fields = fields.borrow_mut();
+        match fields.get_mut(name) {
+            Some(field) => *field = value.clone(),
+            None => { fields.insert(name.to_owned(), value.clone()); },
+        }
+        drop(fields);
+
+        // the assigned value is the result of the expression
+        self.drop(2)?;
+        self.push(value)
+    }
+    fn method(&mut self, index: u32) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let method = match self.peek(0)? {
+            Value::Object(method) => Rc::clone(method),
+            _ => return Err(self.runtime_error(RuntimeError::CalleeNotCallable)),
+        };
+
+        let class = self.peek(1)?;
+        self.as_class_methods(class)?.borrow_mut().insert(name.to_owned(), method);
+
+        self.drop(1)
+    }
+    fn get_super(&mut self, index: u32) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let superclass = self.pop()?;
+        let receiver = self.pop()?;
+
+        let method = self.find_super_method(&superclass, name)?;
+        self.push(Value::Object(Rc::new(Object::BoundMethod { receiver, method })))
+    }
+    fn invoke_constant(&mut self, index: u32, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        self.invoke(name, arg_count, return_ip)
+    }
+    fn super_invoke(&mut self, index: u32, arg_count: u8, return_ip: usize) -> Result<(), VMError> {
+        let chunk = Rc::clone(&self.frame().chunk);
+        let name = self.name_constant(&chunk, index)?;
+        let superclass = self.pop()?;
+
+        let method = self.find_super_method(&superclass, name)?;
+        self.call_function(&method, arg_count, return_ip)
+    }
+    fn upvalue(&self, index: u8) -> Result<Rc<RefCell<UpvalueObject>>, VMError> {
+        match self.frame().upvalues.get(index as usize) {
+            Some(upvalue) => Ok(Rc::clone(upvalue)),
+            None => Err(self.runtime_error(RuntimeError::UndefinedUpvalue(index))),
+        }
+    }
+    // closures capturing the same slot must share the upvalue so they see each other's writes
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<UpvalueObject>> {
+        let existing = self.open_upvalues.iter()
+            .find(|upvalue| match &*upvalue.borrow() { UpvalueObject::Open(open_slot) => *open_slot == slot, _ => false });
+        if let Some(existing) = existing {
+            return Rc::clone(existing);
+        }
+
+        let upvalue = Rc::new(RefCell::new(UpvalueObject::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+
+        upvalue
+    }
+    // moves every value at or above `from_slot` off the stack and into the upvalues capturing it
+    // corrupt bytecode can pop a captured slot without closing it, which leaves an upvalue pointing past the stack
+    fn close_upvalues(&mut self, from_slot: usize) -> Result<(), VMError> {
+        let stack = &self.stack;
+        let mut missing = None;
+
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                UpvalueObject::Open(slot) if *slot >= from_slot => *slot,
+                _ => return true,
+            };
+
+            match stack.get(slot) {
+                Some(value) => {
+                    *upvalue.borrow_mut() = UpvalueObject::Closed(value.clone());
+                    false
+                },
+                None => {
+                    missing = Some(slot);
+                    true
+                },
+            }
+        });
+
+        match missing {
+            Some(slot) => Err(VMError::StackTooSmall(slot + 1, stack.len())),
+            None => Ok(()),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("VM has no call frames")
+    }
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("VM has no call frames")
+    }
+    fn chunk(&self) -> &Chunk {
+        &self.frame().chunk
+    }
+    fn line(&self) -> usize {
+        self.chunk().line(self.frame().ip)
+    }
+
+    fn runtime_error(&self, error: RuntimeError) -> VMError {
+        let ip = self.frame().ip;
+
+        VMError::Runtime {
+            file: self.chunk().files().file(ip).cloned(),
+            line: self.line(),
+            ip,
+            instruction: format_instruction(self.chunk(), ip).unwrap_or_default(),
+            stack: self.stack.iter().rev().take(ERROR_STACK_DEPTH).map(Value::to_string).collect(),
+            error,
         }
     }
 
@@ -200,8 +777,39 @@ impl VM {
         value.is_truthy()
     }
 
+    // strings compare lexicographically, `>=` and `<=` are compiled as the inverse of these so get the same rules
+    fn compare(&mut self, number_op: fn(f64, f64) -> bool, string_op: fn(&str, &str) -> bool) -> Result<(), VMError> {
+        let right = self.peek(0)?;
+        let left = self.peek(1)?;
+
+        let result = match (left, right) {
+            (Value::Number(left), Value::Number(right)) => number_op(*left, *right),
+            (left, right) => match (left.as_str(), right.as_str()) {
+                (Some(left), Some(right)) => string_op(left, right),
+                _ => return Err(self.runtime_error(RuntimeError::InvalidComparisonArguments(left.type_name(), right.type_name()))),
+            },
+        };
+
+        self.drop(2)?;
+        self.push(Value::Boolean(result))?;
+
+        Ok(())
+    }
+
+    // type checks both operands first so a non-number fails the same way it does in the interpreter
+    fn check_divisor(&self) -> Result<(), VMError> {
+        let right = self.as_number(self.peek(0)?)?;
+        self.as_number(self.peek(1)?)?;
+
+        if right == 0f64 {
+            Err(self.runtime_error(RuntimeError::DivideByZero))
+        } else {
+            Ok(())
+        }
+    }
+
     fn as_number(&self, value: &Value) -> Result<f64, VMError> {
-        value.as_number().map_err(|_| VMError::Runtime(self.chunk.line(self.ip), RuntimeError::ExpectedNumber))
+        value.as_number().map_err(|_| self.runtime_error(RuntimeError::ExpectedNumber))
     }
     fn as_string(&self, value: &Value) -> Result<String, VMError> {
         if let Value::Object(obj) = value {
@@ -210,43 +818,122 @@ impl VM {
             }
         }
 
-        Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::ExpectedString))
+        Err(self.runtime_error(RuntimeError::ExpectedString))
     }
-    fn as_identifier(&self, value: &Value) -> Result<String, VMError> {
+    fn as_instance<'v>(&self, value: &'v Value) -> Result<(&'v Object, &'v RefCell<HashMap<String, Value>>), VMError> {
         if let Value::Object(obj) = value {
-            if let Object::String(s) = obj.as_ref() {
-                return Ok(s.to_owned())
+            if let Object::Instance { class, fields } = obj.as_ref() {
+                return Ok((class.as_ref(), fields))
             }
         }
 
-        Err(VMError::Runtime(self.chunk.line(self.ip), RuntimeError::ExpectedIdentifier))
+        Err(self.runtime_error(RuntimeError::ExpectedInstance))
     }
+    fn as_class_methods<'v>(&self, value: &'v Value) -> Result<&'v RefCell<HashMap<String, Rc<Object>>>, VMError> {
+        if let Value::Object(obj) = value {
+            if let Object::Class { methods, .. } = obj.as_ref() {
+                return Ok(methods)
+            }
+        }
 
-    fn calculate_jump_target(&self, base: usize, offset: i16) -> usize {
-        if offset >= 0 {
-            base + offset as usize
-        } else {
-            base - (-offset as usize)
+        Err(self.runtime_error(RuntimeError::ExpectedClass))
+    }
+    // borrowed so a constant is cloned once, straight onto the stack, rather than moved through each `Result` on the way
+    fn constant(&self, index: u32) -> Result<&Value, VMError> {
+        self.chunk().constant(index).map_err(|e| VMError::InvalidConstant(index, e))
+    }
+
+    fn get_local(&mut self, index: u16) -> Result<(), VMError> {
+        let value = self.stack.get(self.frame().slots + index as usize).cloned();
+
+        match value {
+            Some(value) => self.push(value)?,
+            None => return Err(self.runtime_error(RuntimeError::UndefinedLocal(index))),
+        }
+
+        Ok(())
+    }
+    fn set_local(&mut self, index: u16) -> Result<(), VMError> {
+        let value = self.peek(0)?.clone();
+
+        let slot = self.frame().slots + index as usize;
+        match self.stack.get_mut(slot) {
+            Some(local) => *local = value,
+            None => return Err(self.runtime_error(RuntimeError::UndefinedLocal(index))),
+        }
+
+        Ok(())
+    }
+    fn get_global(&mut self, slot: u32) -> Result<(), VMError> {
+        let value = self.globals.get(slot as usize).and_then(Option::as_ref).cloned();
+
+        match value {
+            Some(value) => self.push(value)?,
+            None => return Err(self.undefined_global(slot)),
+        }
+
+        Ok(())
+    }
+    fn define_global(&mut self, slot: u32) -> Result<(), VMError> {
+        let value = self.peek(0)?.clone();
+
+        let slot = slot as usize;
+        if slot >= self.globals.len() {
+            self.globals.resize(slot + 1, None);
+        }
+        self.globals[slot] = Some(value);
+
+        self.drop(1)
+    }
+    fn set_global(&mut self, slot: u32) -> Result<(), VMError> {
+        let value = self.peek(0)?.clone();
+
+        match self.globals.get_mut(slot as usize) {
+            Some(Some(global)) => *global = value,
+            _ => return Err(self.undefined_global(slot)),
+        }
+
+        Ok(())
+    }
+    fn undefined_global(&self, slot: u32) -> VMError {
+        match self.global_names.borrow().name(slot) {
+            Ok(name) => self.runtime_error(RuntimeError::UndefinedGlobal(name.to_owned())),
+            Err(err) => VMError::InvalidGlobal(slot, err),
         }
     }
+
+    // borrowed from `chunk` rather than `self` so the VM can still be mutated while the name is in use, property
+    // lookups run on every access and shouldn't allocate a copy of the name each time
+    fn name_constant<'c>(&self, chunk: &'c Chunk, index: u32) -> Result<&'c str, VMError> {
+        let value = chunk.constant(index).map_err(|e| VMError::InvalidConstant(index, e))?;
+
+        value.as_str().ok_or_else(|| self.runtime_error(RuntimeError::ExpectedIdentifier))
+    }
+
 }
 
 impl VM {
-    fn push(&mut self, value: Rc<Value>) {
-        self.stack.push(value)
+    fn push(&mut self, value: Value) -> Result<(), VMError> {
+        if self.stack.len() >= self.stack_max {
+            return Err(VMError::StackOverflow { depth: self.stack.len(), line: self.line() });
+        }
+
+        self.stack.push(value);
+
+        Ok(())
     }
 
-    fn peek(&self, offset: usize) -> Result<Rc<Value>, VMError> {
+    fn peek(&self, offset: usize) -> Result<&Value, VMError> {
         let len = self.stack.len();
 
         if offset < len {
-            Ok(Rc::clone(&self.stack[len - offset - 1]))
+            Ok(&self.stack[len - offset - 1])
         } else {
             Err(VMError::StackTooSmall(offset + 1, self.stack.len()))
         }
     }
 
-    fn pop(&mut self) -> Result<Rc<Value>, VMError> {
+    fn pop(&mut self) -> Result<Value, VMError> {
         self.stack.pop().ok_or(VMError::StackTooSmall(1, self.stack.len()))
     }
 
@@ -260,12 +947,1823 @@ impl VM {
         }
     }
 
-    #[cfg(feature = "trace_execution")]
-    fn print_stack(&self) {
-        eprint!("          ");
+    fn trace(&mut self) -> std::io::Result<()> {
+        let tracer = match &mut self.tracer {
+            Some(tracer) => tracer,
+            None => return Ok(()),
+        };
+        let frame = self.frames.last().expect("VM has no call frames");
+
+        write!(tracer, "          ")?;
         for value in &self.stack {
-            eprint!("[{}]", value);
+            write!(tracer, "[{}]", value)?;
         }
-        eprintln!();
+        writeln!(tracer)?;
+        disassemble_instruction(tracer.as_mut(), &frame.chunk, frame.ip)?;
+
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::SourceToken;
+    use rlox_parser::Stmt;
+    use rlox_test_utils::parse;
+    use rlox_interpreter::{ CapturedOutput, Interpreter, RuntimeError as InterpreterError, RuntimeErrorDescription };
+    use crate::{ Compiler, CompilerError, ExecutionStats, assemble, disassemble_to_string };
+    use crate::test_utils::compile;
+    use super::*;
+
+
+    fn run(source: &str) -> (VM, Result<(), VMError>) {
+        let chunk = compile(source);
+
+        let mut vm = VM::new(Rc::new(chunk));
+        let result = vm.run();
+
+        (vm, result)
+    }
+
+    // scans, parses, compiles and runs the source, returning everything it printed
+    fn run_source_in_vm(source: &str) -> (String, Result<(), VMError>) {
+        let chunk = compile(source);
+
+        let output = CapturedOutput::new();
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_output(Box::new(output.clone()));
+        let result = vm.run();
+
+        (output.contents(), result)
+    }
+
+    fn global(vm: &VM, name: &str) -> String {
+        let slot = vm.global_names.borrow_mut().resolve(name).unwrap();
+
+        vm.globals.get(slot as usize).and_then(Option::as_ref).map(|value| value.to_string()).unwrap_or_else(|| panic!("Global {} is not defined", name))
+    }
+
+    // runs the source on both backends, comparing the given globals against what the tree-walker prints for them
+    fn assert_matches_interpreter(source: &str, globals: &[&str]) {
+        let (vm, result) = run(source);
+        result.expect("Failed to run script");
+
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+        let prints: String = globals.iter().map(|name| format!("print {};", name)).collect();
+        interpreter.interpret(parse(&format!("{}\n{}", source, prints))).expect("Failed to interpret script");
+
+        let vm_output: String = globals.iter().map(|name| format!("{}\n", global(&vm, name))).collect();
+        assert_eq!(vm_output, output.contents());
     }
-}
\ No newline at end of file
+
+    // for failures both report the same way, the messages including their line must be identical
+    fn assert_error_matches_interpreter(source: &str) {
+        let (_, vm_result) = run_source_in_vm(source);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let interpreter_result = interpreter.interpret(parse(source));
+
+        match (vm_result, interpreter_result) {
+            (Err(vm_error), Err(interpreter_error)) => assert_eq!(vm_error.to_string(), interpreter_error.to_string(), "Messages differ for {:?}", source),
+            results => panic!("Expected {:?} to fail in both, got {:?}", source, results),
+        }
+    }
+
+    fn subtract(vm: &mut VM) -> Result<(), VMError> {
+        pop_number_op!(vm, left - right ; right, left);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_number_op() {
+        let mut vm = VM::new(Rc::new(Chunk::new()));
+        vm.push(Value::Number(3.0)).unwrap();
+        vm.push(Value::Number(1.0)).unwrap();
+
+        subtract(&mut vm).expect("Failed to subtract");
+
+        assert_eq!(vm.stack.len(), 1);
+        assert_eq!(vm.peek(0).unwrap().as_number().unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_pop_number_op_stack_too_small() {
+        let mut vm = VM::new(Rc::new(Chunk::new()));
+        vm.push(Value::Number(1.0)).unwrap();
+
+        match subtract(&mut vm) {
+            Err(VMError::StackTooSmall(2, 1)) => { },
+            result => panic!("Expected StackTooSmall, got {:?}", result),
+        }
+        assert_eq!(vm.stack.len(), 1);
+    }
+
+    #[test]
+    fn test_pop_number_op_expected_number() {
+        let mut vm = VM::new(Rc::new(Chunk::new()));
+        vm.push(Value::Number(1.0)).unwrap();
+        vm.push(Value::Nil).unwrap();
+
+        match subtract(&mut vm) {
+            Err(VMError::Runtime { error: RuntimeError::ExpectedNumber, .. }) => { },
+            result => panic!("Expected ExpectedNumber, got {:?}", result),
+        }
+        assert_eq!(vm.stack.len(), 2);
+    }
+
+    #[test]
+    fn test_call_function() {
+        let (vm, result) = run("var result; fun add(a, b) { result = a + b; } add(1, 2);");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "3");
+        assert_eq!(global(&vm, "add"), "<fn add>");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let (vm, result) = run("\
+var result = \"\";
+fun inner(x) { var suffix = \"!\"; result = result + x + suffix; }
+fun outer(x, y) { var local = y; inner(x); inner(local); }
+outer(\"a\", \"b\");
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "a!b!");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_call_wrong_arity() {
+        let (_, result) = run("fun f(a, b) { }\nf(1);");
+
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::UnexpectedNumberOfArguments { expected: 2, provided: 1 }, .. }) => { },
+            result => panic!("Expected UnexpectedNumberOfArguments, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_call_not_callable() {
+        let (_, result) = run("var f = 1; f();");
+
+        match result {
+            Err(VMError::Runtime { error: RuntimeError::CalleeNotCallable, .. }) => { },
+            result => panic!("Expected CalleeNotCallable, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_runaway_recursion() {
+        let (_, result) = run("fun f(n) {\n    return f(n + 1);\n}\nf(0);");
+
+        match result {
+            Err(VMError::StackOverflow { depth: DEFAULT_FRAMES_MAX, line: 2 }) => { },
+            result => panic!("Expected StackOverflow, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // pushes without ever popping, as if the compiler forgot a pop in a loop body
+        let mut chunk = Chunk::new();
+        chunk.add(OpCode::Nil, 1);
+        chunk.add(OpCode::Loop(4), 2);
+
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_limits(100, DEFAULT_FRAMES_MAX);
+
+        match vm.run() {
+            Err(VMError::StackOverflow { depth: 100, line: 1 }) => { },
+            result => panic!("Expected StackOverflow, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_invalid_local_and_upvalue() {
+        // hand-built chunks skip the checks a loaded one goes through, bad slots are errors rather than panics
+        let cases = vec![
+            (OpCode::SetLocal(3), "Undefined local in slot 3."),
+            (OpCode::GetUpvalue(0), "Undefined upvalue 0."),
+            (OpCode::SetUpvalue(0), "Undefined upvalue 0."),
+            (OpCode::Closure(0, vec![(true, 3)]), "Undefined local in slot 3."),
+            (OpCode::Closure(0, vec![(false, 0)]), "Undefined upvalue 0."),
+        ];
+
+        for (op, expected) in cases {
+            let mut chunk = Chunk::new();
+            let function = Object::Function { name: "f".into(), arity: 0, chunk: Rc::new(Chunk::new()) };
+            chunk.add_constant(Value::Object(Rc::new(function))).unwrap();
+            chunk.add(OpCode::Nil, 1);
+            chunk.add(op, 1);
+            chunk.add(OpCode::Return, 1);
+
+            match VM::new(Rc::new(chunk)).run() {
+                Err(VMError::Runtime { error, .. }) => assert_eq!(error.to_string(), expected),
+                result => panic!("Expected a runtime error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let source = "fun f(n) { if (n > 0) return f(n - 1); return n; }\nvar a = f(10);";
+
+        let chunk = compile(source);
+        let chunk = Rc::new(chunk);
+
+        let mut vm = VM::new(Rc::clone(&chunk));
+        vm.set_limits(DEFAULT_STACK_MAX, 12);
+        vm.run().expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "0");
+
+        let mut vm = VM::new(chunk);
+        vm.set_limits(DEFAULT_STACK_MAX, 11);
+        match vm.run() {
+            Err(VMError::StackOverflow { depth: 11, line: 1 }) => { },
+            result => panic!("Expected StackOverflow, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_fib_matches_interpreter() {
+        let source = "\
+fun fib(n) {
+    if (n <= 1) return n;
+    return fib(n - 2) + fib(n - 1);
+}
+var result = fib(10);
+";
+
+        let (vm, result) = run(source);
+        result.expect("Failed to run script");
+
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+        interpreter.interpret(parse(&format!("{}print result;", source))).expect("Failed to interpret script");
+
+        assert_eq!(format!("{}\n", global(&vm, "result")), output.contents());
+        assert_eq!(global(&vm, "result"), "55");
+    }
+
+    #[test]
+    fn test_early_return() {
+        let (vm, result) = run("\
+fun sign(n) {
+    if (n < 0) { return -1; } else if (n == 0) return 0;
+    return 1;
+}
+var result = \"\" + sign(-5) + sign(0) + sign(5);
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "-101");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_return_from_loop() {
+        let (vm, result) = run("\
+fun find(limit) {
+    var i = 0;
+    while (i < limit) {
+        var doubled = i * 2;
+        if (doubled > 6) return doubled;
+        i = i + 1;
+    }
+}
+var total = 0;
+{
+    var a = 1;
+    var b = find(10);
+    var c = find(2);
+    total = a + b;
+    if (c == nil) total = total + 100;
+}
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "total"), "109");
+    }
+
+    #[test]
+    fn test_render_undefined_global() {
+        let (_, result) = run("var a = 1;\nprint a + b;");
+
+        let error = result.expect_err("Expected an undefined variable");
+        assert_eq!(error.to_string(), "[line 2] Undefined variable 'b'.");
+        assert_eq!(error.render(), "\
+[line 2] Undefined variable 'b'.
+  at 0x0006 OP_GET_GLOBAL    1 'b'
+  stack (top first): [1]");
+    }
+
+    #[test]
+    fn test_render_type_error() {
+        // the failing instruction is found in the function's chunk, not the script's
+        let (_, result) = run("fun negate(n) {\n  return -n;\n}\nnegate(\"a\");");
+
+        let error = result.expect_err("Expected a type error");
+        assert_eq!(error.to_string(), "[line 2] Operands must be numbers.");
+        assert_eq!(error.render(), "\
+[line 2] Operands must be numbers.
+  at 0x0002 OP_NEGATE
+  stack (top first): [a][a][<fn negate>]");
+    }
+
+    #[test]
+    fn test_script_return() {
+        // the parser rejects `return` outside a function, but the compiler still finishes the script at one
+        let mut statements = parse("var a = 1;");
+        statements.push(Stmt::Return(SourceToken::default(), None));
+        statements.extend(parse("a = 2;"));
+
+        let mut chunk = Chunk::new();
+        Compiler::new(&mut chunk).compile(statements).expect("Failed to compile source");
+
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.run().expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "1");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_closure_counter() {
+        assert_matches_interpreter("\
+fun makeCounter() {
+    var i = 0;
+    fun count() {
+        i = i + 1;
+        return i;
+    }
+
+    return count;
+}
+
+var counter = makeCounter();
+var a = counter();
+var b = counter();
+var other = makeCounter();
+var c = other();
+", &["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_closure_shadowing() {
+        assert_matches_interpreter("\
+var a = \"global\";
+var result = \"\";
+{
+    fun showA() {
+        result = result + a + \" \";
+    }
+
+    showA();
+    var a = \"block\";
+    showA();
+}
+", &["result"]);
+    }
+
+    #[test]
+    fn test_closures_share_upvalue() {
+        assert_matches_interpreter("\
+var set;
+var get;
+fun main() {
+    var a = \"initial\";
+    fun setA() { a = \"updated\"; }
+    fun getA() { return a; }
+
+    set = setA;
+    get = getA;
+}
+
+main();
+var before = get();
+set();
+var after = get();
+", &["before", "after"]);
+    }
+
+    #[test]
+    fn test_nested_upvalues() {
+        assert_matches_interpreter("\
+fun outer() {
+    var x = \"outer\";
+    fun middle() {
+        fun inner() {
+            return x;
+        }
+        return inner;
+    }
+    return middle;
+}
+
+var result = outer()()();
+{
+    var captured = 1;
+    fun f() { captured = captured + 1; return captured; }
+    f();
+    result = result + f();
+}
+", &["result"]);
+    }
+
+    #[test]
+    fn test_closures_are_values() {
+        let (vm, result) = run("\
+fun make(n) {
+    fun get() { return n; }
+    return get;
+}
+var first = make(1);
+var second = make(2);
+var name = \"\" + first;
+var same = first == first;
+var different = first == second;
+fun apply(f) { return f(); }
+var applied = apply(second);
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "name"), "<fn get>");
+        assert_eq!(global(&vm, "same"), "true");
+        assert_eq!(global(&vm, "different"), "false");
+        assert_eq!(global(&vm, "applied"), "2");
+
+        let (_, result) = run("fun outer() { var a = 1; fun inner(b) { return a + b; } return inner; }\nouter()();");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::UnexpectedNumberOfArguments { expected: 1, provided: 0 }, .. }) => { },
+            result => panic!("Expected UnexpectedNumberOfArguments, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_closure_captures_loop_variable() {
+        assert_matches_interpreter("\
+var captured;
+for (var i = 0; i < 3; i = i + 1) {
+    var j = i * 10;
+    fun capture() { return j; }
+    if (i == 1) captured = capture;
+}
+var result = captured();
+", &["result"]);
+    }
+
+    #[test]
+    fn test_close_upvalues() {
+        let mut vm = VM::new(Rc::new(Chunk::new()));
+        for n in 0..3 {
+            vm.push(Value::Number(n as f64)).unwrap();
+        }
+
+        let first = vm.capture_upvalue(0);
+        let second = vm.capture_upvalue(1);
+        let third = vm.capture_upvalue(2);
+        // capturing an open slot again shares its upvalue
+        assert!(Rc::ptr_eq(&second, &vm.capture_upvalue(1)));
+        assert_eq!(vm.open_upvalues.len(), 3);
+
+        vm.close_upvalues(1).unwrap();
+        assert!(matches!(*first.borrow(), UpvalueObject::Open(0)));
+        assert!(matches!(*second.borrow(), UpvalueObject::Closed(Value::Number(n)) if n == 1.0));
+        assert!(matches!(*third.borrow(), UpvalueObject::Closed(Value::Number(n)) if n == 2.0));
+        assert_eq!(vm.open_upvalues.len(), 1);
+
+        // the closed values no longer follow their old slots
+        vm.stack[1] = Value::Nil;
+        assert!(matches!(*second.borrow(), UpvalueObject::Closed(Value::Number(n)) if n == 1.0));
+        assert!(!Rc::ptr_eq(&second, &vm.capture_upvalue(1)));
+    }
+
+    #[test]
+    fn test_class_instantiation() {
+        assert_matches_interpreter("\
+class Pair { }
+var klass = Pair;
+var instance = Pair();
+", &["klass", "instance"]);
+
+        let (_, result) = run("class A { } A(1);");
+        match result {
+            Err(VMError::Runtime { error: RuntimeError::UnexpectedNumberOfArguments { expected: 0, provided: 1 }, .. }) => { },
+            result => panic!("Expected UnexpectedNumberOfArguments, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_local_class() {
+        let (vm, result) = run("var result; { class Local { } result = Local(); }");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "Local instance");
+    }
+
+    #[test]
+    fn test_class_identity() {
+        let (vm, result) = run("\
+class A { }
+class B { }
+var a = A;
+var a_is_a = a == A;
+var a_is_b = A == B;
+var instance = A();
+var instances_equal = A() == A();
+var instance_is_self = instance == instance;
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "a_is_a"), "true");
+        assert_eq!(global(&vm, "a_is_b"), "false");
+        assert_eq!(global(&vm, "instances_equal"), "false");
+        assert_eq!(global(&vm, "instance_is_self"), "true");
+    }
+
+    #[test]
+    fn test_modulo() {
+        let (vm, result) = run("var a = 5 % 3 == 2; var b = 7 % 2.5; var c = 1 + 10 % 4 * 2;");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "true");
+        assert_eq!(global(&vm, "b"), "2");
+        assert_eq!(global(&vm, "c"), "5");
+
+        // `%` is `f64::rem`, it truncates so the result takes the sign of the dividend rather than flooring
+        let (vm, result) = run("var a = -7 % 3; var b = 7 % -3; var c = -7.5 % 2; var d = 0.5 % 0.25; var e = 2 % 5;");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "-1");
+        assert_eq!(global(&vm, "b"), "1");
+        assert_eq!(global(&vm, "c"), "-1.5");
+        assert_eq!(global(&vm, "d"), "0");
+        assert_eq!(global(&vm, "e"), "2");
+
+        let (_, result) = run("var a = \"a\" % 2;");
+        match result {
+            Err(VMError::Runtime { line: 1, error: RuntimeError::ExpectedNumber, .. }) => { },
+            result => panic!("Expected ExpectedNumber, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_divide_by_zero() {
+        let (_, result) = run("var a = 1;\nvar b = a / 0;");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::DivideByZero, .. }) => { },
+            result => panic!("Expected DivideByZero, got {:?}", result),
+        }
+
+        let (_, result) = run("var a = 0 / 0;");
+        match result {
+            Err(VMError::Runtime { line: 1, error: RuntimeError::DivideByZero, .. }) => { },
+            result => panic!("Expected DivideByZero, got {:?}", result),
+        }
+
+        let (_, result) = run("var a = 1 % 0;");
+        match result {
+            Err(VMError::Runtime { line: 1, error: RuntimeError::DivideByZero, .. }) => { },
+            result => panic!("Expected DivideByZero, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_division_matches_interpreter() {
+        assert_matches_interpreter("\
+var a = 1 / 4;
+var b = -9 / 3 / 2;
+var c = 0 / 5;
+var d = 7 % 3 / 2;
+var e = 0;
+for (var i = 1; i <= 10; i = i + 1) e = e + 100 / i % 7;
+", &["a", "b", "c", "d", "e"]);
+
+        for source in ["print 1 / 0;", "print 0 / 0;", "print -2 % 0;", "print \"a\" / 0;", "var a = 2; print 1 / (a - 2);"] {
+            let (_, vm_result) = run(source);
+
+            let mut interpreter = Interpreter::new();
+            let interpreter_result = interpreter.interpret(parse(source));
+
+            match (vm_result, interpreter_result) {
+                (Err(VMError::Runtime { error: RuntimeError::DivideByZero, .. }), Err(InterpreterError { description: RuntimeErrorDescription::DivideByZero, .. })) => { },
+                (Err(VMError::Runtime { error: RuntimeError::ExpectedNumber, .. }), Err(InterpreterError { description: RuntimeErrorDescription::ExpectedNumber(_), .. })) => { },
+                results => panic!("Expected {:?} to fail the same way in both, got {:?}", source, results),
+            }
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_match_interpreter() {
+        let sources = [
+            "print a;",
+            "var a = 1;\na = b;",
+            "c = 1;",
+            "class A { }\nprint A().x;",
+            "class A {\n    get() {\n        return this.x;\n    }\n}\nA().get();",
+            "var a = 1;\nprint a.x;",
+            "var a = 1;\na.x = 2;",
+            "var a = \"a\";\na();",
+            "fun f(a) { }\n(f)();",
+            "print 1 / 0;",
+            "var a = 2;\nprint -2 % (a - 2);",
+            "var A = 1;\nclass B < A { }",
+            "class A { }\nclass B < A {\n    get() { return super.get(); }\n}\nB().get();",
+        ];
+
+        for source in sources {
+            assert_error_matches_interpreter(source);
+        }
+    }
+
+    #[test]
+    fn test_string_comparison() {
+        let (vm, result) = run("var a = \"a\" < \"b\"; var b = \"b\" > \"ab\"; var c = \"ab\" >= \"abc\"; var d = \"a\" <= \"a\"; var e = \"\" < \"a\";");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "true");
+        assert_eq!(global(&vm, "b"), "true");
+        assert_eq!(global(&vm, "c"), "false");
+        assert_eq!(global(&vm, "d"), "true");
+        assert_eq!(global(&vm, "e"), "true");
+
+        let (_, result) = run("var a = \"a\";\nprint a >= 1;");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::InvalidComparisonArguments("string", "number"), .. }) => { },
+            result => panic!("Expected InvalidComparisonArguments, got {:?}", result),
+        }
+
+        let (_, result) = run("print nil < true;");
+        match result {
+            Err(VMError::Runtime { line: 1, error: RuntimeError::InvalidComparisonArguments("nil", "boolean"), .. }) => { },
+            result => panic!("Expected InvalidComparisonArguments, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_comparison_matches_interpreter() {
+        assert_matches_interpreter("\
+var a = \"apple\" < \"banana\";
+var b = \"apple\" > \"Apple\";
+var c = \"b\" <= \"abc\";
+var d = \"b\" >= \"b\";
+var e = 1 < 2;
+var f = 2.5 >= 2.5;
+var g = -1 > -0.5;
+var h = 3 <= 1;
+", &["a", "b", "c", "d", "e", "f", "g", "h"]);
+
+        for source in ["print \"a\" < 1;", "print 1 >= \"a\";", "print nil > 1;", "print true <= \"a\";"] {
+            let (_, vm_result) = run(source);
+
+            let mut interpreter = Interpreter::new();
+            let interpreter_result = interpreter.interpret(parse(source));
+
+            match (vm_result, interpreter_result) {
+                (Err(VMError::Runtime { error: RuntimeError::InvalidComparisonArguments(..), .. }), Err(InterpreterError { description: RuntimeErrorDescription::ExpectedNumber(_), .. })) => { },
+                results => panic!("Expected {:?} to fail in both, got {:?}", source, results),
+            }
+        }
+    }
+
+    #[test]
+    fn test_methods_matches_interpreter() {
+        assert_matches_interpreter("\
+class Counter {
+    init(start) {
+        this.count = start;
+        this.steps = 0;
+    }
+
+    increment(by) {
+        this.count = this.count + by;
+        this.steps = this.steps + 1;
+        return this;
+    }
+
+    describe() {
+        return \"count \" + this.count + \" after \" + this.steps + \" steps\";
+    }
+}
+
+var counter = Counter(10);
+counter.increment(2).increment(3);
+var description = counter.describe();
+var count = counter.count;
+var method = counter.describe;
+counter.increment(1);
+var bound = method();
+var reinit = counter.init(0);
+", &["description", "count", "bound", "reinit", "counter", "method"]);
+    }
+
+    #[test]
+    fn test_this_in_closure() {
+        let (vm, result) = run("\
+class Greeter {
+    greet(name) {
+        fun greeting() { return this.prefix + name; }
+        return greeting;
+    }
+}
+var greeter = Greeter();
+greeter.prefix = \"hello \";
+var result = greeter.greet(\"world\")();
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "hello world");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_invoke_field() {
+        let (vm, result) = run("\
+class Box { }
+fun double(n) { return n * 2; }
+var box = Box();
+box.function = double;
+var result = box.function(4);
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "8");
+    }
+
+    #[test]
+    fn test_method_errors() {
+        let (_, result) = run("class A { }\nA().missing();");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::UndefinedProperty(name), .. }) => assert_eq!(name, "missing"),
+            result => panic!("Expected UndefinedProperty, got {:?}", result),
+        }
+
+        let (_, result) = run("class A { init(a, b) { } }\nA(1);");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::UnexpectedNumberOfArguments { expected: 2, provided: 1 }, .. }) => { },
+            result => panic!("Expected UnexpectedNumberOfArguments, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse("print this;")) {
+            Err(CompilerError::ThisOutsideClass) => { },
+            result => panic!("Expected ThisOutsideClass, got {:?}", result),
+        }
+        match Compiler::new(&mut chunk).compile(parse("class A { init() { return 1; } }")) {
+            Err(CompilerError::ReturnValueFromInitializer) => { },
+            result => panic!("Expected ReturnValueFromInitializer, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_super_delegation() {
+        let (vm, result) = run("\
+class Shape {
+    init(name) { this.name = name; }
+    describe() { return \"a \" + this.name; }
+    sides() { return 0; }
+}
+
+class Square < Shape {
+    init() { super.init(\"square\"); }
+    describe() { return super.describe() + \" with \" + this.sides() + \" sides\"; }
+    sides() { return 4; }
+}
+
+var description = Square().describe();
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "description"), "a square with 4 sides");
+
+        let (vm, result) = run("\
+class A { method() { return \"A\"; } }
+class B < A { method() { return \"B\"; } test() { return super.method(); } }
+class C < B { }
+var result = C().test();
+var bound;
+{
+    class Local < C {
+        describe() {
+            var method = super.method;
+            return method;
+        }
+    }
+    bound = Local().describe()();
+}
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "A");
+        assert_eq!(global(&vm, "bound"), "B");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_inherited_method() {
+        let (vm, result) = run("\
+class Base {
+    init(value) { this.value = value; }
+    get() { return this.value; }
+}
+class Derived < Base { }
+var result = Derived(7).get();
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "7");
+    }
+
+    #[test]
+    fn test_inheritance_matches_interpreter() {
+        assert_matches_interpreter("\
+class Shape {
+    init(name) { this.name = name; }
+    describe() { return \"a \" + this.name; }
+    sides() { return 0; }
+}
+class Square < Shape {
+    init() { super.init(\"square\"); }
+    describe() { return super.describe() + \" with \" + this.sides() + \" sides\"; }
+    sides() { return 4; }
+}
+class A { method() { return \"A\"; } }
+class B < A { method() { return \"B\"; } test() { return super.method(); } }
+class C < B { }
+var description = Square().describe();
+var inherited = C().method();
+var result = C().test();
+var bound;
+{
+    class Local < C {
+        describe() {
+            var method = super.method;
+            return method;
+        }
+    }
+    bound = Local().describe()();
+}
+", &["description", "inherited", "result", "bound"]);
+    }
+
+    #[test]
+    fn test_inheritance_errors() {
+        let (_, result) = run("var NotAClass = 1;\nclass A\n< NotAClass { }");
+        match result {
+            Err(VMError::Runtime { line: 3, error: RuntimeError::ExpectedClass, .. }) => { },
+            result => panic!("Expected ExpectedClass, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse("class A < A { }")) {
+            Err(CompilerError::ClassInheritsFromItself(name)) => assert_eq!(name, "A"),
+            result => panic!("Expected ClassInheritsFromItself, got {:?}", result),
+        }
+        match Compiler::new(&mut chunk).compile(parse("class A { method() { return super.method(); } }")) {
+            Err(CompilerError::SuperOutsideSubclass) => { },
+            result => panic!("Expected SuperOutsideSubclass, got {:?}", result),
+        }
+        match Compiler::new(&mut chunk).compile(parse("fun f() { return super.method; }")) {
+            Err(CompilerError::SuperOutsideSubclass) => { },
+            result => panic!("Expected SuperOutsideSubclass, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_properties() {
+        let (vm, result) = run("\
+class Point { }
+var point = Point();
+point.x = 1;
+var other = Point();
+point.y = other.y = point.x + 1;
+var sum = point.x + point.y + other.y;
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "sum"), "5");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_property_errors() {
+        let (_, result) = run("class Point { }\nvar point = Point();\nprint point.x;");
+        match result {
+            Err(VMError::Runtime { line: 3, error: error @ RuntimeError::UndefinedProperty(_), .. }) => assert_eq!(error.to_string(), "Undefined property 'x'."),
+            result => panic!("Expected UndefinedProperty, got {:?}", result),
+        }
+
+        let (_, result) = run("var number = 1;\n\nnumber.x = 2;");
+        match result {
+            Err(VMError::Runtime { line: 3, error: error @ RuntimeError::ExpectedInstance, .. }) => assert_eq!(error.to_string(), "Only instances have properties."),
+            result => panic!("Expected ExpectedInstance, got {:?}", result),
+        }
+
+        let (_, result) = run("var number = 1;\nprint number\n.x;");
+        match result {
+            Err(VMError::Runtime { line: 3, error: RuntimeError::ExpectedInstance, .. }) => { },
+            result => panic!("Expected ExpectedInstance, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_break_with_loop_local() {
+        let (vm, result) = run("\
+var total = 0;
+{
+    var outer = 10;
+    var i = 0;
+    while (true) {
+        var step = i * 2;
+        if (step > 4) break;
+        total = total + step + outer;
+        i = i + 1;
+    }
+    total = total + i;
+}
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "total"), "39");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_break_nested_loops() {
+        assert_matches_interpreter("\
+var log = \"\";
+for (var i = 0; i < 3; i = i + 1) {
+    var j = 0;
+    while (true) {
+        if (j == 2) break;
+        log = log + \"x\";
+        j = j + 1;
+    }
+    log = log + \"|\";
+}
+", &["log"]);
+    }
+
+    #[test]
+    fn test_continue_runs_increment() {
+        let (vm, result) = run("\
+var sum = 0;
+fun captures() {
+    for (var i = 0; i < 5; i = i + 1) {
+        var skipped = i;
+        fun get() { return skipped; }
+        if (i == 2) continue;
+        sum = sum + get();
+    }
+}
+captures();
+");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "sum"), "8");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let token = SourceToken::default();
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(vec![Stmt::Break(token.clone())]) {
+            Err(CompilerError::BreakOutsideLoop) => { },
+            result => panic!("Expected BreakOutsideLoop, got {:?}", result),
+        }
+        match Compiler::new(&mut chunk).compile(vec![Stmt::Continue(token)]) {
+            Err(CompilerError::ContinueOutsideLoop) => { },
+            result => panic!("Expected ContinueOutsideLoop, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_read_own_initializer() {
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse("var a = 1;\n{\n  var a = a;\n}")) {
+            Err(CompilerError::ReadOwnInitializer { name, line }) => assert_eq!((name.as_str(), line), ("a", 3)),
+            result => panic!("Expected ReadOwnInitializer, got {:?}", result),
+        }
+
+        // shadowing through a temporary reads the outer value, and globals can still refer to themselves
+        let (vm, result) = run("var a = 1;\n{ var outer = a; { var a = outer + 1; var b = a; outer = b; } a = outer; }\nvar c = 2; var c = c * 3;");
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "a"), "2");
+        assert_eq!(global(&vm, "c"), "6");
+    }
+
+    #[test]
+    fn test_while_loop() {
+        // `print` writes straight to stdout, so the loop's progress is checked through the globals it leaves behind
+        let (vm, result) = run("var i = 0; while (i < 3) { print i; i = i + 1; }");
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "i"), "3");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_matches_interpreter("\
+var empty = [];
+var nested = [1, [2, 3]];
+var mixed = [\"a\", nil, true, empty];
+", &["empty", "nested", "mixed"]);
+
+        let (vm, result) = run("var deep = [1, [2, [3, [4, [5]]]]];");
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "deep"), "[1, [2, [3, ...]]]");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_too_many_elements() {
+        let elements = vec!["1"; 256].join(", ");
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse(&format!("var a = 1;\nprint [{}];", elements))) {
+            Err(CompilerError::TooManyElements { line: 2 }) => { },
+            result => panic!("Expected TooManyElements, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_destructure_unsupported() {
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse("fun pair() { return [1, 2]; }\nvar (a, b) = pair();")) {
+            Err(CompilerError::Unsupported { statement: "var (...)", line: 2 }) => { },
+            result => panic!("Expected Unsupported, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_for_in_unsupported() {
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse("var list = [1, 2];\nfor a in list { print a; }")) {
+            Err(CompilerError::Unsupported { statement: "for ... in", line: 2 }) => { },
+            result => panic!("Expected Unsupported, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_serialized_chunk_runs_the_same() {
+        let source = "\
+class Counter {
+    init(start) { this.count = start; }
+    increment(by) { this.count = this.count + by; return this; }
+}
+class Named < Counter {
+    init(name) { super.init(0); this.name = name; }
+}
+fun make_adder(n) {
+    fun add(x) { return x + n; }
+    return add;
+}
+var counter = Named(\"c\");
+var add_two = make_adder(2);
+for (var i = 0; i < 10; i = i + 1) {
+    if (i % 3 == 0) continue;
+    counter.increment(add_two(i));
+}
+var total = counter.count;
+var label = counter.name + \"!\";
+var ratio = total / 4;
+";
+        let chunk = compile(source);
+
+        let mut bytes = Vec::new();
+        chunk.serialize(&mut bytes).expect("Failed to serialize chunk");
+        let deserialized = Chunk::deserialize(&mut bytes.as_slice()).expect("Failed to deserialize chunk");
+
+        assert_eq!(disassemble_to_string(&deserialized), disassemble_to_string(&chunk));
+
+        let mut original = VM::new(Rc::new(chunk));
+        original.run().expect("Failed to run original chunk");
+        let mut vm = VM::new(Rc::new(deserialized));
+        vm.run().expect("Failed to run deserialized chunk");
+
+        for name in &["counter", "total", "label", "ratio"] {
+            assert_eq!(global(&vm, name), global(&original, name));
+        }
+        assert_eq!(global(&vm, "total"), "39");
+        assert_eq!(global(&vm, "label"), "c!");
+    }
+
+    #[test]
+    fn test_assembled_do_while() {
+        // the body runs before the condition is first checked, and the loop jumps back past the condition to it,
+        // lox has no do-while so the compiler never emits this shape
+        let chunk = assemble("\
+.constants
+    0
+    1
+    3
+.globals
+    count
+.code
+    OP_CONSTANT 0
+    OP_DEFINE_GLOBAL 0
+body:
+    OP_GET_GLOBAL 0
+    OP_CONSTANT 1
+    OP_ADD
+    OP_SET_GLOBAL 0
+    OP_POP
+    OP_GET_GLOBAL 0
+    OP_CONSTANT 2
+    OP_LESS
+    OP_JUMP_IF_FALSE done
+    OP_POP
+    OP_LOOP body
+done:
+    OP_POP
+    OP_RETURN
+").expect("Failed to assemble");
+
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.run().expect("Failed to run assembled chunk");
+
+        assert_eq!(global(&vm, "count"), "3");
+        assert_eq!(vm.stack.len(), 0);
+
+        // runtime errors carry the assembly's line
+        let mut vm = VM::new(Rc::new(assemble(".constants\n\"a\"\n.code\nOP_CONSTANT 0\nOP_NEGATE\nOP_RETURN").unwrap()));
+        match vm.run() {
+            Err(VMError::Runtime { line: 5, error: _, .. }) => { },
+            result => panic!("Expected a runtime error on line 5, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_loop_jump_targets() {
+        let chunk = compile("var i = 0; while (i < 3) i = i + 1;");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_CONSTANT      0 '0'
+0x0002    | OP_DEFINE_GLOBAL 0 'i'
+L0:
+0x0004    | OP_GET_GLOBAL    0 'i'
+0x0006    | OP_CONSTANT      1 '3'
+0x0008    | OP_LESS
+0x0009    | OP_JUMP_IF_FALSE -> L1
+0x000c    | OP_POP
+0x000d    | OP_GET_GLOBAL    0 'i'
+0x000f    | OP_CONSTANT      2 '1'
+0x0011    | OP_ADD
+0x0012    | OP_SET_GLOBAL    0 'i'
+0x0014    | OP_POP
+0x0015    | OP_LOOP          -> L0
+L1:
+0x0018    | OP_POP
+0x0019    | OP_RETURN
+== constants ==
+   0 number   '0'
+   1 number   '3'
+   2 number   '1'
+");
+    }
+
+    #[test]
+    fn test_loop_to_start_of_chunk() {
+        // the loop jumps back the full length of the chunk to land exactly on offset 0
+        let chunk = compile("while (false) print 1;");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+L0:
+0x0000    1 OP_FALSE
+0x0001    | OP_JUMP_IF_FALSE -> L1
+0x0004    | OP_POP
+0x0005    | OP_CONSTANT      0 '1'
+0x0007    | OP_PRINT
+0x0008    | OP_LOOP          -> L0
+L1:
+0x000b    | OP_POP
+0x000c    | OP_RETURN
+== constants ==
+   0 number   '1'
+");
+    }
+
+    #[test]
+    fn test_jump_too_large() {
+        // each assignment is 5 bytes of bytecode and adds no constants, so only the jump distance overflows
+        let body = "a = a;\n".repeat(14000);
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse(&format!("var a = 1;\nif (a)\n{{\n{}}}", body))) {
+            Err(CompilerError::JumpTooLarge { distance, line }) => {
+                assert!(distance > u16::MAX as usize);
+                assert_eq!(line, 2);
+            },
+            result => panic!("Expected JumpTooLarge, got {:?}", result),
+        }
+
+        let mut chunk = Chunk::new();
+        match Compiler::new(&mut chunk).compile(parse(&format!("var a = 1;\nwhile (a)\n{{\n{}}}", body))) {
+            Err(CompilerError::JumpTooLarge { line, .. }) => assert_eq!(line, 2),
+            result => panic!("Expected JumpTooLarge, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unresolved_jump() {
+        let mut chunk = Chunk::new();
+        let mut compiler = Compiler::new(&mut chunk);
+        compiler.emit_unresolved_jump();
+
+        match compiler.compile(parse("var a = 1;")) {
+            Err(CompilerError::UnresolvedJump(3)) => { },
+            result => panic!("Expected UnresolvedJump, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unresolved_jump_in_enclosing_function() {
+        // the function's `and` jump ends at the same offset in its own chunk as the script's unresolved one
+        let mut chunk = Chunk::new();
+        let mut compiler = Compiler::new(&mut chunk);
+        match parse("true;").pop() {
+            Some(Stmt::Expression(expr)) => compiler.compile_expression(expr).expect("Failed to compile expression"),
+            statement => panic!("Expected an expression, got {:?}", statement),
+        }
+        compiler.emit_unresolved_jump();
+
+        match compiler.compile(parse("fun f(a) { a and a; }")) {
+            Err(CompilerError::UnresolvedJump(5)) => { },
+            result => panic!("Expected UnresolvedJump, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unbalanced_stack() {
+        // the stray value is still on the stack when the script ends, the function's own code is balanced
+        let mut chunk = Chunk::new();
+        let mut compiler = Compiler::new(&mut chunk);
+        compiler.emit_unbalanced_push();
+        match compiler.compile(parse("fun f(a) { var b = a; { var c = b; } return b; }\nprint f(1);")) {
+            Err(CompilerError::UnbalancedStack { .. }) => { },
+            result => panic!("Expected UnbalancedStack, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_script_ends_in_return() {
+        fn last_op(chunk: &Chunk) -> Option<OpCode> {
+            let mut offset = 0;
+            let mut last = None;
+            while let Ok((op, next_offset)) = chunk.decode(offset) {
+                last = Some(op);
+                offset = next_offset;
+            }
+
+            assert_eq!(offset, chunk.len());
+            last
+        }
+
+        let sources = ["", "print 1;", "while (false) {}", "if (true) { var a = 1; }", "fun f() { return 1; }", "1 + 2;"];
+        for source in sources.iter() {
+            let chunk = compile(source);
+            assert_eq!(last_op(&chunk), Some(OpCode::Return), "compiling {:?}", source);
+
+            let mut chunk = Chunk::new();
+            Compiler::new(&mut chunk).compile_repl(parse(source)).expect("Failed to compile source");
+            assert_eq!(last_op(&chunk), Some(OpCode::Return), "compiling {:?} for the repl", source);
+        }
+
+        // the files make up one script, which only returns at the end of the last
+        let mut chunk = Chunk::new();
+        Compiler::new(&mut chunk).compile_multiple_files(vec![("a.lox".into(), parse("var a = 1;")), ("b.lox".into(), parse("var b = a;"))])
+            .expect("Failed to compile files");
+        let disassembly = disassemble_to_string(&chunk);
+        assert_eq!(disassembly.matches("OP_RETURN").count(), 1);
+        assert_eq!(last_op(&chunk), Some(OpCode::Return));
+    }
+
+    #[test]
+    fn test_unbalanced_scope() {
+        let mut chunk = Chunk::new();
+        let mut compiler = Compiler::new(&mut chunk);
+
+        match compiler.end_unopened_scope() {
+            Err(CompilerError::UnbalancedScope) => { },
+            result => panic!("Expected UnbalancedScope, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_if_else_jump_targets() {
+        let chunk = compile("if (true) print 1; else print 2;");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_TRUE
+0x0001    | OP_JUMP_IF_FALSE -> L0
+0x0004    | OP_POP
+0x0005    | OP_CONSTANT      0 '1'
+0x0007    | OP_PRINT
+0x0008    | OP_JUMP          -> L1
+L0:
+0x000b    | OP_POP
+0x000c    | OP_CONSTANT      1 '2'
+0x000e    | OP_PRINT
+L1:
+0x000f    | OP_RETURN
+== constants ==
+   0 number   '1'
+   1 number   '2'
+");
+    }
+
+    #[test]
+    fn test_ternary_jump_targets() {
+        let chunk = compile("print true ? 1 : 2;");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_TRUE
+0x0001    | OP_JUMP_IF_FALSE -> L0
+0x0004    | OP_POP
+0x0005    | OP_CONSTANT      0 '1'
+0x0007    | OP_JUMP          -> L1
+L0:
+0x000a    | OP_POP
+0x000b    | OP_CONSTANT      1 '2'
+L1:
+0x000d    | OP_PRINT
+0x000e    | OP_RETURN
+== constants ==
+   0 number   '1'
+   1 number   '2'
+");
+    }
+
+    #[test]
+    fn test_ternary() {
+        let cases = [
+            ("print true ? 1 : 2;", "1\n"),
+            ("print nil ? 1 : 2;", "2\n"),
+            // only the branch taken is evaluated
+            ("var a = 0; var b = true ? (a = 1) : (a = 2); print a; print b;", "1\n1\n"),
+            ("var a = 0; var b = false ? (a = 1) : (a = 2); print a; print b;", "2\n2\n"),
+            ("fun sign(n) { return n < 0 ? -1 : n > 0 ? 1 : 0; } print sign(-5); print sign(0); print sign(5);", "-1\n0\n1\n"),
+            ("var a = true; var b = false; print a ? b ? 1 : 2 : 3;", "2\n"),
+            // each branch leaves exactly one value, so locals after it are still addressed correctly
+            ("{ var a = false ? 1 : 2; var b = 3; print a + b; }", "5\n"),
+            ("for (var i = 0; i < 3; i = i + 1) print i == 1 ? \"one\" : i;", "0\none\n2\n"),
+        ];
+
+        for (source, expected) in cases.iter() {
+            let (output, result) = run_source_in_vm(source);
+            result.unwrap_or_else(|error| panic!("Failed to run {}: {:?}", source, error));
+            assert_eq!(output, *expected, "output of {}", source);
+        }
+    }
+
+    #[test]
+    fn test_nested_function_disassembly() {
+        let chunk = compile("fun outer() { fun inner() { return 1; } }");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_CLOSURE       0 '<fn outer>'
+0x0003    | OP_DEFINE_GLOBAL 0 'outer'
+0x0005    | OP_RETURN
+== constants ==
+   0 function '<fn outer>'
+== outer ==
+0x0000    1 OP_CLOSURE       0 '<fn inner>'
+0x0003    | OP_NIL
+0x0004    | OP_RETURN
+== constants ==
+   0 function '<fn inner>'
+  == inner ==
+0x0000    1 OP_CONSTANT      0 '1'
+0x0002    | OP_RETURN
+0x0003    | OP_NIL
+0x0004    | OP_RETURN
+== constants ==
+   0 number   '1'
+");
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let mut chunk = Chunk::new();
+        Compiler::new(&mut chunk).compile(parse("\
+var a = 1;
+{
+    var b = a;
+    if (b)
+        print b;
+}
+while (a and false)
+    a = 2;
+")).expect("Failed to compile source");
+
+        assert_eq!(disassemble_to_string(&chunk), "\
+0x0000    1 OP_CONSTANT      0 '1'
+0x0002    | OP_DEFINE_GLOBAL 0 'a'
+0x0004    3 OP_GET_GLOBAL    0 'a'
+0x0006    4 OP_GET_LOCAL     0
+0x0008    | OP_JUMP_IF_FALSE -> L0
+0x000b    | OP_POP
+0x000c    5 OP_GET_LOCAL     0
+0x000e    | OP_PRINT
+0x000f    4 OP_JUMP          -> L1
+L0:
+0x0012    | OP_POP
+L1:
+0x0013    | OP_POP
+L2:
+0x0014    7 OP_GET_GLOBAL    0 'a'
+0x0016    | OP_JUMP_IF_FALSE -> L3
+0x0019    | OP_POP
+0x001a    | OP_FALSE
+L3:
+0x001b    | OP_JUMP_IF_FALSE -> L4
+0x001e    | OP_POP
+0x001f    8 OP_CONSTANT      1 '2'
+0x0021    | OP_SET_GLOBAL    0 'a'
+0x0023    | OP_POP
+0x0024    7 OP_LOOP          -> L2
+L4:
+0x0027    | OP_POP
+0x0028    | OP_RETURN
+== constants ==
+   0 number   '1'
+   1 number   '2'
+");
+    }
+
+    #[test]
+    fn test_no_line_zero() {
+        let mut chunk = Chunk::new();
+        Compiler::new(&mut chunk).compile(parse("\
+fun counter() {
+    var count = 0;
+    fun increment() {
+        count = count + 1;
+        return count;
+    }
+    return increment;
+}
+class A {
+    init(a) { this.a = a; }
+    get() { return this.a; }
+}
+class B < A {
+    get() { return super.get() * 2; }
+}
+for (var i = 0; i < 3; i = i + 1) {
+    if (i == 1) continue;
+    print B(i).get() or counter()();
+}
+")).expect("Failed to compile source");
+
+        let output = disassemble_to_string(&chunk);
+        assert!(output.lines().all(|line| !line.starts_with("0x") || line.get(6..11) != Some("    0")), "Found an instruction on line 0 in:\n{}", output);
+    }
+
+    #[test]
+    fn test_runtime_error_line_in_function() {
+        let (_, result) = run("\
+class A {
+    value() {
+        return this.missing;
+    }
+}
+fun f(a) {
+    return a.value();
+}
+f(A());
+");
+
+        match result {
+            Err(VMError::Runtime { line: 3, error: RuntimeError::UndefinedProperty(_), .. }) => { },
+            result => panic!("Expected an error on line 3, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_line() {
+        let (_, result) = run("\
+var a = 1;
+var b = \"b\";
+{
+    var c = a;
+    if (c == 1)
+    {
+        print c - b;
+    }
+}
+");
+
+        match result {
+            Err(VMError::Runtime { line: 7, error: RuntimeError::ExpectedNumber, .. }) => { },
+            result => panic!("Expected an error on line 7, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_constant_long() {
+        // two constants per line, so later names and literals are well past the short operand's range
+        let source: String = (0..300).map(|i| format!("var s{} = \"v{}\";\n", i, i)).collect();
+        let (vm, result) = run(&format!("{}var last = s299; s0 = s299 + s1;", source));
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "s150"), "v150");
+        assert_eq!(global(&vm, "last"), "v299");
+        assert_eq!(global(&vm, "s0"), "v299v1");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_constant_long_functions_and_classes() {
+        // functions, classes, methods and property names all come after more than 256 constants
+        let source: String = (0..300).map(|i| format!("var s{} = {};\n", i, i)).collect();
+        let (output, result) = run_source_in_vm(&format!("{}\
+fun add(a, b) {{ return a + b; }}
+class Base {{ describe() {{ return \"base\"; }} }}
+class Point < Base {{
+    init(x) {{ this.x = x; }}
+    describe() {{ return super.describe() + \" point\"; }}
+    parent() {{ var describe = super.describe; return describe(); }}
+}}
+var point = Point(s299);
+point.x = add(point.x, 1);
+print point.x;
+print point.describe();
+print point.parent();
+", source));
+        result.expect("Failed to run script");
+        assert_eq!(output, "300\nbase point\nbase\n");
+
+        let chunk = compile(&format!("{}class A {{ m() {{ }} }} A().m(); A().f = 1;", source));
+        let disassembly = disassemble_to_string(&chunk);
+        for name in &["OP_CLOSURE_LONG", "OP_CLASS_LONG", "OP_METHOD_LONG", "OP_INVOKE_LONG", "OP_SET_PROPERTY_LONG"] {
+            assert!(disassembly.contains(name), "Expected {} in {}", name, disassembly);
+        }
+    }
+
+    #[test]
+    fn test_strings_are_interned() {
+        let (vm, result) = run("var a = \"ab\"; var b = \"a\" + \"b\"; var c = \"a\" + \"c\"; var equal = a == b; var different = a == c;");
+        result.expect("Failed to run script");
+
+        let global_value = |name: &str| {
+            let slot = vm.global_names.borrow_mut().resolve(name).unwrap();
+            vm.globals[slot as usize].clone().unwrap()
+        };
+        match (global_value("a"), global_value("b"), global_value("c")) {
+            (Value::Object(a), Value::Object(b), Value::Object(c)) => {
+                assert!(Rc::ptr_eq(&a, &b));
+                assert!(!Rc::ptr_eq(&a, &c));
+            },
+            values => panic!("Expected string objects, got {:?}", values),
+        }
+
+        assert_eq!(global(&vm, "equal"), "true");
+        assert_eq!(global(&vm, "different"), "false");
+    }
+
+    #[test]
+    fn test_undefined_global() {
+        let (_, result) = run("var a = 1;\nb = a;");
+        match result {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::UndefinedGlobal(name), .. }) => assert_eq!(name, "b"),
+            result => panic!("Expected b to be undefined, got {:?}", result),
+        }
+
+        // referenced before the definition runs, so the slot exists without a value
+        let (_, result) = run("fun f() { return c; }\nprint f();\nvar c = 1;");
+        match result {
+            Err(VMError::Runtime { line: 1, error: RuntimeError::UndefinedGlobal(name), .. }) => assert_eq!(name, "c"),
+            result => panic!("Expected c to be undefined, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_print_output() {
+        let cases = [
+            ("print 1 + 2 * 3;\nprint (1 + 2) * 3;\nprint 7 % 4 - -1;", "7\n9\n4\n"),
+            ("var a = \"a\";\nvar b = a + \"b\";\na = b + a;\nprint a;\nprint b;", "aba\nab\n"),
+            ("var a = \"global\";\n{ var a = \"outer\"; { var a = \"inner\"; print a; } print a; }\nprint a;", "inner\nouter\nglobal\n"),
+            ("if (1 < 2) print \"then\"; else print \"else\";\nif (nil) print \"then\"; else print \"else\";\nif (false) print \"skipped\";", "then\nelse\n"),
+            ("var i = 0;\nwhile (i < 3) { print i; i = i + 1; }\nprint \"done\";", "0\n1\n2\ndone\n"),
+        ];
+
+        for (source, expected) in cases.iter() {
+            let (output, result) = run_source_in_vm(source);
+            result.unwrap_or_else(|e| panic!("Failed to run {:?}: {}", source, e));
+            assert_eq!(&output, expected, "output of {:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_print_output_before_error() {
+        let (output, result) = run_source_in_vm("print 1;\nprint -nil;\nprint 2;");
+
+        assert_eq!(output, "1\n");
+        assert!(matches!(result, Err(VMError::Runtime { line: 2, error: RuntimeError::ExpectedNumber, .. })));
+    }
+
+    #[test]
+    fn test_print_write_error() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> { Err(std::io::ErrorKind::BrokenPipe.into()) }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let chunk = compile("print 1;");
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_output(Box::new(Closed));
+
+        match vm.run() {
+            Err(VMError::Output(err)) => assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe),
+            result => panic!("Expected an output error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_fuel() {
+        let chunk = compile("while (true) {}");
+
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_fuel(Some(1000));
+        match vm.run() {
+            Err(VMError::OutOfFuel { consumed: 1000 }) => { },
+            result => panic!("Expected to run out of fuel, got {:?}", result),
+        }
+        assert_eq!(vm.remaining_fuel(), Some(0));
+
+        // var a = 1; is a constant and a define, then the return
+        let chunk = compile("var a = 1;");
+
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_fuel(Some(10));
+        vm.run().expect("Failed to run script");
+        assert_eq!(vm.remaining_fuel(), Some(7));
+    }
+
+    #[test]
+    fn test_stats() {
+        let chunk = compile("fun inc(n) { return n + 1; }\nvar i = 0;\nwhile (i < 10) i = inc(i);");
+
+        let mut vm = VM::new(Rc::new(chunk));
+        assert_eq!(vm.stats(), ExecutionStats::default());
+        vm.set_stats(true);
+        vm.run().expect("Failed to run script");
+        let stats = vm.stats();
+
+        // the condition is checked once more than the body runs, and every iteration loops back
+        assert_eq!(stats.opcode_count("OP_JUMP_IF_FALSE"), 11);
+        assert_eq!(stats.opcode_count("OP_LOOP"), 10);
+        assert_eq!(stats.opcode_count("OP_CALL"), 10);
+        assert_eq!(stats.opcode_count("OP_JUMP"), 0);
+        assert_eq!(stats.instructions, stats.opcodes.iter().map(|&(_, count)| count).sum::<u64>());
+        assert!(stats.max_stack_depth <= 4, "max stack depth was {}", stats.max_stack_depth);
+
+        // get n, the constant, the add and the return
+        assert_eq!(stats.functions.iter().find(|(name, _)| name == "inc").map(|&(_, count)| count), Some(40));
+        assert_eq!(stats.functions.iter().map(|&(_, count)| count).sum::<u64>(), stats.instructions);
+        assert!(stats.opcodes.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        let table = stats.to_string();
+        assert!(table.contains("OP_LOOP                            10\n"), "{}", table);
+    }
+
+    #[test]
+    fn test_trace() {
+        let chunk = compile("var a = 1;\nprint -a;");
+
+        let trace = CapturedOutput::new();
+        let mut vm = VM::new(Rc::new(chunk));
+        vm.set_output(Box::new(CapturedOutput::new()));
+        vm.set_trace(Some(Box::new(trace.clone())));
+        vm.run().expect("Failed to run script");
+
+        let trace = trace.contents();
+        let ops: Vec<&str> = trace.lines().filter(|line| line.starts_with("0x")).map(|line| line[12..].split(' ').next().unwrap()).collect();
+        assert_eq!(ops, vec!["OP_CONSTANT", "OP_DEFINE_GLOBAL", "OP_GET_GLOBAL", "OP_NEGATE", "OP_PRINT", "OP_RETURN"]);
+        // each instruction follows the stack it's about to run on
+        assert!(trace.contains("          [1]\n0x0006    | OP_NEGATE\n"), "{}", trace);
+    }
+
+    #[test]
+    fn test_fuel_across_chunks() {
+        let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+        let compile = |source: &str| {
+            let mut chunk = Chunk::with_global_names(Rc::clone(&global_names));
+            Compiler::new(&mut chunk).compile(parse(source)).expect("Failed to compile source");
+            Rc::new(chunk)
+        };
+
+        let mut vm = VM::with_global_names(Rc::clone(&global_names));
+        vm.set_fuel(Some(7));
+        vm.interpret(compile("var a = 1;")).expect("Failed to run first chunk");
+        assert_eq!(vm.remaining_fuel(), Some(4));
+
+        // a get, a constant, the add, a define and the return is one more than is left
+        match vm.interpret(compile("var b = a + 1;")) {
+            Err(VMError::OutOfFuel { consumed: 7 }) => { },
+            result => panic!("Expected to run out of fuel, got {:?}", result),
+        }
+
+        vm.set_fuel(None);
+        vm.interpret(compile("var c = a + 1;")).expect("Failed to run without a fuel limit");
+        assert_eq!(vm.remaining_fuel(), None);
+        assert_eq!(global(&vm, "c"), "2");
+    }
+
+    #[test]
+    fn test_interpret_keeps_globals() {
+        let mut first = Chunk::new();
+        Compiler::new(&mut first).compile(parse("var a = 1;")).expect("Failed to compile source");
+
+        let mut second = Chunk::with_global_names(Rc::clone(first.global_names()));
+        Compiler::new(&mut second).compile(parse("var b = a + 1;")).expect("Failed to compile source");
+
+        let mut vm = VM::with_global_names(Rc::clone(first.global_names()));
+        vm.interpret(Rc::new(first)).expect("Failed to run first chunk");
+        vm.interpret(Rc::new(second)).expect("Failed to run second chunk");
+
+        assert_eq!(global(&vm, "b"), "2");
+    }
+
+    #[test]
+    fn test_interpret_after_error() {
+        let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+        let compile = |source: &str| {
+            let mut chunk = Chunk::with_global_names(Rc::clone(&global_names));
+            Compiler::new(&mut chunk).compile(parse(source)).expect("Failed to compile source");
+            Rc::new(chunk)
+        };
+
+        let mut vm = VM::with_global_names(Rc::clone(&global_names));
+        match vm.interpret(compile("var a = 1;\nprint 2 + -nil;")) {
+            Err(VMError::Runtime { line: 2, error: RuntimeError::ExpectedNumber, .. }) => { },
+            result => panic!("Expected ExpectedNumber, got {:?}", result),
+        }
+        // the failed negation left its operands behind
+        assert_eq!(vm.take_stack_snapshot().iter().map(Value::to_string).collect::<Vec<_>>(), vec!["2", "nil"]);
+
+        vm.interpret(compile("var b = a + 1;")).expect("Failed to run after an error");
+        assert_eq!(global(&vm, "b"), "2");
+        assert!(vm.take_stack_snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_interpret_repl_lines() {
+        // each line gets its own chunk and compiler, sharing only the global names, like the repl does
+        let global_names = Rc::new(RefCell::new(GlobalNames::new()));
+        let mut vm = VM::with_global_names(Rc::clone(&global_names));
+
+        for line in &["var a = \"first\";", "fun f() { return a + \" line\"; }", "var b = f();"] {
+            let mut chunk = Chunk::with_global_names(Rc::clone(&global_names));
+            Compiler::new(&mut chunk).compile(parse(line)).expect("Failed to compile line");
+
+            vm.interpret(Rc::new(chunk)).expect("Failed to run line");
+        }
+
+        assert_eq!(global(&vm, "b"), "first line");
+    }
+
+    #[test]
+    fn test_local_long() {
+        let locals: String = (0..300).map(|i| format!("var l{} = {};\n", i, i)).collect();
+        let (vm, result) = run(&format!("fun f() {{\n{}l299 = l299 + l0;\nreturn l299 + l1;\n}}\nvar result = f();", locals));
+
+        result.expect("Failed to run script");
+        assert_eq!(global(&vm, "result"), "300");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_deeply_nested_scopes() {
+        // the parser and compiler recurse per block, which needs more than a test thread's default stack in debug builds
+        let nested = std::thread::Builder::new().stack_size(64 * 1024 * 1024).spawn(|| {
+            let (vm, result) = run(&format!("var result;\n{}var x = 1; result = x;{}", "{".repeat(300), "}".repeat(300)));
+
+            result.expect("Failed to run script");
+            assert_eq!(global(&vm, "result"), "1");
+            assert_eq!(vm.stack.len(), 0);
+        }).unwrap();
+
+        nested.join().unwrap();
+    }
+
+    #[test]
+    fn test_captured_local_out_of_range() {
+        let locals: String = (0..300).map(|i| format!("var l{} = {};\n", i, i)).collect();
+        let mut chunk = Chunk::new();
+        let result = Compiler::new(&mut chunk).compile(parse(&format!("fun f() {{\n{}fun g() {{ return l299; }}\n}}", locals)));
+
+        match result {
+            Err(CompilerError::CapturedLocalOutOfRange(name)) => assert_eq!(name, "l299"),
+            _ => panic!("Expected capturing l299 to be out of range"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+use std::process::{ Command, Output };
+
+const PROGRAM: &str = "\
+fun make_counter() {
+    var count = 0;
+    fun increment() { count = count + 1; return count; }
+    return increment;
+}
+class Greeter {
+    init(name) { this.name = name; }
+    greet() { return \"hello \" + this.name; }
+}
+var counter = make_counter();
+for (var i = 0; i < 3; i = i + 1) print counter();
+print Greeter(\"lox\").greet();
+";
+const PROGRAM_OUTPUT: &str = "1\n2\n3\nhello lox\n";
+
+// a scratch directory per test, so tests running in parallel don't trample each other's files
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rlox-compiler-cli-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).expect("Failed to create scratch directory");
+    dir
+}
+
+fn rlox_compiler(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox-compiler"))
+        .args(args)
+        .output()
+        .expect("Failed to start rlox-compiler")
+}
+
+#[test]
+fn test_build_then_run() {
+    let dir = scratch_dir("build_then_run");
+    let source = dir.join("script.lox");
+    let bytecode = dir.join("out.loxc");
+    std::fs::write(&source, PROGRAM).unwrap();
+
+    let output = rlox_compiler(&["build", source.to_str().unwrap(), "-o", bytecode.to_str().unwrap()]);
+    assert!(output.status.success(), "build failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(bytecode.exists());
+
+    let output = rlox_compiler(&["run", bytecode.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), PROGRAM_OUTPUT);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_build_default_output() {
+    let dir = scratch_dir("build_default_output");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, PROGRAM).unwrap();
+
+    let output = rlox_compiler(&["build", source.to_str().unwrap()]);
+    assert!(output.status.success(), "build failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("script.loxc").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_run_source() {
+    let dir = scratch_dir("run_source");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, PROGRAM).unwrap();
+
+    let output = rlox_compiler(&["run", source.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), PROGRAM_OUTPUT);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_print_nested_array() {
+    let dir = scratch_dir("print_nested_array");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, "print [1, [2, 3]];").unwrap();
+
+    let output = rlox_compiler(&["run", source.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[1, [2, 3]]\n");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_disasm_json() {
+    let dir = scratch_dir("disasm_json");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, "var a = 1;\nif (a) print a;\n").unwrap();
+
+    let output = rlox_compiler(&["disasm", "--json", source.to_str().unwrap()]);
+    assert!(output.status.success(), "disasm failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), r#"[
+  {"offset": 0, "length": 2, "line": 1, "name": "OP_CONSTANT", "operands": [{"type": "constant", "index": 0, "value": "1"}]},
+  {"offset": 2, "length": 2, "line": 1, "name": "OP_DEFINE_GLOBAL", "operands": [{"type": "global", "slot": 0, "name": "a"}]},
+  {"offset": 4, "length": 2, "line": 2, "name": "OP_GET_GLOBAL", "operands": [{"type": "global", "slot": 0, "name": "a"}]},
+  {"offset": 6, "length": 3, "line": 2, "name": "OP_JUMP_IF_FALSE", "operands": [{"type": "jump", "distance": 7, "backward": false, "target": 16}]},
+  {"offset": 9, "length": 1, "line": 2, "name": "OP_POP", "operands": []},
+  {"offset": 10, "length": 2, "line": 2, "name": "OP_GET_GLOBAL", "operands": [{"type": "global", "slot": 0, "name": "a"}]},
+  {"offset": 12, "length": 1, "line": 2, "name": "OP_PRINT", "operands": []},
+  {"offset": 13, "length": 3, "line": 2, "name": "OP_JUMP", "operands": [{"type": "jump", "distance": 1, "backward": false, "target": 17}]},
+  {"offset": 16, "length": 1, "line": 2, "name": "OP_POP", "operands": []},
+  {"offset": 17, "length": 1, "line": 2, "name": "OP_RETURN", "operands": []}
+]
+"#);
+
+    let output = rlox_compiler(&["disasm", source.to_str().unwrap()]);
+    assert!(output.status.success(), "disasm failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("OP_JUMP_IF_FALSE -> L0\n"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_run_stats() {
+    let dir = scratch_dir("run_stats");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, "var i = 0;\nwhile (i < 5) i = i + 1;\nprint i;").unwrap();
+
+    let output = rlox_compiler(&["run", "--stats", source.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "5\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("OP_LOOP                             5\n"), "stats missing from: {}", stderr);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_run_trace() {
+    let dir = scratch_dir("run_trace");
+    let source = dir.join("script.lox");
+    std::fs::write(&source, "print 1 + 2;").unwrap();
+
+    let output = rlox_compiler(&["run", source.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    let output = rlox_compiler(&["run", "--trace", "--stats", source.to_str().unwrap()]);
+    assert!(output.status.success(), "run failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("          [1][2]\n0x0004    | OP_ADD\n"), "trace missing from: {}", stderr);
+    assert!(stderr.contains("OP_ADD                              1\n"), "stats missing from: {}", stderr);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = scratch_dir("exit_codes");
+
+    let source = dir.join("compile_error.lox");
+    std::fs::write(&source, "var 1;").unwrap();
+    assert_eq!(rlox_compiler(&["build", source.to_str().unwrap()]).status.code(), Some(65));
+    let output = rlox_compiler(&["run", source.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Error: [line 1] Error at '1': ExpectedIdentifier"));
+
+    let source = dir.join("runtime_error.lox");
+    std::fs::write(&source, "print 1;\nprint -\"a\";").unwrap();
+    let output = rlox_compiler(&["run", source.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Error: [line 2] Operands must be numbers.\n  at 0x0005 OP_NEGATE\n"));
+
+    let bytecode = dir.join("corrupt.loxc");
+    std::fs::write(&bytecode, "print 1;").unwrap();
+    let output = rlox_compiler(&["run", bytecode.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("InvalidMagic"));
+
+    assert_eq!(rlox_compiler(&["run", dir.join("missing.lox").to_str().unwrap()]).status.code(), Some(66));
+    assert_eq!(rlox_compiler(&["run"]).status.code(), Some(64));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,55 @@
+use std::rc::Rc;
+use rlox_compiler::{ Chunk, Compiler, CompilerError, VM, VMError };
+use rlox_test_utils::parse;
+
+#[test]
+fn test_inspect_locals() {
+    let mut chunk = Chunk::new();
+    let mut compiler = Compiler::new(&mut chunk);
+    assert_eq!(compiler.local_count(), 0);
+    assert_eq!(compiler.current_scope_depth(), 0);
+
+    // globals aren't locals, and every block's locals are gone once it's compiled
+    compiler.compile(parse("var g = 1; { var a = 1; { var b = 2; } }")).expect("Failed to compile source");
+    assert_eq!(compiler.local_count(), 0);
+    assert_eq!(compiler.current_scope_depth(), 0);
+
+    // an error part way through leaves the scopes it was in open
+    match compiler.compile(parse("{ var a = 1; { var b = 2; print this; } }")) {
+        Err(CompilerError::ThisOutsideClass) => { },
+        result => panic!("Expected ThisOutsideClass, got {:?}", result),
+    }
+    assert_eq!(compiler.local_count(), 2);
+    assert_eq!(compiler.local_name(0), Some("a"));
+    assert_eq!(compiler.local_name(1), Some("b"));
+    assert_eq!(compiler.local_name(2), None);
+    assert_eq!(compiler.current_scope_depth(), 2);
+}
+
+fn compile_files(files: &[(&str, &str)]) -> Chunk {
+    let mut chunk = Chunk::new();
+    let files = files.iter().map(|(name, source)| (name.to_string(), parse(source))).collect();
+    Compiler::new(&mut chunk).compile_multiple_files(files).expect("Failed to compile files");
+
+    chunk
+}
+
+#[test]
+fn test_multiple_files() {
+    let lib = "var greeting = \"hello\";\nfun fail(value) {\n  return -value;\n}";
+
+    // an error at the top level of the second file
+    let chunk = compile_files(&[("lib.lox", lib), ("main.lox", "print greeting;\nprint -greeting;")]);
+    assert_eq!(chunk.location(0), (Some("lib.lox"), 1));
+    match VM::new(Rc::new(chunk)).run() {
+        Err(err @ VMError::Runtime { .. }) => assert_eq!(err.to_string(), "[line 2 in 'main.lox'] Operands must be numbers."),
+        result => panic!("Expected a runtime error, got {:?}", result),
+    }
+
+    // an error inside a function declared in the first file, called from the second
+    let chunk = compile_files(&[("lib.lox", lib), ("main.lox", "\n\nfail(nil);")]);
+    match VM::new(Rc::new(chunk)).run() {
+        Err(err @ VMError::Runtime { .. }) => assert_eq!(err.to_string(), "[line 3 in 'lib.lox'] Operands must be numbers."),
+        result => panic!("Expected a runtime error, got {:?}", result),
+    }
+}
@@ -1,32 +1,81 @@
-use std::fmt::{Display, Formatter, Error};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Display, Formatter, Error},
+    rc::Rc,
+};
 use rlox_scanner::SourceToken;
 use rlox_parser::Func;
 use crate::{
     Interpreter,
     RuntimeError,
 
+    function::FunctionDefinition,
+    interpreter::Environment,
     value::{ Callable, Value },
 };
-use crate::function::FunctionDefinition;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ClassDefinition {
-    name: SourceToken
+    name: SourceToken,
+    superclass: Option<Rc<ClassDefinition>>,
+    methods: Rc<HashMap<String, Rc<FunctionDefinition>>>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    class: ClassDefinition,
+    fields: HashMap<String, Value>,
 }
 
 impl ClassDefinition {
-    pub fn new(name: &SourceToken, functions: &Vec<Func>) -> ClassDefinition {
-        unimplemented!()
+    pub fn new(name: &SourceToken, superclass: Option<ClassDefinition>, functions: &Vec<Func>, closure: Rc<RefCell<Environment>>) -> ClassDefinition {
+        let mut methods = HashMap::new();
+        for function in functions {
+            let definition = FunctionDefinition::new_method(function, closure.clone());
+            methods.insert(function.name.lexeme.clone(), Rc::new(definition));
+        }
+
+        ClassDefinition {
+            name: name.clone(),
+            superclass: superclass.map(Rc::new),
+            methods: Rc::new(methods),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    // methods the class doesn't define itself are inherited from its superclass
+    pub fn find_method(&self, name: &str) -> Option<Rc<FunctionDefinition>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self.superclass.as_ref().and_then(|superclass| superclass.find_method(name)),
+        }
     }
 }
 
 impl Callable for ClassDefinition {
     fn arity(&self) -> usize {
-        unimplemented!()
+        self.find_method("init").map(|init| init.arity()).unwrap_or(0)
     }
 
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
-        unimplemented!()
+        let instance = Value::Instance(Rc::new(RefCell::new(Instance::new(self.clone()))));
+
+        if let Some(init) = self.find_method("init") {
+            init.bind(instance.clone()).call(interpreter, arguments)?;
+        }
+
+        Ok(instance)
+    }
+
+    fn type_name(&self) -> &'static str {
+        "class"
+    }
+    fn as_class(&self) -> Option<&ClassDefinition> {
+        Some(self)
     }
 }
 
@@ -34,4 +83,56 @@ impl Display for ClassDefinition {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "{}", self.name.lexeme)
     }
-}
\ No newline at end of file
+}
+
+impl Instance {
+    pub fn new(class: ClassDefinition) -> Instance {
+        Instance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn class(&self) -> &ClassDefinition {
+        &self.class
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+    pub fn set_field(&mut self, name: String, value: Value) {
+        self.fields.insert(name, value);
+    }
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "{} instance", self.class.name.lexeme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use rlox_test_utils::parse;
+    use super::*;
+
+
+    fn ident(name: &str) -> SourceToken {
+        SourceToken { token: Token::Identifier(name.into()), lexeme: name.into(), line: 0 }
+    }
+
+    #[test]
+    fn test_class_declaration() {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(parse("class Foo { bar() { return 1; } } var i = Foo();")).expect("Failed to run script");
+
+        let class = interpreter.environment().borrow().get(&ident("Foo")).unwrap();
+        assert_eq!(class.to_string(), "Foo");
+        assert_eq!(class.type_name(), "class");
+
+        let instance = interpreter.environment().borrow().get(&ident("i")).unwrap();
+        assert_eq!(instance.to_string(), "Foo instance");
+        assert_eq!(instance.type_name(), "instance");
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt::{ self, Display, Formatter };
 use rlox_scanner::SourceToken;
 use crate::Value;
 
@@ -5,21 +6,113 @@ use crate::Value;
 pub struct RuntimeError {
     pub token: SourceToken,
     pub description: RuntimeErrorDescription,
+    // functions the error unwound through, innermost first
+    pub call_stack: Vec<StackFrame>,
 }
 
 impl RuntimeError {
     pub fn new(token: SourceToken, description: RuntimeErrorDescription) -> RuntimeError {
-        RuntimeError { token, description }
+        RuntimeError { token, description, call_stack: Vec::new() }
     }
 }
 
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] {}", self.token.line, self.description)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StackFrame {
+    pub name: String,
+    pub line: usize,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum RuntimeErrorDescription {
     Message(String),
-    ExpectedNumber,
-    InvalidAdditionArguments(Value, Value),
+    // the value that wasn't a number, boxed for the same reason as the addition arguments
+    ExpectedNumber(Box<Value>),
+    // boxed so the call stack fits without making every result carrying an error bigger
+    InvalidAdditionArguments(Box<Value>, Box<Value>),
     DivideByZero,
-    UndefinedVariable,
+    UndefinedVariable(String),
+    UndefinedProperty(String),
+    ExpectedInstance,
+    VariableAlreadyDeclared(String),
     CalleeNotCallable,
-    UnexpectedNumberOfArguments { expected: usize, provided: usize },
-}
\ No newline at end of file
+    // the callee's name when it was called by name, i.e. not through an arbitrary expression or by a native. boxed
+    // for the same reason as the addition arguments
+    UnexpectedNumberOfArguments { expected: usize, provided: usize, callee_name: Option<Box<String>> },
+    NotEnoughValuesToUnpack { expected: usize, provided: usize },
+    Exit(i32),
+    AssertionFailed(Option<String>),
+}
+
+// full sentences worded like the VM's runtime errors, so the same failure reads the same in both
+impl Display for RuntimeErrorDescription {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use RuntimeErrorDescription::*;
+
+        match self {
+            Message(message) => f.write_str(message),
+            ExpectedNumber(value) => write!(f, "Expected a number but got '{}'", value),
+            InvalidAdditionArguments(left, right) => write!(f, "Cannot add '{}' and '{}'", left, right),
+            DivideByZero => f.write_str("Division by zero"),
+            UndefinedVariable(name) => write!(f, "Undefined variable '{}'", name),
+            UndefinedProperty(name) => write!(f, "Undefined property '{}'", name),
+            ExpectedInstance => f.write_str("Only instances have properties"),
+            VariableAlreadyDeclared(name) => write!(f, "Variable '{}' is already declared in this scope", name),
+            CalleeNotCallable => f.write_str("Can only call functions and classes"),
+            UnexpectedNumberOfArguments { expected, provided, callee_name: Some(name) } => write!(f, "'{}' expected {} arguments but got {}", name, expected, provided),
+            UnexpectedNumberOfArguments { expected, provided, callee_name: None } => write!(f, "Expected {} arguments but got {}", expected, provided),
+            NotEnoughValuesToUnpack { expected, provided } => write!(f, "Expected {} values to unpack but got {}", expected, provided),
+            Exit(code) => write!(f, "Exited with code {}", code),
+            AssertionFailed(Some(message)) => write!(f, "Assertion failed: {}", message),
+            AssertionFailed(None) => f.write_str("Assertion failed"),
+        }?;
+
+        f.write_str(".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use super::*;
+
+    #[test]
+    fn test_display_description() {
+        use RuntimeErrorDescription::*;
+
+        let cases = vec![
+            (Message("custom".into()), "custom."),
+            (ExpectedNumber(Box::new(Value::String("a".into()))), "Expected a number but got 'a'."),
+            (InvalidAdditionArguments(Box::new(Value::Number(1f64)), Box::new(Value::Nil)), "Cannot add '1' and 'nil'."),
+            (DivideByZero, "Division by zero."),
+            (UndefinedVariable("a".into()), "Undefined variable 'a'."),
+            (UndefinedProperty("b".into()), "Undefined property 'b'."),
+            (ExpectedInstance, "Only instances have properties."),
+            (VariableAlreadyDeclared("c".into()), "Variable 'c' is already declared in this scope."),
+            (CalleeNotCallable, "Can only call functions and classes."),
+            (UnexpectedNumberOfArguments { expected: 2, provided: 1, callee_name: Some(Box::new("f".into())) }, "'f' expected 2 arguments but got 1."),
+            (UnexpectedNumberOfArguments { expected: 0, provided: 3, callee_name: None }, "Expected 0 arguments but got 3."),
+            (NotEnoughValuesToUnpack { expected: 3, provided: 2 }, "Expected 3 values to unpack but got 2."),
+            (Exit(4), "Exited with code 4."),
+            (AssertionFailed(Some("x > 0".into())), "Assertion failed: x > 0."),
+            (AssertionFailed(None), "Assertion failed."),
+        ];
+
+        for (description, expected) in cases {
+            assert_eq!(description.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_error() {
+        let token = SourceToken { token: Token::Identifier("a".into()), lexeme: "a".into(), line: 3 };
+        let error = RuntimeError::new(token, RuntimeErrorDescription::UndefinedVariable("a".into()));
+
+        assert_eq!(error.to_string(), "[line 3] Undefined variable 'a'.");
+    }
+}
@@ -1,3 +1,4 @@
+use std::rc::Rc;
 use rlox_scanner::{ SourceToken, Token };
 use rlox_parser::Expr;
 use crate::{
@@ -15,14 +16,69 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
         Expr::Number(_, value) => Ok(Value::Number(*value)),
         Expr::String(_, value) => Ok(Value::String(value.clone())),
 
-        Expr::Var(name) => {
+        Expr::Var(name) | Expr::This(name) => {
             let value = interpreter.environment().borrow().get(name)?;
 
             Ok((*value).clone())
         },
 
+        Expr::Get(object_expr, name) => {
+            let instance = match evaluate(interpreter, object_expr)? {
+                Value::Instance(instance) => instance,
+                _ => return Err(RuntimeError::new(name.clone(), RuntimeErrorDescription::ExpectedInstance)),
+            };
+
+            // fields shadow methods of the same name
+            if let Some(value) = instance.borrow().get_field(&name.lexeme) {
+                return Ok(value);
+            }
+
+            let method = instance.borrow().class().find_method(&name.lexeme);
+            match method {
+                Some(method) => Ok(Value::Function(Rc::new(method.bind(Value::Instance(instance))))),
+                None => Err(RuntimeError::new(name.clone(), RuntimeErrorDescription::UndefinedProperty(name.lexeme.clone()))),
+            }
+        },
+        Expr::Super(keyword, name) => {
+            // outside a class there's no `super` at all, and in a class without a superclass it's nil
+            let superclass = interpreter.environment().borrow().get(keyword).ok();
+            let superclass = match superclass.as_deref() {
+                Some(Value::Function(callable)) if callable.as_class().is_some() => callable.clone(),
+                _ => return Err(RuntimeError::new(keyword.clone(), RuntimeErrorDescription::Message(String::from("Can't use 'super' in a class with no superclass")))),
+            };
+
+            let this = SourceToken { token: Token::This, lexeme: String::from("this"), line: keyword.line };
+            let instance = (*interpreter.environment().borrow().get(&this)?).clone();
+
+            let method = superclass.as_class().and_then(|superclass| superclass.find_method(&name.lexeme));
+            match method {
+                Some(method) => Ok(Value::Function(Rc::new(method.bind(instance)))),
+                None => Err(RuntimeError::new(name.clone(), RuntimeErrorDescription::UndefinedProperty(name.lexeme.clone()))),
+            }
+        },
+        Expr::Set(object_expr, name, value_expr) => {
+            let instance = match evaluate(interpreter, object_expr)? {
+                Value::Instance(instance) => instance,
+                _ => return Err(RuntimeError::new(name.clone(), RuntimeErrorDescription::ExpectedInstance)),
+            };
+
+            let value = evaluate(interpreter, value_expr)?;
+            instance.borrow_mut().set_field(name.lexeme.clone(), value.clone());
+
+            Ok(value)
+        },
+
         Expr::Grouping(expr) => evaluate(interpreter, expr),
 
+        Expr::List(_, element_exprs) => {
+            let mut elements = Vec::new();
+            for expr in element_exprs {
+                elements.push(evaluate(interpreter, expr)?);
+            }
+
+            Ok(Value::new_list(elements))
+        },
+
         Expr::Unary(op, expr) => {
             let value = evaluate(interpreter, expr)?;
 
@@ -47,6 +103,14 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
             }
         },
 
+        Expr::Ternary(condition, _, then_branch, else_branch) => {
+            if evaluate(interpreter, condition)?.is_truthy() {
+                evaluate(interpreter, then_branch)
+            } else {
+                evaluate(interpreter, else_branch)
+            }
+        },
+
         Expr::Binary(left_expr, op, right_expr) => {
             let left = evaluate(interpreter, left_expr)?;
             let right = evaluate(interpreter, right_expr)?;
@@ -57,7 +121,7 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
                     (Value::String(left), right) => Ok(Value::String(left + &right.to_string())),
                     (left, Value::String(right)) => Ok(Value::String(left.to_string() + &right)),
 
-                    (left, right) => Err(RuntimeError::new(op.clone(), RuntimeErrorDescription::InvalidAdditionArguments(left, right)))
+                    (left, right) => Err(RuntimeError::new(op.clone(), RuntimeErrorDescription::InvalidAdditionArguments(Box::new(left), Box::new(right))))
                 },
                 Token::Minus => Ok(Value::Number(cast_to_number(op, left)? - cast_to_number(op, right)?)),
                 Token::Star => Ok(Value::Number(cast_to_number(op, left)? * cast_to_number(op, right)?)),
@@ -71,11 +135,21 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
                         Ok(Value::Number(left / right))
                     }
                 },
+                Token::Percent => {
+                    let left = cast_to_number(op, left)?;
+                    let right = cast_to_number(op, right)?;
+
+                    if right == 0f64 {
+                        Err(RuntimeError::new(op.clone(), RuntimeErrorDescription::DivideByZero))
+                    } else {
+                        Ok(Value::Number(left % right))
+                    }
+                },
 
-                Token::Greater => Ok(Value::Boolean(cast_to_number(op, left)? > cast_to_number(op, right)?)),
-                Token::GreaterEqual => Ok(Value::Boolean(cast_to_number(op, left)? >= cast_to_number(op, right)?)),
-                Token::Less => Ok(Value::Boolean(cast_to_number(op, left)? < cast_to_number(op, right)?)),
-                Token::LessEqual => Ok(Value::Boolean(cast_to_number(op, left)? <= cast_to_number(op, right)?)),
+                Token::Greater => compare(op, left, right, |left, right| left > right, |left, right| left > right),
+                Token::GreaterEqual => compare(op, left, right, |left, right| left >= right, |left, right| left >= right),
+                Token::Less => compare(op, left, right, |left, right| left < right, |left, right| left < right),
+                Token::LessEqual => compare(op, left, right, |left, right| left <= right, |left, right| left <= right),
 
                 Token::BangEqual => Ok(Value::Boolean(!left.is_equal(&right))),
                 Token::EqualEqual => Ok(Value::Boolean(left.is_equal(&right))),
@@ -96,11 +170,24 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
             let function = callee.as_callable()
                 .map_err(|_| RuntimeError::new(paren.clone(), RuntimeErrorDescription::CalleeNotCallable))?;
 
-            if arguments.len() != function.arity() {
-                return Err(RuntimeError::new(paren.clone(), RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: function.arity(), provided: arguments.len() }))
+            // point arity errors at the function being called rather than the closing paren where possible
+            let arity_error = |expected| {
+                let callee_name = callee_name(callee_expr);
+                let token = callee_name.unwrap_or(paren).clone();
+                let callee_name = callee_name.map(|name| Box::new(name.lexeme.clone()));
+
+                RuntimeError::new(token, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected, provided: arguments.len(), callee_name })
+            };
+            if arguments.len() < function.arity() {
+                return Err(arity_error(function.arity()));
+            }
+            if arguments.len() > function.max_arity() {
+                return Err(arity_error(function.max_arity()));
             }
 
+            // natives don't know where they were called from, so attribute their errors to the call site
             function.call(interpreter, arguments)
+                .map_err(|e| if e.token == SourceToken::default() { RuntimeError::new(paren.clone(), e.description) } else { e })
         },
 
         Expr::Assign(name, expr) => {
@@ -113,13 +200,29 @@ pub fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> EvaluateResult<Va
     }
 }
 
+// two strings compare lexicographically, anything else has to be a pair of numbers
+fn compare(token: &SourceToken, left: Value, right: Value, number_op: fn(f64, f64) -> bool, string_op: fn(&str, &str) -> bool) -> EvaluateResult<Value> {
+    match (&left, &right) {
+        (Value::String(left), Value::String(right)) => Ok(Value::Boolean(string_op(left, right))),
+        _ => Ok(Value::Boolean(number_op(cast_to_number(token, left)?, cast_to_number(token, right)?))),
+    }
+}
+
 fn cast_to_number(token: &SourceToken, value: Value) -> Result<f64, RuntimeError> {
-    value.as_number().map_err(|_| RuntimeError::new(token.clone(), RuntimeErrorDescription::ExpectedNumber))
+    value.as_number_with_token(token)
+}
+
+// the name a function is being called by, for the common `f()`, `a.f()` and `super.f()` forms
+fn callee_name(expr: &Expr) -> Option<&SourceToken> {
+    match expr {
+        Expr::Var(name) | Expr::Get(_, name) | Expr::Super(_, name) => Some(name),
+
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
     use rlox_scanner::{ SourceToken };
 
     use super::*;
@@ -180,6 +283,7 @@ mod tests {
         assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Minus), Box::new(expr_num(4f64)))), Value::Number(4f64));
         assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Star), Box::new(expr_num(4f64)))), Value::Number(32f64));
         assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Slash), Box::new(expr_num(4f64)))), Value::Number(2f64));
+        assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Percent), Box::new(expr_num(3f64)))), Value::Number(2f64));
 
         assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_str("ab".into())), tok_to_src(Token::Plus), Box::new(expr_str("cd".into())))), Value::String("abcd".into()));
         assert_eq!(evaluate_expect(&Expr::Binary(Box::new(expr_str("ab".into())), tok_to_src(Token::Plus), Box::new(expr_num(34f64)))), Value::String("ab34".into()));
@@ -217,6 +321,9 @@ mod tests {
 
         let result = evaluate(&mut interpreter, &Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Slash), Box::new(expr_num(0f64))));
         assert!(result.is_err());
+
+        let result = evaluate(&mut interpreter, &Expr::Binary(Box::new(expr_num(8f64)), tok_to_src(Token::Percent), Box::new(expr_num(0f64))));
+        assert!(result.is_err());
     }
 
     #[test]
@@ -3,11 +3,12 @@ use std::{
     fmt::{ Display, Formatter, Error },
     rc::Rc,
 };
-use rlox_scanner::SourceToken;
+use rlox_scanner::{ SourceToken, Token };
 use rlox_parser::{ Func, Stmt };
 use crate::{
     Interpreter,
     RuntimeError,
+    StackFrame,
 
     interpreter::{Environment, StmtResult},
     value::{Callable, Value},
@@ -19,6 +20,8 @@ pub struct FunctionDefinition {
     pub parameters: Vec<SourceToken>,
     pub body: Vec<Stmt>,
     pub closure: Rc<RefCell<Environment>>,
+    // initializers always return `this`, whatever the body returns
+    pub is_initializer: bool,
 }
 
 impl FunctionDefinition {
@@ -28,9 +31,30 @@ impl FunctionDefinition {
             parameters: func.parameters.clone(),
             body: func.body.clone(),
             closure,
+            is_initializer: false,
         }
 
     }
+    pub fn new_method(func: &Func, closure: Rc<RefCell<Environment>>) -> FunctionDefinition {
+        FunctionDefinition {
+            is_initializer: func.name.lexeme == "init",
+            ..FunctionDefinition::new(func, closure)
+        }
+    }
+
+    // wraps the closure in an environment with `this` bound to the instance the method was accessed through
+    pub fn bind(&self, instance: Value) -> FunctionDefinition {
+        let mut environment = Environment::new_with_parent(self.closure.clone());
+        environment.define(String::from("this"), instance);
+
+        FunctionDefinition {
+            name: self.name.clone(),
+            parameters: self.parameters.clone(),
+            body: self.body.clone(),
+            closure: Rc::new(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
 }
 
 impl Callable for FunctionDefinition {
@@ -48,7 +72,17 @@ impl Callable for FunctionDefinition {
 
         let environment = Rc::new(RefCell::new(environment));
 
-        let result = interpreter.evaluate_block(&self.body, environment)?;
+        let _call = interpreter.enter_call();
+        let result = interpreter.evaluate_block(&self.body, environment)
+            .map_err(|mut e| {
+                e.call_stack.push(StackFrame { name: self.name.lexeme.clone(), line: self.name.line });
+                e
+            })?;
+        if self.is_initializer {
+            let this = SourceToken { token: Token::This, lexeme: String::from("this"), line: self.name.line };
+            return Ok((*self.closure.borrow().get(&this)?).clone());
+        }
+
         let value = if let StmtResult::Return(value) = result { value } else { Value::Nil };
 
         Ok(value)
@@ -59,4 +93,63 @@ impl Display for FunctionDefinition {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "<fn {}>", &self.name.lexeme)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use rlox_test_utils::{ assert_lox_error, parse };
+    use super::*;
+
+    // records the deepest call depth it was called at
+    #[derive(Debug, Default)]
+    struct DepthProbe {
+        max_depth: Rc<Cell<usize>>,
+    }
+
+    impl Callable for DepthProbe {
+        fn arity(&self) -> usize {
+            0
+        }
+
+        fn call(&self, interpreter: &mut Interpreter, _: Vec<Value>) -> Result<Value, RuntimeError> {
+            self.max_depth.set(self.max_depth.get().max(interpreter.call_depth()));
+            Ok(Value::Nil)
+        }
+    }
+
+    impl Display for DepthProbe {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "<native fn>")
+        }
+    }
+
+    fn interpreter_with_probe() -> (Interpreter, Rc<Cell<usize>>) {
+        let max_depth = Rc::new(Cell::new(0));
+        let interpreter = Interpreter::new();
+        interpreter.global_environment().borrow_mut().define(String::from("probe"), Value::Function(Rc::new(DepthProbe { max_depth: max_depth.clone() })));
+
+        (interpreter, max_depth)
+    }
+
+    #[test]
+    fn test_call_depth() {
+        let (mut interpreter, max_depth) = interpreter_with_probe();
+        interpreter.interpret(parse("fun f(n) { probe(); if (n > 1) f(n - 1); } f(5);")).expect("Failed to run script");
+
+        assert_eq!(max_depth.get(), 5);
+        assert_eq!(interpreter.call_depth(), 0);
+    }
+
+    #[test]
+    fn test_call_depth_after_error() {
+        let source = "fun f(n) { if (n > 1) f(n - 1); else -\"a\"; } f(3);";
+        assert_lox_error!(source, ExpectedNumber(_));
+
+        // the error is raised three calls deep, and unwinding each of them restores the depth
+        let (mut interpreter, max_depth) = interpreter_with_probe();
+        let error = interpreter.interpret(parse("fun f(n) { probe(); if (n > 1) f(n - 1); else -\"a\"; } f(3);")).unwrap_err();
+        assert_eq!(max_depth.get(), 3);
+        assert_eq!(error.call_stack.len(), 3);
+        assert_eq!(interpreter.call_depth(), 0);
+    }
+}
@@ -1,7 +1,9 @@
 use std::{
-    cell::RefCell,
+    cell::{ Cell, RefCell },
     collections::HashMap,
+    io::Write,
     rc::Rc,
+    time::Instant,
 };
 use rlox_scanner::{ SourceToken, Token };
 use rlox_parser::Stmt;
@@ -15,32 +17,62 @@ use crate::{
     expression::evaluate,
     function::FunctionDefinition,
     native,
+    output::CapturedOutput,
 };
 
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
     global_environment: Rc<RefCell<Environment>>,
+
+    started: Instant,
+    output: Box<dyn Write>,
+    strict: bool,
+    // shared with the guards held by running function calls, see `enter_call`
+    call_depth: Rc<Cell<usize>>,
 }
 
+#[derive(Debug)]
 pub enum StmtResult {
     None,
+    // the value of an expression statement, so the REPL can echo it
+    Value(Value),
     Return(Value),
+    // unwinds to the nearest enclosing loop
+    Break,
+    Continue,
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        let mut globals = Environment::new();
+        Interpreter::with_standard_natives()
+    }
 
-        native::define_functions(&mut globals);
+    pub fn with_standard_natives() -> Interpreter {
+        let mut interpreter = Interpreter::new_bare();
+        interpreter.register_standard_natives();
+
+        interpreter
+    }
 
-        let env = Rc::new(RefCell::new(globals));
+    /// Creates an interpreter without any natives defined, embedders can then choose exactly what is available
+    pub fn new_bare() -> Interpreter {
+        let env = Rc::new(RefCell::new(Environment::new()));
 
         Interpreter {
             environment: env.clone(),
             global_environment: env.clone(),
+
+            started: Instant::now(),
+            output: Box::new(std::io::stdout()),
+            strict: false,
+            call_depth: Rc::new(Cell::new(0)),
         }
     }
 
+    pub fn register_standard_natives(&mut self) {
+        native::define_functions(&mut self.global_environment.borrow_mut());
+    }
+
 
     pub fn environment(&self) -> Rc<RefCell<Environment>> {
         self.environment.clone()
@@ -48,30 +80,140 @@ impl Interpreter {
     pub fn global_environment(&self) -> Rc<RefCell<Environment>> {
         self.global_environment.clone()
     }
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    // how many lox functions are currently running, 0 at the top level of a script
+    pub fn call_depth(&self) -> usize {
+        self.call_depth.get()
+    }
+    // counts a call as running until the returned guard is dropped, however the call exits
+    pub(crate) fn enter_call(&self) -> CallDepthGuard {
+        self.call_depth.set(self.call_depth.get() + 1);
+
+        CallDepthGuard { call_depth: Rc::clone(&self.call_depth) }
+    }
+
+    // in strict mode re-declaring a variable in the same scope is an error rather than overwriting it
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+    // redirects all output into a buffer which can be read back from the returned handle
+    pub fn capture_output(&mut self) -> CapturedOutput {
+        let output = CapturedOutput::new();
+        self.set_output(Box::new(output.clone()));
+
+        output
+    }
 
     pub fn interpret(&mut self, statements: Vec<Stmt>) -> EvaluateResult<StmtResult> {
         let mut result = StmtResult::None;
         for statement in statements {
-            result = self.evaluate_stmt(&statement)?;
+            result = self.interpret_stmt(&statement)?;
         }
 
         Ok(result)
     }
 
-    fn evaluate_stmt(&mut self, stmt: &Stmt) -> EvaluateResult<StmtResult> {
+    // runs a single statement in the current environment, for embedders feeding statements in one at a time
+    pub fn interpret_stmt(&mut self, stmt: &Stmt) -> EvaluateResult<StmtResult> {
         match stmt {
-            Stmt::Class(name, functions) => {
-                self.environment.borrow_mut().define(name.lexeme.clone(), Value::Nil);
+            Stmt::Class(name, superclass, functions) => {
+                let superclass = match superclass {
+                    Some(superclass) if superclass.lexeme == name.lexeme => {
+                        return Err(RuntimeError::new(superclass.clone(), RuntimeErrorDescription::Message(String::from("A class can't inherit from itself"))));
+                    },
+                    Some(superclass) => {
+                        let value = self.environment.borrow().get(superclass)?;
+                        let class = match &*value {
+                            Value::Function(callable) => callable.as_class().cloned(),
+                            _ => None,
+                        };
+
+                        Some(class.ok_or_else(|| RuntimeError::new(superclass.clone(), RuntimeErrorDescription::Message(String::from("Superclass must be a class"))))?)
+                    },
+                    None => None,
+                };
+
+                if self.strict {
+                    self.environment.borrow_mut().define_new(name, Value::Nil)?;
+                } else {
+                    self.environment.borrow_mut().define(name.lexeme.clone(), Value::Nil);
+                }
+
+                // the methods close over `super`, which is nil in a class without a superclass so it can't see the
+                // superclass of a class it's nested in
+                let mut closure = Environment::new_with_parent(self.environment.clone());
+                let super_value = superclass.as_ref().map_or(Value::Nil, |superclass| Value::Function(Rc::new(superclass.clone())));
+                closure.define(String::from("super"), super_value);
 
-                let definition = ClassDefinition::new(name, functions);
+                let definition = ClassDefinition::new(name, superclass, functions, Rc::new(RefCell::new(closure)));
                 let value = Value::Function(Rc::new(definition));
 
                 self.environment.borrow_mut().define(name.lexeme.clone(), value);
 
                 Ok(StmtResult::None)
             },
+            Stmt::Destructure(names, expr) => {
+                let values = match evaluate(self, expr)? {
+                    Value::List(list) => list.borrow().clone(),
+
+                    value => return Err(RuntimeError::new(names[0].clone(), RuntimeErrorDescription::Message(format!("Can only unpack lists, got {}", value.type_name())))),
+                };
+
+                if values.len() < names.len() {
+                    return Err(RuntimeError::new(names[0].clone(), RuntimeErrorDescription::NotEnoughValuesToUnpack { expected: names.len(), provided: values.len() }));
+                }
+
+                let mut sub_environment = Environment::new_declaration(self.environment.clone());
+                for (name, value) in names.iter().zip(values) {
+                    if self.strict {
+                        sub_environment.define_new(name, value)?;
+                    } else {
+                        sub_environment.define(name.lexeme.clone(), value);
+                    }
+                }
+
+                self.environment = Rc::new(RefCell::new(sub_environment));
+
+                Ok(StmtResult::None)
+            },
             Stmt::Expression(expr) => {
-                evaluate( self, expr)?;
+                let value = evaluate( self, expr)?;
+
+                Ok(StmtResult::Value(value))
+            },
+            Stmt::ForIn(name, collection, body) => {
+                let elements = match evaluate(self, collection)? {
+                    // iterate a snapshot so the body can modify the list without affecting the loop
+                    Value::List(list) => list.borrow().clone(),
+                    Value::String(value) => value.chars().map(|c| Value::String(c.to_string())).collect(),
+
+                    value => return Err(RuntimeError::new(name.clone(), RuntimeErrorDescription::Message(format!("Can only iterate over lists and strings, got {}", value.type_name())))),
+                };
+
+                for element in elements {
+                    let mut environment = Environment::new_with_parent(Rc::clone(&self.environment));
+                    environment.define(name.lexeme.clone(), element);
+
+                    let previous = ::std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+                    let result = self.interpret_stmt(body);
+                    self.environment = previous;
+
+                    match result? {
+                        StmtResult::Break => break,
+                        result @ StmtResult::Return(_) => return Ok(result),
+                        _ => { },
+                    }
+                }
 
                 Ok(StmtResult::None)
             },
@@ -79,7 +221,11 @@ impl Interpreter {
                 let definition = FunctionDefinition::new(func, self.environment.clone());
                 let value = Value::Function(Rc::new(definition));
 
-                self.environment.borrow_mut().define(func.name.lexeme.clone(), value);
+                if self.strict {
+                    self.environment.borrow_mut().define_new(&func.name, value)?;
+                } else {
+                    self.environment.borrow_mut().define(func.name.lexeme.clone(), value);
+                }
 
                 Ok(StmtResult::None)
             }
@@ -87,16 +233,17 @@ impl Interpreter {
                 let cond_value = evaluate(self, cond)?;
 
                 if cond_value.is_truthy() {
-                    self.evaluate_stmt(then_branch)
+                    self.interpret_stmt(then_branch)
                 } else if let Some(else_branch) = else_branch_opt {
-                    self.evaluate_stmt(else_branch)
+                    self.interpret_stmt(else_branch)
                 } else {
                     Ok(StmtResult::None)
                 }
             }
             Stmt::Print(expr) => {
                 let value = evaluate(self, expr)?;
-                println!("{}", value);
+                writeln!(self.output, "{}", value)
+                    .map_err(|e| RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("Error writing output: {:?}", e))))?;
 
                 Ok(StmtResult::None)
             },
@@ -114,41 +261,62 @@ impl Interpreter {
                     None => Value::Nil,
                 };
 
-                let mut sub_environment = Environment::new_with_parent(self.environment.clone());
-                sub_environment.define(name.lexeme.clone(), value);
+                let mut sub_environment = Environment::new_declaration(self.environment.clone());
+                if self.strict {
+                    sub_environment.define_new(name, value)?;
+                } else {
+                    sub_environment.define(name.lexeme.clone(), value);
+                }
 
                 self.environment = Rc::new(RefCell::new(sub_environment));
 
                 Ok(StmtResult::None)
             },
-            Stmt::While(condition, body) => {
-                let mut result = StmtResult::None;
+            Stmt::While(condition, body, increment) => {
                 while evaluate(self, condition)?.is_truthy() {
-                    result = self.evaluate_stmt(body)?;
-                    if let StmtResult::Return(_) = &result {
-                        break;
+                    match self.interpret_stmt(body)? {
+                        StmtResult::Break => break,
+                        result @ StmtResult::Return(_) => return Ok(result),
+                        _ => { },
+                    }
+
+                    if let Some(increment) = increment {
+                        evaluate(self, increment)?;
                     }
                 }
 
-                Ok(result)
+                Ok(StmtResult::None)
             },
             Stmt::Block(statements) => {
                 let environment= Rc::new(RefCell::new(Environment::new_with_parent(Rc::clone(&self.environment))));
 
                 self.evaluate_block(&statements, environment)
-            }
+            },
+            Stmt::Break(_) => Ok(StmtResult::Break),
+            Stmt::Continue(_) => Ok(StmtResult::Continue),
+        }
+    }
+
+    // runs `statements` in a child of the current environment, so nothing they declare outlives them,
+    // evaluating to the value of a trailing expression statement or `return`
+    pub fn eval_block_in_scope(&mut self, statements: &[Stmt]) -> EvaluateResult<Value> {
+        let environment = Rc::new(RefCell::new(Environment::new_with_parent(Rc::clone(&self.environment))));
+
+        match self.evaluate_block(statements, environment)? {
+            StmtResult::Value(value) | StmtResult::Return(value) => Ok(value),
+            _ => Ok(Value::Nil),
         }
     }
 
-    pub fn evaluate_block(&mut self, statements: &Vec<Stmt>, mut environment: Rc<RefCell<Environment>>) -> EvaluateResult<StmtResult> {
+    pub fn evaluate_block(&mut self, statements: &[Stmt], mut environment: Rc<RefCell<Environment>>) -> EvaluateResult<StmtResult> {
         ::std::mem::swap(&mut self.environment, &mut environment);
 
         let mut result = StmtResult::None;
         for statement in statements {
-            match self.evaluate_stmt(statement) {
+            match self.interpret_stmt(statement) {
                 Ok(stmt_result) => {
                     result = stmt_result;
-                    if let StmtResult::Return(_) = &result {
+                    if let StmtResult::Return(_) | StmtResult::Break | StmtResult::Continue = &result {
                         break;
                     }
                 }
@@ -164,10 +332,23 @@ impl Interpreter {
     }
 }
 
+pub(crate) struct CallDepthGuard {
+    call_depth: Rc<Cell<usize>>,
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        self.call_depth.set(self.call_depth.get() - 1);
+    }
+}
+
 #[derive(Debug)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, Rc<Value>>,
+
+    // each var statement gets its own environment, but they still belong to the scope of their parent
+    continues_scope: bool,
 }
 
 impl Environment {
@@ -175,6 +356,8 @@ impl Environment {
         Environment {
             parent: None,
             values: HashMap::new(),
+
+            continues_scope: false,
         }
     }
 
@@ -182,6 +365,28 @@ impl Environment {
         Environment {
             parent: Some(parent),
             values: HashMap::new(),
+
+            continues_scope: false,
+        }
+    }
+
+    pub fn new_declaration(parent: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            parent: Some(parent),
+            values: HashMap::new(),
+
+            continues_scope: true,
+        }
+    }
+
+    pub fn is_declared_in_scope(&self, name: &str) -> bool {
+        if self.values.contains_key(name) {
+            return true;
+        }
+
+        match &self.parent {
+            Some(parent) if self.continues_scope => parent.borrow().is_declared_in_scope(name),
+            _ => false,
         }
     }
 
@@ -195,7 +400,7 @@ impl Environment {
 
                         env.get(token)
                     },
-                    None => Err(RuntimeError::new(token.clone(), RuntimeErrorDescription::UndefinedVariable))
+                    None => Err(RuntimeError::new(token.clone(), RuntimeErrorDescription::UndefinedVariable(Self::get_identifier_name(token).to_string())))
                 }
             },
         }
@@ -205,11 +410,23 @@ impl Environment {
         self.values.insert(name, Rc::new(value));
     }
 
+    pub fn define_new(&mut self, token: &SourceToken, value: Value) -> EvaluateResult<()> {
+        let name = Self::get_identifier_name(token);
+
+        if self.is_declared_in_scope(name) {
+            return Err(RuntimeError::new(token.clone(), RuntimeErrorDescription::VariableAlreadyDeclared(name.to_string())));
+        }
+
+        self.values.insert(name.to_string(), Rc::new(value));
+
+        Ok(())
+    }
+
     pub fn assign(&mut self, token: &SourceToken, value: Value) -> EvaluateResult<()> {
         let name = Self::get_identifier_name(token);
 
         if self.values.contains_key(name) {
-            self.values.insert(name.clone(), Rc::new(value));
+            self.values.insert(name.to_string(), Rc::new(value));
 
             Ok(())
         } else {
@@ -219,16 +436,170 @@ impl Environment {
 
                     env.assign(token, value)
                 },
-                None => Err(RuntimeError::new(token.clone(), RuntimeErrorDescription::UndefinedVariable))
+                None => Err(RuntimeError::new(token.clone(), RuntimeErrorDescription::UndefinedVariable(Self::get_identifier_name(token).to_string())))
             }
         }
     }
 
-    fn get_identifier_name(token: &SourceToken) -> &String {
+    fn get_identifier_name(token: &SourceToken) -> &str {
         match &token.token {
             Token::Identifier(value) => value,
+            Token::This => "this",
+            Token::Super => "super",
 
             t => panic!("Invalid token {:?} for variable name", t),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_test_utils::parse;
+    use super::*;
+
+
+    fn ident(name: &str) -> SourceToken {
+        SourceToken { token: Token::Identifier(name.into()), lexeme: name.into(), line: 0 }
+    }
+
+    #[test]
+    fn test_bare_interpreter() {
+        let mut interpreter = Interpreter::new_bare();
+
+        interpreter.interpret(parse("var a = 1 + 2;")).expect("Failed to run script");
+        assert_eq!(*interpreter.environment().borrow().get(&ident("a")).unwrap(), Value::Number(3f64));
+
+        let result = interpreter.interpret(parse("clock();"));
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::UndefinedVariable("clock".into())));
+    }
+
+    #[test]
+    fn test_expression_statement_value() {
+        let mut interpreter = Interpreter::new();
+
+        match interpreter.interpret(parse("var a = 1; a + 1;")) {
+            Ok(StmtResult::Value(Value::Number(value))) => assert_eq!(value, 2f64),
+            result => panic!("Expected the expression's value, got {:?}", result),
+        }
+        match interpreter.interpret(parse("a + 1; var b = 1;")) {
+            Ok(StmtResult::None) => { },
+            result => panic!("Expected no value, got {:?}", result),
+        }
+        match interpreter.interpret(parse("while (a < 3) a = a + 1;")) {
+            Ok(StmtResult::None) => { },
+            result => panic!("Expected loops to have no value, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_eval_block_in_scope() {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(parse("var outer = 1;")).expect("Failed to run script");
+
+        let value = interpreter.eval_block_in_scope(&parse("var inner = outer + 1; outer = inner; inner * 10;")).expect("Failed to run block");
+        assert_eq!(value, Value::Number(20f64));
+
+        // assignments reach the outer scope but declarations don't leak out of it
+        assert_eq!(*interpreter.environment().borrow().get(&ident("outer")).unwrap(), Value::Number(2f64));
+        assert!(interpreter.environment().borrow().get(&ident("inner")).is_err());
+
+        let value = interpreter.eval_block_in_scope(&parse("var outer = 5; print outer;")).expect("Failed to run block");
+        assert_eq!(value, Value::Nil);
+        assert_eq!(*interpreter.environment().borrow().get(&ident("outer")).unwrap(), Value::Number(2f64));
+
+        let result = interpreter.eval_block_in_scope(&parse("var a = 1; missing;"));
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::UndefinedVariable("missing".into())));
+        assert!(interpreter.environment().borrow().get(&ident("a")).is_err());
+    }
+
+    #[test]
+    fn test_interpret_stmt() {
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+        let get = |interpreter: &Interpreter, name: &str| interpreter.environment().borrow().get(&ident(name)).map(|value| (*value).clone());
+
+        let statements = parse("var a = 1; fun double(n) { return n * 2; } { var b = a; a = double(b) + 1; } while (a < 10) a = a * 2; print a; a + 1;");
+        let mut results = Vec::new();
+        for statement in &statements {
+            results.push(interpreter.interpret_stmt(statement).expect("Failed to run statement"));
+
+            if results.len() == 1 {
+                assert_eq!(get(&interpreter, "a"), Ok(Value::Number(1f64)));
+            }
+        }
+
+        assert_eq!(get(&interpreter, "a"), Ok(Value::Number(12f64)));
+        assert!(get(&interpreter, "b").is_err());
+        assert!(get(&interpreter, "double").is_ok());
+        assert_eq!(output.contents(), "12\n");
+
+        match results.as_slice() {
+            // a block passes on the value of its last statement, here the assignment
+            [StmtResult::None, StmtResult::None, StmtResult::Value(block), StmtResult::None, StmtResult::None, StmtResult::Value(value)] => {
+                assert_eq!(*block, Value::Number(3f64));
+                assert_eq!(*value, Value::Number(13f64));
+            },
+            results => panic!("Unexpected statement results {:?}", results),
+        }
+
+        let statements = parse("a = missing;");
+        let result = interpreter.interpret_stmt(&statements[0]);
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::UndefinedVariable("missing".into())));
+        assert_eq!(get(&interpreter, "a"), Ok(Value::Number(12f64)));
+    }
+
+    #[test]
+    fn test_redeclaration() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter.interpret(parse("var a = 1; var a = 2; { var b = 1; var b = 2; }")).expect("Permissive mode allows re-declaration");
+        assert_eq!(*interpreter.environment().borrow().get(&ident("a")).unwrap(), Value::Number(2f64));
+    }
+
+    #[test]
+    fn test_strict_redeclaration() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_strict(true);
+
+        let result = interpreter.interpret(parse("var a = 1; var b = 2; var a = 3;"));
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::VariableAlreadyDeclared("a".into())));
+
+        let result = interpreter.interpret(parse("{ var c = 1; { var c = 2; } var d = 3; var c = 4; }"));
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::VariableAlreadyDeclared("c".into())));
+
+        interpreter.interpret(parse("var e = 1; { var e = 2; } fun f(e) { var g = e; } f(1); f(2);")).expect("Shadowing is allowed in strict mode");
+    }
+
+    #[test]
+    fn test_strict_function_and_class_redeclaration() {
+        let cases = [
+            ("var f = 1; fun f() { }", "f"),
+            ("fun g() { } fun g() { }", "g"),
+            ("var C = 1; class C { }", "C"),
+            ("class D { } class D { }", "D"),
+            ("fun E() { } class E { }", "E"),
+        ];
+        for (source, name) in cases.iter() {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_strict(true);
+
+            let result = interpreter.interpret(parse(source));
+            assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::VariableAlreadyDeclared(name.to_string())), "{}", source);
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_strict(true);
+        interpreter.interpret(parse("fun f() { } { fun f() { } class C { } } class C { m() { } } C().m();")).expect("Shadowing is allowed in strict mode");
+
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(parse("var f = 1; fun f() { } class f { }")).expect("Permissive mode allows re-declaration");
+    }
+
+    #[test]
+    fn test_register_standard_natives() {
+        let mut interpreter = Interpreter::new_bare();
+        interpreter.register_standard_natives();
+
+        interpreter.interpret(parse("clock();")).expect("Failed to call clock");
+    }
+}
@@ -3,12 +3,14 @@ mod error;
 mod expression;
 mod function;
 mod interpreter;
+mod output;
 mod value;
 
 mod native;
 
-pub use error::{ RuntimeError, RuntimeErrorDescription };
-pub use interpreter::Interpreter;
+pub use error::{ RuntimeError, RuntimeErrorDescription, StackFrame };
+pub use interpreter::{ Interpreter, StmtResult };
+pub use output::CapturedOutput;
 pub use value::Value;
 
 pub type EvaluateResult<T> = Result<T, RuntimeError>;
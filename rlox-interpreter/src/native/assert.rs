@@ -0,0 +1,89 @@
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+#[derive(Clone, Debug)]
+pub struct Assert;
+
+impl Callable for Assert {
+    fn arity(&self) -> usize {
+        1
+    }
+    fn max_arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        if arguments[0].is_truthy() {
+            return Ok(Value::Nil);
+        }
+
+        let message = arguments.get(1).map(|message| message.to_string());
+        Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::AssertionFailed(message)))
+    }
+}
+
+impl Display for Assert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use rlox_test_utils::parse;
+    use crate::EvaluateResult;
+    use crate::interpreter::StmtResult;
+    use super::*;
+
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> EvaluateResult<StmtResult> {
+        interpreter.interpret(parse(source))
+    }
+
+    fn ident(name: &str) -> SourceToken {
+        SourceToken { token: Token::Identifier(name.into()), lexeme: name.into(), line: 0 }
+    }
+
+    #[test]
+    fn test_passing_assertion() {
+        let mut interpreter = Interpreter::new();
+
+        run(&mut interpreter, "assert(true); assert(1 == 1, \"maths\");").expect("Assertions should pass");
+    }
+
+    #[test]
+    fn test_failing_assertion() {
+        let mut interpreter = Interpreter::new();
+
+        let error = run(&mut interpreter, "assert(true);\nassert(false);").unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::AssertionFailed(None));
+        assert_eq!(error.token.line, 2);
+
+        let error = run(&mut interpreter, "\n\nassert(1 > 2, \"one is not \" + 2);").unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::AssertionFailed(Some("one is not 2".into())));
+        assert_eq!(error.token.line, 3);
+    }
+
+    #[test]
+    fn test_assertion_in_function() {
+        let mut interpreter = Interpreter::new();
+
+        let error = run(&mut interpreter, "var a = 1; fun f() { assert(nil, \"inner\"); a = 2; } f(); a = 3;").unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::AssertionFailed(Some("inner".into())));
+
+        assert_eq!(*interpreter.environment().borrow().get(&ident("a")).unwrap(), Value::Number(1f64));
+    }
+
+    #[test]
+    fn test_assertion_arity() {
+        let mut interpreter = Interpreter::new();
+
+        let error = run(&mut interpreter, "assert();").unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: 1, provided: 0, callee_name: Some(Box::new("assert".into())) });
+
+        let error = run(&mut interpreter, "assert(true, 1, 2);").unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: 2, provided: 3, callee_name: Some(Box::new("assert".into())) });
+    }
+}
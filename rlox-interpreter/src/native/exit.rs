@@ -0,0 +1,78 @@
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+#[derive(Clone, Debug)]
+pub struct Exit;
+
+impl Callable for Exit {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let code = match &arguments[0] {
+            Value::Number(value) if value.fract() == 0f64 && *value >= 0f64 && *value <= 255f64 => *value as i32,
+
+            value => return Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("Exit code must be an integer between 0 and 255, got {}", value)))),
+        };
+
+        // not really an error, but it is the only way to unwind the interpreter from inside a call
+        Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Exit(code)))
+    }
+}
+
+impl Display for Exit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use rlox_test_utils::parse;
+    use crate::EvaluateResult;
+    use crate::interpreter::StmtResult;
+    use super::*;
+
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> EvaluateResult<StmtResult> {
+        interpreter.interpret(parse(source))
+    }
+
+    fn ident(name: &str) -> SourceToken {
+        SourceToken { token: Token::Identifier(name.into()), lexeme: name.into(), line: 0 }
+    }
+
+    #[test]
+    fn test_exit_reports_code() {
+        let mut interpreter = Interpreter::new();
+
+        let result = run(&mut interpreter, "exit(3);");
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::Exit(3)));
+    }
+
+    #[test]
+    fn test_exit_stops_execution() {
+        let mut interpreter = Interpreter::new();
+
+        let result = run(&mut interpreter, "var a = 1; fun f() { exit(0); a = 2; } f(); a = 3;");
+        assert_eq!(result.err().map(|e| e.description), Some(RuntimeErrorDescription::Exit(0)));
+
+        assert_eq!(*interpreter.environment().borrow().get(&ident("a")).unwrap(), Value::Number(1f64));
+    }
+
+    #[test]
+    fn test_exit_invalid_code() {
+        let mut interpreter = Interpreter::new();
+
+        for source in &["exit(-1);", "exit(256);", "exit(1.5);", "exit(\"1\");"] {
+            let result = run(&mut interpreter, source);
+            match result.err().map(|e| e.description) {
+                Some(RuntimeErrorDescription::Message(_)) => { },
+                other => panic!("Expected an error message for {}, got {:?}", source, other),
+            }
+        }
+    }
+}
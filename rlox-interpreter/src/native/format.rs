@@ -0,0 +1,267 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Specifier {
+    alignment: Option<Alignment>,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+fn error(format: &str, placeholder: usize, message: &str) -> RuntimeError {
+    RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("Invalid format string \"{}\", placeholder {}: {}", format, placeholder, message)))
+}
+
+// Rust's formatter only accepts widths and precisions that fit in a u16
+const MAX_NUMBER: usize = u16::MAX as usize;
+
+fn parse_number(chars: &mut Peekable<Chars>, too_large: &'static str) -> Result<Option<usize>, &'static str> {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        digits.push(*c);
+        chars.next();
+    }
+
+    if digits.is_empty() {
+        return Ok(None);
+    }
+
+    match digits.parse() {
+        Ok(number) if number <= MAX_NUMBER => Ok(Some(number)),
+        _ => Err(too_large),
+    }
+}
+
+// parses the contents of a placeholder between the braces, e.g. ":>8.2"
+fn parse_specifier(spec: &str) -> Result<Specifier, &'static str> {
+    if spec.is_empty() {
+        return Ok(Specifier::default());
+    }
+    if !spec.starts_with(':') {
+        return Err("expected ':' at the start of the specifier");
+    }
+
+    let mut chars = spec[1..].chars().peekable();
+    let mut specifier = Specifier::default();
+
+    specifier.alignment = match chars.peek() {
+        Some('<') => Some(Alignment::Left),
+        Some('>') => Some(Alignment::Right),
+        Some('^') => Some(Alignment::Center),
+        _ => None,
+    };
+    if specifier.alignment.is_some() {
+        chars.next();
+    }
+
+    specifier.width = parse_number(&mut chars, "width is too large")?;
+
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        specifier.precision = Some(parse_number(&mut chars, "precision is too large")?.ok_or("expected a precision after '.'")?);
+    }
+
+    if chars.next().is_some() {
+        return Err("unexpected character in specifier");
+    }
+
+    Ok(specifier)
+}
+
+fn format_value(value: &Value, specifier: &Specifier) -> Result<String, &'static str> {
+    let formatted = match (value, specifier.precision) {
+        (Value::Number(number), Some(precision)) => format!("{:.*}", precision, number),
+        (_, Some(_)) => return Err("precision can only be applied to numbers"),
+        (value, None) => value.to_string(),
+    };
+
+    let width = specifier.width.unwrap_or(0);
+    // numbers line up on the right by default, everything else on the left
+    let alignment = specifier.alignment.unwrap_or(match value { Value::Number(_) => Alignment::Right, _ => Alignment::Left });
+
+    Ok(match alignment {
+        Alignment::Left => format!("{:<1$}", formatted, width),
+        Alignment::Right => format!("{:>1$}", formatted, width),
+        Alignment::Center => format!("{:^1$}", formatted, width),
+    })
+}
+
+pub fn format(format: &str, arguments: &[Value]) -> Result<String, RuntimeError> {
+    let mut result = String::new();
+    let mut placeholder = 0;
+
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); result.push('{'); },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); result.push('}'); },
+            '}' => return Err(error(format, placeholder, "unmatched '}', use '}}' for a literal brace")),
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(error(format, placeholder, "unterminated placeholder")),
+                    }
+                }
+
+                let specifier = parse_specifier(&spec).map_err(|e| error(format, placeholder, e))?;
+                let value = arguments.get(placeholder).ok_or_else(|| error(format, placeholder, "not enough arguments"))?;
+
+                result.push_str(&format_value(value, &specifier).map_err(|e| error(format, placeholder, e))?);
+                placeholder += 1;
+            },
+
+            c => result.push(c),
+        }
+    }
+
+    if placeholder < arguments.len() {
+        return Err(error(format, placeholder, &format!("too many arguments, {} provided for {} placeholders", arguments.len(), placeholder)));
+    }
+
+    Ok(result)
+}
+
+fn expect_format(native: &str, value: &Value) -> Result<String, RuntimeError> {
+    match value {
+        Value::String(format) => Ok(format.clone()),
+
+        value => Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("{} expected a format string but got {}", native, value.type_name())))),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Format;
+
+impl Callable for Format {
+    fn arity(&self) -> usize {
+        1
+    }
+    fn max_arity(&self) -> usize {
+        255
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let fmt = expect_format("format", &arguments[0])?;
+
+        Ok(Value::String(format(&fmt, &arguments[1..])?))
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Printf;
+
+impl Callable for Printf {
+    fn arity(&self) -> usize {
+        1
+    }
+    fn max_arity(&self) -> usize {
+        255
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let fmt = expect_format("printf", &arguments[0])?;
+        let output = format(&fmt, &arguments[1..])?;
+
+        write!(interpreter.output(), "{}", output)
+            .map_err(|e| RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("Error writing output: {:?}", e))))?;
+
+        Ok(Value::Nil)
+    }
+}
+
+impl Display for Printf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(result: Result<String, RuntimeError>) -> String {
+        match result.unwrap_err().description {
+            RuntimeErrorDescription::Message(message) => message,
+            description => panic!("Expected a message, got {:?}", description),
+        }
+    }
+
+    #[test]
+    fn test_format_mixed_arguments() {
+        let arguments = vec![Value::String("a".into()), Value::Number(1.5), Value::Boolean(true), Value::Nil];
+
+        assert_eq!(format("{} {} {} {}", &arguments).unwrap(), "a 1.5 true nil");
+    }
+
+    #[test]
+    fn test_format_escaped_braces() {
+        assert_eq!(format("{{}} {}", &[Value::Number(1.0)]).unwrap(), "{} 1");
+    }
+
+    #[test]
+    fn test_format_precision() {
+        assert_eq!(format("{:.2}", &[Value::Number(1.23456)]).unwrap(), "1.23");
+        assert_eq!(format("{:.0}", &[Value::Number(2.5)]).unwrap(), "2");
+    }
+
+    #[test]
+    fn test_format_width() {
+        assert_eq!(format("[{:>8}]", &[Value::String("abc".into())]).unwrap(), "[     abc]");
+        assert_eq!(format("[{:<6}]", &[Value::Number(1.0)]).unwrap(), "[1     ]");
+        assert_eq!(format("[{:^5}]", &[Value::String("a".into())]).unwrap(), "[  a  ]");
+        assert_eq!(format("[{:8.2}]", &[Value::Number(1.0)]).unwrap(), "[    1.00]");
+    }
+
+    #[test]
+    fn test_format_too_few_arguments() {
+        assert_eq!(message(format("{} {}", &[Value::Nil])), "Invalid format string \"{} {}\", placeholder 1: not enough arguments");
+    }
+
+    #[test]
+    fn test_format_too_many_arguments() {
+        assert_eq!(message(format("{}", &[Value::Nil, Value::Nil])), "Invalid format string \"{}\", placeholder 1: too many arguments, 2 provided for 1 placeholders");
+    }
+
+    #[test]
+    fn test_format_malformed_specifier() {
+        assert_eq!(message(format("{:x}", &[Value::Nil])), "Invalid format string \"{:x}\", placeholder 0: unexpected character in specifier");
+        assert_eq!(message(format("{} {:.}", &[Value::Nil, Value::Nil])), "Invalid format string \"{} {:.}\", placeholder 1: expected a precision after '.'");
+        assert_eq!(message(format("{:.2}", &[Value::Nil])), "Invalid format string \"{:.2}\", placeholder 0: precision can only be applied to numbers");
+        assert_eq!(message(format("{:>70000}", &[Value::Number(1.0)])), "Invalid format string \"{:>70000}\", placeholder 0: width is too large");
+        assert_eq!(message(format("{:.70000}", &[Value::Number(1.5)])), "Invalid format string \"{:.70000}\", placeholder 0: precision is too large");
+        assert_eq!(message(format("{:99999999999999999999999}", &[Value::Nil])), "Invalid format string \"{:99999999999999999999999}\", placeholder 0: width is too large");
+        assert_eq!(format("[{:65535}]", &[Value::Nil]).unwrap().len(), 65537);
+        assert_eq!(message(format("{", &[])), "Invalid format string \"{\", placeholder 0: unterminated placeholder");
+        assert_eq!(message(format("}", &[])), "Invalid format string \"}\", placeholder 0: unmatched '}', use '}}' for a literal brace");
+    }
+
+    #[test]
+    fn test_printf() {
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+
+        Printf.call(&mut interpreter, vec![Value::String("{}-{:.1}".into()), Value::String("x".into()), Value::Number(2.0)]).unwrap();
+        Printf.call(&mut interpreter, vec![Value::String("!".into())]).unwrap();
+
+        assert_eq!(output.contents(), "x-2.0!");
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    cell::RefCell,
+    fmt::{Display, Formatter, Error},
+    rc::Rc,
+};
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+
+fn error(message: String) -> RuntimeError {
+    RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(message))
+}
+
+fn expect_list(native: &str, value: &Value) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+
+        value => Err(error(format!("{} expected a list but got {}", native, value.type_name()))),
+    }
+}
+
+// `allow_end` permits an index one past the last element, for inserting at the end of the list
+fn expect_index(native: &str, value: &Value, length: usize, allow_end: bool) -> Result<usize, RuntimeError> {
+    let limit = if allow_end { length + 1 } else { length };
+
+    match value {
+        Value::Number(index) if index.fract() == 0f64 && *index >= 0f64 && *index < limit as f64 => Ok(*index as usize),
+        Value::Number(index) => Err(error(format!("{} index {} is out of range for a list of length {}", native, index, length))),
+
+        value => Err(error(format!("{} expected a number index but got {}", native, value.type_name()))),
+    }
+}
+
+fn call_function(native: &str, interpreter: &mut Interpreter, function: &Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let function = function.as_callable()
+        .map_err(|_| error(format!("{} expected a function but got {}", native, function.type_name())))?;
+
+    if arguments.len() < function.arity() || arguments.len() > function.max_arity() {
+        return Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: function.arity(), provided: arguments.len(), callee_name: None }));
+    }
+
+    function.call(interpreter, arguments)
+}
+
+macro_rules! native_display {
+    ($($name:ident),+) => {
+        $(
+            impl Display for $name {
+                fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+                    write!(f, "<native fn>")
+                }
+            }
+        )+
+    };
+}
+
+native_display!(Push, Pop, Len, Insert, RemoveAt, Contains, Map, Filter, Each);
+
+#[derive(Clone, Debug)]
+pub struct Push;
+
+impl Callable for Push {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let list = expect_list("push", &arguments[0])?;
+        list.borrow_mut().push(arguments[1].clone());
+
+        Ok(Value::Nil)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pop;
+
+impl Callable for Pop {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let list = expect_list("pop", &arguments[0])?;
+        let value = list.borrow_mut().pop();
+
+        Ok(value.unwrap_or(Value::Nil))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Len;
+
+impl Callable for Len {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match &arguments[0] {
+            Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+            Value::String(value) => Ok(Value::Number(value.chars().count() as f64)),
+
+            value => Err(error(format!("len expected a list or string but got {}", value.type_name()))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Insert;
+
+impl Callable for Insert {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let list = expect_list("insert", &arguments[0])?;
+        let index = expect_index("insert", &arguments[1], list.borrow().len(), true)?;
+
+        list.borrow_mut().insert(index, arguments[2].clone());
+
+        Ok(Value::Nil)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoveAt;
+
+impl Callable for RemoveAt {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let list = expect_list("removeAt", &arguments[0])?;
+        let index = expect_index("removeAt", &arguments[1], list.borrow().len(), false)?;
+
+        let value = list.borrow_mut().remove(index);
+
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Contains;
+
+impl Callable for Contains {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let list = expect_list("contains", &arguments[0])?;
+        let found = list.borrow().iter().any(|value| value.is_equal(&arguments[1]));
+
+        Ok(Value::Boolean(found))
+    }
+}
+
+// the higher-order natives iterate over a snapshot so the callback is free to modify the list
+
+#[derive(Clone, Debug)]
+pub struct Map;
+
+impl Callable for Map {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let elements = expect_list("map", &arguments[0])?.borrow().clone();
+
+        let mut result = Vec::new();
+        for element in elements {
+            result.push(call_function("map", interpreter, &arguments[1], vec![element])?);
+        }
+
+        Ok(Value::new_list(result))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Filter;
+
+impl Callable for Filter {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let elements = expect_list("filter", &arguments[0])?.borrow().clone();
+
+        let mut result = Vec::new();
+        for element in elements {
+            if call_function("filter", interpreter, &arguments[1], vec![element.clone()])?.is_truthy() {
+                result.push(element);
+            }
+        }
+
+        Ok(Value::new_list(result))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Each;
+
+impl Callable for Each {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let elements = expect_list("each", &arguments[0])?.borrow().clone();
+
+        for element in elements {
+            call_function("each", interpreter, &arguments[1], vec![element])?;
+        }
+
+        Ok(Value::Nil)
+    }
+}
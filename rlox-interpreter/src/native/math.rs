@@ -0,0 +1,266 @@
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+fn number_argument(function: &str, value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Number(value) => Ok(*value),
+
+        value => Err(math_error(format!("{} requires a number, got {}", function, value))),
+    }
+}
+
+fn math_error(message: String) -> RuntimeError {
+    RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(message))
+}
+
+macro_rules! native_fn_display {
+    ($($type:ident),+) => {
+        $(
+            impl Display for $type {
+                fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+                    write!(f, "<native fn>")
+                }
+            }
+        )+
+    };
+}
+
+#[derive(Clone, Debug)]
+pub struct Sqrt;
+
+impl Callable for Sqrt {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let x = number_argument("sqrt", &arguments[0])?;
+        if x < 0f64 {
+            return Err(math_error("sqrt requires non-negative argument".into()));
+        }
+
+        Ok(Value::Number(x.sqrt()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Abs;
+
+impl Callable for Abs {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(number_argument("abs", &arguments[0])?.abs()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Floor;
+
+impl Callable for Floor {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(number_argument("floor", &arguments[0])?.floor()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ceil;
+
+impl Callable for Ceil {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(number_argument("ceil", &arguments[0])?.ceil()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pow;
+
+impl Callable for Pow {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let base = number_argument("pow", &arguments[0])?;
+        let exp = number_argument("pow", &arguments[1])?;
+
+        // e.g. a fractional power of a negative number
+        let result = base.powf(exp);
+        if result.is_nan() {
+            return Err(math_error(format!("pow({}, {}) is not a real number", base, exp)));
+        }
+
+        Ok(Value::Number(result))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sin;
+
+impl Callable for Sin {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(number_argument("sin", &arguments[0])?.sin()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cos;
+
+impl Callable for Cos {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(number_argument("cos", &arguments[0])?.cos()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Log;
+
+impl Callable for Log {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let x = number_argument("log", &arguments[0])?;
+        if x <= 0f64 {
+            return Err(math_error("log requires positive argument".into()));
+        }
+
+        Ok(Value::Number(x.ln()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Max;
+
+impl Callable for Max {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let a = number_argument("max", &arguments[0])?;
+        let b = number_argument("max", &arguments[1])?;
+
+        Ok(Value::Number(a.max(b)))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Min;
+
+impl Callable for Min {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let a = number_argument("min", &arguments[0])?;
+        let b = number_argument("min", &arguments[1])?;
+
+        Ok(Value::Number(a.min(b)))
+    }
+}
+
+native_fn_display!(Sqrt, Abs, Floor, Ceil, Pow, Sin, Cos, Log, Max, Min);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(function: &dyn Callable, arguments: Vec<f64>) -> Result<f64, RuntimeError> {
+        let mut interpreter = Interpreter::new();
+        let arguments = arguments.into_iter().map(Value::Number).collect();
+
+        function.call(&mut interpreter, arguments).map(|value| value.as_number().unwrap())
+    }
+
+    fn assert_message(result: Result<f64, RuntimeError>, expected: &str) {
+        match result {
+            Err(RuntimeError { description: RuntimeErrorDescription::Message(message), .. }) => assert_eq!(message, expected),
+            result => panic!("Expected error {:?}, got {:?}", expected, result.map_err(|e| e.description)),
+        }
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(call(&Sqrt, vec![9f64]).unwrap(), 3f64);
+        assert_eq!(call(&Sqrt, vec![0f64]).unwrap(), 0f64);
+        assert_message(call(&Sqrt, vec![-1f64]), "sqrt requires non-negative argument");
+    }
+
+    #[test]
+    fn test_abs() {
+        assert_eq!(call(&Abs, vec![-2.5f64]).unwrap(), 2.5f64);
+        assert_eq!(call(&Abs, vec![2.5f64]).unwrap(), 2.5f64);
+    }
+
+    #[test]
+    fn test_floor_ceil() {
+        assert_eq!(call(&Floor, vec![1.5f64]).unwrap(), 1f64);
+        assert_eq!(call(&Floor, vec![-1.5f64]).unwrap(), -2f64);
+        assert_eq!(call(&Ceil, vec![1.5f64]).unwrap(), 2f64);
+        assert_eq!(call(&Ceil, vec![-1.5f64]).unwrap(), -1f64);
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(call(&Pow, vec![2f64, 10f64]).unwrap(), 1024f64);
+        assert_eq!(call(&Pow, vec![4f64, 0.5f64]).unwrap(), 2f64);
+        assert_message(call(&Pow, vec![-8f64, 0.5f64]), "pow(-8, 0.5) is not a real number");
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        assert_eq!(call(&Sin, vec![0f64]).unwrap(), 0f64);
+        assert!((call(&Sin, vec![std::f64::consts::FRAC_PI_2]).unwrap() - 1f64).abs() < 1e-12);
+        assert_eq!(call(&Cos, vec![0f64]).unwrap(), 1f64);
+        assert!((call(&Cos, vec![std::f64::consts::PI]).unwrap() + 1f64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_log() {
+        assert_eq!(call(&Log, vec![1f64]).unwrap(), 0f64);
+        assert!((call(&Log, vec![std::f64::consts::E]).unwrap() - 1f64).abs() < 1e-12);
+        assert_message(call(&Log, vec![0f64]), "log requires positive argument");
+        assert_message(call(&Log, vec![-1f64]), "log requires positive argument");
+    }
+
+    #[test]
+    fn test_max_min() {
+        assert_eq!(call(&Max, vec![1f64, 2f64]).unwrap(), 2f64);
+        assert_eq!(call(&Max, vec![-1f64, -2f64]).unwrap(), -1f64);
+        assert_eq!(call(&Min, vec![1f64, 2f64]).unwrap(), 1f64);
+        assert_eq!(call(&Min, vec![-1f64, -2f64]).unwrap(), -2f64);
+    }
+
+    #[test]
+    fn test_non_number_argument() {
+        let mut interpreter = Interpreter::new();
+
+        let error = Sqrt.call(&mut interpreter, vec![Value::String("a".into())]).unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::Message("sqrt requires a number, got a".into()));
+
+        let error = Max.call(&mut interpreter, vec![Value::Number(1f64), Value::Nil]).unwrap_err();
+        assert_eq!(error.description, RuntimeErrorDescription::Message("max requires a number, got nil".into()));
+    }
+}
@@ -4,8 +4,42 @@ use crate::{
     interpreter::Environment,
 };
 
+mod assert;
 mod clock;
+mod exit;
+mod format;
+mod list;
+mod math;
+mod time;
+mod type_of;
 
 pub fn define_functions(environment: &mut Environment){
+    environment.define(String::from("assert"), Value::Function(Rc::new(assert::Assert)));
+    environment.define(String::from("className"), Value::Function(Rc::new(type_of::ClassName)));
     environment.define(String::from("clock"), Value::Function(Rc::new(clock::Clock)));
+    environment.define(String::from("exit"), Value::Function(Rc::new(exit::Exit)));
+    environment.define(String::from("format"), Value::Function(Rc::new(format::Format)));
+    environment.define(String::from("printf"), Value::Function(Rc::new(format::Printf)));
+    environment.define(String::from("push"), Value::Function(Rc::new(list::Push)));
+    environment.define(String::from("pop"), Value::Function(Rc::new(list::Pop)));
+    environment.define(String::from("len"), Value::Function(Rc::new(list::Len)));
+    environment.define(String::from("insert"), Value::Function(Rc::new(list::Insert)));
+    environment.define(String::from("removeAt"), Value::Function(Rc::new(list::RemoveAt)));
+    environment.define(String::from("contains"), Value::Function(Rc::new(list::Contains)));
+    environment.define(String::from("map"), Value::Function(Rc::new(list::Map)));
+    environment.define(String::from("filter"), Value::Function(Rc::new(list::Filter)));
+    environment.define(String::from("each"), Value::Function(Rc::new(list::Each)));
+    environment.define(String::from("sqrt"), Value::Function(Rc::new(math::Sqrt)));
+    environment.define(String::from("abs"), Value::Function(Rc::new(math::Abs)));
+    environment.define(String::from("floor"), Value::Function(Rc::new(math::Floor)));
+    environment.define(String::from("ceil"), Value::Function(Rc::new(math::Ceil)));
+    environment.define(String::from("pow"), Value::Function(Rc::new(math::Pow)));
+    environment.define(String::from("sin"), Value::Function(Rc::new(math::Sin)));
+    environment.define(String::from("cos"), Value::Function(Rc::new(math::Cos)));
+    environment.define(String::from("log"), Value::Function(Rc::new(math::Log)));
+    environment.define(String::from("max"), Value::Function(Rc::new(math::Max)));
+    environment.define(String::from("min"), Value::Function(Rc::new(math::Min)));
+    environment.define(String::from("monotonic"), Value::Function(Rc::new(time::Monotonic)));
+    environment.define(String::from("sleep"), Value::Function(Rc::new(time::Sleep)));
+    environment.define(String::from("type"), Value::Function(Rc::new(type_of::TypeOf)));
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use std::time::Duration;
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+#[derive(Clone, Debug)]
+pub struct Sleep;
+
+impl Callable for Sleep {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let invalid = |value: &Value| RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("Sleep duration must be a non-negative number of milliseconds, got {}", value)));
+
+        let duration = match &arguments[0] {
+            // finite values can still be too large to fit in a Duration
+            Value::Number(value) => Duration::try_from_secs_f64(value / 1000f64).map_err(|_| invalid(&arguments[0]))?,
+
+            value => return Err(invalid(value)),
+        };
+
+        std::thread::sleep(duration);
+
+        Ok(Value::Nil)
+    }
+}
+
+impl Display for Sleep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Monotonic;
+
+impl Callable for Monotonic {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, _arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        // measured from the interpreter's creation so it is immune to wall-clock adjustments
+        let elapsed = interpreter.started().elapsed();
+
+        Ok(Value::Number(elapsed.as_secs_f64()))
+    }
+}
+
+impl Display for Monotonic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monotonic(interpreter: &mut Interpreter) -> f64 {
+        Monotonic.call(interpreter, vec![]).unwrap().as_number().unwrap()
+    }
+
+    #[test]
+    fn test_monotonic_non_decreasing() {
+        let mut interpreter = Interpreter::new();
+
+        let mut previous = monotonic(&mut interpreter);
+        for _ in 0..100 {
+            let current = monotonic(&mut interpreter);
+            assert!(current >= previous);
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn test_sleep() {
+        let mut interpreter = Interpreter::new();
+
+        let start = monotonic(&mut interpreter);
+        Sleep.call(&mut interpreter, vec![Value::Number(50f64)]).unwrap();
+        let end = monotonic(&mut interpreter);
+
+        assert!(end - start >= 0.045, "only {}s passed", end - start);
+    }
+
+    #[test]
+    fn test_sleep_invalid_duration() {
+        let mut interpreter = Interpreter::new();
+
+        assert!(Sleep.call(&mut interpreter, vec![Value::Number(-1f64)]).is_err());
+        assert!(Sleep.call(&mut interpreter, vec![Value::Number(f64::INFINITY)]).is_err());
+        assert!(Sleep.call(&mut interpreter, vec![Value::Number(f64::NAN)]).is_err());
+        assert!(Sleep.call(&mut interpreter, vec![Value::Number(f64::MAX)]).is_err());
+        assert!(Sleep.call(&mut interpreter, vec![Value::Number(1e30)]).is_err());
+        assert!(Sleep.call(&mut interpreter, vec![Value::Nil]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use rlox_scanner::SourceToken;
+use crate::{RuntimeError, RuntimeErrorDescription, value::{Callable, Value}, Interpreter};
+use std::fmt::{Display, Formatter, Error};
+
+#[derive(Clone, Debug)]
+pub struct TypeOf;
+
+impl Callable for TypeOf {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::String(arguments[0].type_name().into()))
+    }
+}
+
+impl Display for TypeOf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClassName;
+
+impl Callable for ClassName {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match &arguments[0] {
+            Value::Instance(instance) => Ok(Value::String(instance.borrow().class().name().into())),
+
+            value => Err(RuntimeError::new(SourceToken::default(), RuntimeErrorDescription::Message(format!("className expected an instance but got {}", value.type_name())))),
+        }
+    }
+}
+
+impl Display for ClassName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use rlox_test_utils::{ assert_lox_error, parse };
+    use super::*;
+
+
+    fn ident(name: &str) -> SourceToken {
+        SourceToken { token: Token::Identifier(name.into()), lexeme: name.into(), line: 0 }
+    }
+
+    fn evaluate_global(source: &str, name: &str) -> Value {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(parse(source)).expect("Failed to run script");
+
+        let value = interpreter.environment().borrow().get(&ident(name)).unwrap();
+        (*value).clone()
+    }
+
+    fn type_of(expr: &str) -> Value {
+        evaluate_global(&format!("fun f() {{ }} var t = type({});", expr), "t")
+    }
+
+    #[test]
+    fn test_type_names() {
+        assert_eq!(type_of("nil"), Value::String("nil".into()));
+        assert_eq!(type_of("true"), Value::String("boolean".into()));
+        assert_eq!(type_of("1"), Value::String("number".into()));
+        assert_eq!(type_of("\"abc\""), Value::String("string".into()));
+        assert_eq!(type_of("f"), Value::String("function".into()));
+        assert_eq!(type_of("clock"), Value::String("function".into()));
+    }
+
+    #[test]
+    fn test_type_comparison() {
+        let result = evaluate_global("var x = 1; var r; if (type(x) == \"number\") r = \"yes\"; else r = \"no\";", "r");
+        assert_eq!(result, Value::String("yes".into()));
+    }
+
+    #[test]
+    fn test_class_name() {
+        assert_eq!(evaluate_global("class Point { } var n = className(Point());", "n"), Value::String("Point".into()));
+        assert_eq!(evaluate_global("class A { } class B < A { } var n = className(B());", "n"), Value::String("B".into()));
+        assert_lox_error!("class Point { } className(Point);", Message(_));
+        assert_lox_error!("className(1);", Message(_));
+    }
+}
@@ -1,6 +1,9 @@
+use std::cell::RefCell;
 use std::fmt::{ Debug, Display };
 use std::rc::Rc;
-use crate::{ Interpreter, RuntimeError };
+use rlox_scanner::SourceToken;
+use crate::{ Interpreter, RuntimeError, RuntimeErrorDescription };
+use crate::class::{ ClassDefinition, Instance };
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -9,14 +12,47 @@ pub enum Value {
     Number(f64),
     String(String),
     Function(Rc<dyn Callable>),
+    Instance(Rc<RefCell<Instance>>),
+    List(Rc<RefCell<Vec<Value>>>),
 }
 
 pub trait Callable : Debug + Display {
     fn arity(&self) -> usize;
+    // callables accepting optional trailing arguments can accept up to this many, arity() is the minimum
+    fn max_arity(&self) -> usize {
+        self.arity()
+    }
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError>;
+
+    fn type_name(&self) -> &'static str {
+        "function"
+    }
+    // only classes can be inherited from
+    fn as_class(&self) -> Option<&ClassDefinition> {
+        None
+    }
 }
 
 impl Value {
+    pub fn new_list(values: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(values)))
+    }
+
+    /// The name scripts see from `type(value)`, these must stay stable as scripts can branch on them
+    pub fn type_name(&self) -> &'static str {
+        use Value::*;
+
+        match self {
+            Nil => "nil",
+            Boolean(_) => "boolean",
+            Number(_) => "number",
+            String(_) => "string",
+            Function(function) => function.type_name(),
+            Instance(_) => "instance",
+            List(_) => "list",
+        }
+    }
+
     pub fn as_number(&self) -> Result<f64, ()> {
         use Value::*;
 
@@ -26,6 +62,13 @@ impl Value {
             _ => Err(()),
         }
     }
+    // as_number(), blaming `token` when the value isn't a number
+    pub fn as_number_with_token(&self, token: &SourceToken) -> Result<f64, RuntimeError> {
+        self.as_number().map_err(|_| RuntimeError::new(token.clone(), RuntimeErrorDescription::ExpectedNumber(Box::new(self.clone()))))
+    }
+    pub fn require_number(&self, token: &SourceToken) -> Result<f64, RuntimeError> {
+        self.as_number_with_token(token)
+    }
 
     pub fn as_callable(&self) -> Result<&dyn Callable, ()> {
         use Value::*;
@@ -57,6 +100,8 @@ impl Value {
             (Number(left), Number(right)) => *left == *right,
             (String(left), String(right)) => *left == *right,
             (Function(left), Function(right)) => ::std::ptr::eq(left.as_ref(), right.as_ref()),
+            (Instance(left), Instance(right)) => Rc::ptr_eq(left, right),
+            (List(left), List(right)) => Rc::ptr_eq(left, right),
 
             _ => false
         }
@@ -65,12 +110,36 @@ impl Value {
 
 impl ::std::fmt::Display for Value {
     fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> Result<(), ::std::fmt::Error> {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    // `enclosing` holds the lists currently being printed so a list containing itself prints as [...] instead of
+    // recursing forever. only ancestors are tracked, the same list appearing twice side by side is printed both times
+    fn fmt_nested(&self, f: &mut ::std::fmt::Formatter<'_>, enclosing: &mut Vec<*const RefCell<Vec<Value>>>) -> Result<(), ::std::fmt::Error> {
         match self {
             Value::Nil => f.write_str("nil"),
             Value::Boolean(value) => if *value { f.write_str("true") } else { f.write_str("false") },
             Value::Number(value) => write!(f, "{}", value),
             Value::String(value) => f.write_str(value),
             Value::Function(function) => write!(f, "{}", function),
+            Value::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Value::List(list) => {
+                let pointer = Rc::as_ptr(list);
+                if enclosing.contains(&pointer) {
+                    return f.write_str("[...]");
+                }
+
+                enclosing.push(pointer);
+                f.write_str("[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 { f.write_str(", ")?; }
+                    value.fmt_nested(f, enclosing)?;
+                }
+                enclosing.pop();
+                f.write_str("]")
+            },
         }
     }
 }
@@ -80,3 +149,27 @@ impl PartialEq for Value {
         self.is_equal(other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use super::*;
+
+    fn token(line: usize) -> SourceToken {
+        SourceToken { token: Token::Minus, lexeme: "-".into(), line }
+    }
+
+    #[test]
+    fn test_as_number_with_token() {
+        assert_eq!(Value::Number(2f64).as_number_with_token(&token(1)).unwrap(), 2f64);
+        assert_eq!(Value::Number(2f64).require_number(&token(1)).unwrap(), 2f64);
+
+        let error = Value::Nil.as_number_with_token(&token(7)).unwrap_err();
+        assert_eq!(error.token.line, 7);
+        assert_eq!(error.description, RuntimeErrorDescription::ExpectedNumber(Box::new(Value::Nil)));
+
+        let error = Value::String("a".into()).require_number(&token(12)).unwrap_err();
+        assert_eq!(error.token.line, 12);
+        assert_eq!(error.description, RuntimeErrorDescription::ExpectedNumber(Box::new(Value::String("a".into()))));
+    }
+}
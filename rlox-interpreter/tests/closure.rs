@@ -0,0 +1,54 @@
+use rlox_test_utils::assert_lox_output;
+
+#[test]
+fn test_make_counter() {
+    assert_lox_output!("\
+fun make_counter() {
+    var count = 0;
+    fun increment() {
+        count = count + 1;
+        return count;
+    }
+    return increment;
+}
+var c = make_counter();
+print c();
+print c();
+", "1\n2\n");
+}
+
+#[test]
+fn test_counters_are_independent() {
+    assert_lox_output!("\
+fun make_counter() {
+    var count = 0;
+    fun increment() {
+        count = count + 1;
+        return count;
+    }
+    return increment;
+}
+var a = make_counter();
+var b = make_counter();
+a();
+a();
+print a();
+print b();
+", "3\n1\n");
+}
+
+#[test]
+fn test_closure_captures_enclosing_function_scope() {
+    // the inner function must see its enclosing function's locals, not globals of the same name
+    assert_lox_output!("\
+var name = \"global\";
+fun outer() {
+    var name = \"outer\";
+    fun inner() {
+        return name;
+    }
+    return inner;
+}
+print outer()();
+", "outer\n");
+}
@@ -0,0 +1,133 @@
+use rlox_interpreter::RuntimeErrorDescription;
+use rlox_test_utils::{ assert_lox_error, assert_lox_output };
+
+#[test]
+fn test_print() {
+    assert_lox_output!("print 1 + 1;", "2\n");
+    assert_lox_output!("print \"a\" + 1; print nil; print true;", "a1\nnil\ntrue\n");
+    assert_lox_output!("var café = \"🦀 crab\"; print café;", "🦀 crab\n");
+}
+
+#[test]
+fn test_control_flow() {
+    assert_lox_output!("if (1 > 2) print \"yes\"; else print \"no\";", "no\n");
+    assert_lox_output!("var a = 0; while (a < 3) { print a; a = a + 1; }", "0\n1\n2\n");
+    assert_lox_output!("for (var i = 0; i < 2; i = i + 1) print i;", "0\n1\n");
+}
+
+#[test]
+fn test_ternary() {
+    assert_lox_output!("print 1 > 2 ? \"yes\" : \"no\";", "no\n");
+    assert_lox_output!("var a = 0; var b = nil ? (a = 1) : (a = 2); print a; print b;", "2\n2\n");
+    assert_lox_output!("fun sign(n) { return n < 0 ? -1 : n > 0 ? 1 : 0; } print sign(-5); print sign(0); print sign(5);", "-1\n0\n1\n");
+}
+
+#[test]
+fn test_functions() {
+    assert_lox_output!("fun add(a, b) { return a + b; } print add(1, 2);", "3\n");
+    assert_lox_output!("fun fib(n) { if (n <= 1) return n; return fib(n - 2) + fib(n - 1); } print fib(10);", "55\n");
+}
+
+#[test]
+fn test_runtime_errors() {
+    assert_lox_error!("print 1 - \"a\";", ExpectedNumber(_));
+    assert_lox_error!("print 1 / 0;", DivideByZero);
+    assert_lox_error!("print a;", UndefinedVariable(_));
+    assert_lox_error!("fun f(a) { } f();", UnexpectedNumberOfArguments { expected: 1, provided: 0, callee_name: Some(_) });
+}
+
+#[test]
+fn test_format() {
+    assert_lox_output!("print format(\"{} + {} = {:.1}\", 1, \"a\", 2);", "1 + a = 2.0\n");
+    assert_lox_output!("printf(\"{:>4}|\", 7); printf(\"{{}}\"); print \"\";", "   7|{}\n");
+}
+
+#[test]
+fn test_modulo() {
+    assert_lox_output!("print 5 % 3; print -7 % 3; print 7.5 % 2;", "2\n-1\n1.5\n");
+    assert_lox_error!("print 1 % 0;", DivideByZero);
+}
+
+#[test]
+fn test_methods() {
+    assert_lox_output!("class A { } var a = A(); a.x = 1; print a.x; print a.y = 2;", "1\n2\n");
+    assert_lox_output!("class A { get() { return this.x; } } var a = A(); a.x = 3; var get = a.get; a.x = 4; print get();", "4\n");
+    assert_lox_output!("class P { init(x) { this.x = x; return; } } var p = P(5); print p.x; print p.init(6); print p.x;", "5\nP instance\n6\n");
+    assert_lox_error!("class A { } print A().x;", UndefinedProperty(_));
+    assert_lox_error!("var a = 1; print a.x;", ExpectedInstance);
+    assert_lox_error!("class P { init(x) { } } P();", UnexpectedNumberOfArguments { expected: 1, provided: 0, callee_name: Some(_) });
+}
+
+#[test]
+fn test_bound_methods() {
+    // a bound method keeps the instance it was read from, wherever it's stored or called
+    assert_lox_output!("class A { init(n) { this.n = n; } get() { return this.n; } } var a = A(1); var b = A(2); b.f = a.get; print b.f(); print b.get();", "1\n2\n");
+    assert_lox_output!("class A { one() { return 1; } two() { return this.one() + 1; } } print A().two();", "2\n");
+    assert_lox_output!("class A { counter() { fun inc() { this.count = this.count + 1; return this.count; } return inc; } } var a = A(); a.count = 0; var inc = a.counter(); inc(); print inc(); print a.count;", "2\n2\n");
+}
+
+#[test]
+fn test_inheritance() {
+    assert_lox_output!("class A { init(n) { this.n = n; } get() { return this.n; } } class B < A { } print B(7).get();", "7\n");
+    assert_lox_output!("class A { name() { return \"A\"; } } class B < A { name() { return \"B\" + super.name(); } } class C < B { } print C().name();", "BA\n");
+    assert_lox_output!("class A { init(n) { this.n = n; } } class B < A { init() { super.init(3); } } print B().n;", "3\n");
+    assert_lox_output!("class A { get() { return this.n; } } class B < A { get() { var get = super.get; return get; } } var b = B(); b.n = 5; print b.get()();", "5\n");
+    assert_lox_error!("var A = 1; class B < A { }", Message(_));
+    assert_lox_error!("class A < A { }", Message(_));
+    assert_lox_error!("class A { get() { return super.get(); } } A().get();", Message(_));
+    assert_lox_error!("class A { } class B < A { get() { return super.missing(); } } B().get();", UndefinedProperty(_));
+    // a class nested in a subclass's method doesn't see the outer superclass
+    assert_lox_error!("class A { f() { } } class B < A { g() { class C { h() { return super.f; } } return C().h(); } } B().g();", Message(_));
+}
+
+#[test]
+fn test_break_continue() {
+    assert_lox_output!("var i = 0; while (true) { i = i + 1; if (i > 2) break; print i; }", "1\n2\n");
+    assert_lox_output!("for (var i = 0; i < 4; i = i + 1) { if (i == 1) continue; print i; }", "0\n2\n3\n");
+    assert_lox_output!("for (var i = 0; i < 2; i = i + 1) { for (var j = 0; j < 3; j = j + 1) { if (j == 1) break; print j; } print i; }", "0\n0\n0\n1\n");
+    assert_lox_output!("for x in [1, 2, 3, 4] { if (x == 2) continue; if (x == 4) break; print x; }", "1\n3\n");
+}
+
+#[test]
+fn test_call_stack() {
+    let source = "
+fun inner() { return 1 / 0; }
+fun middle() { return inner(); }
+fun outer() { return middle(); }
+outer();
+";
+    match rlox_test_utils::run_interpreter(source) {
+        (_, Err(rlox_test_utils::LoxError::Runtime(e))) => {
+            let frames: Vec<_> = e.call_stack.iter().map(|frame| (frame.name.as_str(), frame.line)).collect();
+            assert_eq!(frames, vec![("inner", 2), ("middle", 3), ("outer", 4)]);
+        },
+        other => panic!("Expected a runtime error, but got {:?}", other),
+    }
+}
+
+#[test]
+fn test_arity_error_names_callee() {
+    let error = |source: &str| match rlox_test_utils::run_interpreter(source) {
+        (_, Err(rlox_test_utils::LoxError::Runtime(e))) => e,
+        other => panic!("Expected a runtime error, but got {:?}", other),
+    };
+
+    let e = error("fun add(a, b) {}\n\nadd(1);");
+    assert_eq!(e.description, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: 2, provided: 1, callee_name: Some(Box::new("add".into())) });
+    assert_eq!((e.token.lexeme.as_str(), e.token.line), ("add", 3));
+
+    let e = error("class A { f(x) {} }\nA().f();");
+    assert_eq!(e.description, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: 1, provided: 0, callee_name: Some(Box::new("f".into())) });
+    assert_eq!(e.token.lexeme, "f");
+
+    // an arbitrary callee expression has no name, so the error stays on the closing paren
+    let e = error("fun f() { fun g(a) {} return g; }\nf()(1, 2);");
+    assert_eq!(e.description, RuntimeErrorDescription::UnexpectedNumberOfArguments { expected: 1, provided: 2, callee_name: None });
+    assert_eq!(e.token.lexeme, ")");
+}
+
+#[test]
+fn test_string_comparison() {
+    assert_lox_output!("print \"a\" < \"b\"; print \"b\" <= \"ab\"; print \"abc\" > \"ab\"; print \"a\" >= \"a\";", "true\nfalse\ntrue\ntrue\n");
+    assert_lox_error!("print \"a\" < 1;", ExpectedNumber(_));
+}
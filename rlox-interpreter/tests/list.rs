@@ -0,0 +1,66 @@
+use rlox_test_utils::{ assert_lox_error, assert_lox_output };
+
+#[test]
+fn test_list_literal() {
+    assert_lox_output!("print [];", "[]\n");
+    assert_lox_output!("print [1, \"a\", nil, [true]];", "[1, a, nil, [true]]\n");
+    assert_lox_output!("print type([]);", "list\n");
+}
+
+#[test]
+fn test_print_cyclic_list() {
+    assert_lox_output!("var a = [1]; push(a, a); print a;", "[1, [...]]\n");
+    assert_lox_output!("var a = [1]; var b = [a]; push(a, b); print a; print b;", "[1, [[...]]]\n[[1, [...]]]\n");
+    assert_lox_output!("var a = [1]; print [a, a];", "[[1], [1]]\n");
+}
+
+#[test]
+fn test_push_pop_len() {
+    assert_lox_output!("var l = []; for (var i = 0; i < 3; i = i + 1) push(l, i * 2); print l; print len(l);", "[0, 2, 4]\n3\n");
+    assert_lox_output!("var l = [1, 2]; print pop(l); print pop(l); print pop(l); print l;", "2\n1\nnil\n[]\n");
+    assert_lox_output!("print len(\"abc\");", "3\n");
+}
+
+#[test]
+fn test_insert_remove_contains() {
+    assert_lox_output!("var l = [1, 3]; insert(l, 1, 2); insert(l, 3, 4); print l;", "[1, 2, 3, 4]\n");
+    assert_lox_output!("var l = [1, 2, 3]; print removeAt(l, 0); print l;", "1\n[2, 3]\n");
+    assert_lox_output!("var l = [1, \"a\"]; print contains(l, \"a\"); print contains(l, 2);", "true\nfalse\n");
+}
+
+#[test]
+fn test_higher_order() {
+    assert_lox_output!("fun double(x) { return x * 2; } print map([1, 2, 3], double);", "[2, 4, 6]\n");
+    assert_lox_output!("fun big(x) { return x > 2; } print filter([1, 2, 3, 4], big);", "[3, 4]\n");
+    assert_lox_output!("fun show(x) { print x; } each([1, 2], show);", "1\n2\n");
+}
+
+#[test]
+fn test_list_errors() {
+    assert_lox_error!("removeAt([1, 2], 2);", Message(_));
+    assert_lox_error!("insert([1], 3, 1);", Message(_));
+    assert_lox_error!("removeAt([1], 0.5);", Message(_));
+    assert_lox_error!("push(1, 2);", Message(_));
+    assert_lox_error!("len(1);", Message(_));
+    assert_lox_error!("map([1], 1);", Message(_));
+    assert_lox_error!("fun f(a, b) { } map([1], f);", UnexpectedNumberOfArguments { expected: 2, provided: 1, callee_name: None });
+}
+
+#[test]
+fn test_for_in() {
+    assert_lox_output!("for x in [1, 2, 3] print x * 2;", "2\n4\n6\n");
+    assert_lox_output!("for c in \"abc\" { print c; }", "a\nb\nc\n");
+    assert_lox_output!("var l = [1, 2]; for x in l { push(l, x); } print l;", "[1, 2, 1, 2]\n");
+    assert_lox_output!("fun first(l) { for x in l { if (x > 1) return x; } return nil; } print first([1, 5, 7]);", "5\n");
+    assert_lox_output!("var x = \"outer\"; for x in [1] { } print x;", "outer\n");
+    assert_lox_output!("for x in [] print x; print \"done\";", "done\n");
+    assert_lox_error!("for x in 1 print x;", Message(_));
+}
+
+#[test]
+fn test_destructure() {
+    assert_lox_output!("fun min_max(a, b) { if (a < b) return [a, b]; return [b, a]; } var (lo, hi) = min_max(5, 2); print lo; print hi;", "2\n5\n");
+    assert_lox_output!("var (a) = [1, 2]; print a;", "1\n");
+    assert_lox_error!("var (a, b, c) = [1, 2];", NotEnoughValuesToUnpack { expected: 3, provided: 2 });
+    assert_lox_error!("var (a, b) = 1;", Message(_));
+}
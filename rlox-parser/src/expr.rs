@@ -5,10 +5,18 @@ pub enum Expr {
     Assign(SourceToken, Box<Expr>),
     Binary(Box<Expr>, SourceToken, Box<Expr>),
     Call(Box<Expr>, SourceToken, Vec<Expr>),
+    Get(Box<Expr>, SourceToken),
+    Set(Box<Expr>, SourceToken, Box<Expr>),
+    // the `super` keyword and the method name being accessed on the superclass
+    Super(SourceToken, SourceToken),
     Logical(Box<Expr>, SourceToken, Box<Expr>),
+    // `condition ? then : else`, the token is the `?`
+    Ternary(Box<Expr>, SourceToken, Box<Expr>, Box<Expr>),
     Unary(SourceToken, Box<Expr>),
     Grouping(Box<Expr>),
+    List(SourceToken, Vec<Expr>),
 
+    This(SourceToken),
     Var(SourceToken),
     String(SourceToken, String),
     Number(SourceToken, f64),
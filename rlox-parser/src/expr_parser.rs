@@ -10,7 +10,7 @@ pub struct ExprParser<'a> {
 }
 
 type PrefixFn<'a> = fn(&mut ExprParser<'a>, can_assign: bool) -> ParserResult<Expr>;
-type InfixFn<'a> = fn(&mut ExprParser<'a>, Expr) -> ParserResult<Expr>;
+type InfixFn<'a> = fn(&mut ExprParser<'a>, Expr, can_assign: bool) -> ParserResult<Expr>;
 
 struct ParseRule<'a> {
     prefix: Option<PrefixFn<'a>>,
@@ -39,6 +39,7 @@ impl<'a> ParseRule<'a> {
 enum Precedence {
     None = 0,
     Assignment,
+    Conditional,
     Or,
     And,
     Equality,
@@ -72,7 +73,10 @@ impl<'a> ExprParser<'a> {
         }
 
         add_rule(&mut rules, Token::Eof, ParseRule::new(None, None, Precedence::None));
-        add_rule(&mut rules, Token::LeftParen, ParseRule::new_prefix(ExprParser::grouping, Precedence::None));
+        add_rule(&mut rules, Token::LeftParen, ParseRule::new(Some(ExprParser::grouping), Some(ExprParser::call), Precedence::Call));
+
+        add_rule(&mut rules, Token::Dot, ParseRule::new_infix(ExprParser::dot, Precedence::Call));
+        add_rule(&mut rules, Token::LeftBracket, ParseRule::new_prefix(ExprParser::list, Precedence::None));
 
         add_rule(&mut rules, Token::Identifier(String::new()), ParseRule::new_prefix(ExprParser::variable, Precedence::None));
         add_rule(&mut rules, Token::Number(0f64), ParseRule::new_prefix(ExprParser::literal, Precedence::None));
@@ -80,6 +84,8 @@ impl<'a> ExprParser<'a> {
         add_rule(&mut rules, Token::True, ParseRule::new_prefix(ExprParser::literal, Precedence::None));
         add_rule(&mut rules, Token::False, ParseRule::new_prefix(ExprParser::literal, Precedence::None));
         add_rule(&mut rules, Token::Nil, ParseRule::new_prefix(ExprParser::literal, Precedence::None));
+        add_rule(&mut rules, Token::This, ParseRule::new_prefix(ExprParser::this, Precedence::None));
+        add_rule(&mut rules, Token::Super, ParseRule::new_prefix(ExprParser::super_method, Precedence::Primary));
 
         add_rule(&mut rules, Token::Bang, ParseRule::new_prefix(ExprParser::unary, Precedence::Unary));
 
@@ -87,6 +93,7 @@ impl<'a> ExprParser<'a> {
         add_rule(&mut rules, Token::Minus, ParseRule::new(Some(ExprParser::unary), Some(ExprParser::binary), Precedence::Term));
         add_rule(&mut rules, Token::Star, ParseRule::new_infix(ExprParser::binary, Precedence::Factor));
         add_rule(&mut rules, Token::Slash, ParseRule::new_infix(ExprParser::binary, Precedence::Factor));
+        add_rule(&mut rules, Token::Percent, ParseRule::new_infix(ExprParser::binary, Precedence::Factor));
         add_rule(&mut rules, Token::BangEqual, ParseRule::new_infix(ExprParser::binary, Precedence::Equality));
         add_rule(&mut rules, Token::EqualEqual, ParseRule::new_infix(ExprParser::binary, Precedence::Equality));
         add_rule(&mut rules, Token::Greater, ParseRule::new_infix(ExprParser::binary, Precedence::Comparison));
@@ -96,6 +103,7 @@ impl<'a> ExprParser<'a> {
 
         add_rule(&mut rules, Token::And, ParseRule::new_infix(ExprParser::logical, Precedence::And));
         add_rule(&mut rules, Token::Or, ParseRule::new_infix(ExprParser::logical, Precedence::Or));
+        add_rule(&mut rules, Token::Question, ParseRule::new_infix(ExprParser::conditional, Precedence::Conditional));
 
         ExprParser {
             parser,
@@ -120,7 +128,7 @@ impl<'a> ExprParser<'a> {
             let prev = self.parser.previous();
             let infix = self.infix_rule(prev)?;
             expr = match infix {
-                Some(infix) => infix(self, expr)?,
+                Some(infix) => infix(self, expr, can_assign)?,
                 None => panic!("invalid rule for {:?}", prev),
             }
         }
@@ -132,7 +140,7 @@ impl<'a> ExprParser<'a> {
         }
     }
 
-    fn binary(&mut self, left: Expr) -> ParserResult<Expr> {
+    fn binary(&mut self, left: Expr, _can_assign: bool) -> ParserResult<Expr> {
         let op = self.parser.previous().clone();
 
         let precedence = self.precedence(&op);
@@ -140,7 +148,7 @@ impl<'a> ExprParser<'a> {
 
         Ok(Expr::Binary(Box::new(left), op, Box::new(right)))
     }
-    fn logical(&mut self, left: Expr) -> ParserResult<Expr> {
+    fn logical(&mut self, left: Expr, _can_assign: bool) -> ParserResult<Expr> {
         let op = self.parser.previous().clone();
 
         let precedence = self.precedence(&op);
@@ -149,6 +157,49 @@ impl<'a> ExprParser<'a> {
         Ok(Expr::Logical(Box::new(left), op, Box::new(right)))
     }
 
+    // the else branch is parsed at the same precedence so `a ? b : c ? d : e` nests to the right
+    fn conditional(&mut self, condition: Expr, _can_assign: bool) -> ParserResult<Expr> {
+        let question = self.parser.previous().clone();
+
+        let then_branch = self.parse()?;
+        self.parser.consume(Token::Colon, ParserErrorDescription::ExpectedToken(Token::Colon, "Expected ':' after then branch of conditional expression".into()))?;
+        let else_branch = self.parse_precedence(Precedence::Conditional)?;
+
+        Ok(Expr::Ternary(Box::new(condition), question, Box::new(then_branch), Box::new(else_branch)))
+    }
+
+    fn call(&mut self, callee: Expr, _can_assign: bool) -> ParserResult<Expr> {
+        let mut arguments = Vec::new();
+
+        if !self.parser.check(Token::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(self.parser.error(self.parser.peek(), ParserErrorDescription::TooManyArguments));
+                }
+
+                arguments.push(self.parse()?);
+
+                if !self.parser.try_consume(Token::Comma) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.parser.consume(Token::RightParen, ParserErrorDescription::ExpectedToken(Token::RightParen, "Expected ')' after arguments".into()))?.clone();
+
+        Ok(Expr::Call(Box::new(callee), paren, arguments))
+    }
+    fn dot(&mut self, object: Expr, can_assign: bool) -> ParserResult<Expr> {
+        let name = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected property name after '.'".into()))?.clone();
+
+        if can_assign && self.parser.try_consume(Token::Equal) {
+            let value = self.parse()?;
+            Ok(Expr::Set(Box::new(object), name, Box::new(value)))
+        } else {
+            Ok(Expr::Get(Box::new(object), name))
+        }
+    }
+
     fn unary(&mut self, can_assign: bool) -> ParserResult<Expr> {
         let op = self.parser.previous().clone();
         let expr = self.parse_precedence(Precedence::Unary)?;
@@ -163,6 +214,37 @@ impl<'a> ExprParser<'a> {
         Ok(Expr::Grouping(Box::new(expr)))
     }
 
+    fn list(&mut self, _can_assign: bool) -> ParserResult<Expr> {
+        let mut elements = Vec::new();
+
+        if !self.parser.check(Token::RightBracket) {
+            loop {
+                elements.push(self.parse()?);
+
+                if !self.parser.try_consume(Token::Comma) {
+                    break;
+                }
+            }
+        }
+
+        let bracket = self.parser.consume(Token::RightBracket, ParserErrorDescription::ExpectedToken(Token::RightBracket, "Expected ']' after list elements".into()))?.clone();
+
+        Ok(Expr::List(bracket, elements))
+    }
+
+    fn this(&mut self, _can_assign: bool) -> ParserResult<Expr> {
+        Ok(Expr::This(self.parser.previous().clone()))
+    }
+
+    // whether `super` is inside a subclass isn't known here, the backends check that context
+    fn super_method(&mut self, _can_assign: bool) -> ParserResult<Expr> {
+        let keyword = self.parser.previous().clone();
+        self.parser.consume(Token::Dot, ParserErrorDescription::ExpectedToken(Token::Dot, "Expected '.' after 'super'".into()))?;
+        let method = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected superclass method name".into()))?.clone();
+
+        Ok(Expr::Super(keyword, method))
+    }
+
     fn variable(&mut self, can_assign: bool) -> ParserResult<Expr> {
         self.named_variable(self.parser.previous().clone(), can_assign)
     }
@@ -277,7 +359,7 @@ mod tests {
 
     #[test]
     fn test_binary() {
-        for operator in vec![Token::Slash, Token::Star, Token::Minus, Token::Plus, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual, Token::BangEqual, Token::EqualEqual] {
+        for operator in vec![Token::Slash, Token::Percent, Token::Star, Token::Minus, Token::Plus, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual, Token::BangEqual, Token::EqualEqual] {
             assert_eq!(expect_parse_expression(vec![Token::Number(123f64), operator.clone(), Token::Number(456f64)]),
                        Expr::Binary(Box::new(expr_num(123f64)), tok_to_src(operator.clone()), Box::new(expr_num(456f64))));
             assert_eq!(expect_parse_expression(vec![Token::Number(123f64), operator.clone(), Token::Number(456f64), operator.clone(), Token::Number(789f64)]),
@@ -290,7 +372,7 @@ mod tests {
 
     #[test]
     fn test_logical() {
-        for operator in vec![Token::And, Token::Or] {
+        for operator in [Token::And, Token::Or] {
             assert_eq!(expect_parse_expression(vec![Token::False, operator.clone(), Token::True]),
                        Expr::Logical(Box::new(expr_bool(false)), tok_to_src(operator.clone()), Box::new(expr_bool(true))));
             assert_eq!(expect_parse_expression(vec![Token::False, operator.clone(), Token::True, operator.clone(), Token::True]),
@@ -298,11 +380,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ternary() {
+        let ternary = |condition, then_branch, else_branch| Expr::Ternary(Box::new(condition), tok_to_src(Token::Question), Box::new(then_branch), Box::new(else_branch));
+
+        assert_eq!(expect_parse_expression(vec![Token::True, Token::Question, Token::Number(1f64), Token::Colon, Token::Number(2f64)]),
+                   ternary(expr_bool(true), expr_num(1f64), expr_num(2f64)));
+        // binds looser than `or` and nests to the right
+        assert_eq!(expect_parse_expression(vec![Token::False, Token::Or, Token::True, Token::Question, Token::Number(1f64), Token::Colon, Token::False, Token::Question, Token::Number(2f64), Token::Colon, Token::Number(3f64)]),
+                   ternary(Expr::Logical(Box::new(expr_bool(false)), tok_to_src(Token::Or), Box::new(expr_bool(true))), expr_num(1f64), ternary(expr_bool(false), expr_num(2f64), expr_num(3f64))));
+        // a ternary in the then branch needs no grouping
+        assert_eq!(expect_parse_expression(vec![Token::True, Token::Question, Token::False, Token::Question, Token::Number(1f64), Token::Colon, Token::Number(2f64), Token::Colon, Token::Number(3f64)]),
+                   ternary(expr_bool(true), ternary(expr_bool(false), expr_num(1f64), expr_num(2f64)), expr_num(3f64)));
+        // and binds tighter than assignment
+        assert_eq!(expect_parse_expression(vec![ident("a"), Token::Equal, Token::True, Token::Question, Token::Number(1f64), Token::Colon, Token::Number(2f64)]),
+                   Expr::Assign(tok_to_src(ident("a")), Box::new(ternary(expr_bool(true), expr_num(1f64), expr_num(2f64)))));
+
+        assert!(parse_expression(vec![Token::True, Token::Question, Token::Number(1f64)]).is_err());
+        assert!(parse_expression(vec![Token::True, Token::Question, Token::Number(1f64), Token::Colon]).is_err());
+    }
+
     #[test]
     fn test_call() {
         assert_eq!(expect_parse_expression(vec![ident("abc"), Token::LeftParen, Token::RightParen]), Expr::Call(Box::new(Expr::Var(tok_to_src(ident("abc")))), tok_to_src(Token::RightParen), vec![]));
         assert_eq!(expect_parse_expression(vec![ident("abc"), Token::LeftParen, Token::Number(123f64), Token::RightParen]), Expr::Call(Box::new(Expr::Var(tok_to_src(ident("abc")))), tok_to_src(Token::RightParen), vec![expr_num(123f64)]));
         assert_eq!(expect_parse_expression(vec![ident("abc"), Token::LeftParen, Token::Number(123f64), Token::Comma, Token::Number(456f64), Token::RightParen]), Expr::Call(Box::new(Expr::Var(tok_to_src(ident("abc")))), tok_to_src(Token::RightParen), vec![expr_num(123f64), expr_num(456f64)]));
+        // calls chain, the result of one call is the callee of the next
+        assert_eq!(expect_parse_expression(vec![ident("abc"), Token::LeftParen, Token::RightParen, Token::LeftParen, Token::RightParen]),
+                   Expr::Call(Box::new(Expr::Call(Box::new(Expr::Var(tok_to_src(ident("abc")))), tok_to_src(Token::RightParen), vec![])), tok_to_src(Token::RightParen), vec![]));
+
+        assert!(parse_expression(vec![ident("abc"), Token::LeftParen, Token::Number(123f64)]).is_err());
+    }
+
+    #[test]
+    fn test_call_argument_limit() {
+        let call = |count: usize| {
+            let mut tokens = vec![ident("abc"), Token::LeftParen];
+            for i in 0..count {
+                if i > 0 {
+                    tokens.push(Token::Comma);
+                }
+                tokens.push(Token::Number(i as f64));
+            }
+            tokens.push(Token::RightParen);
+            parse_expression(tokens)
+        };
+
+        match call(255) {
+            Ok(Expr::Call(_, _, arguments)) => assert_eq!(arguments.len(), 255),
+            result => panic!("Expected a call, got {:?}", result),
+        }
+        assert_eq!(call(256).map_err(|e| e.description), Err(ParserErrorDescription::TooManyArguments));
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(expect_parse_expression(vec![Token::LeftBracket, Token::RightBracket]), Expr::List(tok_to_src(Token::RightBracket), vec![]));
+        assert_eq!(expect_parse_expression(vec![Token::LeftBracket, Token::Number(123f64), Token::Comma, Token::True, Token::RightBracket]), Expr::List(tok_to_src(Token::RightBracket), vec![expr_num(123f64), expr_bool(true)]));
+        assert_eq!(expect_parse_expression(vec![Token::LeftBracket, Token::LeftBracket, Token::RightBracket, Token::RightBracket]), Expr::List(tok_to_src(Token::RightBracket), vec![Expr::List(tok_to_src(Token::RightBracket), vec![])]));
+
+        assert!(parse_expression(vec![Token::LeftBracket, Token::Number(123f64)]).is_err());
     }
 
     #[test]
@@ -311,6 +448,33 @@ mod tests {
         assert_eq!(expect_parse_expression(vec![ident("abc"), Token::Equal, ident("def"), Token::Equal, Token::Number(123f64)]), Expr::Assign(tok_to_src(ident("abc")), Box::new(Expr::Assign(tok_to_src(ident("def")), Box::new(expr_num(123f64))))));
     }
 
+    #[test]
+    fn test_property() {
+        let object = Box::new(Expr::Var(tok_to_src(ident("a"))));
+
+        assert_eq!(expect_parse_expression(vec![ident("a"), Token::Dot, ident("b")]), Expr::Get(object.clone(), tok_to_src(ident("b"))));
+        assert_eq!(expect_parse_expression(vec![ident("a"), Token::Dot, ident("b"), Token::Dot, ident("c")]), Expr::Get(Box::new(Expr::Get(object.clone(), tok_to_src(ident("b")))), tok_to_src(ident("c"))));
+        assert_eq!(expect_parse_expression(vec![ident("a"), Token::Dot, ident("b"), Token::LeftParen, Token::RightParen]), Expr::Call(Box::new(Expr::Get(object.clone(), tok_to_src(ident("b")))), tok_to_src(Token::RightParen), vec![]));
+        assert_eq!(expect_parse_expression(vec![ident("a"), Token::Dot, ident("b"), Token::Equal, Token::Number(123f64)]), Expr::Set(object.clone(), tok_to_src(ident("b")), Box::new(expr_num(123f64))));
+        assert_eq!(expect_parse_expression(vec![Token::This, Token::Dot, ident("b")]), Expr::Get(Box::new(Expr::This(tok_to_src(Token::This))), tok_to_src(ident("b"))));
+
+        assert!(parse_expression(vec![ident("a"), Token::Dot, Token::Number(123f64)]).is_err());
+        assert!(parse_expression(vec![Token::Minus, ident("a"), Token::Dot, ident("b"), Token::Equal, Token::Number(123f64)]).is_err());
+    }
+
+    #[test]
+    fn test_super() {
+        let super_method = Expr::Super(tok_to_src(Token::Super), tok_to_src(ident("method")));
+
+        assert_eq!(expect_parse_expression(vec![Token::Super, Token::Dot, ident("method")]), super_method.clone());
+        assert_eq!(expect_parse_expression(vec![Token::Super, Token::Dot, ident("method"), Token::LeftParen, Token::Number(123f64), Token::RightParen]),
+                   Expr::Call(Box::new(super_method), tok_to_src(Token::RightParen), vec![expr_num(123f64)]));
+
+        assert!(parse_expression(vec![Token::Super]).is_err());
+        assert!(parse_expression(vec![Token::Super, Token::LeftParen, Token::RightParen]).is_err());
+        assert!(parse_expression(vec![Token::Super, Token::Dot, Token::Number(123f64)]).is_err());
+    }
+
     #[test]
     fn test_error() {
         let result = parse_expression(vec![Token::LeftParen, Token::False]);
@@ -1,11 +1,17 @@
+// the parser runs on every token, a `&vec![...]` that could be an array allocates on each call
+#![deny(clippy::useless_vec)]
+
 mod expr;
 mod expr_parser;
 mod parser;
+mod printer;
 mod stmt;
 mod stmt_parser;
+pub mod visitor;
 
 pub use expr::Expr;
 pub use expr_parser::ExprParser;
-pub use parser::{ Parser, ParserError };
+pub use parser::{ Parser, ParserError, ParserErrorDescription, ParserErrorLocation };
+pub use printer::AstPrinter;
 pub use stmt::{ Func, Stmt };
 pub use stmt_parser::StmtParser;
\ No newline at end of file
@@ -1,8 +1,12 @@
+use std::fmt::{ Display, Formatter };
 use std::mem::Discriminant;
 use rlox_scanner::{ SourceToken, Token };
 
 pub struct Parser {
-    tokens: Vec<SourceToken>,
+    // tokens never change once parsing starts, a boxed slice drops the vec's spare capacity
+    tokens: Box<[SourceToken]>,
+    // handed out when reading past the end, so token lists without a trailing EOF don't panic
+    eof: SourceToken,
 
     current: usize,
 }
@@ -10,9 +14,15 @@ pub struct Parser {
 #[derive(Debug, PartialEq)]
 pub struct ParserError {
     pub line: usize,
-    pub location: String,
+    pub location: ParserErrorLocation,
     pub description: ParserErrorDescription,
 }
+// where on the line the error is, the token's lexeme when it's not the end of the source
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParserErrorLocation {
+    AtEnd,
+    AtToken(String),
+}
 #[derive(Debug, PartialEq)]
 pub enum ParserErrorDescription {
     ExpectedToken(Token, String),
@@ -21,24 +31,45 @@ pub enum ParserErrorDescription {
     InvalidAssignmentTarget,
     TooManyArguments,
     TooManyParameters,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    ReturnOutsideFunction,
 }
 
 pub type ParserResult<T> = Result<T, ParserError>;
 
+impl Display for ParserErrorLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParserErrorLocation::AtEnd => write!(f, "at end"),
+            ParserErrorLocation::AtToken(lexeme) => write!(f, "at '{}'", lexeme),
+        }
+    }
+}
+
+// `new`, `is_at_end` and `peek` are for driving the parsers from other crates, e.g. checking nothing trails an expression,
+// the token-level movement and checks are only for `ExprParser`/`StmtParser`
 impl Parser {
     pub fn new(tokens: Vec<SourceToken>) -> Parser {
+        let eof = SourceToken {
+            token: Token::Eof,
+            lexeme: String::new(),
+            line: tokens.last().map(|token| token.line).unwrap_or(0),
+        };
+
         Parser {
-            tokens,
+            tokens: tokens.into_boxed_slice(),
+            eof,
 
             current: 0,
         }
     }
 
     // movement
-    pub fn try_consume(&mut self, token: Token) -> bool {
+    pub(crate) fn try_consume(&mut self, token: Token) -> bool {
         self.try_consume_discriminant(::std::mem::discriminant(&token))
     }
-    pub fn try_consume_discriminant(&mut self, token: Discriminant<Token>) -> bool {
+    pub(crate) fn try_consume_discriminant(&mut self, token: Discriminant<Token>) -> bool {
         if self.check_discriminant(token) {
             self.advance();
             true
@@ -47,20 +78,10 @@ impl Parser {
         }
     }
 
-    pub fn try_consume_one_of(&mut self, tokens: Vec<Token>) -> bool {
-        for token in tokens {
-            if self.try_consume(token) {
-                return true
-            }
-        }
-
-        false
-    }
-
-    pub fn consume(&mut self, expected: Token, error: ParserErrorDescription) -> ParserResult<&SourceToken> {
+    pub(crate) fn consume(&mut self, expected: Token, error: ParserErrorDescription) -> ParserResult<&SourceToken> {
         self.consume_discriminant(::std::mem::discriminant(&expected), error)
     }
-    pub fn consume_discriminant(&mut self, expected: Discriminant<Token>, error: ParserErrorDescription) -> ParserResult<&SourceToken> {
+    pub(crate) fn consume_discriminant(&mut self, expected: Discriminant<Token>, error: ParserErrorDescription) -> ParserResult<&SourceToken> {
         if self.check_discriminant(expected) {
            Ok(self.advance())
         } else {
@@ -68,24 +89,32 @@ impl Parser {
         }
     }
 
-    pub fn advance(&mut self) -> &SourceToken {
+    // for backtracking after trying one parse out
+    pub(crate) fn position(&self) -> usize {
+        self.current
+    }
+    pub(crate) fn rewind(&mut self, position: usize) {
+        self.current = position;
+    }
+
+    pub(crate) fn advance(&mut self) -> &SourceToken {
         self.current += 1;
         self.previous()
     }
 
-    pub fn error(&self, token: &SourceToken, description: ParserErrorDescription) -> ParserError {
+    pub(crate) fn error(&self, token: &SourceToken, description: ParserErrorDescription) -> ParserError {
         ParserError {
             line: token.line,
-            location: if token.token == Token::Eof { "at end".into() } else { format!("at '{}'", token.lexeme) },
+            location: if token.token == Token::Eof { ParserErrorLocation::AtEnd } else { ParserErrorLocation::AtToken(token.lexeme.clone()) },
             description,
         }
     }
 
     // checks
-    pub fn check(&self, token: Token) -> bool {
+    pub(crate) fn check(&self, token: Token) -> bool {
         self.check_discriminant(::std::mem::discriminant(&token))
     }
-    pub fn check_discriminant(&self, token: Discriminant<Token>) -> bool {
+    pub(crate) fn check_discriminant(&self, token: Discriminant<Token>) -> bool {
         if self.is_at_end() {
             false
         } else {
@@ -94,19 +123,45 @@ impl Parser {
     }
 
     pub fn is_at_end(&self) -> bool {
-        self.current >= self.tokens.len() || self.peek().token == Token::Eof
+        self.peek().token == Token::Eof
     }
 
     pub fn peek(&self) -> &SourceToken {
-        self.tokens.get(self.current).unwrap()
+        self.tokens.get(self.current).unwrap_or(&self.eof)
     }
 
-    pub fn previous(&self) -> &SourceToken {
-        if self.current > self.tokens.len() {
-            &self.tokens[self.tokens.len()]
-        } else {
-            &self.tokens[self.current - 1]
+    pub(crate) fn previous(&self) -> &SourceToken {
+        self.current.checked_sub(1)
+            .and_then(|index| self.tokens.get(index))
+            .unwrap_or(&self.eof)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tok_to_src(t: Token) -> SourceToken {
+        SourceToken {
+            token: t.clone(),
+            lexeme: format!("{:?}", t),
+            line: 0
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_error_location() {
+        let mut parser = Parser::new(vec![SourceToken { token: Token::Identifier("a".into()), lexeme: "a".into(), line: 3 }, tok_to_src(Token::Eof)]);
+
+        let error = parser.consume(Token::Semicolon, ParserErrorDescription::ExpectedExpression).unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.location, ParserErrorLocation::AtToken("a".into()));
+        assert_eq!(error.location.to_string(), "at 'a'");
+
+        parser.advance();
+        let error = parser.consume(Token::Semicolon, ParserErrorDescription::ExpectedExpression).unwrap_err();
+        assert!(matches!(error.location, ParserErrorLocation::AtEnd));
+        assert_eq!(error.location.to_string(), "at end");
+    }
+}
@@ -0,0 +1,221 @@
+use rlox_scanner::SourceToken;
+use crate::{ Expr, Func, Stmt };
+use crate::visitor::{ ExprVisitor, StmtVisitor };
+
+// prints the AST as nested s-expressions, e.g. `1 + 2 * 3` is `(+ 1 (* 2 3))`
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn new() -> AstPrinter {
+        AstPrinter
+    }
+
+    pub fn print_expr(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    pub fn print_stmt(&mut self, stmt: &Stmt) -> String {
+        stmt.accept(self)
+    }
+
+    fn parenthesize(&mut self, name: &str, parts: Vec<String>) -> String {
+        let mut result = format!("({}", name);
+        for part in parts {
+            result.push(' ');
+            result.push_str(&part);
+        }
+        result.push(')');
+        result
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Vec<String> {
+        exprs.iter().map(|expr| self.print_expr(expr)).collect()
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Vec<String> {
+        stmts.iter().map(|stmt| self.print_stmt(stmt)).collect()
+    }
+
+    fn names(names: &[SourceToken]) -> String {
+        let names: Vec<&str> = names.iter().map(|name| name.lexeme.as_str()).collect();
+        format!("({})", names.join(" "))
+    }
+}
+
+impl Default for AstPrinter {
+    fn default() -> AstPrinter {
+        AstPrinter::new()
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_assign(&mut self, name: &SourceToken, value: &Expr) -> String {
+        let parts = vec![name.lexeme.clone(), self.print_expr(value)];
+        self.parenthesize("=", parts)
+    }
+    fn visit_binary(&mut self, left: &Expr, operator: &SourceToken, right: &Expr) -> String {
+        let parts = vec![self.print_expr(left), self.print_expr(right)];
+        self.parenthesize(&operator.lexeme, parts)
+    }
+    fn visit_call(&mut self, callee: &Expr, _paren: &SourceToken, arguments: &[Expr]) -> String {
+        let mut parts = vec![self.print_expr(callee)];
+        parts.extend(self.exprs(arguments));
+        self.parenthesize("call", parts)
+    }
+    fn visit_get(&mut self, object: &Expr, name: &SourceToken) -> String {
+        let parts = vec![self.print_expr(object), name.lexeme.clone()];
+        self.parenthesize(".", parts)
+    }
+    fn visit_set(&mut self, object: &Expr, name: &SourceToken, value: &Expr) -> String {
+        let parts = vec![self.print_expr(object), name.lexeme.clone(), self.print_expr(value)];
+        self.parenthesize(".=", parts)
+    }
+    fn visit_super(&mut self, _keyword: &SourceToken, method: &SourceToken) -> String {
+        self.parenthesize("super", vec![method.lexeme.clone()])
+    }
+    fn visit_logical(&mut self, left: &Expr, operator: &SourceToken, right: &Expr) -> String {
+        let parts = vec![self.print_expr(left), self.print_expr(right)];
+        self.parenthesize(&operator.lexeme, parts)
+    }
+    fn visit_ternary(&mut self, condition: &Expr, _question: &SourceToken, then_branch: &Expr, else_branch: &Expr) -> String {
+        let parts = vec![self.print_expr(condition), self.print_expr(then_branch), self.print_expr(else_branch)];
+        self.parenthesize("?:", parts)
+    }
+    fn visit_unary(&mut self, operator: &SourceToken, right: &Expr) -> String {
+        let parts = vec![self.print_expr(right)];
+        self.parenthesize(&operator.lexeme, parts)
+    }
+    fn visit_grouping(&mut self, expr: &Expr) -> String {
+        let parts = vec![self.print_expr(expr)];
+        self.parenthesize("group", parts)
+    }
+    fn visit_list(&mut self, _bracket: &SourceToken, elements: &[Expr]) -> String {
+        let parts = self.exprs(elements);
+        self.parenthesize("list", parts)
+    }
+    fn visit_this(&mut self, _keyword: &SourceToken) -> String {
+        "this".into()
+    }
+    fn visit_var(&mut self, name: &SourceToken) -> String {
+        name.lexeme.clone()
+    }
+    fn visit_string(&mut self, _token: &SourceToken, value: &str) -> String {
+        format!("{:?}", value)
+    }
+    fn visit_number(&mut self, _token: &SourceToken, value: f64) -> String {
+        value.to_string()
+    }
+    fn visit_boolean(&mut self, _token: &SourceToken, value: bool) -> String {
+        value.to_string()
+    }
+    fn visit_nil(&mut self, _token: &SourceToken) -> String {
+        "nil".into()
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_block(&mut self, statements: &[Stmt]) -> String {
+        let parts = self.stmts(statements);
+        self.parenthesize("block", parts)
+    }
+    fn visit_break(&mut self, _keyword: &SourceToken) -> String {
+        "(break)".into()
+    }
+    fn visit_class(&mut self, name: &SourceToken, superclass: Option<&SourceToken>, methods: &[Func]) -> String {
+        let mut parts = vec![name.lexeme.clone()];
+        if let Some(superclass) = superclass {
+            parts.push("<".into());
+            parts.push(superclass.lexeme.clone());
+        }
+        parts.extend(methods.iter().map(|method| self.visit_function(method)));
+        self.parenthesize("class", parts)
+    }
+    fn visit_continue(&mut self, _keyword: &SourceToken) -> String {
+        "(continue)".into()
+    }
+    fn visit_destructure(&mut self, names: &[SourceToken], value: &Expr) -> String {
+        let parts = vec![AstPrinter::names(names), self.print_expr(value)];
+        self.parenthesize("var", parts)
+    }
+    fn visit_expression(&mut self, expr: &Expr) -> String {
+        let parts = vec![self.print_expr(expr)];
+        self.parenthesize(";", parts)
+    }
+    fn visit_for_in(&mut self, name: &SourceToken, iterable: &Expr, body: &Stmt) -> String {
+        let parts = vec![name.lexeme.clone(), "in".into(), self.print_expr(iterable), self.print_stmt(body)];
+        self.parenthesize("for", parts)
+    }
+    fn visit_function(&mut self, func: &Func) -> String {
+        let mut parts = vec![func.name.lexeme.clone(), AstPrinter::names(&func.parameters)];
+        parts.extend(self.stmts(&func.body));
+        self.parenthesize("fun", parts)
+    }
+    fn visit_if(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> String {
+        let mut parts = vec![self.print_expr(condition), self.print_stmt(then_branch)];
+        if let Some(else_branch) = else_branch {
+            parts.push(self.print_stmt(else_branch));
+        }
+        self.parenthesize("if", parts)
+    }
+    fn visit_print(&mut self, expr: &Expr) -> String {
+        let parts = vec![self.print_expr(expr)];
+        self.parenthesize("print", parts)
+    }
+    fn visit_return(&mut self, _keyword: &SourceToken, value: Option<&Expr>) -> String {
+        let parts = value.map(|value| self.print_expr(value)).into_iter().collect();
+        self.parenthesize("return", parts)
+    }
+    fn visit_var(&mut self, name: &SourceToken, initializer: Option<&Expr>) -> String {
+        let mut parts = vec![name.lexeme.clone()];
+        parts.extend(initializer.map(|initializer| self.print_expr(initializer)));
+        self.parenthesize("var", parts)
+    }
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> String {
+        let mut parts = vec![self.print_expr(condition), self.print_stmt(body)];
+        parts.extend(increment.map(|increment| self.print_expr(increment)));
+        self.parenthesize("while", parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use super::*;
+
+    fn tok(token: Token, lexeme: &str) -> SourceToken {
+        SourceToken { token, lexeme: lexeme.into(), line: 0 }
+    }
+
+    #[test]
+    fn test_print_expr() {
+        // -123 * (45.67)
+        let expr = Expr::Binary(
+            Box::new(Expr::Unary(tok(Token::Minus, "-"), Box::new(Expr::Number(tok(Token::Number(123.0), "123"), 123.0)))),
+            tok(Token::Star, "*"),
+            Box::new(Expr::Grouping(Box::new(Expr::Number(tok(Token::Number(45.67), "45.67"), 45.67)))),
+        );
+
+        assert_eq!(AstPrinter::new().print_expr(&expr), "(* (- 123) (group 45.67))");
+
+        // a ? 1 : 2
+        let expr = Expr::Ternary(
+            Box::new(Expr::Var(tok(Token::Identifier("a".into()), "a"))),
+            tok(Token::Question, "?"),
+            Box::new(Expr::Number(tok(Token::Number(1.0), "1"), 1.0)),
+            Box::new(Expr::Number(tok(Token::Number(2.0), "2"), 2.0)),
+        );
+
+        assert_eq!(AstPrinter::new().print_expr(&expr), "(?: a 1 2)");
+    }
+
+    #[test]
+    fn test_print_stmt() {
+        let stmt = Stmt::If(
+            Expr::Boolean(tok(Token::True, "true"), true),
+            Box::new(Stmt::Print(Expr::String(tok(Token::String("a".into()), "\"a\""), "a".into()))),
+            Some(Box::new(Stmt::Return(tok(Token::Return, "return"), None))),
+        );
+
+        assert_eq!(AstPrinter::new().print_stmt(&stmt), "(if true (print \"a\") (return))");
+    }
+}
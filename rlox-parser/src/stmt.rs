@@ -4,14 +4,20 @@ use crate::Expr;
 #[derive(Clone, Debug, PartialEq)]
 pub enum Stmt {
     Block(Vec<Stmt>),
-    Class(SourceToken, Vec<Func>),
+    Break(SourceToken),
+    Class(SourceToken, Option<SourceToken>, Vec<Func>),
+    Continue(SourceToken),
+    // `var (a, b) = expr;`, binding each name to the matching element of a list
+    Destructure(Vec<SourceToken>, Expr),
     Expression(Expr),
+    ForIn(SourceToken, Expr, Box<Stmt>),
     Function(Func),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
     Print(Expr),
     Return(SourceToken, Option<Expr>),
     Var(SourceToken, Option<Expr>),
-    While(Expr, Box<Stmt>),
+    // the optional expression is a desugared for loop's increment, it runs after the body and on `continue`
+    While(Expr, Box<Stmt>, Option<Expr>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
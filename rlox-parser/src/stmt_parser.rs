@@ -1,16 +1,24 @@
-use rlox_scanner::Token;
+use rlox_scanner::{ SourceToken, Token };
 use crate::parser::{ Parser, ParserErrorDescription, ParserResult };
 use crate::expr_parser::ExprParser;
 use crate::{ Expr, Func, Stmt };
 
 pub struct StmtParser<'a> {
-    parser: &'a mut Parser
+    parser: &'a mut Parser,
+
+    // number of loops enclosing the current statement, reset inside function bodies
+    loop_depth: usize,
+    // number of functions and methods enclosing the current statement, `return` is only allowed inside one
+    function_depth: usize,
 }
 
 impl<'a> StmtParser<'a> {
     pub fn new(parser: &'a mut Parser) -> StmtParser<'a> {
         StmtParser {
-            parser
+            parser,
+
+            loop_depth: 0,
+            function_depth: 0,
         }
     }
 
@@ -24,6 +32,21 @@ impl<'a> StmtParser<'a> {
         statements
     }
 
+    // for REPLs, where a bare `1 + 2` should be evaluated rather than rejected for missing its semicolon. `None` when
+    // the remaining tokens aren't exactly one expression, leaving the parser where it was so they can be parsed as
+    // statements instead
+    pub fn try_parse_expression(&mut self) -> Option<ParserResult<Expr>> {
+        let start = self.parser.position();
+
+        match self.expression() {
+            Ok(expr) if self.parser.is_at_end() => Some(Ok(expr)),
+            _ => {
+                self.parser.rewind(start);
+                None
+            },
+        }
+    }
+
     // statements
     fn declaration(&mut self) -> ParserResult<Stmt> {
         let decl = if self.parser.try_consume(Token::Class) {
@@ -50,6 +73,13 @@ impl<'a> StmtParser<'a> {
         let name = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected class name".into()))?;
         let name = name.clone();
 
+        let superclass = if self.parser.try_consume(Token::Less) {
+            let superclass = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected superclass name".into()))?;
+            Some(superclass.clone())
+        } else {
+            None
+        };
+
         self.parser.consume(Token::LeftBrace, ParserErrorDescription::ExpectedToken(Token::LeftBrace, "Expected '{' before class body".into()))?;
 
         let mut functions = Vec::new();
@@ -59,11 +89,15 @@ impl<'a> StmtParser<'a> {
 
         self.parser.consume(Token::RightBrace, ParserErrorDescription::ExpectedToken(Token::RightBrace, "Expected '}' after class body".into()))?;
 
-        Ok(Stmt::Class(name, functions))
+        Ok(Stmt::Class(name, superclass, functions))
     }
 
     fn var_declaration(&mut self) -> ParserResult<Stmt> {
         // var keyword is already consumed
+        if self.parser.try_consume(Token::LeftParen) {
+            return self.destructure_declaration();
+        }
+
         let name = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected variable name".into()))?;
         let name = name.clone();
 
@@ -78,8 +112,33 @@ impl<'a> StmtParser<'a> {
         Ok(Stmt::Var(name, initializer))
     }
 
+    fn destructure_declaration(&mut self) -> ParserResult<Stmt> {
+        // var keyword and left paren are already consumed
+        let mut names = Vec::new();
+        loop {
+            let name = self.parser.consume_discriminant(::std::mem::discriminant(&Token::Identifier(String::new())), ParserErrorDescription::ExpectedIdentifier("Expected variable name".into()))?;
+            names.push(name.clone());
+
+            if !self.parser.try_consume(Token::Comma) {
+                break;
+            }
+        }
+        self.parser.consume(Token::RightParen, ParserErrorDescription::ExpectedToken(Token::RightParen, "Expected ')' after variable names".into()))?;
+
+        self.parser.consume(Token::Equal, ParserErrorDescription::ExpectedToken(Token::Equal, "Expected '=' after variable names".into()))?;
+        let value = self.expression()?;
+
+        self.parser.consume(Token::Semicolon, ParserErrorDescription::ExpectedToken(Token::Semicolon, "Expected ';' after variable declaration".into()))?;
+
+        Ok(Stmt::Destructure(names, value))
+    }
+
     fn statement(&mut self) -> ParserResult<Stmt> {
-        if self.parser.try_consume(Token::For) {
+        if self.parser.try_consume(Token::Break) {
+            self.loop_control_statement(Stmt::Break, ParserErrorDescription::BreakOutsideLoop)
+        } else if self.parser.try_consume(Token::Continue) {
+            self.loop_control_statement(Stmt::Continue, ParserErrorDescription::ContinueOutsideLoop)
+        } else if self.parser.try_consume(Token::For) {
             self.for_statement()
         } else if self.parser.try_consume(Token::If) {
             self.if_statement()
@@ -98,6 +157,10 @@ impl<'a> StmtParser<'a> {
 
     fn for_statement(&mut self) -> ParserResult<Stmt> {
         // for keyword is already consumed
+        if self.parser.check_discriminant(::std::mem::discriminant(&Token::Identifier(String::new()))) {
+            return self.for_in_statement();
+        }
+
         self.parser.consume(Token::LeftParen, ParserErrorDescription::ExpectedToken(Token::LeftParen, "Expected '(' after 'for'".into()))?;
 
         let initializer = if self.parser.try_consume(Token::Semicolon) {
@@ -122,13 +185,8 @@ impl<'a> StmtParser<'a> {
         };
         self.parser.consume(Token::RightParen, ParserErrorDescription::ExpectedToken(Token::RightParen, "Expected ')' after for update".into()))?;
 
-        let mut body = self.statement()?;
-
-        if let Some(update) = update {
-            body = Stmt::Block(vec![body, Stmt::Expression(update)]);
-        }
-
-        body = Stmt::While(condition, Box::new(body));
+        let body = self.loop_body()?;
+        let mut body = Stmt::While(condition, Box::new(body), update);
 
         if let Some(initializer) = initializer {
             body = Stmt::Block(vec![initializer, body]);
@@ -137,6 +195,17 @@ impl<'a> StmtParser<'a> {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> ParserResult<Stmt> {
+        // for keyword is already consumed
+        let name = self.parser.advance().clone();
+        self.parser.consume(Token::In, ParserErrorDescription::ExpectedToken(Token::In, "Expected 'in' after for loop variable".into()))?;
+
+        let collection = self.expression()?;
+        let body = self.loop_body()?;
+
+        Ok(Stmt::ForIn(name, collection, Box::new(body)))
+    }
+
     fn if_statement(&mut self) -> ParserResult<Stmt> {
         // if keyword is already consumed
         self.parser.consume(Token::LeftParen, ParserErrorDescription::ExpectedToken(Token::LeftParen, "Expected '(' after 'if'".into()))?;
@@ -165,6 +234,10 @@ impl<'a> StmtParser<'a> {
     fn return_statement(&mut self) -> ParserResult<Stmt> {
         let token = self.parser.previous().clone();
 
+        if self.function_depth == 0 {
+            return Err(self.parser.error(&token, ParserErrorDescription::ReturnOutsideFunction));
+        }
+
         let value = if self.parser.check(Token::Semicolon) {
             None
         } else {
@@ -182,9 +255,30 @@ impl<'a> StmtParser<'a> {
         let condition = self.expression()?;
         self.parser.consume(Token::RightParen, ParserErrorDescription::ExpectedToken(Token::RightParen, "Expected ')' after if condition".into()))?;
 
-        let body = Box::new(self.statement()?);
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While(condition, body, None))
+    }
+
+    fn loop_body(&mut self) -> ParserResult<Stmt> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+
+        body
+    }
+
+    fn loop_control_statement(&mut self, stmt: fn(SourceToken) -> Stmt, outside_loop: ParserErrorDescription) -> ParserResult<Stmt> {
+        // break/continue keyword is already consumed
+        let token = self.parser.previous().clone();
+
+        if self.loop_depth == 0 {
+            return Err(self.parser.error(&token, outside_loop));
+        }
+
+        self.parser.consume(Token::Semicolon, ParserErrorDescription::ExpectedToken(Token::Semicolon, format!("Expected ';' after '{}'", token.lexeme)))?;
 
-        Ok(Stmt::While(condition, body))
+        Ok(stmt(token))
     }
 
     fn block(&mut self) -> ParserResult<Vec<Stmt>> {
@@ -228,7 +322,14 @@ impl<'a> StmtParser<'a> {
         }
         self.parser.consume(Token::RightParen, ParserErrorDescription::ExpectedToken(Token::RightParen, "Expected ')' after parameters".into()))?;
 
-        let body = match self.statement()? {
+        // a loop around the declaration doesn't extend into the function's body
+        let loop_depth = ::std::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.statement();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+
+        let body = match body? {
             Stmt::Block(stmts) => {
                 stmts
             },
@@ -252,7 +353,7 @@ impl<'a> StmtParser<'a> {
             }
 
             match self.parser.peek().token {
-                Token::Class | Token::Fun | Token::Var | Token::For | Token::If | Token::While | Token::Print | Token::Return => return,
+                Token::Class | Token::Fun | Token::Var | Token::For | Token::If | Token::While | Token::Print | Token::Return | Token::Break | Token::Continue => return,
                 _ => { }
             }
 
@@ -264,7 +365,7 @@ impl<'a> StmtParser<'a> {
 #[cfg(test)]
 mod tests {
     use rlox_scanner::SourceToken;
-    use crate::Expr;
+    use crate::{ Expr, ParserError, ParserErrorLocation };
     use super::*;
 
     fn parse_statement(tokens: Vec<Token>) -> ParserResult<Stmt> {
@@ -301,6 +402,45 @@ mod tests {
         Expr::Boolean(tok_to_src(if b { Token::True } else { Token::False }), b)
     }
 
+    fn try_parse_expression(tokens: Vec<Token>) -> (Option<ParserResult<Expr>>, Vec<ParserResult<Stmt>>) {
+        let mut source_tokens: Vec<SourceToken> = tokens.into_iter().map(tok_to_src).collect();
+        source_tokens.push(tok_to_src(Token::Eof));
+
+        let mut parser = Parser::new(source_tokens);
+        let mut stmt_parser = StmtParser::new(&mut parser);
+        let expr = stmt_parser.try_parse_expression();
+
+        (expr, stmt_parser.parse())
+    }
+
+    #[test]
+    fn test_try_parse_expression() {
+        let (expr, rest) = try_parse_expression(vec![Token::Number(1f64), Token::Plus, Token::Number(2f64)]);
+        assert_eq!(expr, Some(Ok(Expr::Binary(Box::new(expr_num(1f64)), tok_to_src(Token::Plus), Box::new(expr_num(2f64))))));
+        assert!(rest.is_empty());
+
+        // anything that isn't exactly one expression is left to be parsed as statements
+        let (expr, rest) = try_parse_expression(vec![Token::Number(1f64), Token::Semicolon]);
+        assert_eq!(expr, None);
+        assert_eq!(rest, vec![Ok(Stmt::Expression(expr_num(1f64)))]);
+
+        let (expr, rest) = try_parse_expression(vec![Token::Var, ident("a"), Token::Semicolon]);
+        assert_eq!(expr, None);
+        assert_eq!(rest, vec![Ok(Stmt::Var(tok_to_src(ident("a")), None))]);
+
+        let (expr, rest) = try_parse_expression(vec![Token::Number(1f64), Token::Number(2f64)]);
+        assert_eq!(expr, None);
+        assert!(rest[0].is_err());
+
+        let (expr, rest) = try_parse_expression(vec![Token::Number(1f64), Token::Plus]);
+        assert_eq!(expr, None);
+        assert!(rest[0].is_err());
+
+        let (expr, rest) = try_parse_expression(vec![]);
+        assert_eq!(expr, None);
+        assert!(rest.is_empty());
+    }
+
     #[test]
     fn test_fun_declaration() {
         assert_eq!(expect_parse_statement(vec![Token::Fun, ident("abc"), Token::LeftParen, Token::RightParen, Token::LeftBrace, Token::RightBrace]), Stmt::Function(Func::new(tok_to_src(ident("abc")), vec![], vec![])));
@@ -309,12 +449,29 @@ mod tests {
         assert_eq!(expect_parse_statement(vec![Token::Fun, ident("abc"), Token::LeftParen, Token::RightParen, Token::LeftBrace, Token::Print, Token::Number(1f64), Token::Semicolon, Token::RightBrace]), Stmt::Function(Func::new(tok_to_src(ident("abc")), vec![], vec![Stmt::Print(expr_num(1f64))])));
     }
 
+    #[test]
+    fn test_class_declaration() {
+        assert_eq!(expect_parse_statement(vec![Token::Class, ident("A"), Token::LeftBrace, Token::RightBrace]), Stmt::Class(tok_to_src(ident("A")), None, vec![]));
+        assert_eq!(expect_parse_statement(vec![Token::Class, ident("A"), Token::Less, ident("B"), Token::LeftBrace, ident("f"), Token::LeftParen, Token::RightParen, Token::LeftBrace, Token::RightBrace, Token::RightBrace]),
+                   Stmt::Class(tok_to_src(ident("A")), Some(tok_to_src(ident("B"))), vec![Func::new(tok_to_src(ident("f")), vec![], vec![])]));
+        assert!(parse_statement(vec![Token::Class, ident("A"), Token::Less, Token::LeftBrace, Token::RightBrace]).is_err());
+    }
+
     #[test]
     fn test_var_declaration() {
         assert_eq!(expect_parse_statement(vec![Token::Var, ident("abc"), Token::Semicolon]), Stmt::Var(tok_to_src(ident("abc")), None));
         assert_eq!(expect_parse_statement(vec![Token::Var, ident("abc"), Token::Equal, Token::Number(123f64), Token::Semicolon]), Stmt::Var(tok_to_src(ident("abc")), Some(expr_num(123f64))));
     }
 
+    #[test]
+    fn test_for_in() {
+        assert_eq!(expect_parse_statement(vec![Token::For, ident("a"), Token::In, ident("b"), Token::Print, ident("a"), Token::Semicolon]),
+                   Stmt::ForIn(tok_to_src(ident("a")), Expr::Var(tok_to_src(ident("b"))), Box::new(Stmt::Print(Expr::Var(tok_to_src(ident("a")))))));
+        assert_eq!(expect_parse_statement(vec![Token::For, ident("a"), Token::In, ident("b"), Token::LeftBrace, Token::RightBrace]),
+                   Stmt::ForIn(tok_to_src(ident("a")), Expr::Var(tok_to_src(ident("b"))), Box::new(Stmt::Block(vec![]))));
+        assert!(parse_statement(vec![Token::For, ident("a"), ident("b"), Token::LeftBrace, Token::RightBrace]).is_err());
+    }
+
     #[test]
     fn test_for() {
         let empty_for = vec![Token::For, Token::LeftParen, Token::Semicolon, Token::Semicolon, Token::RightParen, Token::Print, Token::Number(2f64), Token::Semicolon];
@@ -326,29 +483,34 @@ mod tests {
         let blank_true = Expr::Boolean(tok_to_src(Token::Semicolon), true);
 
         assert_eq!(expect_parse_statement(empty_for),
-                   Stmt::While(blank_true.clone(), Box::new(Stmt::Print(expr_num(2f64)))));
+                   Stmt::While(blank_true.clone(), Box::new(Stmt::Print(expr_num(2f64))), None));
         assert_eq!(expect_parse_statement(just_init_for),
                    Stmt::Block(vec![
                        Stmt::Var(tok_to_src(ident("a")), None),
-                       Stmt::While(blank_true.clone(), Box::new(Stmt::Print(expr_num(2f64)))),
+                       Stmt::While(blank_true.clone(), Box::new(Stmt::Print(expr_num(2f64))), None),
                    ]));
         assert_eq!(expect_parse_statement(just_cond_for),
-                   Stmt::While(expr_bool(false), Box::new(Stmt::Print(expr_num(2f64)))));
+                   Stmt::While(expr_bool(false), Box::new(Stmt::Print(expr_num(2f64))), None));
         assert_eq!(expect_parse_statement(just_update_for),
-                   Stmt::While(blank_true.clone(), Box::new(Stmt::Block(vec![
-                       Stmt::Print(expr_num(2f64)),
-                       Stmt::Expression(Expr::Assign(tok_to_src(ident("a")), Box::new(expr_bool(false))))
-                   ]))));
+                   Stmt::While(blank_true.clone(), Box::new(Stmt::Print(expr_num(2f64))),
+                               Some(Expr::Assign(tok_to_src(ident("a")), Box::new(expr_bool(false))))));
         assert_eq!(expect_parse_statement(all_for),
                    Stmt::Block(vec![
                        Stmt::Var(tok_to_src(ident("a")), None),
-                       Stmt::While(Expr::Unary(tok_to_src(Token::Bang), Box::new(Expr::Var(tok_to_src(ident("a"))))), Box::new(Stmt::Block(vec![
-                           Stmt::Print(expr_num(2f64)),
-                           Stmt::Expression(Expr::Assign(tok_to_src(ident("a")), Box::new(expr_bool(false))))
-                       ]))),
+                       Stmt::While(Expr::Unary(tok_to_src(Token::Bang), Box::new(Expr::Var(tok_to_src(ident("a"))))),
+                                   Box::new(Stmt::Print(expr_num(2f64))),
+                                   Some(Expr::Assign(tok_to_src(ident("a")), Box::new(expr_bool(false))))),
                    ]));
     }
 
+    #[test]
+    fn test_destructure() {
+        assert_eq!(expect_parse_statement(vec![Token::Var, Token::LeftParen, ident("a"), Token::Comma, ident("b"), Token::RightParen, Token::Equal, ident("c"), Token::Semicolon]),
+                   Stmt::Destructure(vec![tok_to_src(ident("a")), tok_to_src(ident("b"))], Expr::Var(tok_to_src(ident("c")))));
+        assert!(parse_statement(vec![Token::Var, Token::LeftParen, ident("a"), Token::RightParen, Token::Semicolon]).is_err());
+        assert!(parse_statement(vec![Token::Var, Token::LeftParen, Token::RightParen, Token::Equal, ident("c"), Token::Semicolon]).is_err());
+    }
+
     #[test]
     fn test_if() {
         assert_eq!(expect_parse_statement(vec![Token::If, Token::LeftParen, Token::Number(1f64), Token::RightParen, Token::Print, Token::Number(2f64), Token::Semicolon]),
@@ -364,17 +526,77 @@ mod tests {
 
     #[test]
     fn test_return() {
-        assert_eq!(expect_parse_statement(vec![Token::Return, Token::Semicolon]), Stmt::Return(tok_to_src(Token::Return), None));
-        assert_eq!(expect_parse_statement(vec![Token::Return, Token::Number(123f64), Token::Semicolon]), Stmt::Return(tok_to_src(Token::Return), Some(expr_num(123f64))));
+        let function = |body: Vec<Token>| {
+            let mut tokens = vec![Token::Fun, ident("f"), Token::LeftParen, Token::RightParen, Token::LeftBrace];
+            tokens.extend(body);
+            tokens.push(Token::RightBrace);
+            tokens
+        };
+        let function_body = |stmt: Stmt| match stmt {
+            Stmt::Function(func) => func.body,
+            stmt => panic!("Expected a function, got {:?}", stmt),
+        };
+
+        assert_eq!(function_body(expect_parse_statement(function(vec![Token::Return, Token::Semicolon]))), vec![Stmt::Return(tok_to_src(Token::Return), None)]);
+        assert_eq!(function_body(expect_parse_statement(function(vec![Token::Return, Token::Number(123f64), Token::Semicolon]))), vec![Stmt::Return(tok_to_src(Token::Return), Some(expr_num(123f64)))]);
+        // nested blocks and loops in the function still count as inside it
+        assert!(parse_statement(function(vec![Token::While, Token::LeftParen, Token::True, Token::RightParen, Token::LeftBrace, Token::Return, Token::Semicolon, Token::RightBrace])).is_ok());
+
+        match parse_statement(vec![Token::Return, Token::Number(123f64), Token::Semicolon]) {
+            Err(ParserError { description: ParserErrorDescription::ReturnOutsideFunction, .. }) => { },
+            result => panic!("Expected return outside a function to fail, got {:?}", result),
+        }
+        match parse_statement(vec![Token::LeftBrace, Token::Return, Token::Semicolon, Token::RightBrace]) {
+            Err(ParserError { description: ParserErrorDescription::ReturnOutsideFunction, .. }) => { },
+            result => panic!("Expected return in a top level block to fail, got {:?}", result),
+        }
     }
 
     #[test]
     fn test_while() {
-        assert_eq!(expect_parse_statement(vec![Token::While, Token::LeftParen, Token::Number(123f64), Token::RightParen, Token::Print, Token::Number(456f64), Token::Semicolon]), Stmt::While(expr_num(123f64), Box::new(Stmt::Print(expr_num(456f64)))));
+        assert_eq!(expect_parse_statement(vec![Token::While, Token::LeftParen, Token::Number(123f64), Token::RightParen, Token::Print, Token::Number(456f64), Token::Semicolon]), Stmt::While(expr_num(123f64), Box::new(Stmt::Print(expr_num(456f64))), None));
+    }
+
+    #[test]
+    fn test_break_continue() {
+        assert_eq!(expect_parse_statement(vec![Token::While, Token::LeftParen, Token::True, Token::RightParen, Token::Break, Token::Semicolon]),
+                   Stmt::While(expr_bool(true), Box::new(Stmt::Break(tok_to_src(Token::Break))), None));
+        assert_eq!(expect_parse_statement(vec![Token::For, ident("a"), Token::In, ident("b"), Token::Continue, Token::Semicolon]),
+                   Stmt::ForIn(tok_to_src(ident("a")), Expr::Var(tok_to_src(ident("b"))), Box::new(Stmt::Continue(tok_to_src(Token::Continue)))));
+
+        match parse_statement(vec![Token::Break, Token::Semicolon]) {
+            Err(ParserError { description: ParserErrorDescription::BreakOutsideLoop, .. }) => { },
+            result => panic!("Expected break outside a loop to fail, got {:?}", result),
+        }
+        // a function declared inside a loop can't break out of it
+        match parse_statement(vec![Token::While, Token::LeftParen, Token::True, Token::RightParen, Token::LeftBrace, Token::Fun, ident("f"), Token::LeftParen, Token::RightParen, Token::LeftBrace, Token::Continue, Token::Semicolon, Token::RightBrace, Token::RightBrace]) {
+            Err(ParserError { description: ParserErrorDescription::ContinueOutsideLoop, .. }) => { },
+            result => panic!("Expected continue outside a loop to fail, got {:?}", result),
+        }
     }
 
     #[test]
     fn test_expression_statement() {
         assert_eq!(expect_parse_statement(vec![Token::Number(123f64), Token::Semicolon]), Stmt::Expression(expr_num(123f64)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_missing_eof() {
+        let parse_without_eof = |tokens: Vec<Token>| {
+            let mut parser = Parser::new(tokens.into_iter().map(tok_to_src).collect());
+            StmtParser::new(&mut parser).parse()
+        };
+
+        assert_eq!(parse_without_eof(vec![Token::Print, Token::Number(123f64), Token::Semicolon]), vec![Ok(Stmt::Print(expr_num(123f64)))]);
+        assert_eq!(parse_without_eof(vec![]), vec![]);
+
+        match parse_without_eof(vec![Token::Print, Token::Number(123f64)]).as_slice() {
+            [Err(ParserError { location, description: ParserErrorDescription::ExpectedToken(Token::Semicolon, _), .. })] => assert_eq!(location, &ParserErrorLocation::AtEnd),
+            result => panic!("Expected a missing semicolon error, got {:?}", result),
+        }
+        match parse_without_eof(vec![Token::Print, Token::Minus]).as_slice() {
+            [Err(ParserError { description: ParserErrorDescription::ExpectedExpression, .. })] => { },
+            result => panic!("Expected a missing expression error, got {:?}", result),
+        }
+    }
+}
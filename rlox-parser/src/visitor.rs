@@ -0,0 +1,84 @@
+use rlox_scanner::SourceToken;
+use crate::{ Expr, Func, Stmt };
+
+// one method per Expr variant, each given that variant's fields
+pub trait ExprVisitor<T> {
+    fn visit_assign(&mut self, name: &SourceToken, value: &Expr) -> T;
+    fn visit_binary(&mut self, left: &Expr, operator: &SourceToken, right: &Expr) -> T;
+    fn visit_call(&mut self, callee: &Expr, paren: &SourceToken, arguments: &[Expr]) -> T;
+    fn visit_get(&mut self, object: &Expr, name: &SourceToken) -> T;
+    fn visit_set(&mut self, object: &Expr, name: &SourceToken, value: &Expr) -> T;
+    fn visit_super(&mut self, keyword: &SourceToken, method: &SourceToken) -> T;
+    fn visit_logical(&mut self, left: &Expr, operator: &SourceToken, right: &Expr) -> T;
+    fn visit_ternary(&mut self, condition: &Expr, question: &SourceToken, then_branch: &Expr, else_branch: &Expr) -> T;
+    fn visit_unary(&mut self, operator: &SourceToken, right: &Expr) -> T;
+    fn visit_grouping(&mut self, expr: &Expr) -> T;
+    fn visit_list(&mut self, bracket: &SourceToken, elements: &[Expr]) -> T;
+    fn visit_this(&mut self, keyword: &SourceToken) -> T;
+    fn visit_var(&mut self, name: &SourceToken) -> T;
+    fn visit_string(&mut self, token: &SourceToken, value: &str) -> T;
+    fn visit_number(&mut self, token: &SourceToken, value: f64) -> T;
+    fn visit_boolean(&mut self, token: &SourceToken, value: bool) -> T;
+    fn visit_nil(&mut self, token: &SourceToken) -> T;
+}
+
+// one method per Stmt variant, each given that variant's fields
+pub trait StmtVisitor<T> {
+    fn visit_block(&mut self, statements: &[Stmt]) -> T;
+    fn visit_break(&mut self, keyword: &SourceToken) -> T;
+    fn visit_class(&mut self, name: &SourceToken, superclass: Option<&SourceToken>, methods: &[Func]) -> T;
+    fn visit_continue(&mut self, keyword: &SourceToken) -> T;
+    fn visit_destructure(&mut self, names: &[SourceToken], value: &Expr) -> T;
+    fn visit_expression(&mut self, expr: &Expr) -> T;
+    fn visit_for_in(&mut self, name: &SourceToken, iterable: &Expr, body: &Stmt) -> T;
+    fn visit_function(&mut self, func: &Func) -> T;
+    fn visit_if(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> T;
+    fn visit_print(&mut self, expr: &Expr) -> T;
+    fn visit_return(&mut self, keyword: &SourceToken, value: Option<&Expr>) -> T;
+    fn visit_var(&mut self, name: &SourceToken, initializer: Option<&Expr>) -> T;
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> T;
+}
+
+impl Expr {
+    pub fn accept<T>(&self, visitor: &mut dyn ExprVisitor<T>) -> T {
+        match self {
+            Expr::Assign(name, value) => visitor.visit_assign(name, value),
+            Expr::Binary(left, operator, right) => visitor.visit_binary(left, operator, right),
+            Expr::Call(callee, paren, arguments) => visitor.visit_call(callee, paren, arguments),
+            Expr::Get(object, name) => visitor.visit_get(object, name),
+            Expr::Set(object, name, value) => visitor.visit_set(object, name, value),
+            Expr::Super(keyword, method) => visitor.visit_super(keyword, method),
+            Expr::Logical(left, operator, right) => visitor.visit_logical(left, operator, right),
+            Expr::Ternary(condition, question, then_branch, else_branch) => visitor.visit_ternary(condition, question, then_branch, else_branch),
+            Expr::Unary(operator, right) => visitor.visit_unary(operator, right),
+            Expr::Grouping(expr) => visitor.visit_grouping(expr),
+            Expr::List(bracket, elements) => visitor.visit_list(bracket, elements),
+            Expr::This(keyword) => visitor.visit_this(keyword),
+            Expr::Var(name) => visitor.visit_var(name),
+            Expr::String(token, value) => visitor.visit_string(token, value),
+            Expr::Number(token, value) => visitor.visit_number(token, *value),
+            Expr::Boolean(token, value) => visitor.visit_boolean(token, *value),
+            Expr::Nil(token) => visitor.visit_nil(token),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn accept<T>(&self, visitor: &mut dyn StmtVisitor<T>) -> T {
+        match self {
+            Stmt::Block(statements) => visitor.visit_block(statements),
+            Stmt::Break(keyword) => visitor.visit_break(keyword),
+            Stmt::Class(name, superclass, methods) => visitor.visit_class(name, superclass.as_ref(), methods),
+            Stmt::Continue(keyword) => visitor.visit_continue(keyword),
+            Stmt::Destructure(names, value) => visitor.visit_destructure(names, value),
+            Stmt::Expression(expr) => visitor.visit_expression(expr),
+            Stmt::ForIn(name, iterable, body) => visitor.visit_for_in(name, iterable, body),
+            Stmt::Function(func) => visitor.visit_function(func),
+            Stmt::If(condition, then_branch, else_branch) => visitor.visit_if(condition, then_branch, else_branch.as_deref()),
+            Stmt::Print(expr) => visitor.visit_print(expr),
+            Stmt::Return(keyword, value) => visitor.visit_return(keyword, value.as_ref()),
+            Stmt::Var(name, initializer) => visitor.visit_var(name, initializer.as_ref()),
+            Stmt::While(condition, body, increment) => visitor.visit_while(condition, body, increment.as_ref()),
+        }
+    }
+}
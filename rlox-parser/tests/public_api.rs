@@ -0,0 +1,58 @@
+use rlox_scanner::Token;
+use rlox_parser::{ AstPrinter, Expr, ExprParser, Parser, ParserError, ParserErrorDescription, ParserErrorLocation, StmtParser };
+use rlox_test_utils::tokens;
+
+#[test]
+fn test_parse_expression_then_check_end() {
+    let mut parser = Parser::new(tokens("1 + 2 3"));
+
+    match ExprParser::new(&mut parser).parse() {
+        Ok(Expr::Binary(_, _, _)) => { },
+        result => panic!("Expected a binary expression, got {:?}", result),
+    }
+
+    assert!(!parser.is_at_end());
+    assert_eq!(parser.peek().token, Token::Number(3.0));
+}
+
+#[test]
+fn test_parse_statements() {
+    let mut parser = Parser::new(tokens("var a = 1; print a;"));
+    let statements = StmtParser::new(&mut parser).parse();
+
+    assert_eq!(statements.len(), 2);
+    assert!(statements.iter().all(Result::is_ok));
+    assert!(parser.is_at_end());
+}
+
+#[test]
+fn test_in_is_reserved() {
+    let mut parser = Parser::new(tokens("var in = 1;"));
+
+    match StmtParser::new(&mut parser).parse().as_slice() {
+        [Err(ParserError { description: ParserErrorDescription::ExpectedIdentifier(_), location, .. }), ..] => assert_eq!(location, &ParserErrorLocation::AtToken("in".into())),
+        result => panic!("Expected `in` to be rejected as a variable name, got {:?}", result),
+    }
+}
+
+
+#[test]
+fn test_print_ast() {
+    let mut parser = Parser::new(tokens("\
+class B < A { init(x) { this.x = x; } }
+for (var i = 0; i < 2; i = i + 1) print i;
+var (a, b) = [1, \"two\"];
+print f(a, b) and !b.c;
+"));
+    let mut printer = AstPrinter::new();
+    let printed: Vec<String> = StmtParser::new(&mut parser).parse().into_iter()
+        .map(|result| printer.print_stmt(&result.expect("Failed to parse source")))
+        .collect();
+
+    assert_eq!(printed, vec![
+        "(class B < A (fun init (x) (; (.= this x x))))",
+        "(block (var i 0) (while (< i 2) (print i) (= i (+ i 1))))",
+        "(var (a b) (list 1 \"two\"))",
+        "(print (and (call f a b) (! (. b c))))",
+    ]);
+}
@@ -1,7 +1,8 @@
+#[cfg(not(feature = "readline"))]
 use std::io::{ self, Write };
 use rlox_scanner::{ Scanner, ScannerError, Token };
-use rlox_parser::{ Parser, ParserError, StmtParser };
-use rlox_interpreter::{ Interpreter, RuntimeError as InterpreterError };
+use rlox_parser::{ Parser, ParserError, Stmt, StmtParser };
+use rlox_interpreter::{ Interpreter, RuntimeError as InterpreterError, RuntimeErrorDescription, StmtResult, Value };
 
 #[derive(Debug)]
 enum ReplError {
@@ -10,48 +11,181 @@ enum ReplError {
     Interpreter(InterpreterError)
 }
 
+#[cfg(not(feature = "readline"))]
 fn main() {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
     let mut interpreter = Interpreter::new();
+    let mut buffer = String::new();
 
     loop {
-        print!("lox> ");
+        print!("{}", prompt(&buffer));
         stdout.flush().unwrap();
 
-        let mut buffer = String::new();
         stdin.read_line(&mut buffer).unwrap();
+        if bracket_depth(&buffer) > 0 {
+            continue;
+        }
 
-        match run(&mut interpreter, &buffer) {
+        let source = std::mem::take(&mut buffer);
+        match run(&mut interpreter, &source) {
+            Err(ReplError::Interpreter(InterpreterError { description: RuntimeErrorDescription::Exit(code), .. })) => std::process::exit(code),
             Err(e) => eprintln!("{:?}", e),
             _ => { }
         }
     }
 }
 
+#[cfg(feature = "readline")]
+fn main() {
+    use rustyline::{ DefaultEditor, error::ReadlineError };
+
+    let mut rl = DefaultEditor::new().unwrap();
+    let history = history_path();
+    if let Some(history) = &history {
+        // a missing history file is expected on first run
+        let _ = rl.load_history(history);
+    }
+
+    let mut interpreter = Interpreter::new();
+    let mut exit_code = 0;
+    let mut buffer = String::new();
+
+    loop {
+        match rl.readline(prompt(&buffer)) {
+            Ok(line) => {
+                let _ = rl.add_history_entry(line.as_str());
+
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if bracket_depth(&buffer) > 0 {
+                    continue;
+                }
+
+                let source = std::mem::take(&mut buffer);
+                match run(&mut interpreter, &source) {
+                    Err(ReplError::Interpreter(InterpreterError { description: RuntimeErrorDescription::Exit(code), .. })) => {
+                        exit_code = code;
+                        break;
+                    },
+                    Err(e) => eprintln!("{:?}", e),
+                    _ => { }
+                }
+            },
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                break;
+            },
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = rl.save_history(history) {
+            eprintln!("Failed to save history: {:?}", e);
+        }
+    }
+
+    std::process::exit(exit_code);
+}
+
+#[cfg(feature = "readline")]
+fn history_path() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".rlox_history"))
+}
+
+fn prompt(buffer: &str) -> &'static str {
+    if buffer.is_empty() { "lox> " } else { "...  " }
+}
+
+// how many brackets are left open, input is read until they're all closed before it's parsed. this only counts tokens,
+// so brackets inside strings and comments are ignored, and anything after a scanner error is left for `run` to report
+fn bracket_depth(source: &str) -> isize {
+    let mut depth = 0;
+    for result in Scanner::new(source).tokens() {
+        match result.map(|token| token.token) {
+            Ok(Token::LeftParen) | Ok(Token::LeftBrace) | Ok(Token::LeftBracket) => depth += 1,
+            Ok(Token::RightParen) | Ok(Token::RightBrace) | Ok(Token::RightBracket) => depth -= 1,
+            Ok(_) => { },
+            Err(_) => break,
+        }
+    }
+
+    depth
+}
+
 fn run(interpreter: &mut Interpreter, source: &String) -> Result<(), ReplError> {
     let scanner = Scanner::new(source);
     let mut tokens = Vec::new();
     for result in scanner.tokens() {
         let token = result.map_err(ReplError::Scanner)?;
 
-        match &token.token {
-            Token::NewLine | Token::Whitespace | Token::Comment => { }
-
-            _ => tokens.push(token),
+        if !token.token.is_trivia() {
+            tokens.push(token.to_owned());
         }
     }
 
     let mut parser = Parser::new(tokens);
     let mut parser = StmtParser::new(&mut parser);
-    let statements = parser.parse();
+    // a lone expression doesn't need its semicolon
+    let statements = match parser.try_parse_expression() {
+        Some(expr) => vec![expr.map(Stmt::Expression)],
+        None => parser.parse(),
+    };
 
     for result in statements {
         let statement = result.map_err(ReplError::Parser)?;
 
-        interpreter.interpret(vec![statement]).map_err(ReplError::Interpreter)?;
+        match interpreter.interpret(vec![statement]).map_err(ReplError::Interpreter)? {
+            // echo what expressions evaluate to, nil is mostly from calls made for their side effects
+            StmtResult::Value(Value::Nil) => { },
+            StmtResult::Value(value) => println!("{}", value),
+            _ => { },
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bracket_depth() {
+        assert_eq!(bracket_depth("print 1;\n"), 0);
+        assert_eq!(bracket_depth("fun f() {\n"), 1);
+        assert_eq!(bracket_depth("fun f() {\n  if (a) {\n"), 2);
+        assert_eq!(bracket_depth("fun f() {\n  print [1,\n"), 2);
+        assert_eq!(bracket_depth("fun f() {\n  return 1;\n}\n"), 0);
+
+        // a stray close is left for the parser to complain about
+        assert_eq!(bracket_depth("}\n"), -1);
+    }
+
+    #[test]
+    fn test_bracket_depth_ignores_strings_and_comments() {
+        assert_eq!(bracket_depth("print \"{(\";\n"), 0);
+        assert_eq!(bracket_depth("print \"}\" + (\n"), 1);
+        assert_eq!(bracket_depth("{ // }\n"), 1);
+    }
+
+    #[test]
+    fn test_multi_line_input() {
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+
+        let mut buffer = String::new();
+        for line in &["fun f(a) {\n", "  print a;\n", "}\n", "f(\"(\"\n", ");\n"] {
+            buffer.push_str(line);
+            if bracket_depth(&buffer) > 0 {
+                continue;
+            }
+
+            run(&mut interpreter, &std::mem::take(&mut buffer)).expect("Failed to run input");
+        }
+
+        assert_eq!(output.contents(), "(\n");
+    }
+}
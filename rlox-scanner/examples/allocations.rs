@@ -0,0 +1,42 @@
+// counts the allocations made scanning a 1000 line source with borrowed and owned lexemes, run with:
+// cargo run -p rlox-scanner --release --example allocations
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Instant;
+use rlox_scanner::{ Scanner, SourceToken, SourceTokenRef };
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn measure<T>(name: &str, f: impl Fn() -> T) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    drop(result);
+
+    println!("{:8} {:>8} allocations {:>8.2}ms", name, allocations, elapsed.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let source: String = (0..1000).map(|i| format!("var a{} = (a{} + {}) * 2 - len(\"s{}\"); // line {}\n", i + 1, i, i, i, i)).collect();
+    let scanner = Scanner::new(&source);
+
+    measure("borrowed", || scanner.tokens().collect::<Result<Vec<SourceTokenRef>, _>>().unwrap());
+    measure("owned", || scanner.tokens().map(|result| result.map(SourceToken::from)).collect::<Result<Vec<_>, _>>().unwrap());
+}
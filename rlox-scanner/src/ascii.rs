@@ -0,0 +1,98 @@
+// the bytes the scanner matches on, source is scanned as bytes so these are all single byte ASCII characters
+
+pub const NUL: u8 = b'\0';
+pub const TAB: u8 = b'\t';
+pub const NEW_LINE: u8 = b'\n';
+pub const CARRIAGE_RETURN: u8 = b'\r';
+pub const SPACE: u8 = b' ';
+
+pub const BANG: u8 = b'!';
+pub const QUOTE: u8 = b'"';
+pub const PERCENT: u8 = b'%';
+pub const LEFT_PAREN: u8 = b'(';
+pub const RIGHT_PAREN: u8 = b')';
+pub const STAR: u8 = b'*';
+pub const PLUS: u8 = b'+';
+pub const COMMA: u8 = b',';
+pub const MINUS: u8 = b'-';
+pub const DOT: u8 = b'.';
+pub const SLASH: u8 = b'/';
+pub const COLON: u8 = b':';
+pub const SEMICOLON: u8 = b';';
+pub const LESS: u8 = b'<';
+pub const EQUAL: u8 = b'=';
+pub const GREATER: u8 = b'>';
+pub const QUESTION: u8 = b'?';
+pub const LEFT_BRACKET: u8 = b'[';
+pub const RIGHT_BRACKET: u8 = b']';
+pub const UNDERSCORE: u8 = b'_';
+pub const LEFT_BRACE: u8 = b'{';
+pub const RIGHT_BRACE: u8 = b'}';
+
+// inclusive bounds of the character ranges
+pub const DIGIT_ZERO: u8 = b'0';
+pub const DIGIT_NINE: u8 = b'9';
+pub const UPPER_A: u8 = b'A';
+pub const UPPER_Z: u8 = b'Z';
+pub const LOWER_A: u8 = b'a';
+pub const LOWER_Z: u8 = b'z';
+
+// fails to compile when the condition is false
+macro_rules! const_assert {
+    ($condition:expr) => {
+        const _: [(); 0 - !{ $condition } as usize] = [];
+    };
+}
+
+const SINGLE_CHARACTERS: [u8; 25] = [
+    NUL, TAB, NEW_LINE, CARRIAGE_RETURN, SPACE,
+    BANG, QUOTE, PERCENT, LEFT_PAREN, RIGHT_PAREN, STAR, PLUS, COMMA, MINUS, DOT, SLASH, SEMICOLON,
+    LESS, EQUAL, GREATER, LEFT_BRACKET, RIGHT_BRACKET, UNDERSCORE, LEFT_BRACE, RIGHT_BRACE,
+];
+
+const fn all_distinct(values: &[u8]) -> bool {
+    let mut i = 0;
+    while i < values.len() {
+        let mut j = i + 1;
+        while j < values.len() {
+            if values[i] == values[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+const fn outside_range(values: &[u8], start: u8, end: u8) -> bool {
+    let mut i = 0;
+    while i < values.len() {
+        if values[i] >= start && values[i] <= end {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+const_assert!(all_distinct(&SINGLE_CHARACTERS));
+const_assert!(outside_range(&SINGLE_CHARACTERS, DIGIT_ZERO, DIGIT_NINE));
+const_assert!(outside_range(&SINGLE_CHARACTERS, UPPER_A, UPPER_Z));
+const_assert!(outside_range(&SINGLE_CHARACTERS, LOWER_A, LOWER_Z));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        assert_eq!([NUL, TAB, NEW_LINE, CARRIAGE_RETURN, SPACE], [0x00, 0x09, 0x0A, 0x0D, 0x20]);
+        assert_eq!([BANG, QUOTE, PERCENT, LEFT_PAREN, RIGHT_PAREN, STAR, PLUS, COMMA, MINUS, DOT, SLASH], [0x21, 0x22, 0x25, 0x28, 0x29, 0x2A, 0x2B, 0x2C, 0x2D, 0x2E, 0x2F]);
+        assert_eq!([SEMICOLON, LESS, EQUAL, GREATER], [0x3B, 0x3C, 0x3D, 0x3E]);
+        assert_eq!([LEFT_BRACKET, RIGHT_BRACKET, UNDERSCORE, LEFT_BRACE, RIGHT_BRACE], [0x5B, 0x5D, 0x5F, 0x7B, 0x7D]);
+        assert_eq!([DIGIT_ZERO, DIGIT_NINE, UPPER_A, UPPER_Z, LOWER_A, LOWER_Z], [0x30, 0x39, 0x41, 0x5A, 0x61, 0x7A]);
+    }
+}
@@ -1,5 +1,6 @@
+pub mod ascii;
 mod token;
 mod scanner;
 
-pub use token::{ Token, SourceToken };
+pub use token::{ Token, SourceToken, SourceTokenRef };
 pub use scanner::{ Scanner, ScannerError };
\ No newline at end of file
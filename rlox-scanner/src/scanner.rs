@@ -1,7 +1,9 @@
-use crate::{ Token, SourceToken };
+use std::borrow::Cow;
+use crate::{ Token, SourceTokenRef };
+use crate::ascii::*;
 
 pub struct Scanner<'a> {
-    source: &'a str,
+    source: Cow<'a, str>,
 }
 
 pub struct ScannerIterator<'a> {
@@ -13,7 +15,7 @@ pub struct ScannerIterator<'a> {
     line: usize,
 }
 
-type ScanResult = Result<SourceToken, ScannerError>;
+type ScanResult<'a> = Result<SourceTokenRef<'a>, ScannerError>;
 
 #[derive(Debug)]
 pub struct ScannerError {
@@ -27,6 +29,10 @@ pub struct ScannerError {
 #[derive(Debug, PartialEq)]
 pub enum ScannerErrorType {
     UnknownCharacter(u8),
+    // a valid non-ASCII character that can't appear outside strings and comments
+    UnknownUnicodeCharacter(char),
+    // the lead byte and continuation bytes of a malformed multi-byte sequence
+    InvalidUtf8Sequence(Vec<u8>),
     Utf8Error(::std::str::Utf8Error),
     UnterminatedString,
     InvalidNumber(::std::num::ParseFloatError)
@@ -35,7 +41,7 @@ pub enum ScannerErrorType {
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Scanner {
         Scanner {
-            source,
+            source: Cow::Borrowed(source),
         }
     }
 
@@ -51,8 +57,17 @@ impl<'a> Scanner<'a> {
     }
 }
 
+impl Scanner<'static> {
+    // owns the source, for when it is built at runtime and there is nothing to borrow it from
+    pub fn from_string(source: String) -> Scanner<'static> {
+        Scanner {
+            source: Cow::Owned(source),
+        }
+    }
+}
+
 impl<'a> ScannerIterator<'a> {
-    fn scan_token(&mut self) -> ScanResult {
+    fn scan_token(&mut self) -> ScanResult<'a> {
         if self.is_at_end() {
             let token = self.token(Token::Eof);
             self.current += 1;
@@ -62,57 +77,79 @@ impl<'a> ScannerIterator<'a> {
         let c = self.advance();
 
         match c {
-            0x28 => self.token(Token::LeftParen),
-            0x29 => self.token(Token::RightParen),
-            0x7B => self.token(Token::LeftBrace),
-            0x7D => self.token(Token::RightBrace),
-            0x2C => self.token(Token::Comma),
-            0x2E => self.token(Token::Dot),
-            0x2D => self.token(Token::Minus),
-            0x2B => self.token(Token::Plus),
-            0x3B => self.token(Token::Semicolon),
-            0x2A => self.token(Token::Star),
-
-            0x21 => if self.expect(0x3D) { self.token(Token::BangEqual) } else { self.token(Token::Bang) },
-            0x3D => if self.expect(0x3D) { self.token(Token::EqualEqual) } else { self.token(Token::Equal) },
-            0x3C => if self.expect(0x3D) { self.token(Token::LessEqual) } else { self.token(Token::Less) },
-            0x3E => if self.expect(0x3D) { self.token(Token::GreaterEqual) } else { self.token(Token::Greater) },
-
-            0x2F => {
-                if self.expect(0x2F) {
-                    while self.peek() != 0x0A && !self.is_at_end() { self.advance(); }
+            LEFT_PAREN => self.token(Token::LeftParen),
+            RIGHT_PAREN => self.token(Token::RightParen),
+            LEFT_BRACE => self.token(Token::LeftBrace),
+            RIGHT_BRACE => self.token(Token::RightBrace),
+            LEFT_BRACKET => self.token(Token::LeftBracket),
+            RIGHT_BRACKET => self.token(Token::RightBracket),
+            COLON => self.token(Token::Colon),
+            COMMA => self.token(Token::Comma),
+            DOT => self.token(Token::Dot),
+            MINUS => self.token(Token::Minus),
+            PERCENT => self.token(Token::Percent),
+            PLUS => self.token(Token::Plus),
+            QUESTION => self.token(Token::Question),
+            SEMICOLON => self.token(Token::Semicolon),
+            STAR => self.token(Token::Star),
+
+            BANG => if self.expect(EQUAL) { self.token(Token::BangEqual) } else { self.token(Token::Bang) },
+            EQUAL => if self.expect(EQUAL) { self.token(Token::EqualEqual) } else { self.token(Token::Equal) },
+            LESS => if self.expect(EQUAL) { self.token(Token::LessEqual) } else { self.token(Token::Less) },
+            GREATER => if self.expect(EQUAL) { self.token(Token::GreaterEqual) } else { self.token(Token::Greater) },
+
+            SLASH => {
+                if self.expect(SLASH) {
+                    while self.peek() != NEW_LINE && !self.is_at_end() { self.advance(); }
                     self.token(Token::Comment)
                 } else {
                     self.token(Token::Slash)
                 }
             }
 
-            0x09 | 0x0D | 0x20 => {
+            TAB | CARRIAGE_RETURN | SPACE => {
                 self.token(Token::Whitespace)
             }
 
-            0x0A => {
+            NEW_LINE => {
                 let token = self.token(Token::NewLine);
                 self.line += 1;
                 token
             }
 
-            0x22 => self.string(),
-
-            0x30..=0x39 => self.number(),
-
-            0x41..=0x5A | 0x5F | 0x61..=0x7A => self.identifier(),
+            QUOTE => self.string(),
+
+            DIGIT_ZERO..=DIGIT_NINE => self.number(),
+
+            UPPER_A..=UPPER_Z | UNDERSCORE | LOWER_A..=LOWER_Z => self.identifier(),
+
+            // non-ASCII letters and digits can be used in identifiers, e.g. `var café = 1;`
+            c if c >= 0x80 => match self.multi_byte_char(self.start) {
+                Some((c, length)) => {
+                    self.current = self.start + length;
+                    if c.is_alphanumeric() {
+                        self.identifier()
+                    } else {
+                        Err(self.error(ScannerErrorType::UnknownUnicodeCharacter(c)))
+                    }
+                },
+                None => {
+                    // the whole broken sequence is one error, rather than one per continuation byte
+                    while is_continuation(self.peek()) { self.advance(); }
+                    Err(self.error(ScannerErrorType::InvalidUtf8Sequence(self.source[self.start..self.current].to_vec())))
+                },
+            },
 
             _ => Err(self.error(ScannerErrorType::UnknownCharacter(c)))
         }
     }
 
     // tokens
-    fn string(&mut self) -> ScanResult {
+    fn string(&mut self) -> ScanResult<'a> {
         // already consumed the opening "
 
-        while self.peek() != 0x22 && !self.is_at_end() {
-            if self.peek() == 0x0A { self.line += 1 }
+        while self.peek() != QUOTE && !self.is_at_end() {
+            if self.peek() == NEW_LINE { self.line += 1 }
             self.advance();
         }
 
@@ -127,12 +164,12 @@ impl<'a> ScannerIterator<'a> {
         }
     }
 
-     fn number(&mut self) -> ScanResult {
+     fn number(&mut self) -> ScanResult<'a> {
          while is_digit(self.peek()) {
              self.advance();
          }
 
-         if self.peek() == 0x2E && is_digit(self.peek_next()) {
+         if self.peek() == DOT && is_digit(self.peek_next()) {
              // consume .
              self.advance();
 
@@ -148,9 +185,15 @@ impl<'a> ScannerIterator<'a> {
          self.token(Token::Number(value))
      }
 
-    fn identifier(&mut self) -> ScanResult {
-        while is_alphanumeric(self.peek()) {
-            self.advance();
+    fn identifier(&mut self) -> ScanResult<'a> {
+        loop {
+            if is_alphanumeric(self.peek()) {
+                self.advance();
+            } else if let Some((_, length)) = self.multi_byte_char(self.current).filter(|(c, _)| c.is_alphanumeric()) {
+                self.current += length;
+            } else {
+                break;
+            }
         }
 
         let value = self.slice_source(self.start..self.current)?;
@@ -160,12 +203,12 @@ impl<'a> ScannerIterator<'a> {
         }    }
 
     // results
-    fn token(&self, token: Token) -> ScanResult {
+    fn token(&self, token: Token) -> ScanResult<'a> {
         let lexeme = self.slice_source(self.start..self.current)?;
 
-        Ok(SourceToken {
+        Ok(SourceTokenRef {
             token,
-            lexeme: lexeme.into(),
+            lexeme,
 
             line: self.line,
         })
@@ -184,14 +227,14 @@ impl<'a> ScannerIterator<'a> {
     // movement
     fn peek(&self) -> u8 {
         if self.is_at_end() {
-            0
+            NUL
         } else {
             self.source[self.current]
         }
     }
     fn peek_next(&self) -> u8 {
         if self.current + 1 >= self.source.len()  {
-            0
+            NUL
         } else {
             self.source[self.current + 1]
         }
@@ -214,7 +257,28 @@ impl<'a> ScannerIterator<'a> {
         return true;
     }
 
-    fn slice_source(&self, range: ::std::ops::Range<usize>) -> Result<&str, ScannerError> {
+    // the length of the well formed multi-byte UTF-8 sequence starting at `offset`, if there is one
+    fn multi_byte_length(&self, offset: usize) -> Option<usize> {
+        let length = match self.source.get(offset)? {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return None,
+        };
+
+        let continuation = self.source.get(offset + 1..offset + length)?;
+        if continuation.iter().all(|&b| is_continuation(b)) { Some(length) } else { None }
+    }
+
+    // the character encoded at `offset` and its length in bytes, if it's a valid multi-byte sequence
+    fn multi_byte_char(&self, offset: usize) -> Option<(char, usize)> {
+        let length = self.multi_byte_length(offset)?;
+        let c = ::std::str::from_utf8(&self.source[offset..offset + length]).ok()?.chars().next()?;
+
+        Some((c, length))
+    }
+
+    fn slice_source(&self, range: ::std::ops::Range<usize>) -> Result<&'a str, ScannerError> {
         ::std::str::from_utf8(&self.source[range])
             .map_err(|e| self.error(ScannerErrorType::Utf8Error(e)))
     }
@@ -230,24 +294,30 @@ impl<'a> ScannerIterator<'a> {
 }
 
 fn is_digit(v: u8) -> bool {
-    match v { 0x30..=0x39 => true, _ => false }
+    match v { DIGIT_ZERO..=DIGIT_NINE => true, _ => false }
 }
 fn is_alpha(v: u8) -> bool {
-    match v { 0x41..=0x5A | 0x5F | 0x61..=0x7A => true, _ => false }
+    match v { UPPER_A..=UPPER_Z | UNDERSCORE | LOWER_A..=LOWER_Z => true, _ => false }
 }
 fn is_alphanumeric(v: u8) -> bool {
     is_alpha(v) || is_digit(v)
 }
+fn is_continuation(v: u8) -> bool {
+    v & 0xC0 == 0x80
+}
 
 fn identifier_to_keyword(identifier: &str) -> Option<Token> {
     match identifier {
         "and" => Some(Token::And),
+        "break" => Some(Token::Break),
         "class" => Some(Token::Class),
+        "continue" => Some(Token::Continue),
         "else" => Some(Token::Else),
         "false" => Some(Token::False),
         "for" => Some(Token::For),
         "fun" => Some(Token::Fun),
         "if" => Some(Token::If),
+        "in" => Some(Token::In),
         "nil" => Some(Token::Nil),
         "or" => Some(Token::Or),
         "print" => Some(Token::Print),
@@ -263,7 +333,7 @@ fn identifier_to_keyword(identifier: &str) -> Option<Token> {
 }
 
 impl<'a> Iterator for ScannerIterator<'a> {
-    type Item = ScanResult;
+    type Item = ScanResult<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.is_past_end() {
@@ -277,22 +347,25 @@ impl<'a> Iterator for ScannerIterator<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::SourceToken;
     use super::*;
 
-    fn parse(source: &str) -> Vec<ScanResult> {
+    type OwnedScanResult = Result<SourceToken, ScannerError>;
+
+    fn parse(source: &str) -> Vec<OwnedScanResult> {
         let scanner = Scanner::new(source);
         let tokens =  scanner.tokens();
 
-        tokens.collect()
+        tokens.map(|result| result.map(SourceToken::from)).collect()
     }
 
-    fn get_token(source: &str, index: usize) -> ScanResult {
+    fn get_token(source: &str, index: usize) -> OwnedScanResult {
         let mut tokens = parse(source);
         assert!(index < tokens.len(), "Tried to get token at index {} but there was only {} tokens", index, tokens.len());
         tokens.remove(index)
     }
 
-    fn assert_error(result: ScanResult, expected: ScannerErrorType) {
+    fn assert_error(result: OwnedScanResult, expected: ScannerErrorType) {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().error, expected)
 
@@ -304,10 +377,15 @@ mod tests {
         assert_eq!(get_token(")", 0)?.token, Token::RightParen);
         assert_eq!(get_token("{", 0)?.token, Token::LeftBrace);
         assert_eq!(get_token("}", 0)?.token, Token::RightBrace);
+        assert_eq!(get_token("[", 0)?.token, Token::LeftBracket);
+        assert_eq!(get_token("]", 0)?.token, Token::RightBracket);
+        assert_eq!(get_token(":", 0)?.token, Token::Colon);
         assert_eq!(get_token(",", 0)?.token, Token::Comma);
         assert_eq!(get_token(".", 0)?.token, Token::Dot);
         assert_eq!(get_token("-", 0)?.token, Token::Minus);
+        assert_eq!(get_token("%", 0)?.token, Token::Percent);
         assert_eq!(get_token("+", 0)?.token, Token::Plus);
+        assert_eq!(get_token("?", 0)?.token, Token::Question);
         assert_eq!(get_token(";", 0)?.token, Token::Semicolon);
         assert_eq!(get_token("*", 0)?.token, Token::Star);
 
@@ -372,18 +450,25 @@ mod tests {
         assert_eq!(get_token("_a", 0)?.token, Token::Identifier("_a".into()));
         assert_eq!(get_token("_0", 0)?.token, Token::Identifier("_0".into()));
 
+        // keywords only match the whole identifier
+        assert_eq!(get_token("breakout", 0)?.token, Token::Identifier("breakout".into()));
+        assert_eq!(get_token("continued", 0)?.token, Token::Identifier("continued".into()));
+
         Ok(())
     }
 
     #[test]
     fn test_parse_keyword() -> Result<(), ScannerError> {
         assert_eq!(get_token("and", 0)?.token, Token::And);
+        assert_eq!(get_token("break", 0)?.token, Token::Break);
         assert_eq!(get_token("class", 0)?.token, Token::Class);
+        assert_eq!(get_token("continue", 0)?.token, Token::Continue);
         assert_eq!(get_token("else", 0)?.token, Token::Else);
         assert_eq!(get_token("false", 0)?.token, Token::False);
         assert_eq!(get_token("for", 0)?.token, Token::For);
         assert_eq!(get_token("fun", 0)?.token, Token::Fun);
         assert_eq!(get_token("if", 0)?.token, Token::If);
+        assert_eq!(get_token("in", 0)?.token, Token::In);
         assert_eq!(get_token("nil", 0)?.token, Token::Nil);
         assert_eq!(get_token("or", 0)?.token, Token::Or);
         assert_eq!(get_token("print", 0)?.token, Token::Print);
@@ -397,6 +482,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_keyword_prefixes() -> Result<(), ScannerError> {
+        // `in` is reserved for `for ... in` loops, identifiers merely starting with it are unaffected
+        assert_eq!(get_token("in", 0)?.token, Token::In);
+        assert_eq!(get_token("inner", 0)?.token, Token::Identifier("inner".into()));
+        assert_eq!(get_token("in_", 0)?.token, Token::Identifier("in_".into()));
+        assert_eq!(get_token("index", 0)?.token, Token::Identifier("index".into()));
+        assert_eq!(get_token("forin", 0)?.token, Token::Identifier("forin".into()));
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_new_line() -> Result<(), ScannerError> {
         assert_eq!(get_token("+\n+", 0)?.line, 1);
@@ -419,9 +516,88 @@ mod tests {
         assert_error(result, ScannerErrorType::UnknownCharacter(0x40));
     }
 
+    #[test]
+    fn test_parse_unicode() -> Result<(), ScannerError> {
+        assert_eq!(get_token("café", 0)?.token, Token::Identifier("café".into()));
+        assert_eq!(get_token("_λ2", 0)?.token, Token::Identifier("_λ2".into()));
+        assert_eq!(get_token("日本", 0)?.token, Token::Identifier("日本".into()));
+        assert_eq!(get_token("\"🦀 ok\"", 0)?.token, Token::String("🦀 ok".into()));
+        assert_eq!(get_token("// ünïcödé 🦀\n+", 2)?.token, Token::Plus);
+
+        let tokens: Vec<_> = parse("var café = \"🦀\";").into_iter().collect::<Result<_, _>>()?;
+        assert_eq!(tokens.len(), 9);
+        assert_eq!(tokens[2].lexeme, "café");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_unicode_non_identifier() -> Result<(), ScannerError> {
+        // symbols and unicode whitespace aren't identifier characters, wherever they appear
+        assert_error(get_token("🦀", 0), ScannerErrorType::UnknownUnicodeCharacter('🦀'));
+        assert_error(get_token("a\u{a0}", 1), ScannerErrorType::UnknownUnicodeCharacter('\u{a0}'));
+        assert_error(get_token("\u{2028}", 0), ScannerErrorType::UnknownUnicodeCharacter('\u{2028}'));
+        assert_eq!(get_token("crab🦀", 0)?.token, Token::Identifier("crab".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_utf8() {
+        // a lead byte for 3 bytes followed by only one continuation byte, then a stray continuation byte
+        let source: &[u8] = &[0xE2, 0x82, b'+', 0x82];
+        let iterator = ScannerIterator { source, start: 0, current: 0, line: 1 };
+        let results: Vec<_> = iterator.map(|result| result.map(|token| token.token).map_err(|e| e.error)).collect();
+
+        assert_eq!(results, vec![
+            Err(ScannerErrorType::InvalidUtf8Sequence(vec![0xE2, 0x82])),
+            Ok(Token::Plus),
+            Err(ScannerErrorType::InvalidUtf8Sequence(vec![0x82])),
+            Ok(Token::Eof),
+        ]);
+    }
+
     #[test]
     fn test_parse_unterminated_string() {
         let result = get_token("\"abc", 0);
         assert_error(result, ScannerErrorType::UnterminatedString);
     }
-}
\ No newline at end of file
+
+    fn generated_scanner(count: usize) -> Scanner<'static> {
+        let source = (0..count).map(|i| format!("var a{} = {};\n", i, i)).collect::<String>();
+
+        Scanner::from_string(source)
+    }
+
+    #[test]
+    fn test_from_string() -> Result<(), ScannerError> {
+        let scanner = generated_scanner(3);
+        let tokens = scanner.tokens()
+            .filter(|result| match result { Ok(token) => token.token != Token::Whitespace && token.token != Token::NewLine, Err(_) => true })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(tokens.len(), 3 * 5 + 1);
+        assert_eq!(tokens[1].token, Token::Identifier("a0".into()));
+        assert_eq!(tokens[13].line, 3);
+        assert_eq!(tokens[13].token, Token::Number(2f64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexeme_borrows_source() -> Result<(), ScannerError> {
+        let source = "var name = \"value\";";
+        let scanner = Scanner::new(source);
+        let tokens = scanner.tokens().collect::<Result<Vec<_>, _>>()?;
+
+        let source_range = source.as_bytes().as_ptr_range();
+        for token in &tokens {
+            assert!(source_range.contains(&token.lexeme.as_ptr()) || token.lexeme.is_empty(), "{:?} doesn't point into the source", token);
+        }
+
+        assert_eq!(tokens[2].lexeme, "name");
+        assert_eq!(tokens[2].to_owned(), SourceToken { token: Token::Identifier("name".into()), lexeme: "name".into(), line: 1 });
+
+        Ok(())
+    }
+}
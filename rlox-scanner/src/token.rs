@@ -1,8 +1,8 @@
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     // Single-character tokens.
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Colon, Comma, Dot, Minus, Percent, Plus, Question, Semicolon, Slash, Star,
 
     // One or two character tokens.
     Bang, BangEqual,
@@ -16,12 +16,39 @@ pub enum Token {
     Number(f64),
 
     // Keywords.
-    And, Class, Else, False, Fun, For, If, Nil, Or,
+    And, Break, Class, Continue, Else, False, Fun, For, If, In, Nil, Or,
     Print, Return, Super, This, True, Var, While,
 
     Comment, Whitespace, NewLine, Eof
 }
 
+impl Token {
+    // tokens with no meaning to the parser, which filters them out
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Token::Comment | Token::Whitespace | Token::NewLine)
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        matches!(self,
+            Token::And | Token::Break | Token::Class | Token::Continue | Token::Else | Token::False | Token::Fun | Token::For |
+            Token::If | Token::In | Token::Nil | Token::Or | Token::Print | Token::Return | Token::Super | Token::This |
+            Token::True | Token::Var | Token::While)
+    }
+
+    // strings and numbers, `true`, `false` and `nil` are keywords
+    pub fn is_literal(&self) -> bool {
+        matches!(self, Token::String(_) | Token::Number(_))
+    }
+
+    // arithmetic, comparison, assignment and the conditional's `?` and `:`, `and` and `or` are keywords
+    pub fn is_operator(&self) -> bool {
+        matches!(self,
+            Token::Colon | Token::Question | Token::Minus | Token::Percent | Token::Plus | Token::Slash | Token::Star |
+            Token::Bang | Token::BangEqual | Token::Equal | Token::EqualEqual |
+            Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SourceToken {
     pub token: Token,
@@ -29,6 +56,34 @@ pub struct SourceToken {
     pub line: usize,
 }
 
+// a token whose lexeme borrows from the source being scanned, the owned SourceToken is to this what String is to str
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceTokenRef<'a> {
+    pub token: Token,
+    pub lexeme: &'a str,
+    pub line: usize,
+}
+
+impl<'a> SourceTokenRef<'a> {
+    pub fn to_owned(&self) -> SourceToken {
+        SourceToken {
+            token: self.token.clone(),
+            lexeme: self.lexeme.into(),
+            line: self.line,
+        }
+    }
+}
+
+impl<'a> From<SourceTokenRef<'a>> for SourceToken {
+    fn from(token: SourceTokenRef<'a>) -> Self {
+        SourceToken {
+            token: token.token,
+            lexeme: token.lexeme.into(),
+            line: token.line,
+        }
+    }
+}
+
 impl Default for SourceToken {
     fn default() -> Self {
         SourceToken {
@@ -37,4 +92,59 @@ impl Default for SourceToken {
             line: 0
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_tokens() -> Vec<Token> {
+        vec![
+            Token::LeftParen, Token::RightParen, Token::LeftBrace, Token::RightBrace, Token::LeftBracket, Token::RightBracket,
+            Token::Colon, Token::Comma, Token::Dot, Token::Minus, Token::Percent, Token::Plus, Token::Question, Token::Semicolon, Token::Slash, Token::Star,
+            Token::Bang, Token::BangEqual, Token::Equal, Token::EqualEqual, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual,
+            Token::Identifier("a".into()), Token::String("a".into()), Token::Number(1.0),
+            Token::And, Token::Break, Token::Class, Token::Continue, Token::Else, Token::False, Token::Fun, Token::For, Token::If,
+            Token::In, Token::Nil, Token::Or, Token::Print, Token::Return, Token::Super, Token::This, Token::True, Token::Var, Token::While,
+            Token::Comment, Token::Whitespace, Token::NewLine, Token::Eof,
+        ]
+    }
+
+    fn matching(predicate: fn(&Token) -> bool) -> Vec<Token> {
+        all_tokens().into_iter().filter(predicate).collect()
+    }
+
+    #[test]
+    fn test_is_trivia() {
+        assert_eq!(matching(Token::is_trivia), vec![Token::Comment, Token::Whitespace, Token::NewLine]);
+    }
+
+    #[test]
+    fn test_is_keyword() {
+        let keywords = matching(Token::is_keyword);
+        assert_eq!(keywords.len(), 19);
+        assert!(keywords.contains(&Token::True) && keywords.contains(&Token::Nil) && keywords.contains(&Token::And));
+        assert!(!Token::Identifier("while".into()).is_keyword());
+    }
+
+    #[test]
+    fn test_is_literal() {
+        assert_eq!(matching(Token::is_literal), vec![Token::String("a".into()), Token::Number(1.0)]);
+    }
+
+    #[test]
+    fn test_is_operator() {
+        assert_eq!(matching(Token::is_operator), vec![
+            Token::Colon, Token::Minus, Token::Percent, Token::Plus, Token::Question, Token::Slash, Token::Star,
+            Token::Bang, Token::BangEqual, Token::Equal, Token::EqualEqual, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual,
+        ]);
+    }
+
+    #[test]
+    fn test_classes_are_disjoint() {
+        for token in all_tokens() {
+            let classes = [token.is_trivia(), token.is_keyword(), token.is_literal(), token.is_operator()];
+            assert!(classes.iter().filter(|&&class| class).count() <= 1, "{:?} is in more than one class", token);
+        }
+    }
+}
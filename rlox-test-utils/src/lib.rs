@@ -0,0 +1,142 @@
+use rlox_scanner::{ Scanner, ScannerError, SourceToken };
+use rlox_parser::{ Parser, ParserError, Stmt, StmtParser };
+use rlox_interpreter::{ Interpreter, RuntimeError };
+
+pub use rlox_interpreter::RuntimeErrorDescription;
+
+#[derive(Debug)]
+pub enum LoxError {
+    Scanner(ScannerError),
+    Parser(ParserError),
+    Runtime(RuntimeError),
+}
+
+/// Scans `source` into the tokens the parser expects, without trivia, panicking if it fails to scan
+pub fn tokens(source: &str) -> Vec<SourceToken> {
+    Scanner::new(source).tokens()
+        .map(|result| result.expect("Failed to scan source"))
+        .filter(|token| !token.token.is_trivia())
+        .map(|token| token.to_owned())
+        .collect()
+}
+
+/// Scans and parses `source` into statements, panicking if either fails
+pub fn parse(source: &str) -> Vec<Stmt> {
+    let mut parser = Parser::new(tokens(source));
+    StmtParser::new(&mut parser).parse().into_iter()
+        .map(|result| result.expect("Failed to parse source"))
+        .collect()
+}
+
+pub fn run_interpreter(source: &str) -> (String, Result<(), LoxError>) {
+    let mut interpreter = Interpreter::new();
+    let output = interpreter.capture_output();
+
+    let result = run(&mut interpreter, source);
+
+    (output.contents(), result)
+}
+
+fn run(interpreter: &mut Interpreter, source: &str) -> Result<(), LoxError> {
+    let scanner = Scanner::new(source);
+    let mut tokens = Vec::new();
+    for result in scanner.tokens() {
+        let token = result.map_err(LoxError::Scanner)?;
+
+        if !token.token.is_trivia() {
+            tokens.push(token.to_owned());
+        }
+    }
+
+    let mut parser = Parser::new(tokens);
+    let mut parser = StmtParser::new(&mut parser);
+    let mut statements = Vec::new();
+    for result in parser.parse() {
+        statements.push(result.map_err(LoxError::Parser)?);
+    }
+
+    interpreter.interpret(statements).map_err(LoxError::Runtime)?;
+
+    Ok(())
+}
+
+/// Runs `source` in a fresh interpreter and asserts everything it printed matches `expected`
+#[macro_export]
+macro_rules! assert_lox_output {
+    ($source:expr, $expected:expr) => {
+        {
+            let (output, result) = $crate::run_interpreter($source);
+            if let Err(e) = result {
+                panic!("Failed to run {:?}: {:?}\noutput: {:?}", $source, e, output);
+            }
+
+            assert_eq!(output, $expected, "output of {:?}", $source);
+        }
+    };
+}
+
+/// Runs `source` in a fresh interpreter and asserts it fails with a runtime error matching `pattern`
+#[macro_export]
+macro_rules! assert_lox_error {
+    ($source:expr, $pattern:pat) => {
+        {
+            let (_, result) = $crate::run_interpreter($source);
+            match result {
+                Err($crate::LoxError::Runtime(e)) => {
+                    #[allow(unused_imports)]
+                    use $crate::RuntimeErrorDescription::*;
+
+                    match e.description {
+                        $pattern => { },
+                        description => panic!("Expected {:?} to fail with {}, but got {:?}", $source, stringify!($pattern), description),
+                    }
+                },
+                other => panic!("Expected {:?} to fail with {}, but got {:?}", $source, stringify!($pattern), other),
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use rlox_scanner::Token;
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tokens: Vec<Token> = tokens("var a = 1; // one\n").into_iter().map(|token| token.token).collect();
+        assert_eq!(tokens, vec![Token::Var, Token::Identifier("a".into()), Token::Equal, Token::Number(1.0), Token::Semicolon, Token::Eof]);
+
+        assert_eq!(parse("var a = 1; print a;").len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to parse source")]
+    fn test_parse_error() {
+        parse("var 1;");
+    }
+
+    #[test]
+    fn test_assert_lox_output() {
+        assert_lox_output!("print 1 + 1;", "2\n");
+        assert_lox_output!("", "");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_assert_lox_output_mismatch() {
+        assert_lox_output!("print 1;", "2\n");
+    }
+
+    #[test]
+    fn test_assert_lox_error() {
+        assert_lox_error!("print -nil;", ExpectedNumber(_));
+        assert_lox_error!("exit(2);", Exit(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_assert_lox_error_success() {
+        assert_lox_error!("print 1;", ExpectedNumber(_));
+    }
+}
@@ -1,12 +1,11 @@
-use rlox_scanner::{ Scanner, ScannerError, Token };
+use rlox_scanner::{ Scanner, ScannerError };
 use rlox_parser::{ Parser, ParserError, StmtParser };
-use rlox_interpreter::{ Interpreter, RuntimeError as InterpreterError };
+use rlox_interpreter::{ Interpreter, RuntimeErrorDescription };
 
 #[derive(Debug)]
 enum RloxError {
     Scanner(ScannerError),
     Parser(ParserError),
-    Interpreter(InterpreterError)
 }
 
 
@@ -40,11 +39,8 @@ fn run_file(file_name: &String) -> Result<(), i32> {
 
         match result {
             Ok(token) => {
-                match
-                    &token.token {
-                    Token::NewLine | Token::Whitespace | Token::Comment => {}
-
-                    _ => tokens.push(token),
+                if !token.token.is_trivia() {
+                    tokens.push(token.to_owned());
                 }
             },
 
@@ -73,8 +69,18 @@ fn run_file(file_name: &String) -> Result<(), i32> {
     }
 
     let mut interpreter = Interpreter::new();
-    interpreter.interpret(statements).map_err(RloxError::Interpreter)
-        .map_err(|e| { eprintln!("Runtime error: {:?}", e); 70 })?;
+    interpreter.interpret(statements)
+        .map_err(|mut e| match e.description {
+            RuntimeErrorDescription::Exit(code) => code,
+            _ => {
+                let call_stack = std::mem::take(&mut e.call_stack);
+                eprintln!("Runtime error: {}", e);
+                for frame in call_stack {
+                    eprintln!("    in {}() declared on line {}", frame.name, frame.line);
+                }
+                70
+            },
+        })?;
 
     Ok(())
 }
\ No newline at end of file